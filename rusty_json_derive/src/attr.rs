@@ -0,0 +1,116 @@
+//! Parsing of the `#[json(...)]` attributes.
+use syn::ext::IdentExt;
+use syn::{Attribute, Error, ExprPath, Field, Ident, LitStr, Result};
+
+/// How enum variants are represented.
+pub enum Tagging {
+    /// `{"Variant": content}`, unit variants as `"Variant"`. The default.
+    External,
+    /// `#[json(tag = "t")]`: `{"t": "Variant", ...fields}`.
+    Internal(String),
+    /// `#[json(tag = "t", content = "c")]`: `{"t": "Variant", "c": content}`.
+    Adjacent(String, String),
+    /// `#[json(untagged)]`: just the content, the first variant that converts wins.
+    Untagged,
+}
+
+pub fn container(attrs: &[Attribute]) -> Result<Tagging> {
+    let mut tag = None;
+    let mut content = None;
+    let mut untagged = None;
+    for attr in json_attrs(attrs) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                tag = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("content") {
+                let path = meta.path.clone();
+                content = Some((meta.value()?.parse::<LitStr>()?.value(), path));
+            } else if meta.path.is_ident("untagged") {
+                untagged = Some(meta.path.clone());
+            } else {
+                return Err(meta.error("unknown container attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    match (tag, content, untagged) {
+        (None, None, None) => Ok(Tagging::External),
+        (Some(tag), None, None) => Ok(Tagging::Internal(tag)),
+        (Some(tag), Some((content, _)), None) => Ok(Tagging::Adjacent(tag, content)),
+        (None, None, Some(_)) => Ok(Tagging::Untagged),
+        (_, _, Some(path)) => Err(Error::new_spanned(
+            path,
+            "`untagged` can't be combined with `tag` or `content`",
+        )),
+        (None, Some((_, path)), None) => Err(Error::new_spanned(path, "`content` requires `tag`")),
+    }
+}
+
+pub enum Default {
+    Trait,
+    Path(ExprPath),
+}
+
+pub struct FieldAttrs {
+    /// The member name in JSON.
+    pub key: String,
+    pub default: Option<Default>,
+    pub skip: bool,
+    pub flatten: bool,
+}
+
+pub fn field(field: &Field, index: usize) -> Result<FieldAttrs> {
+    let mut attrs = FieldAttrs {
+        key: match &field.ident {
+            Some(ident) => ident.unraw().to_string(),
+            None => index.to_string(),
+        },
+        default: None,
+        skip: false,
+        flatten: false,
+    };
+    for attr in json_attrs(&field.attrs) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                attrs.key = meta.value()?.parse::<LitStr>()?.value();
+            } else if meta.path.is_ident("default") {
+                attrs.default = Some(if meta.input.peek(syn::Token![=]) {
+                    Default::Path(meta.value()?.parse::<LitStr>()?.parse()?)
+                } else {
+                    Default::Trait
+                });
+            } else if meta.path.is_ident("skip") {
+                attrs.skip = true;
+            } else if meta.path.is_ident("flatten") {
+                if field.ident.is_none() {
+                    return Err(meta.error("only named fields can be flattened"));
+                }
+                attrs.flatten = true;
+            } else {
+                return Err(meta.error("unknown field attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attrs)
+}
+
+/// The name of a variant in JSON.
+pub fn variant(ident: &Ident, attrs: &[Attribute]) -> Result<String> {
+    let mut name = ident.unraw().to_string();
+    for attr in json_attrs(attrs) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("unknown variant attribute"))
+            }
+        })?;
+    }
+    Ok(name)
+}
+
+fn json_attrs(attrs: &[Attribute]) -> impl Iterator<Item = &Attribute> {
+    attrs.iter().filter(|attr| attr.path().is_ident("json"))
+}
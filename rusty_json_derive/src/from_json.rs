@@ -0,0 +1,272 @@
+use crate::attr::{self, Tagging};
+use crate::{with_bound, FieldInfo, Shape};
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Result};
+
+pub fn expand(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let generics = with_bound(&input.generics, quote!(::rusty_json::derive::FromJson));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let mut from_flattened = TokenStream2::new();
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let shape = Shape::of(&data.fields)?;
+            if let Shape::Struct(fields) = &shape {
+                let members = members(&quote!(Self), fields);
+                from_flattened = quote! {
+                    fn from_flattened(
+                        rest: &mut ::std::collections::HashMap<
+                            ::std::string::String,
+                            ::rusty_json::parser::Json,
+                        >,
+                    ) -> ::core::result::Result<Self, ::rusty_json::convert::ConversionError> {
+                        #members
+                    }
+                };
+            }
+            construct(&quote!(Self), &shape, &quote!(json), None)
+        }
+        Data::Enum(data) => {
+            let tagging = attr::container(&input.attrs)?;
+            let variants = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let ident = &variant.ident;
+                    let shape = Shape::of(&variant.fields)?;
+                    if let (Tagging::Internal(_), Shape::Tuple(_)) = (&tagging, &shape) {
+                        return Err(Error::new_spanned(
+                            variant,
+                            "internally tagged enums can't have tuple variants",
+                        ));
+                    }
+                    Ok((
+                        attr::variant(ident, &variant.attrs)?,
+                        quote!(Self::#ident),
+                        shape,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            from_variants(&tagging, &name.to_string(), &variants)
+        }
+        Data::Union(_) => return Err(Error::new_spanned(input, "unions are not supported")),
+    };
+    Ok(quote! {
+        impl #impl_generics ::rusty_json::derive::FromJson for #name #ty_generics #where_clause {
+            fn from_json(
+                json: &::rusty_json::parser::Json,
+            ) -> ::core::result::Result<Self, ::rusty_json::convert::ConversionError> {
+                #body
+            }
+            #from_flattened
+        }
+    })
+}
+
+/// An expression converting `json` into the struct or variant at `path`. The `tag` member of an
+/// internally tagged variant is left out of what its flattened fields see.
+fn construct(
+    path: &TokenStream2,
+    shape: &Shape,
+    json: &TokenStream2,
+    tag: Option<&str>,
+) -> TokenStream2 {
+    let body = match shape {
+        Shape::Unit => quote! {
+            ::rusty_json::derive::__private::null(#json)?;
+            ::core::result::Result::Ok(#path)
+        },
+        Shape::Newtype(_) => quote! {
+            ::core::result::Result::Ok(#path(::rusty_json::derive::FromJson::from_json(#json)?))
+        },
+        Shape::Tuple(fields) => {
+            let len = fields.iter().filter(|field| !field.attrs.skip).count();
+            let mut index = 0usize..;
+            let values = fields.iter().map(|field| match field.attrs.skip {
+                true => default(field),
+                false => {
+                    let i = index.next().expect("unbounded range");
+                    quote!(::rusty_json::derive::__private::element(items, #i)?)
+                }
+            });
+            quote! {
+                let items = ::rusty_json::derive::__private::array(#json, #len)?;
+                ::core::result::Result::Ok(#path(#(#values),*))
+            }
+        }
+        Shape::Struct(fields) if fields.iter().any(|field| field.attrs.flatten) => {
+            let members = members(path, fields);
+            let tag = tag.into_iter();
+            quote! {
+                let rest = &mut ::rusty_json::derive::__private::object(#json)?.clone();
+                #(rest.remove(#tag);)*
+                #members
+            }
+        }
+        Shape::Struct(fields) => {
+            let values = fields.iter().map(value);
+            quote! {
+                let object = ::rusty_json::derive::__private::object(#json)?;
+                ::core::result::Result::Ok(#path { #(#values),* })
+            }
+        }
+    };
+    // a closure, so that `?` only leaves this conversion (relevant for untagged enums)
+    quote! {
+        (|| -> ::core::result::Result<Self, ::rusty_json::convert::ConversionError> {
+            #body
+        })()
+    }
+}
+
+/// Builds the struct at `path` out of `rest`, the members left for it, removing the ones it uses.
+/// Flattened fields get what the fields before them have left, in declaration order.
+fn members(path: &TokenStream2, fields: &[FieldInfo]) -> TokenStream2 {
+    let keys = fields
+        .iter()
+        .filter(|field| !field.attrs.skip && !field.attrs.flatten)
+        .map(|field| &field.attrs.key);
+    let values = fields.iter().map(value);
+    quote! {
+        let object = &::rusty_json::derive::__private::take(rest, &[#(#keys),*]);
+        ::core::result::Result::Ok(#path { #(#values),* })
+    }
+}
+
+/// The initializer of a named field, read from `object`, or from `rest` if it is flattened.
+fn value(field: &FieldInfo) -> TokenStream2 {
+    let ident = &field.ident;
+    let key = &field.attrs.key;
+    let value = if field.attrs.skip {
+        default(field)
+    } else if field.attrs.flatten {
+        quote!(::rusty_json::derive::FromJson::from_flattened(rest)?)
+    } else if field.attrs.default.is_some() {
+        let default = default(field);
+        quote!(::rusty_json::derive::__private::field_or(object, #key, || #default)?)
+    } else {
+        quote!(::rusty_json::derive::__private::field(object, #key)?)
+    };
+    quote!(#ident: #value)
+}
+
+fn default(field: &FieldInfo) -> TokenStream2 {
+    match &field.attrs.default {
+        Some(attr::Default::Path(path)) => quote!(#path()),
+        _ => quote!(::core::default::Default::default()),
+    }
+}
+
+fn unknown_tag(tag: &str) -> TokenStream2 {
+    quote! {
+        other => ::core::result::Result::Err(
+            ::rusty_json::derive::__private::unknown_variant(other, VARIANTS).within(#tag),
+        ),
+    }
+}
+
+fn from_variants(
+    tagging: &Tagging,
+    enum_name: &str,
+    variants: &[(String, TokenStream2, Shape)],
+) -> TokenStream2 {
+    let names = variants.iter().map(|(name, _, _)| name);
+    let unknown = quote! {
+        other => ::core::result::Result::Err(
+            ::rusty_json::derive::__private::unknown_variant(other, VARIANTS),
+        ),
+    };
+    let matched = match tagging {
+        Tagging::External => {
+            let unit_arms = variants
+                .iter()
+                .filter(|(_, _, shape)| matches!(shape, Shape::Unit))
+                .map(|(name, path, _)| quote!(#name => ::core::result::Result::Ok(#path),));
+            let content_arms = variants.iter().map(|(name, path, shape)| {
+                let value = construct(path, shape, &quote!(content), None);
+                quote!(#name => #value.map_err(|e| e.within(name)),)
+            });
+            quote! {
+                match json {
+                    ::rusty_json::parser::Json::Value(
+                        ::rusty_json::lexer::Constant::StringLiteral(name),
+                    ) => match name.as_str() {
+                        #(#unit_arms)*
+                        #unknown
+                    },
+                    ::rusty_json::parser::Json::Dict(object) if object.len() == 1 => {
+                        let (name, content) = object.iter().next().expect("checked length");
+                        match name.as_str() {
+                            #(#content_arms)*
+                            #unknown
+                        }
+                    }
+                    _ => ::core::result::Result::Err(::rusty_json::derive::__private::mismatch(
+                        json,
+                        "string or object with a single member",
+                    )),
+                }
+            }
+        }
+        Tagging::Internal(tag) => {
+            let unknown_tag = unknown_tag(tag);
+            let arms = variants.iter().map(|(name, path, shape)| match shape {
+                Shape::Unit => quote!(#name => ::core::result::Result::Ok(#path),),
+                _ => {
+                    let value = construct(path, shape, &quote!(json), Some(tag.as_str()));
+                    quote!(#name => #value,)
+                }
+            });
+            quote! {
+                let object = ::rusty_json::derive::__private::object(json)?;
+                match ::rusty_json::derive::__private::tag(object, #tag)? {
+                    #(#arms)*
+                    #unknown_tag
+                }
+            }
+        }
+        Tagging::Adjacent(tag, content_key) => {
+            let unknown_tag = unknown_tag(tag);
+            let arms = variants.iter().map(|(name, path, shape)| match shape {
+                Shape::Unit => quote!(#name => ::core::result::Result::Ok(#path),),
+                _ => {
+                    let value = construct(path, shape, &quote!(content), None);
+                    let member = quote!(::rusty_json::derive::__private::member);
+                    quote!(#name => {
+                        let content = #member(object, #content_key)?;
+                        #value.map_err(|e| e.within(#content_key))
+                    })
+                }
+            });
+            quote! {
+                let object = ::rusty_json::derive::__private::object(json)?;
+                match ::rusty_json::derive::__private::tag(object, #tag)? {
+                    #(#arms)*
+                    #unknown_tag
+                }
+            }
+        }
+        Tagging::Untagged => {
+            let attempts = variants.iter().map(|(_, path, shape)| {
+                let value = construct(path, shape, &quote!(json), None);
+                quote! {
+                    if let ::core::result::Result::Ok(value) = #value {
+                        return ::core::result::Result::Ok(value);
+                    }
+                }
+            });
+            quote! {
+                #(#attempts)*
+                ::core::result::Result::Err(
+                    ::rusty_json::derive::__private::no_variant_matched(#enum_name),
+                )
+            }
+        }
+    };
+    quote! {
+        #[allow(dead_code)]
+        const VARIANTS: &[&str] = &[#(#names),*];
+        #matched
+    }
+}
@@ -0,0 +1,133 @@
+//! `#[derive(ToJson, FromJson)]` for `rusty_json`, without serde.
+//!
+//! Attributes, all inside `#[json(...)]`:
+//!
+//! - on enums: `tag = "t"` (internally tagged), `tag = "t", content = "c"` (adjacently
+//!   tagged) or `untagged`. Without them, enums are externally tagged.
+//! - on variants: `rename = "name"`.
+//! - on fields: `rename = "name"`, `default`, `default = "path::to::function"`, `skip` (left
+//!   out of the JSON, filled in with the default when reading) and `flatten` (the members of
+//!   the field's object are merged into the parent object; when reading, the field gets the
+//!   members the other fields and the flattened fields before it don't use).
+//!
+//! Structs with named fields and internally or adjacently tagged enums always convert to
+//! objects, so they also implement `ToJsonObject`. Flattened fields and the newtype variants
+//! of internally tagged enums need it, anything else is rejected at compile time:
+//!
+//! ```compile_fail
+//! use rusty_json::ToJson;
+//!
+//! #[derive(ToJson)]
+//! struct Outer {
+//!     #[json(flatten)]
+//!     inner: u8,
+//! }
+//! ```
+//!
+//! Conversion errors carry the JSON Pointer of the value that failed.
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, DeriveInput, Error, Fields, Generics, Ident, Result};
+
+mod attr;
+mod from_json;
+mod to_json;
+
+#[proc_macro_derive(ToJson, attributes(json))]
+pub fn derive_to_json(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    to_json::expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(FromJson, attributes(json))]
+pub fn derive_from_json(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    from_json::expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+struct FieldInfo {
+    /// The field name, `None` for tuple fields.
+    ident: Option<Ident>,
+    /// Name of the variable the field is bound to when destructuring.
+    binding: Ident,
+    /// Span of the field's type, for errors about it.
+    ty_span: Span,
+    attrs: attr::FieldAttrs,
+}
+
+/// The fields of a struct or variant.
+enum Shape {
+    Unit,
+    Newtype(FieldInfo),
+    Tuple(Vec<FieldInfo>),
+    Struct(Vec<FieldInfo>),
+}
+
+impl Shape {
+    fn of(fields: &Fields) -> Result<Shape> {
+        let infos = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                Ok(FieldInfo {
+                    ident: field.ident.clone(),
+                    binding: format_ident!("__field{}", i),
+                    ty_span: field.ty.span(),
+                    attrs: attr::field(field, i)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(match fields {
+            Fields::Unit => Shape::Unit,
+            Fields::Unnamed(unnamed) if infos.len() == 1 => {
+                let info = infos.into_iter().next().expect("checked length");
+                if info.attrs.skip {
+                    return Err(Error::new_spanned(
+                        unnamed,
+                        "the field of a newtype can't be skipped",
+                    ));
+                }
+                Shape::Newtype(info)
+            }
+            Fields::Unnamed(_) => Shape::Tuple(infos),
+            Fields::Named(_) => Shape::Struct(infos),
+        })
+    }
+
+    /// A pattern that binds every field to its `binding`, e.g. `Self::V { a: __field0 }`.
+    fn pattern(&self, path: &TokenStream2) -> TokenStream2 {
+        match self {
+            Shape::Unit => quote!(#path),
+            Shape::Newtype(field) => {
+                let binding = &field.binding;
+                quote!(#path(#binding))
+            }
+            Shape::Tuple(fields) => {
+                let bindings = fields.iter().map(|field| &field.binding);
+                quote!(#path(#(#bindings),*))
+            }
+            Shape::Struct(fields) => {
+                let members = fields.iter().map(|field| {
+                    let (ident, binding) = (&field.ident, &field.binding);
+                    quote!(#ident: #binding)
+                });
+                quote!(#path { #(#members),* })
+            }
+        }
+    }
+}
+
+/// Adds `bound` to every type parameter.
+fn with_bound(generics: &Generics, bound: TokenStream2) -> Generics {
+    let mut generics = generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(syn::parse_quote!(#bound));
+    }
+    generics
+}
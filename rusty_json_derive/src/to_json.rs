@@ -0,0 +1,206 @@
+use crate::attr::{self, Tagging};
+use crate::{with_bound, FieldInfo, Shape};
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::{Data, DeriveInput, Error, Result};
+
+pub fn expand(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let generics = with_bound(&input.generics, quote!(::rusty_json::derive::ToJson));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    // named structs and internally or adjacently tagged enums are objects, and implement
+    // `ToJsonObject` with the body below
+    let (body, is_object) = match &input.data {
+        Data::Struct(data) => {
+            let shape = Shape::of(&data.fields)?;
+            let pattern = shape.pattern(&quote!(Self));
+            let (content, is_object) = match &shape {
+                Shape::Struct(fields) => (object(fields, None), true),
+                _ => (content(&shape, None), false),
+            };
+            let body = quote! {
+                let #pattern = self;
+                #content
+            };
+            (body, is_object)
+        }
+        Data::Enum(data) => {
+            let tagging = attr::container(&input.attrs)?;
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let ident = &variant.ident;
+                    let name = attr::variant(ident, &variant.attrs)?;
+                    let shape = Shape::of(&variant.fields)?;
+                    let pattern = shape.pattern(&quote!(Self::#ident));
+                    let json = tagged(&tagging, &name, &shape)
+                        .map_err(|message| Error::new_spanned(variant, message))?;
+                    Ok(quote!(#pattern => #json,))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let body = quote! {
+                match self {
+                    #(#arms)*
+                }
+            };
+            let is_object = matches!(tagging, Tagging::Internal(_) | Tagging::Adjacent(..));
+            (body, is_object)
+        }
+        Data::Union(_) => return Err(Error::new_spanned(input, "unions are not supported")),
+    };
+    if !is_object {
+        return Ok(quote! {
+            impl #impl_generics ::rusty_json::derive::ToJson for #name #ty_generics #where_clause {
+                #[allow(unused_variables)]
+                fn to_json(&self) -> ::rusty_json::parser::Json {
+                    #body
+                }
+            }
+        });
+    }
+    Ok(quote! {
+        impl #impl_generics ::rusty_json::derive::ToJson for #name #ty_generics #where_clause {
+            fn to_json(&self) -> ::rusty_json::parser::Json {
+                ::rusty_json::parser::Json::Dict(
+                    ::rusty_json::derive::ToJsonObject::to_json_object(self),
+                )
+            }
+        }
+
+        impl #impl_generics ::rusty_json::derive::ToJsonObject for #name #ty_generics
+            #where_clause
+        {
+            #[allow(unused_variables)]
+            fn to_json_object(
+                &self,
+            ) -> ::std::collections::HashMap<::std::string::String, ::rusty_json::parser::Json> {
+                #body
+            }
+        }
+    })
+}
+
+/// The JSON of the fields alone, with `tag` inserted if the fields are an object.
+fn content(shape: &Shape, tag: Option<(&str, &str)>) -> TokenStream2 {
+    match shape {
+        Shape::Unit => quote!(::rusty_json::parser::Json::Value(
+            ::rusty_json::lexer::Constant::Null
+        )),
+        Shape::Newtype(field) => {
+            let binding = &field.binding;
+            quote!(::rusty_json::derive::ToJson::to_json(#binding))
+        }
+        Shape::Tuple(fields) => {
+            let bindings = fields
+                .iter()
+                .filter(|field| !field.attrs.skip)
+                .map(|field| &field.binding);
+            quote!(::rusty_json::parser::Json::List(::std::vec![
+                #(::rusty_json::derive::ToJson::to_json(#bindings)),*
+            ]))
+        }
+        Shape::Struct(fields) => {
+            let object = object(fields, tag);
+            quote!(::rusty_json::parser::Json::Dict(#object))
+        }
+    }
+}
+
+/// The members of the fields, with `tag` inserted.
+fn object(fields: &[FieldInfo], tag: Option<(&str, &str)>) -> TokenStream2 {
+    let inserts = fields
+        .iter()
+        .filter(|field| !field.attrs.skip)
+        .map(|field| {
+            let binding = &field.binding;
+            let key = &field.attrs.key;
+            if field.attrs.flatten {
+                let members = members_of(field);
+                quote!(object.extend(#members);)
+            } else {
+                quote! {
+                    object.insert(
+                        ::std::string::String::from(#key),
+                        ::rusty_json::derive::ToJson::to_json(#binding),
+                    );
+                }
+            }
+        });
+    let tag = tag.map(|(key, name)| insert_str(key, name));
+    quote!({
+        let mut object = ::std::collections::HashMap::new();
+        #(#inserts)*
+        #tag
+        object
+    })
+}
+
+/// The members of a flattened field or of the content of an internally tagged newtype variant,
+/// spanned so that a type that isn't an object is reported on the field.
+fn members_of(field: &FieldInfo) -> TokenStream2 {
+    let binding = &field.binding;
+    quote_spanned! {field.ty_span=>
+        ::rusty_json::derive::ToJsonObject::to_json_object(#binding)
+    }
+}
+
+fn insert_str(key: &str, value: &str) -> TokenStream2 {
+    quote! {
+        object.insert(
+            ::std::string::String::from(#key),
+            ::rusty_json::parser::Json::from(#value),
+        );
+    }
+}
+
+/// The JSON of a variant, or an error message if the tagging can't represent it. The variants
+/// of internally and adjacently tagged enums are the members of an object instead.
+fn tagged(
+    tagging: &Tagging,
+    name: &str,
+    shape: &Shape,
+) -> std::result::Result<TokenStream2, &'static str> {
+    let content = content(shape, None);
+    Ok(match (tagging, shape) {
+        (Tagging::External, Shape::Unit) => quote!(::rusty_json::parser::Json::from(#name)),
+        (Tagging::External, _) => {
+            quote!({
+                let mut object = ::std::collections::HashMap::new();
+                object.insert(::std::string::String::from(#name), #content);
+                ::rusty_json::parser::Json::Dict(object)
+            })
+        }
+        (Tagging::Internal(_), Shape::Tuple(_)) => {
+            return Err("internally tagged enums can't have tuple variants")
+        }
+        (Tagging::Internal(tag), Shape::Struct(fields)) => object(fields, Some((tag, name))),
+        (Tagging::Internal(tag), Shape::Newtype(field)) => {
+            let members = members_of(field);
+            let insert_tag = insert_str(tag, name);
+            quote!({
+                let mut object = #members;
+                #insert_tag
+                object
+            })
+        }
+        (Tagging::Internal(tag) | Tagging::Adjacent(tag, _), Shape::Unit) => {
+            let insert_tag = insert_str(tag, name);
+            quote!({
+                let mut object = ::std::collections::HashMap::new();
+                #insert_tag
+                object
+            })
+        }
+        (Tagging::Adjacent(tag, content_key), _) => {
+            let insert_tag = insert_str(tag, name);
+            quote!({
+                let mut object = ::std::collections::HashMap::new();
+                #insert_tag
+                object.insert(::std::string::String::from(#content_key), #content);
+                object
+            })
+        }
+        (Tagging::Untagged, _) => content,
+    })
+}
@@ -0,0 +1,45 @@
+use rusty_json::codegen::rust_types;
+use rusty_json::infer::{InferOptions, Shape};
+use rusty_json::parser::{parse, Json};
+use rusty_json::FromJson;
+
+mod generated {
+    include!("generated/orders.rs");
+}
+
+use generated::{Code, Order};
+
+#[test]
+fn test_generated_types_decode_the_samples() {
+    let Json::List(samples) = parse(include_str!("generated/orders.json")).unwrap() else {
+        panic!("the samples are an array")
+    };
+    let shape = Shape::infer(&samples, &InferOptions::default());
+    assert_eq!(
+        rust_types(&shape, "order"),
+        include_str!("generated/orders.rs")
+    );
+    let orders = samples
+        .iter()
+        .map(Order::from_json)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        orders.iter().map(|x| x.code.clone()).collect::<Vec<_>>(),
+        [
+            Code::Integer(7),
+            Code::String("X7".to_string()),
+            Code::Array(vec![1])
+        ]
+    );
+    assert_eq!(
+        orders[0].customer.e_mail.as_deref(),
+        Some("ann@example.com")
+    );
+    assert_eq!(orders[1].note.as_deref(), Some("gift"));
+    assert_eq!(
+        orders[2].lines[0].discount.as_ref().map(|x| x.pct),
+        Some(10)
+    );
+    assert_eq!((orders[1].total, orders[2].field_2fa), (5.25, false));
+}
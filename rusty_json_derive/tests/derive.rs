@@ -0,0 +1,227 @@
+use rusty_json::convert::ConversionError;
+use rusty_json::parser::{parse, Json};
+use rusty_json::{json, FromJson, ToJson, ToJsonObject};
+use std::collections::BTreeMap;
+
+#[derive(Debug, PartialEq, ToJson, FromJson)]
+struct Config {
+    name: String,
+    #[json(rename = "max-size")]
+    max_size: u32,
+    #[json(default)]
+    verbose: bool,
+    #[json(default = "default_level")]
+    level: u8,
+    #[json(skip)]
+    cache: Vec<u8>,
+    nickname: Option<String>,
+    #[json(flatten)]
+    extra: Extra,
+    r#type: Kind,
+}
+
+#[derive(Debug, PartialEq, ToJson, FromJson)]
+struct Flattened {
+    id: u8,
+    #[json(flatten)]
+    extra: Option<Extra>,
+    #[json(flatten)]
+    rest: BTreeMap<String, Json>,
+}
+
+#[derive(Debug, PartialEq, ToJson, FromJson)]
+struct Extra {
+    owner: String,
+}
+
+fn default_level() -> u8 {
+    3
+}
+
+#[derive(Debug, PartialEq, ToJson, FromJson)]
+enum Kind {
+    Plain,
+    #[json(rename = "sized")]
+    Sized(u64),
+    Pair(i8, i8),
+    Named {
+        x: f64,
+    },
+}
+
+#[derive(Debug, PartialEq, ToJson, FromJson)]
+#[json(tag = "type")]
+enum Internal {
+    Empty,
+    Point { x: i32, y: i32 },
+    Wrapped(Extra),
+}
+
+#[derive(Debug, PartialEq, ToJson, FromJson)]
+#[json(tag = "t", content = "c")]
+enum Adjacent {
+    Empty,
+    Number(i64),
+    Pair(String, bool),
+}
+
+#[derive(Debug, PartialEq, ToJson, FromJson)]
+#[json(untagged)]
+enum Untagged {
+    Nothing,
+    Number(u8),
+    Text(String),
+    Point { x: i32, y: i32 },
+}
+
+#[derive(Debug, PartialEq, ToJson, FromJson)]
+struct Wrapper<T>(Vec<T>);
+
+#[derive(Debug, PartialEq, ToJson, FromJson)]
+struct Pair(u8, #[json(skip)] u8, String);
+
+#[derive(Debug, PartialEq, ToJson, FromJson)]
+struct Unit;
+
+fn roundtrip<T: ToJson + FromJson + PartialEq + std::fmt::Debug>(value: T, expected: Json) {
+    let json = value.to_json();
+    assert_eq!(json, expected);
+    assert_eq!(T::from_json(&json), Ok(value));
+}
+
+#[test]
+fn test_struct_attributes() {
+    let json = parse(
+        r#"{"name": "app", "max-size": 10, "owner": "me", "type": {"Named": {"x": 1.5}},
+        "cache": [1]}"#,
+    )
+    .unwrap();
+    let config = Config::from_json(&json).expect("should not error");
+    assert_eq!(
+        config,
+        Config {
+            name: "app".to_string(),
+            max_size: 10,
+            verbose: false,
+            level: 3,
+            cache: vec![],
+            nickname: None,
+            extra: Extra {
+                owner: "me".to_string()
+            },
+            r#type: Kind::Named { x: 1.5 },
+        }
+    );
+    assert_eq!(
+        config.to_json(),
+        json!({
+            "name": "app", "max-size": 10, "verbose": false, "level": 3, "nickname": null,
+            "owner": "me", "type": {"Named": {"x": 1.5}}
+        })
+    );
+}
+
+#[test]
+fn test_flatten() {
+    let mut rest = BTreeMap::new();
+    rest.insert("x".to_string(), json!([1]));
+    let value = Flattened {
+        id: 1,
+        extra: None,
+        rest,
+    };
+    assert_eq!(value.to_json(), json!({"id": 1, "x": [1]}));
+    assert_eq!(Flattened::from_json(&value.to_json()).as_ref(), Ok(&value));
+    let value = Flattened {
+        extra: Some(Extra {
+            owner: "me".to_string(),
+        }),
+        ..value
+    };
+    assert_eq!(value.to_json(), json!({"id": 1, "owner": "me", "x": [1]}));
+    assert_eq!(Flattened::from_json(&value.to_json()).as_ref(), Ok(&value));
+    assert_eq!(
+        Adjacent::Number(1).to_json_object().get("t"),
+        Some(&json!("Number"))
+    );
+}
+
+#[test]
+fn test_error_paths() {
+    let json = json!({"name": "app", "max-size": 10, "owner": "me", "type": {"Pair": [1, 300]}});
+    assert_eq!(
+        Config::from_json(&json),
+        Err(ConversionError::OutOfRange(
+            "/type/Pair/1".to_string(),
+            "i8"
+        ))
+    );
+    let json = json!({"name": "app", "max-size": 10, "type": "Plain"});
+    assert_eq!(
+        Config::from_json(&json),
+        Err(ConversionError::MissingMember("/owner".to_string()))
+    );
+    let json = json!({"a": {"type": "Point", "x": 1, "y": "2"}});
+    assert_eq!(
+        BTreeMap::<String, Internal>::from_json(&json),
+        Err(ConversionError::TypeMismatch(
+            "/a/y".to_string(),
+            "i32",
+            "string"
+        ))
+    );
+    let error = Internal::from_json(&json!({"type": "Line"})).unwrap_err();
+    assert!(matches!(&error, ConversionError::InvalidValue(path, _) if path == "/type"));
+    assert_eq!(
+        Adjacent::from_json(&json!({"t": "Number"})),
+        Err(ConversionError::MissingMember("/c".to_string()))
+    );
+    assert!(matches!(
+        Untagged::from_json(&json!([1])),
+        Err(ConversionError::InvalidValue(_, _))
+    ));
+}
+
+#[test]
+fn test_enum_tagging() {
+    roundtrip(Kind::Plain, json!("Plain"));
+    roundtrip(Kind::Sized(7), json!({"sized": 7}));
+    roundtrip(Kind::Pair(-1, 1), json!({"Pair": [-1, 1]}));
+
+    roundtrip(Internal::Empty, json!({"type": "Empty"}));
+    roundtrip(
+        Internal::Point { x: 1, y: 2 },
+        json!({"type": "Point", "x": 1, "y": 2}),
+    );
+    roundtrip(
+        Internal::Wrapped(Extra {
+            owner: "me".to_string(),
+        }),
+        json!({"type": "Wrapped", "owner": "me"}),
+    );
+
+    roundtrip(Adjacent::Empty, json!({"t": "Empty"}));
+    roundtrip(Adjacent::Number(-5), json!({"t": "Number", "c": -5}));
+    roundtrip(
+        Adjacent::Pair("a".to_string(), true),
+        json!({"t": "Pair", "c": ["a", true]}),
+    );
+
+    roundtrip(Untagged::Nothing, json!(null));
+    roundtrip(Untagged::Number(5), json!(5));
+    roundtrip(Untagged::Text("5".to_string()), json!("5"));
+    roundtrip(Untagged::Point { x: 1, y: 2 }, json!({"x": 1, "y": 2}));
+}
+
+#[test]
+fn test_tuple_and_generic_structs() {
+    roundtrip(Wrapper(vec![1u8, 2]), json!([1, 2]));
+    roundtrip(Unit, json!(null));
+    let pair = Pair::from_json(&json!([1, "x"])).unwrap();
+    assert_eq!(pair, Pair(1, 0, "x".to_string()));
+    assert_eq!(pair.to_json(), json!([1, "x"]));
+    assert!(matches!(
+        Pair::from_json(&json!([1, 2, "x"])),
+        Err(ConversionError::InvalidValue(_, _))
+    ));
+}
@@ -0,0 +1,52 @@
+use rusty_json::parser::Json;
+use rusty_json::{FromJson, ToJson};
+
+#[derive(Debug, Clone, PartialEq, ToJson, FromJson)]
+pub struct Order {
+    #[json(rename = "2fa")]
+    pub field_2fa: bool,
+    pub code: Code,
+    #[json(rename = "createdAt")]
+    pub created_at: String,
+    pub customer: Customer,
+    pub extras: Vec<Option<Json>>,
+    pub id: i64,
+    pub lines: Vec<LinesItem>,
+    pub meta: Meta,
+    pub note: Option<String>,
+    #[json(rename = "self")]
+    pub self_: bool,
+    pub total: f64,
+    pub r#type: String,
+}
+
+#[derive(Debug, Clone, PartialEq, ToJson, FromJson)]
+#[json(untagged)]
+pub enum Code {
+    Integer(i64),
+    String(String),
+    Array(Vec<i64>),
+}
+
+#[derive(Debug, Clone, PartialEq, ToJson, FromJson)]
+pub struct Customer {
+    #[json(rename = "e-mail")]
+    pub e_mail: Option<String>,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, ToJson, FromJson)]
+pub struct LinesItem {
+    pub discount: Option<Discount>,
+    pub price: f64,
+    pub qty: i64,
+    pub sku: String,
+}
+
+#[derive(Debug, Clone, PartialEq, ToJson, FromJson)]
+pub struct Discount {
+    pub pct: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, ToJson, FromJson)]
+pub struct Meta {}
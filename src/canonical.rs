@@ -0,0 +1,232 @@
+//! Canonical JSON (RFC 8785, the JSON Canonicalization Scheme): deterministic text for signing
+//! and hashing.
+use crate::float_format::{shortest_digits, FloatFormatError};
+use crate::lexer::{escape, Constant};
+use crate::parser::Json;
+use sha2::{Digest, Sha256};
+
+/// The canonical text of `json`: no whitespace, object keys sorted by their UTF-16 code units,
+/// numbers written as ECMAScript does and strings with only the escapes JSON requires.
+/// Numbers are IEEE doubles in the scheme, so integers beyond 2^53 are rounded. NaN and the
+/// infinities have no canonical form.
+pub fn to_canonical_string(json: &Json) -> Result<String, FloatFormatError> {
+    let mut out = String::new();
+    write_canonical(&mut out, json)?;
+    Ok(out)
+}
+
+fn write_canonical(out: &mut String, json: &Json) -> Result<(), FloatFormatError> {
+    match json {
+        Json::Dict(x) => {
+            let mut keys: Vec<&String> = x.keys().collect();
+            keys.sort_by(|a, b| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push('"');
+                out.push_str(&escape(key));
+                out.push_str("\":");
+                write_canonical(out, &x[key])?;
+            }
+            out.push('}');
+        }
+        Json::List(x) => {
+            out.push('[');
+            for (i, value) in x.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(out, value)?;
+            }
+            out.push(']');
+        }
+        Json::Value(Constant::Float(x)) => write_number(out, *x)?,
+        Json::Value(Constant::Int(x)) => write_number(out, *x as f64)?,
+        Json::Value(Constant::UInt(x)) => write_number(out, *x as f64)?,
+        Json::Value(Constant::Number(x)) => write_number(out, x.as_f64())?,
+        Json::Value(Constant::StringLiteral(x)) => {
+            out.push('"');
+            out.push_str(&escape(x));
+            out.push('"');
+        }
+        Json::Value(Constant::Null) => out.push_str("null"),
+        Json::Value(Constant::Boolean(x)) => out.push_str(if *x { "true" } else { "false" }),
+    }
+    Ok(())
+}
+
+/// `Number.prototype.toString` of ECMAScript: the shortest digits that round trip, in plain
+/// notation from 1e-6 up to 1e21 and in exponential notation outside of that.
+fn write_number(out: &mut String, x: f64) -> Result<(), FloatFormatError> {
+    if !x.is_finite() {
+        return Err(FloatFormatError::Rejected(x));
+    }
+    if x == 0.0 {
+        // -0 too
+        out.push('0');
+        return Ok(());
+    }
+    if x < 0.0 {
+        out.push('-');
+    }
+    let (digits, n) = ecmascript_digits(x);
+    let k = digits.len() as i32;
+    if k <= n && n <= 21 {
+        out.push_str(&digits);
+        out.extend(std::iter::repeat_n('0', (n - k) as usize));
+    } else if 0 < n && n <= 21 {
+        out.push_str(&digits[..n as usize]);
+        out.push('.');
+        out.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        out.push_str("0.");
+        out.extend(std::iter::repeat_n('0', -n as usize));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        let sign = if n > 0 { '+' } else { '-' };
+        out.push_str(&format!("e{}{}", sign, (n - 1).abs()));
+    }
+    Ok(())
+}
+
+/// `shortest_digits`, except that of two shortest decimals exactly as close to `x`, ECMAScript
+/// takes the even one where `shortest_digits` rounds up.
+fn ecmascript_digits(x: f64) -> (String, i32) {
+    let (digits, n) = shortest_digits(x);
+    let k = digits.len();
+    // halfway means that the exact value has one digit more, a 5
+    let is_halfway = |precision: usize| {
+        let exact = format!("{:.*e}", precision, x.abs());
+        let (mantissa, exponent) = exact.split_once('e').expect("`{:e}` writes an exponent");
+        let mantissa = mantissa.replace('.', "");
+        let halfway = exponent.parse::<i32>() == Ok(n - 1)
+            && mantissa[k..].starts_with('5')
+            && mantissa[k + 1..].bytes().all(|b| b == b'0');
+        halfway.then(|| mantissa[..k].parse::<u64>().ok()).flatten()
+    };
+    // the exact expansion of a double has at most 767 significant digits
+    let Some(lower) = is_halfway(k + 20).and_then(|_| is_halfway(800)) else {
+        return (digits, n);
+    };
+    let even = (lower + lower % 2).to_string();
+    let round_trips = format!("{}e{}", even, n - k as i32).parse::<f64>() == Ok(x.abs());
+    match even.len() == k && round_trips {
+        true => (even, n),
+        false => (digits, n),
+    }
+}
+
+/// SHA-256 (FIPS 180-4).
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+impl Json {
+    /// The SHA-256 digest of the canonical text, equal for documents that only differ in
+    /// formatting, member order or the notation of numbers.
+    pub fn canonical_hash(&self) -> Result<[u8; 32], FloatFormatError> {
+        Ok(sha256(to_canonical_string(self)?.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_rfc_8785_examples() {
+        let json = parse(
+            r#"{"numbers": [333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001],
+            "string": "\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+            "literals": [null, true, false]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            to_canonical_string(&json).unwrap(),
+            concat!(
+                r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,"#,
+                r#"1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#
+            )
+        );
+        let json = parse(
+            r#"{"\u20ac": 1, "\r": 2, "\ufb33": 3, "1": 4, "\ud83d\ude00": 5, "\u0080": 6,
+            "\u00f6": 7}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            to_canonical_string(&json).unwrap(),
+            "{\"\\r\":2,\"1\":4,\"\u{80}\":6,\"ö\":7,\"€\":1,\"😀\":5,\"\u{fb33}\":3}"
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        let cases = [
+            (0x0000000000000000, "0"),
+            (0x8000000000000000, "0"),
+            (0x0000000000000001, "5e-324"),
+            (0x8000000000000001, "-5e-324"),
+            (0x7fefffffffffffff, "1.7976931348623157e+308"),
+            (0x4340000000000000, "9007199254740992"),
+            (0x4430000000000000, "295147905179352830000"),
+            (0x44b52d02c7e14af5, "9.999999999999997e+22"),
+            (0x44b52d02c7e14af6, "1e+23"),
+            (0x444b1ae4d6e2ef4f, "999999999999999900000"),
+            (0x444b1ae4d6e2ef50, "1e+21"),
+            (0x3eb0c6f7a0b5ed8d, "0.000001"),
+            (0x3eb0c6f7a0b5ed8e, "0.0000010000000000000002"),
+            (0x3eb0c6f7a0b5ed8c, "9.999999999999997e-7"),
+            // halfway between two shortest decimals, the even one wins
+            (0x430e89174ef647ca, "1074372812916985.2"),
+        ];
+        for (bits, expected) in cases {
+            let mut out = String::new();
+            write_number(&mut out, f64::from_bits(bits)).unwrap();
+            assert_eq!(out, expected);
+        }
+        assert!(write_number(&mut String::new(), f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_canonical_hash() {
+        let hex = |digest: [u8; 32]| {
+            digest
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        };
+        assert_eq!(
+            hex(sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        let a = parse(r#"{"b": [1.0, 2e0], "a": "x"}"#).unwrap();
+        let b = parse(r#"{"a":"x","b":[1,2]}"#).unwrap();
+        assert_eq!(a.canonical_hash().unwrap(), b.canonical_hash().unwrap());
+        assert_eq!(
+            hex(b.canonical_hash().unwrap()),
+            "721ef82f2d6c0997bffb7a8ab3f40f8fb45b0b52ce2af3afa6b0f05efbdc317f"
+        );
+        assert_ne!(
+            a.canonical_hash().unwrap(),
+            json!({"a": "y"}).canonical_hash().unwrap()
+        );
+    }
+}
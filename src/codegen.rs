@@ -0,0 +1,587 @@
+//! Type definitions generated from the inferred `Shape` of sample documents, or for TypeScript
+//! from a JSON Schema.
+use crate::infer::Shape;
+use crate::lexer::Constant;
+use crate::parser::Json;
+use crate::schema::percent_decode;
+use std::collections::{HashMap, HashSet};
+
+/// The keywords that can't be field names. Most work as raw identifiers, the ones in
+/// `NOT_RAW` get a trailing underscore instead.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+const NOT_RAW: &[&str] = &["crate", "self", "super"];
+
+/// The words of a key, split at anything but ASCII letters and digits and at camelCase humps.
+fn words(key: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut previous = None::<char>;
+    for c in key.chars() {
+        let hump = c.is_ascii_uppercase()
+            && previous.is_some_and(|p| p.is_ascii_lowercase() || p.is_ascii_digit());
+        if (!c.is_ascii_alphanumeric() || hump) && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        if c.is_ascii_alphanumeric() {
+            word.push(c.to_ascii_lowercase());
+        }
+        previous = Some(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn snake_case(key: &str) -> String {
+    let name = words(key).join("_");
+    match name.chars().next() {
+        None => "field".to_string(),
+        Some(c) if c.is_ascii_digit() => format!("field_{}", name),
+        Some(_) if NOT_RAW.contains(&name.as_str()) => format!("{}_", name),
+        Some(_) if KEYWORDS.contains(&name.as_str()) => format!("r#{}", name),
+        Some(_) => name,
+    }
+}
+
+fn pascal_case(key: &str) -> String {
+    let name = words(key)
+        .iter()
+        .map(|word| word[..1].to_ascii_uppercase() + &word[1..])
+        .collect::<String>();
+    match name.chars().next() {
+        None => "Value".to_string(),
+        Some(c) if c.is_ascii_digit() => format!("Value{}", name),
+        Some(_) if name == "Self" => "Self_".to_string(),
+        Some(_) => name,
+    }
+}
+
+/// Appends a number to `name` until it isn't in `taken`, and takes it.
+fn unique(name: String, taken: &mut HashSet<String>) -> String {
+    let mut candidate = name.clone();
+    let mut n = 1;
+    while taken.contains(&candidate) {
+        n += 1;
+        candidate = format!("{}{}", name, n);
+    }
+    taken.insert(candidate.clone());
+    candidate
+}
+
+/// The number of types of values that aren't null.
+fn kinds(shape: &Shape) -> usize {
+    let numbers = shape.integers + shape.floats;
+    [
+        shape.booleans,
+        numbers,
+        shape.strings,
+        shape.arrays,
+        shape.objects,
+    ]
+    .iter()
+    .filter(|count| **count > 0)
+    .count()
+}
+
+const DERIVE: &str = "#[derive(Debug, Clone, PartialEq, ToJson, FromJson)]";
+
+/// Types the generated Rust code refers to, which definitions mustn't shadow.
+const PRELUDE: &[&str] = &[
+    "Box", "FromJson", "Json", "Option", "String", "ToJson", "Vec",
+];
+
+struct RustGenerator {
+    /// Type names in use.
+    names: HashSet<String>,
+    /// Definitions in the order their names were taken, so the outer types come first.
+    definitions: Vec<String>,
+    uses_json: bool,
+}
+
+impl RustGenerator {
+    /// The type of the values, `Option` if some were null.
+    fn nullable(&mut self, shape: &Shape, name: &str) -> String {
+        match shape.nulls {
+            0 => self.ty(shape, name),
+            _ => format!("Option<{}>", self.ty(shape, name)),
+        }
+    }
+
+    /// The type of the values that aren't null.
+    fn ty(&mut self, shape: &Shape, name: &str) -> String {
+        let numbers = shape.integers + shape.floats;
+        match kinds(shape) {
+            0 => {
+                self.uses_json = true;
+                "Json".to_string()
+            }
+            1 if shape.booleans > 0 => "bool".to_string(),
+            1 if numbers > 0 => self.number(shape).to_string(),
+            1 if shape.strings > 0 => "String".to_string(),
+            1 if shape.arrays > 0 => self.array(shape, &format!("{} Item", name)),
+            1 => self.object(shape, name),
+            _ => self.union(shape, name),
+        }
+    }
+
+    fn number(&self, shape: &Shape) -> &'static str {
+        match shape.floats {
+            0 => "i64",
+            _ => "f64",
+        }
+    }
+
+    fn array(&mut self, shape: &Shape, item_name: &str) -> String {
+        match &shape.items {
+            Some(items) => format!("Vec<{}>", self.nullable(items, item_name)),
+            None => {
+                self.uses_json = true;
+                "Vec<Json>".to_string()
+            }
+        }
+    }
+
+    fn object(&mut self, shape: &Shape, name: &str) -> String {
+        let name = unique(pascal_case(name), &mut self.names);
+        let index = self.definitions.len();
+        self.definitions.push(String::new());
+        let mut fields = HashSet::new();
+        let mut out = format!("{}\npub struct {} {{\n", DERIVE, name);
+        for (key, property) in &shape.properties {
+            let field = unique(snake_case(key), &mut fields);
+            let missing = property.count() < shape.objects;
+            let ty = match (missing, property.nulls) {
+                (true, 0) => format!("Option<{}>", self.ty(property, key)),
+                _ => self.nullable(property, key),
+            };
+            if field.trim_start_matches("r#") != key {
+                out.push_str(&format!("    #[json(rename = {:?})]\n", key));
+            }
+            out.push_str(&format!("    pub {}: {},\n", field, ty));
+        }
+        match shape.properties.is_empty() {
+            true => out.replace_range(out.len() - 1.., "}"),
+            false => out.push('}'),
+        }
+        self.definitions[index] = out;
+        name
+    }
+
+    /// An untagged enum with a variant for each type of value.
+    fn union(&mut self, shape: &Shape, name: &str) -> String {
+        let name = unique(pascal_case(name), &mut self.names);
+        let index = self.definitions.len();
+        self.definitions.push(String::new());
+        let mut variants = Vec::new();
+        if shape.booleans > 0 {
+            variants.push(("Boolean", "bool".to_string()));
+        }
+        if shape.integers + shape.floats > 0 {
+            let variant = match shape.floats {
+                0 => "Integer",
+                _ => "Number",
+            };
+            variants.push((variant, self.number(shape).to_string()));
+        }
+        if shape.strings > 0 {
+            variants.push(("String", "String".to_string()));
+        }
+        if shape.arrays > 0 {
+            variants.push(("Array", self.array(shape, &format!("{} Item", name))));
+        }
+        if shape.objects > 0 {
+            variants.push(("Object", self.object(shape, &format!("{} Object", name))));
+        }
+        let mut out = format!("{}\n#[json(untagged)]\npub enum {} {{\n", DERIVE, name);
+        for (variant, ty) in variants {
+            out.push_str(&format!("    {}({}),\n", variant, ty));
+        }
+        out.push('}');
+        self.definitions[index] = out;
+        name
+    }
+}
+
+/// Rust definitions for the values of `shape`, with the outermost type called `name`. Structs
+/// and untagged enums derive `ToJson` and `FromJson`, members that weren't always present are
+/// `Option`s and keys that aren't identifiers are renamed. Integers become `i64`, values of
+/// unknown type `Json`.
+pub fn rust_types(shape: &Shape, name: &str) -> String {
+    let mut generator = RustGenerator {
+        names: PRELUDE.iter().map(|name| name.to_string()).collect(),
+        definitions: Vec::new(),
+        uses_json: false,
+    };
+    // otherwise the outermost type is an alias, whose name nothing else may take
+    let defined = shape.nulls == 0 && (kinds(shape) > 1 || shape.objects > 0);
+    let alias = (!defined).then(|| unique(pascal_case(name), &mut generator.names));
+    let ty = generator.nullable(shape, name);
+    let mut out = String::new();
+    if generator.uses_json {
+        out.push_str("use rusty_json::parser::Json;\n");
+    }
+    out.push_str("use rusty_json::{FromJson, ToJson};\n");
+    if let Some(alias) = alias {
+        out.push_str(&format!("\npub type {} = {};\n", alias, ty));
+    }
+    for definition in &generator.definitions {
+        out.push('\n');
+        out.push_str(definition);
+        out.push('\n');
+    }
+    out
+}
+
+/// The members of a schema for objects that have `properties`, which become an interface.
+fn object_members(schema: &Json) -> Option<&HashMap<String, Json>> {
+    let Json::Dict(members) = schema else {
+        return None;
+    };
+    let object = match members.get("type") {
+        None => true,
+        Some(t) => *t == Json::from("object"),
+    };
+    let combined = ["$ref", "const", "enum", "anyOf", "oneOf", "allOf"]
+        .iter()
+        .any(|keyword| members.contains_key(*keyword));
+    (object && !combined && members.contains_key("properties")).then_some(members)
+}
+
+/// A union of distinct types, `null` last.
+fn union(types: Vec<String>) -> String {
+    let mut distinct: Vec<String> = Vec::new();
+    for t in types {
+        if !distinct.contains(&t) {
+            distinct.push(t);
+        }
+    }
+    if let Some(i) = distinct.iter().position(|t| t == "null") {
+        let null = distinct.remove(i);
+        distinct.push(null);
+    }
+    match distinct.is_empty() {
+        true => "never".to_string(),
+        false => distinct.join(" | "),
+    }
+}
+
+/// Parenthesizes unions and intersections for use in a bigger type.
+fn operand(t: String) -> String {
+    match t.contains(' ') && !t.starts_with(['{', '[']) {
+        true => format!("({})", t),
+        false => t,
+    }
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+/// Global TypeScript types that declarations mustn't shadow.
+const BUILTINS: &[&str] = &[
+    "Array", "Boolean", "Date", "Error", "Function", "Map", "Number", "Object", "Promise",
+    "Record", "Set", "String", "Symbol",
+];
+
+struct TypeScriptGenerator<'a> {
+    root: &'a Json,
+    names: HashSet<String>,
+    definitions: Vec<String>,
+    /// The names of the types declared for `$ref` targets, by JSON pointer.
+    references: HashMap<String, String>,
+}
+
+impl<'a> TypeScriptGenerator<'a> {
+    /// Declares a type called `name`, which has to be taken already: an interface for objects
+    /// with properties, an alias for anything else.
+    fn declare(&mut self, schema: &'a Json, name: &str) {
+        let index = self.definitions.len();
+        self.definitions.push(String::new());
+        self.definitions[index] = match object_members(schema) {
+            Some(members) => self.interface(members, name),
+            None => format!("export type {} = {};", name, self.ty(schema, name)),
+        };
+    }
+
+    fn interface(&mut self, members: &'a HashMap<String, Json>, name: &str) -> String {
+        let required = match members.get("required") {
+            Some(Json::List(required)) => required.iter().collect(),
+            _ => Vec::new(),
+        };
+        let mut out = format!("export interface {} {{\n", name);
+        if let Some(Json::Dict(properties)) = members.get("properties") {
+            let mut keys = properties.keys().collect::<Vec<_>>();
+            keys.sort();
+            for key in keys {
+                let t = self.ty(&properties[key], key);
+                let optional = match required.contains(&&Json::from(key.as_str())) {
+                    true => "",
+                    false => "?",
+                };
+                let key = match is_identifier(key) {
+                    true => key.clone(),
+                    false => Json::from(key.as_str()).to_string(),
+                };
+                out.push_str(&format!("  {}{}: {};\n", key, optional, t));
+            }
+        }
+        // other members must be assignable to an index signature, so it can't be more precise
+        if let Some(Json::Dict(_)) = members.get("additionalProperties") {
+            out.push_str("  [key: string]: unknown;\n");
+        }
+        match out.ends_with("{\n") {
+            true => out.replace_range(out.len() - 1.., "}"),
+            false => out.push('}'),
+        }
+        out
+    }
+
+    fn reference(&mut self, reference: &str) -> String {
+        let Some(pointer) = reference.strip_prefix('#').and_then(percent_decode) else {
+            return "unknown".to_string();
+        };
+        if let Some(name) = self.references.get(&pointer) {
+            return name.clone();
+        }
+        let Some(target) = self.root.pointer(&pointer) else {
+            return "unknown".to_string();
+        };
+        let hint = pointer.rsplit('/').next().unwrap_or_default();
+        let name = unique(pascal_case(hint), &mut self.names);
+        self.references.insert(pointer, name.clone());
+        self.declare(target, &name);
+        name
+    }
+
+    /// The type of the values valid against `schema`. Nested interfaces are named after `name`.
+    fn ty(&mut self, schema: &'a Json, name: &str) -> String {
+        let members = match schema {
+            Json::Dict(members) => members,
+            Json::Value(Constant::Boolean(false)) => return "never".to_string(),
+            _ => return "unknown".to_string(),
+        };
+        if let Some(Json::Value(Constant::StringLiteral(reference))) = members.get("$ref") {
+            return self.reference(reference);
+        }
+        if let Some(value) = members.get("const") {
+            return literal(value);
+        }
+        if let Some(Json::List(values)) = members.get("enum") {
+            return union(values.iter().map(literal).collect());
+        }
+        for keyword in ["anyOf", "oneOf"] {
+            if let Some(Json::List(schemas)) = members.get(keyword) {
+                return union(schemas.iter().map(|x| self.ty(x, name)).collect());
+            }
+        }
+        if let Some(Json::List(schemas)) = members.get("allOf") {
+            let types = schemas.iter().map(|x| operand(self.ty(x, name)));
+            return types.collect::<Vec<_>>().join(" & ");
+        }
+        if let Some(members) = object_members(schema) {
+            let name = unique(pascal_case(name), &mut self.names);
+            self.declare_interface(members, &name);
+            return name;
+        }
+        let types = match members.get("type") {
+            Some(Json::Value(Constant::StringLiteral(t))) => vec![t.as_str()],
+            Some(Json::List(types)) => types
+                .iter()
+                .filter_map(|t| match t {
+                    Json::Value(Constant::StringLiteral(t)) => Some(t.as_str()),
+                    _ => None,
+                })
+                .collect(),
+            _ if members.contains_key("properties") => vec!["object"],
+            _ if members.contains_key("items") || members.contains_key("prefixItems") => {
+                vec!["array"]
+            }
+            _ => return "unknown".to_string(),
+        };
+        let types = types
+            .into_iter()
+            .map(|t| self.simple(t, members, name))
+            .collect();
+        union(types)
+    }
+
+    fn declare_interface(&mut self, members: &'a HashMap<String, Json>, name: &str) {
+        let index = self.definitions.len();
+        self.definitions.push(String::new());
+        self.definitions[index] = self.interface(members, name);
+    }
+
+    /// The type of the values of one JSON Schema type.
+    fn simple(&mut self, t: &str, members: &'a HashMap<String, Json>, name: &str) -> String {
+        match t {
+            "null" | "boolean" | "string" => t.to_string(),
+            "integer" | "number" => "number".to_string(),
+            "array" => {
+                let item_name = format!("{} Item", name);
+                let items = members.get("items");
+                let Some(Json::List(prefix)) = members.get("prefixItems") else {
+                    return match items {
+                        Some(items) => format!("{}[]", operand(self.ty(items, &item_name))),
+                        None => "unknown[]".to_string(),
+                    };
+                };
+                let mut types = prefix
+                    .iter()
+                    .map(|x| self.ty(x, &item_name))
+                    .collect::<Vec<_>>();
+                match items {
+                    Some(Json::Value(Constant::Boolean(false))) => {}
+                    Some(items) => {
+                        types.push(format!("...{}[]", operand(self.ty(items, &item_name))))
+                    }
+                    None => types.push("...unknown[]".to_string()),
+                }
+                format!("[{}]", types.join(", "))
+            }
+            "object" => match (
+                members.get("properties"),
+                members.get("additionalProperties"),
+            ) {
+                (Some(_), _) => {
+                    let name = unique(pascal_case(name), &mut self.names);
+                    self.declare_interface(members, &name);
+                    name
+                }
+                (None, Some(values @ Json::Dict(_))) => {
+                    format!(
+                        "Record<string, {}>",
+                        self.ty(values, &format!("{} Value", name))
+                    )
+                }
+                _ => "Record<string, unknown>".to_string(),
+            },
+            _ => "unknown".to_string(),
+        }
+    }
+}
+
+/// A literal type for a scalar, objects and arrays are too complex.
+fn literal(value: &Json) -> String {
+    match value {
+        Json::Value(_) => value.to_string(),
+        _ => "unknown".to_string(),
+    }
+}
+
+/// TypeScript declarations for the values valid against `schema`, with the outermost type
+/// called `name`. Objects with properties become interfaces, `$defs` that are referenced
+/// become declarations of their own and enums become unions of literals.
+pub fn typescript_types_from_schema(schema: &Json, name: &str) -> String {
+    let mut generator = TypeScriptGenerator {
+        root: schema,
+        names: BUILTINS.iter().map(|name| name.to_string()).collect(),
+        definitions: Vec::new(),
+        references: HashMap::new(),
+    };
+    let root = unique(pascal_case(name), &mut generator.names);
+    generator.references.insert(String::new(), root.clone());
+    generator.declare(schema, &root);
+    let declarations = generator.definitions.join("\n\n");
+    declarations + "\n"
+}
+
+/// TypeScript declarations for the values of `shape`, via the schema it infers.
+pub fn typescript_types(shape: &Shape, name: &str) -> String {
+    typescript_types_from_schema(&shape.to_schema(), name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::infer::InferOptions;
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(snake_case("userID"), "user_id");
+        assert_eq!(snake_case("Content-Type"), "content_type");
+        assert_eq!(snake_case("match"), "r#match");
+        assert_eq!(snake_case("super"), "super_");
+        assert_eq!(snake_case("1st"), "field_1st");
+        assert_eq!(snake_case("$"), "field");
+        assert_eq!(pascal_case("line items"), "LineItems");
+        assert_eq!(pascal_case("self"), "Self_");
+        let mut taken = HashSet::new();
+        let names = ["a", "a", "a"].map(|x| unique(x.to_string(), &mut taken));
+        assert_eq!(names, ["a", "a2", "a3"]);
+    }
+
+    #[test]
+    fn test_root_alias() {
+        let samples = [json!([{"item": 1}, null])];
+        let shape = Shape::infer(&samples, &InferOptions::default());
+        assert_eq!(
+            rust_types(&shape, "item"),
+            "use rusty_json::{FromJson, ToJson};\n\npub type Item = Vec<Option<ItemItem>>;\n\n\
+             #[derive(Debug, Clone, PartialEq, ToJson, FromJson)]\npub struct ItemItem {\n    \
+             pub item: i64,\n}\n"
+        );
+        // names that the generated code refers to aren't shadowed
+        let samples = [
+            json!({"option": {"vec": [1]}, "json": 1}),
+            json!({"option": null, "json": "a"}),
+        ];
+        let shape = Shape::infer(&samples, &InferOptions::default());
+        let code = rust_types(&shape, "string");
+        assert!(code.contains("pub struct String2 {"));
+        assert!(code.contains("pub option: Option<Option2>,"));
+        assert!(code.contains("pub struct Option2 {\n    pub vec: Vec<i64>,"));
+        assert!(code.contains("pub json: Json2,"));
+        assert!(code.contains("pub enum Json2 {"));
+    }
+    #[test]
+    fn test_typescript_from_samples() {
+        let samples = [
+            json!({"id": 1, "kind": "a", "tags": ["x", 2], "user": {"first-name": "A"}}),
+            json!({"id": 2.5, "kind": "b", "tags": [], "user": null, "extra": {}}),
+            json!({"id": 3, "kind": "a", "tags": [true]}),
+            json!({"id": 4, "kind": "b", "tags": []}),
+        ];
+        let shape = Shape::infer(&samples, &InferOptions::default());
+        assert_eq!(
+            typescript_types(&shape, "record"),
+            "export interface Record2 {\n  extra?: Extra;\n  id: number;\n  \
+             kind: \"a\" | \"b\";\n  tags: (boolean | number | string)[];\n  \
+             user?: User | null;\n}\n\nexport interface Extra {}\n\n\
+             export interface User {\n  \"first-name\": string;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_typescript_from_schema() {
+        let schema = json!({
+            "$defs": {
+                "node": {
+                    "type": "object",
+                    "properties": {"next": {"$ref": "#/$defs/node"}, "value": {}},
+                    "required": ["value"]
+                },
+                "pair": {"prefixItems": [{"type": "string"}, {"const": 1}], "items": false}
+            },
+            "type": ["array", "null"],
+            "items": {"anyOf": [{"$ref": "#/$defs/node"}, {"$ref": "#/$defs/pair"}]}
+        });
+        assert_eq!(
+            typescript_types_from_schema(&schema, "list"),
+            "export type List = (Node | Pair)[] | null;\n\n\
+             export interface Node {\n  next?: Node;\n  value: unknown;\n}\n\n\
+             export type Pair = [string, 1];\n"
+        );
+    }
+}
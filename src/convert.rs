@@ -0,0 +1,428 @@
+//! Conversions between `Json` and Rust types.
+use crate::lexer::Constant;
+use crate::parser::Json;
+use crate::pointer::escape_pointer_token;
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+impl From<Constant> for Json {
+    fn from(value: Constant) -> Self {
+        Json::Value(value)
+    }
+}
+
+macro_rules! from_signed {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Json {
+            fn from(value: $t) -> Self {
+                Json::Value(Constant::Int(value as i64))
+            }
+        })*
+    };
+}
+
+macro_rules! from_unsigned {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Json {
+            fn from(value: $t) -> Self {
+                Json::Value(match i64::try_from(value) {
+                    Ok(x) => Constant::Int(x),
+                    Err(_) => Constant::UInt(value as u64),
+                })
+            }
+        })*
+    };
+}
+
+from_signed!(i8, i16, i32, i64, isize);
+from_unsigned!(u8, u16, u32, u64, usize);
+
+impl From<f32> for Json {
+    fn from(value: f32) -> Self {
+        Json::Value(Constant::Float(value as f64))
+    }
+}
+
+impl From<f64> for Json {
+    fn from(value: f64) -> Self {
+        Json::Value(Constant::Float(value))
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Value(Constant::Boolean(value))
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::Value(Constant::StringLiteral(value))
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::Value(Constant::StringLiteral(value.to_string()))
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(x) => x.into(),
+            None => Json::Value(Constant::Null),
+        }
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(value: Vec<T>) -> Self {
+        Json::List(value.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Json>> From<HashMap<String, T>> for Json {
+    fn from(value: HashMap<String, T>) -> Self {
+        Json::Dict(value.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
+
+impl<T: Into<Json>> From<BTreeMap<String, T>> for Json {
+    fn from(value: BTreeMap<String, T>) -> Self {
+        Json::Dict(value.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ConversionError {
+    /// JSON Pointer of the value, the expected type and the JSON type that was found.
+    #[error("ConversionError: Expected {1} at \"{0}\", found {2}")]
+    TypeMismatch(String, &'static str, &'static str),
+    /// JSON Pointer of the number and the type it doesn't fit into.
+    #[error("ConversionError: Number at \"{0}\" is out of range for {1}")]
+    OutOfRange(String, &'static str),
+    /// JSON Pointer of the member that is missing from its object.
+    #[error("ConversionError: Missing member \"{0}\"")]
+    MissingMember(String),
+    /// JSON Pointer of the value and what is wrong with it.
+    #[error("ConversionError: Invalid value at \"{0}\": {1}")]
+    InvalidValue(String, String),
+}
+
+impl ConversionError {
+    /// Prefixes the path with the reference token (unescaped key or index) of the parent.
+    pub fn within(self, token: &str) -> Self {
+        let prefix = format!("/{}", escape_pointer_token(token));
+        match self {
+            Self::TypeMismatch(path, expected, found) => {
+                Self::TypeMismatch(prefix + &path, expected, found)
+            }
+            Self::OutOfRange(path, expected) => Self::OutOfRange(prefix + &path, expected),
+            Self::MissingMember(path) => Self::MissingMember(prefix + &path),
+            Self::InvalidValue(path, message) => Self::InvalidValue(prefix + &path, message),
+        }
+    }
+}
+
+/// Name of the JSON type of `json`, as used in `ConversionError`.
+fn type_name(json: &Json) -> &'static str {
+    match json {
+        Json::Dict(_) => "object",
+        Json::List(_) => "array",
+        Json::Value(Constant::StringLiteral(_)) => "string",
+        Json::Value(Constant::Boolean(_)) => "boolean",
+        Json::Value(Constant::Null) => "null",
+        Json::Value(_) => "number",
+    }
+}
+
+pub(crate) fn mismatch(json: &Json, expected: &'static str) -> ConversionError {
+    ConversionError::TypeMismatch(String::new(), expected, type_name(json))
+}
+
+macro_rules! try_from_integer {
+    ($($t:ty),*) => {
+        $(impl TryFrom<&Json> for $t {
+            type Error = ConversionError;
+
+            fn try_from(json: &Json) -> Result<Self, Self::Error> {
+                let out_of_range = || ConversionError::OutOfRange(String::new(), stringify!($t));
+                match json {
+                    Json::Value(Constant::Int(x)) => {
+                        <$t>::try_from(*x).map_err(|_| out_of_range())
+                    }
+                    Json::Value(Constant::UInt(x)) => {
+                        <$t>::try_from(*x).map_err(|_| out_of_range())
+                    }
+                    Json::Value(Constant::Number(x)) if x.is_integer() => x
+                        .as_i128()
+                        .and_then(|x| <$t>::try_from(x).ok())
+                        .ok_or_else(out_of_range),
+                    _ => Err(mismatch(json, stringify!($t))),
+                }
+            }
+        }
+
+        impl TryFrom<Json> for $t {
+            type Error = ConversionError;
+
+            fn try_from(json: Json) -> Result<Self, Self::Error> {
+                Self::try_from(&json)
+            }
+        })*
+    };
+}
+
+try_from_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl TryFrom<&Json> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(json: &Json) -> Result<Self, Self::Error> {
+        match json {
+            Json::Value(Constant::Float(x)) => Ok(*x),
+            Json::Value(Constant::Int(x)) => Ok(*x as f64),
+            Json::Value(Constant::UInt(x)) => Ok(*x as f64),
+            Json::Value(Constant::Number(x)) => Ok(x.as_f64()),
+            _ => Err(mismatch(json, "f64")),
+        }
+    }
+}
+
+impl TryFrom<&Json> for f32 {
+    type Error = ConversionError;
+
+    fn try_from(json: &Json) -> Result<Self, Self::Error> {
+        f64::try_from(json)
+            .map(|x| x as f32)
+            .map_err(|_| mismatch(json, "f32"))
+    }
+}
+
+impl TryFrom<&Json> for bool {
+    type Error = ConversionError;
+
+    fn try_from(json: &Json) -> Result<Self, Self::Error> {
+        match json {
+            Json::Value(Constant::Boolean(x)) => Ok(*x),
+            _ => Err(mismatch(json, "boolean")),
+        }
+    }
+}
+
+impl TryFrom<&Json> for String {
+    type Error = ConversionError;
+
+    fn try_from(json: &Json) -> Result<Self, Self::Error> {
+        match json {
+            Json::Value(Constant::StringLiteral(x)) => Ok(x.clone()),
+            _ => Err(mismatch(json, "string")),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a Json> for &'a str {
+    type Error = ConversionError;
+
+    fn try_from(json: &'a Json) -> Result<Self, Self::Error> {
+        match json {
+            Json::Value(Constant::StringLiteral(x)) => Ok(x),
+            _ => Err(mismatch(json, "string")),
+        }
+    }
+}
+
+macro_rules! try_from_owned_via_ref {
+    ($($t:ty),*) => {
+        $(impl TryFrom<Json> for $t {
+            type Error = ConversionError;
+
+            fn try_from(json: Json) -> Result<Self, Self::Error> {
+                Self::try_from(&json)
+            }
+        })*
+    };
+}
+
+try_from_owned_via_ref!(f64, f32, bool);
+
+impl TryFrom<Json> for String {
+    type Error = ConversionError;
+
+    fn try_from(json: Json) -> Result<Self, Self::Error> {
+        match json {
+            Json::Value(Constant::StringLiteral(x)) => Ok(x),
+            _ => Err(mismatch(&json, "string")),
+        }
+    }
+}
+
+impl<'a, T: TryFrom<&'a Json, Error = ConversionError>> TryFrom<&'a Json> for Option<T> {
+    type Error = ConversionError;
+
+    fn try_from(json: &'a Json) -> Result<Self, Self::Error> {
+        match json {
+            Json::Value(Constant::Null) => Ok(None),
+            _ => T::try_from(json).map(Some),
+        }
+    }
+}
+
+impl<T: TryFrom<Json, Error = ConversionError>> TryFrom<Json> for Option<T> {
+    type Error = ConversionError;
+
+    fn try_from(json: Json) -> Result<Self, Self::Error> {
+        match json {
+            Json::Value(Constant::Null) => Ok(None),
+            _ => T::try_from(json).map(Some),
+        }
+    }
+}
+
+impl<'a, T: TryFrom<&'a Json, Error = ConversionError>> TryFrom<&'a Json> for Vec<T> {
+    type Error = ConversionError;
+
+    fn try_from(json: &'a Json) -> Result<Self, Self::Error> {
+        match json {
+            Json::List(x) => x
+                .iter()
+                .enumerate()
+                .map(|(i, value)| T::try_from(value).map_err(|e| e.within(&i.to_string())))
+                .collect(),
+            _ => Err(mismatch(json, "array")),
+        }
+    }
+}
+
+impl<T: TryFrom<Json, Error = ConversionError>> TryFrom<Json> for Vec<T> {
+    type Error = ConversionError;
+
+    fn try_from(json: Json) -> Result<Self, Self::Error> {
+        match json {
+            Json::List(x) => x
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| T::try_from(value).map_err(|e| e.within(&i.to_string())))
+                .collect(),
+            _ => Err(mismatch(&json, "array")),
+        }
+    }
+}
+
+macro_rules! try_from_map {
+    ($($map:ident),*) => {
+        $(impl<'a, T: TryFrom<&'a Json, Error = ConversionError>> TryFrom<&'a Json>
+            for $map<String, T>
+        {
+            type Error = ConversionError;
+
+            fn try_from(json: &'a Json) -> Result<Self, Self::Error> {
+                match json {
+                    Json::Dict(x) => x
+                        .iter()
+                        .map(|(k, v)| Ok((k.clone(), T::try_from(v).map_err(|e| e.within(k))?)))
+                        .collect(),
+                    _ => Err(mismatch(json, "object")),
+                }
+            }
+        }
+
+        impl<T: TryFrom<Json, Error = ConversionError>> TryFrom<Json> for $map<String, T> {
+            type Error = ConversionError;
+
+            fn try_from(json: Json) -> Result<Self, Self::Error> {
+                match json {
+                    Json::Dict(x) => x
+                        .into_iter()
+                        .map(|(k, v)| {
+                            let v = T::try_from(v).map_err(|e| e.within(&k))?;
+                            Ok((k, v))
+                        })
+                        .collect(),
+                    _ => Err(mismatch(&json, "object")),
+                }
+            }
+        })*
+    };
+}
+
+try_from_map!(HashMap, BTreeMap);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::NumberMode;
+    use crate::parser::{parse, parse_with_options, ParseOptions};
+
+    #[test]
+    fn test_into_json() {
+        let mut map = BTreeMap::new();
+        map.insert("b".to_string(), vec![Some(1u8), None]);
+        map.insert("a".to_string(), vec![]);
+        assert_eq!(Json::from(map).to_string(), r#"{"a":[],"b":[1,null]}"#);
+        assert_eq!(Json::from(u64::MAX), Json::Value(Constant::UInt(u64::MAX)));
+        assert_eq!(Json::from(7usize), Json::Value(Constant::Int(7)));
+        assert_eq!(Json::from(-1i8), json!(-1));
+        assert_eq!(Json::from("x"), Json::from("x".to_string()));
+        assert_eq!(
+            Json::from(HashMap::from([("k".to_string(), true)])),
+            json!({"k": true})
+        );
+    }
+
+    #[test]
+    fn test_try_from_json() {
+        let json = parse(r#"{"ids": [1, 2, 3], "name": "x", "ratio": 2, "none": null}"#).unwrap();
+        let Json::Dict(fields) = &json else {
+            panic!("should be a dict");
+        };
+        assert_eq!(Vec::<u8>::try_from(&fields["ids"]), Ok(vec![1, 2, 3]));
+        assert_eq!(<&str>::try_from(&fields["name"]), Ok("x"));
+        assert_eq!(f64::try_from(&fields["ratio"]), Ok(2.0));
+        assert_eq!(Option::<bool>::try_from(&fields["none"]), Ok(None));
+        assert_eq!(
+            String::try_from(&fields["ratio"]),
+            Err(ConversionError::TypeMismatch(
+                String::new(),
+                "string",
+                "number"
+            ))
+        );
+
+        let nested = json!({"a/b": [{"x": 1}, {"x": "2"}]});
+        assert_eq!(
+            HashMap::<String, Vec<HashMap<String, i32>>>::try_from(nested),
+            Err(ConversionError::TypeMismatch(
+                "/a~1b/1/x".to_string(),
+                "i32",
+                "string"
+            ))
+        );
+        let error = Vec::<i8>::try_from(json!([1, 300])).unwrap_err();
+        assert_eq!(error, ConversionError::OutOfRange("/1".to_string(), "i8"));
+        assert_eq!(
+            error.to_string(),
+            "ConversionError: Number at \"/1\" is out of range for i8"
+        );
+        assert_eq!(u64::try_from(json!(u64::MAX)), Ok(u64::MAX));
+        assert!(u32::try_from(json!(-1)).is_err());
+        assert!(i64::try_from(json!(1.0)).is_err());
+
+        let options = ParseOptions {
+            number_mode: NumberMode::Preserve,
+            ..Default::default()
+        };
+        let preserved =
+            parse_with_options("[170141183460469231731687303715884105727, 2]", &options);
+        let numbers = preserved.unwrap();
+        assert_eq!(
+            Vec::<u64>::try_from(&numbers),
+            Err(ConversionError::OutOfRange("/0".to_string(), "u64"))
+        );
+        let Json::List(numbers) = numbers else {
+            panic!("should be a list");
+        };
+        assert_eq!(u16::try_from(&numbers[1]), Ok(2));
+    }
+}
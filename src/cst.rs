@@ -0,0 +1,744 @@
+//! Lossless concrete syntax tree.
+//!
+//! Unlike `parser::Json`, a `Document` remembers the exact source text: whitespace, comments,
+//! the spelling of numbers and the order of keys. Nodes only store byte spans into that text,
+//! and every edit splices new text into the affected range and re-parses, so the untouched
+//! parts of a document stay byte-for-byte identical.
+use crate::lexer::{escape, scan_number, unescape, Constant};
+use crate::limits::Limits;
+use crate::parser::{Json, JsonError};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum CstError {
+    #[error("CstError: Unexpected character {0:?} at byte {1}")]
+    UnexpectedCharacter(char, usize),
+    #[error("CstError: Unterminated string starting at byte {0}")]
+    UnterminatedString(usize),
+    #[error("CstError: Unterminated comment starting at byte {0}")]
+    UnterminatedComment(usize),
+    #[error("CstError: Invalid escape sequence at byte {0}")]
+    InvalidEscape(usize),
+    #[error("CstError: Invalid number at byte {0}")]
+    InvalidNumber(usize),
+    #[error("CstError: Unexpected token {0:?} at byte {1}")]
+    UnexpectedToken(String, usize),
+    #[error("CstError: Nesting deeper than {0} levels at byte {1}")]
+    TooDeep(usize, usize),
+    #[error("CstError: Unexpected end of input")]
+    UnexpectedEndOfInput,
+    #[error("CstError: Nothing found at path {0}")]
+    PathNotFound(String),
+    #[error("CstError: Value at path {0} is not {1}")]
+    WrongType(String, &'static str),
+    #[error("CstError: Key {0:?} already exists")]
+    KeyExists(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Whitespace,
+    LineComment,
+    BlockComment,
+    StartOfDict,
+    EndOfDict,
+    StartOfList,
+    EndOfList,
+    Colon,
+    Comma,
+    String,
+    Number,
+    True,
+    False,
+    Null,
+}
+
+impl TokenKind {
+    pub fn is_trivia(self) -> bool {
+        matches!(
+            self,
+            TokenKind::Whitespace | TokenKind::LineComment | TokenKind::BlockComment
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstToken {
+    pub kind: TokenKind,
+    pub span: Range<usize>,
+}
+
+/// Splits `source` into tokens, including whitespace and comments. Concatenating the spans of
+/// the returned tokens gives back `source`.
+pub fn lex(source: &str) -> Result<Vec<CstToken>, CstError> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < source.len() {
+        let token = next_token(source, pos)?;
+        pos = token.span.end;
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Reads the token starting at byte `pos`, which has to be smaller than `source.len()`.
+pub(crate) fn next_token(source: &str, mut pos: usize) -> Result<CstToken, CstError> {
+    let bytes = source.as_bytes();
+    let start = pos;
+    let kind = match bytes[pos] {
+        b' ' | b'\t' | b'\n' | b'\r' => {
+            while pos < bytes.len() && matches!(bytes[pos], b' ' | b'\t' | b'\n' | b'\r') {
+                pos += 1;
+            }
+            TokenKind::Whitespace
+        }
+        b'/' if bytes.get(pos + 1) == Some(&b'/') => {
+            pos = source[pos..].find('\n').map_or(bytes.len(), |i| pos + i);
+            TokenKind::LineComment
+        }
+        b'/' if bytes.get(pos + 1) == Some(&b'*') => {
+            pos = match source[pos + 2..].find("*/") {
+                Some(i) => pos + 2 + i + 2,
+                None => return Err(CstError::UnterminatedComment(start)),
+            };
+            TokenKind::BlockComment
+        }
+        b'{' => single(&mut pos, TokenKind::StartOfDict),
+        b'}' => single(&mut pos, TokenKind::EndOfDict),
+        b'[' => single(&mut pos, TokenKind::StartOfList),
+        b']' => single(&mut pos, TokenKind::EndOfList),
+        b':' => single(&mut pos, TokenKind::Colon),
+        b',' => single(&mut pos, TokenKind::Comma),
+        b'"' => {
+            pos = scan_string(bytes, pos)?;
+            TokenKind::String
+        }
+        b'-' | b'0'..=b'9' => {
+            pos = scan_number(bytes, pos).ok_or(CstError::InvalidNumber(pos))?;
+            TokenKind::Number
+        }
+        _ => {
+            let (word, kind) = if source[pos..].starts_with("true") {
+                ("true", TokenKind::True)
+            } else if source[pos..].starts_with("false") {
+                ("false", TokenKind::False)
+            } else if source[pos..].starts_with("null") {
+                ("null", TokenKind::Null)
+            } else {
+                return Err(unexpected_character(source, pos));
+            };
+            pos += word.len();
+            kind
+        }
+    };
+    Ok(CstToken {
+        kind,
+        span: start..pos,
+    })
+}
+
+fn single(pos: &mut usize, kind: TokenKind) -> TokenKind {
+    *pos += 1;
+    kind
+}
+
+fn unexpected_character(source: &str, pos: usize) -> CstError {
+    CstError::UnexpectedCharacter(source[pos..].chars().next().unwrap_or('\0'), pos)
+}
+
+/// Returns the position right after the closing quote of the string starting at `start`.
+fn scan_string(bytes: &[u8], start: usize) -> Result<usize, CstError> {
+    let mut pos = start + 1;
+    loop {
+        match bytes.get(pos) {
+            None => return Err(CstError::UnterminatedString(start)),
+            Some(b'"') => return Ok(pos + 1),
+            Some(b'\\') => match bytes.get(pos + 1) {
+                Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => pos += 2,
+                Some(b'u')
+                    if bytes.len() >= pos + 6
+                        && bytes[pos + 2..pos + 6].iter().all(u8::is_ascii_hexdigit) =>
+                {
+                    pos += 6
+                }
+                _ => return Err(CstError::InvalidEscape(pos)),
+            },
+            Some(b'\n' | b'\r') => return Err(CstError::UnterminatedString(start)),
+            Some(&c) if c < 0x20 => {
+                return Err(CstError::UnexpectedCharacter(c as char, pos));
+            }
+            Some(_) => pos += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    /// Span of the value itself, without surrounding whitespace or comments.
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    Object(Vec<Member>),
+    Array(Vec<Element>),
+    /// Strings, numbers, booleans and null. The token kind tells which one it is, the spelling
+    /// is the text covered by the node span.
+    Scalar(TokenKind),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    /// Decoded key.
+    pub name: String,
+    /// Span of the key including its quotes.
+    pub key: Range<usize>,
+    pub value: Node,
+    /// Position of the comma following the value, if there is one.
+    pub comma: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    pub value: Node,
+    /// Position of the comma following the value, if there is one.
+    pub comma: Option<usize>,
+}
+
+impl Node {
+    pub fn members(&self) -> Option<&[Member]> {
+        match &self.kind {
+            NodeKind::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+
+    pub fn elements(&self) -> Option<&[Element]> {
+        match &self.kind {
+            NodeKind::Array(elements) => Some(elements),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathSegment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+fn path_to_string(path: &[PathSegment]) -> String {
+    let mut result = String::new();
+    for segment in path {
+        match segment {
+            PathSegment::Key(key) => result.push_str(&format!("/{}", key)),
+            PathSegment::Index(i) => result.push_str(&format!("/{}", i)),
+        }
+    }
+    result
+}
+
+struct CstParser<'a> {
+    source: &'a str,
+    tokens: &'a [CstToken],
+    pos: usize,
+    /// Containers open around the current token, bounded like `Limits::max_depth`.
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> CstParser<'a> {
+    fn peek(&mut self) -> Option<&'a CstToken> {
+        while self.pos < self.tokens.len() && self.tokens[self.pos].kind.is_trivia() {
+            self.pos += 1;
+        }
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<&'a CstToken, CstError> {
+        let token = self.peek().ok_or(CstError::UnexpectedEndOfInput)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn unexpected(&self, token: &CstToken) -> CstError {
+        CstError::UnexpectedToken(
+            self.source[token.span.clone()].to_string(),
+            token.span.start,
+        )
+    }
+
+    fn parse_value(&mut self) -> Result<Node, CstError> {
+        let token = self.next()?;
+        match token.kind {
+            TokenKind::StartOfDict | TokenKind::StartOfList => {
+                if self.depth == self.max_depth {
+                    return Err(CstError::TooDeep(self.max_depth, token.span.start));
+                }
+                self.depth += 1;
+                let node = match token.kind {
+                    TokenKind::StartOfDict => self.parse_object(token.span.start),
+                    _ => self.parse_array(token.span.start),
+                };
+                self.depth -= 1;
+                node
+            }
+            TokenKind::String
+            | TokenKind::Number
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Null => Ok(Node {
+                kind: NodeKind::Scalar(token.kind),
+                span: token.span.clone(),
+            }),
+            _ => Err(self.unexpected(token)),
+        }
+    }
+
+    fn parse_object(&mut self, start: usize) -> Result<Node, CstError> {
+        let mut members: Vec<Member> = Vec::new();
+        loop {
+            let token = self.next()?;
+            match token.kind {
+                TokenKind::EndOfDict => {
+                    return Ok(Node {
+                        kind: NodeKind::Object(members),
+                        span: start..token.span.end,
+                    })
+                }
+                TokenKind::String if members.last().is_none_or(|m| m.comma.is_some()) => {
+                    let name = unescape(&self.source[token.span.start + 1..token.span.end - 1])
+                        .map_err(|_| CstError::InvalidEscape(token.span.start))?;
+                    let colon = self.next()?;
+                    if colon.kind != TokenKind::Colon {
+                        return Err(self.unexpected(colon));
+                    }
+                    let value = self.parse_value()?;
+                    members.push(Member {
+                        name,
+                        key: token.span.clone(),
+                        value,
+                        comma: None,
+                    });
+                }
+                TokenKind::Comma if members.last().is_some_and(|m| m.comma.is_none()) => {
+                    members.last_mut().unwrap().comma = Some(token.span.start); // checked above
+                }
+                _ => return Err(self.unexpected(token)),
+            }
+        }
+    }
+
+    fn parse_array(&mut self, start: usize) -> Result<Node, CstError> {
+        let mut elements: Vec<Element> = Vec::new();
+        loop {
+            let token = self.peek().ok_or(CstError::UnexpectedEndOfInput)?;
+            match token.kind {
+                TokenKind::EndOfList => {
+                    self.pos += 1;
+                    return Ok(Node {
+                        kind: NodeKind::Array(elements),
+                        span: start..token.span.end,
+                    });
+                }
+                TokenKind::Comma if elements.last().is_some_and(|e| e.comma.is_none()) => {
+                    self.pos += 1;
+                    elements.last_mut().unwrap().comma = Some(token.span.start);
+                    // checked above
+                }
+                _ if elements.last().is_none_or(|e| e.comma.is_some()) => {
+                    let value = self.parse_value()?;
+                    elements.push(Element { value, comma: None });
+                }
+                _ => return Err(self.unexpected(token)),
+            }
+        }
+    }
+}
+
+/// A parsed JSON document that keeps its original text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    source: String,
+    tokens: Vec<CstToken>,
+    root: Node,
+}
+
+impl FromStr for Document {
+    type Err = CstError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Document::parse(s)
+    }
+}
+
+impl std::fmt::Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Document {
+    pub fn parse(source: &str) -> Result<Document, CstError> {
+        Document::parse_with_max_depth(source, Limits::default().max_depth)
+    }
+
+    pub(crate) fn parse_with_max_depth(
+        source: &str,
+        max_depth: usize,
+    ) -> Result<Document, CstError> {
+        let tokens = lex(source)?;
+        let mut parser = CstParser {
+            source,
+            tokens: &tokens,
+            pos: 0,
+            depth: 0,
+            max_depth,
+        };
+        let root = parser.parse_value()?;
+        if let Some(token) = parser.peek() {
+            return Err(parser.unexpected(token));
+        }
+        Ok(Document {
+            source: source.to_string(),
+            tokens,
+            root,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// All tokens of the document, trivia included.
+    pub fn tokens(&self) -> &[CstToken] {
+        &self.tokens
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    /// The original spelling of a node, e.g. `1.50` or `"\u0041"`.
+    pub fn text(&self, node: &Node) -> &str {
+        &self.source[node.span.clone()]
+    }
+
+    pub fn get(&self, path: &[PathSegment]) -> Option<&Node> {
+        let mut node = &self.root;
+        for segment in path {
+            node = match (segment, &node.kind) {
+                // later duplicates win, just like in `to_json`
+                (PathSegment::Key(key), NodeKind::Object(members)) => {
+                    &members.iter().rev().find(|m| m.name == *key)?.value
+                }
+                (PathSegment::Index(i), NodeKind::Array(elements)) => &elements.get(*i)?.value,
+                _ => return None,
+            };
+        }
+        Some(node)
+    }
+
+    pub fn to_json(&self) -> Result<Json, JsonError> {
+        self.node_to_json(&self.root)
+    }
+
+    fn node_to_json(&self, node: &Node) -> Result<Json, JsonError> {
+        Ok(match &node.kind {
+            NodeKind::Object(members) => {
+                let mut result = HashMap::new();
+                for member in members {
+                    result.insert(member.name.clone(), self.node_to_json(&member.value)?);
+                }
+                Json::Dict(result)
+            }
+            NodeKind::Array(elements) => Json::List(
+                elements
+                    .iter()
+                    .map(|e| self.node_to_json(&e.value))
+                    .collect::<Result<_, _>>()?,
+            ),
+            NodeKind::Scalar(_) => {
+                Json::Value(Constant::from_str(self.text(node)).map_err(JsonError::JsonLexError)?)
+            }
+        })
+    }
+
+    /// Replaces the value at `path` with `value`, which has to be valid JSON text.
+    pub fn set_value(&mut self, path: &[PathSegment], value: &str) -> Result<(), CstError> {
+        let value = fragment(value)?;
+        let span = self.node(path)?.span.clone();
+        self.splice(span, &value)
+    }
+
+    /// Appends a new member to the object at `path`, copying the indentation and colon spacing
+    /// of the existing members.
+    pub fn insert_key(
+        &mut self,
+        path: &[PathSegment],
+        key: &str,
+        value: &str,
+    ) -> Result<(), CstError> {
+        let value = fragment(value)?;
+        let node = self.node(path)?;
+        let members = node
+            .members()
+            .ok_or_else(|| CstError::WrongType(path_to_string(path), "an object"))?;
+        if members.iter().any(|m| m.name == key) {
+            return Err(CstError::KeyExists(key.to_string()));
+        }
+        let key = format!("\"{}\"", escape(key));
+        let Some(last) = members.last() else {
+            let at = node.span.start + 1;
+            return self.splice(at..at, &format!("{}: {}", key, value));
+        };
+        let previous = match members.len() {
+            1 => node.span.start,
+            n => members[n - 2].comma.unwrap_or(node.span.start),
+        };
+        let gap = &self.source[previous + 1..last.key.start];
+        let indent = if members.len() == 1 && !gap.contains('\n') {
+            // `{"a": 1}` says nothing about the spacing after commas
+            " "
+        } else {
+            whitespace_tail(gap)
+        };
+        let colon = &self.source[last.key.end..last.value.span.start];
+        let colon = if colon.trim() == ":" { colon } else { ": " };
+        let entry = format!("{}{}{}{}", indent, key, colon, value);
+        match last.comma {
+            Some(comma) => self.splice(comma + 1..comma + 1, &format!("{},", entry)),
+            None => {
+                let at = last.value.span.end;
+                self.splice(at..at, &format!(",{}", entry))
+            }
+        }
+    }
+
+    /// Removes `key` and its value from the object at `path`, together with the separating comma.
+    pub fn remove_key(&mut self, path: &[PathSegment], key: &str) -> Result<(), CstError> {
+        let node = self.node(path)?;
+        let members = node
+            .members()
+            .ok_or_else(|| CstError::WrongType(path_to_string(path), "an object"))?;
+        let items: Vec<_> = members
+            .iter()
+            .map(|m| (m.key.start, m.value.span.end, m.comma))
+            .collect();
+        let index = members
+            .iter()
+            .rposition(|m| m.name == key)
+            .ok_or_else(|| CstError::PathNotFound(format!("{}/{}", path_to_string(path), key)))?;
+        let range = self.removal_range(&items, index);
+        self.splice(range, "")
+    }
+
+    /// Removes the element at `index` from the array at `path`, together with the separating
+    /// comma.
+    pub fn remove_element(&mut self, path: &[PathSegment], index: usize) -> Result<(), CstError> {
+        let node = self.node(path)?;
+        let elements = node
+            .elements()
+            .ok_or_else(|| CstError::WrongType(path_to_string(path), "an array"))?;
+        if index >= elements.len() {
+            return Err(CstError::PathNotFound(format!(
+                "{}/{}",
+                path_to_string(path),
+                index
+            )));
+        }
+        let items: Vec<_> = elements
+            .iter()
+            .map(|e| (e.value.span.start, e.value.span.end, e.comma))
+            .collect();
+        let range = self.removal_range(&items, index);
+        self.splice(range, "")
+    }
+
+    fn node(&self, path: &[PathSegment]) -> Result<&Node, CstError> {
+        self.get(path)
+            .ok_or_else(|| CstError::PathNotFound(path_to_string(path)))
+    }
+
+    /// `items` are (start, end, comma) of the entries of a container. The returned range covers
+    /// the entry at `index` and exactly one comma, so that the remaining entries stay valid.
+    fn removal_range(&self, items: &[(usize, usize, Option<usize>)], index: usize) -> Range<usize> {
+        let (start, end, comma) = items[index];
+        let is_last = index + 1 == items.len();
+        match (comma, index.checked_sub(1).map(|i| items[i].2)) {
+            (Some(comma), Some(Some(previous))) if is_last => previous + 1..comma + 1,
+            (Some(comma), _) => {
+                let mut stop = comma + 1;
+                if !is_last {
+                    let bytes = self.source.as_bytes();
+                    while stop < items[index + 1].0 && bytes[stop].is_ascii_whitespace() {
+                        stop += 1;
+                    }
+                }
+                start..stop
+            }
+            (None, Some(Some(previous))) => previous..end,
+            (None, _) => start..end,
+        }
+    }
+
+    fn splice(&mut self, range: Range<usize>, text: &str) -> Result<(), CstError> {
+        let mut source = String::with_capacity(self.source.len() + text.len());
+        source.push_str(&self.source[..range.start]);
+        source.push_str(text);
+        source.push_str(&self.source[range.end..]);
+        // re-parsing keeps all spans valid and leaves `self` untouched if the edit was bad
+        *self = Document::parse(&source)?;
+        Ok(())
+    }
+}
+
+/// Validates a value that is about to be spliced into a document and strips surrounding trivia,
+/// so a trailing line comment can't swallow the rest of the line.
+fn fragment(value: &str) -> Result<String, CstError> {
+    let document = Document::parse(value)?;
+    Ok(document.text(&document.root).to_string())
+}
+
+/// The whitespace a line starts with, including the line break, or a single space if the text
+/// contains more than whitespace.
+fn whitespace_tail(text: &str) -> &str {
+    let tail = &text[text.rfind('\n').unwrap_or(0)..];
+    if tail.trim().is_empty() {
+        tail
+    } else {
+        " "
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    const CONFIG: &str = r#"{
+    // service settings
+    "name": "demo",
+    "port": 8080,
+    "ratio": 1.50,
+    "tags": [ "a", "b", "c" ], /* trailing */
+    "nested": {"x": 1e3}
+}
+"#;
+
+    #[test]
+    fn test_roundtrip_is_lossless() {
+        let document = Document::parse(CONFIG).expect("should not error");
+        assert_eq!(document.to_string(), CONFIG);
+        let joined: String = document
+            .tokens()
+            .iter()
+            .map(|t| &CONFIG[t.span.clone()])
+            .collect();
+        assert_eq!(joined, CONFIG);
+        let ratio = document.get(&[PathSegment::Key("ratio")]).unwrap();
+        assert_eq!(document.text(ratio), "1.50");
+    }
+
+    #[test]
+    fn test_to_json_matches_parser() {
+        let json = r#"{"key1": 1, "key2": "va\"lue", "key3": [1, 2, 3,], "key4": {"key5": null}}"#;
+        let document = Document::parse(json).expect("should not error");
+        assert_eq!(document.to_json().unwrap(), parse(json).unwrap());
+    }
+
+    #[test]
+    fn test_set_value_only_touches_value() {
+        let mut document = Document::parse(CONFIG).unwrap();
+        document
+            .set_value(&[PathSegment::Key("port")], "9090 // new port")
+            .unwrap();
+        assert_eq!(document.to_string(), CONFIG.replace("8080", "9090"));
+        document
+            .set_value(
+                &[PathSegment::Key("nested"), PathSegment::Key("x")],
+                "[true]",
+            )
+            .unwrap();
+        assert_eq!(
+            document.to_string(),
+            CONFIG.replace("8080", "9090").replace("1e3", "[true]")
+        );
+    }
+
+    #[test]
+    fn test_insert_key_follows_layout() {
+        let mut document = Document::parse(CONFIG).unwrap();
+        document.insert_key(&[], "debug", "false").unwrap();
+        assert_eq!(
+            document.to_string(),
+            CONFIG.replace("1e3}\n}", "1e3},\n    \"debug\": false\n}")
+        );
+        document
+            .insert_key(&[PathSegment::Key("nested")], "y\"", "2")
+            .unwrap();
+        assert!(document.to_string().contains(r#"{"x": 1e3, "y\"": 2}"#));
+        assert!(matches!(
+            document.insert_key(&[], "port", "1"),
+            Err(CstError::KeyExists(_))
+        ));
+
+        let mut empty = Document::parse("{ }").unwrap();
+        empty.insert_key(&[], "a", "1").unwrap();
+        assert_eq!(empty.to_string(), r#"{"a": 1 }"#);
+    }
+
+    #[test]
+    fn test_remove() {
+        let tags = [PathSegment::Key("tags")];
+        let mut document = Document::parse(CONFIG).unwrap();
+        document.remove_element(&tags, 1).unwrap();
+        assert!(document
+            .to_string()
+            .contains(r#"[ "a", "c" ], /* trailing */"#));
+        document.remove_element(&tags, 1).unwrap();
+        assert!(document.to_string().contains(r#"[ "a" ], /* trailing */"#));
+        document.remove_element(&tags, 0).unwrap();
+        assert!(document.to_string().contains(r#"[  ], /* trailing */"#));
+        assert!(document.remove_element(&tags, 0).is_err());
+
+        document.remove_key(&[], "ratio").unwrap();
+        assert!(!document.to_string().contains("ratio"));
+        assert!(document
+            .to_string()
+            .contains("\"port\": 8080,\n    \"tags\""));
+
+        let mut trailing = Document::parse("[\n  1,\n  2,\n]").unwrap();
+        trailing.remove_element(&[], 1).unwrap();
+        assert_eq!(trailing.to_string(), "[\n  1,\n]");
+    }
+
+    #[test]
+    fn test_invalid_documents() {
+        assert!(matches!(
+            Document::parse(r#"{"a": 01}"#),
+            Err(CstError::InvalidNumber(6))
+        ));
+        assert!(matches!(
+            Document::parse(r#"{"a" 1}"#),
+            Err(CstError::UnexpectedToken(_, 5))
+        ));
+        assert!(matches!(
+            Document::parse("[1, 2"),
+            Err(CstError::UnexpectedEndOfInput)
+        ));
+        assert!(Document::parse("[1 2]").is_err());
+        let deep = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert_eq!(Document::parse(&deep), Err(CstError::TooDeep(128, 128)));
+        let limit = format!("{}{}", "[".repeat(128), "]".repeat(128));
+        assert!(Document::parse(&limit).is_ok());
+        assert!(Document::parse("{\"a\": \"\\x\"}").is_err());
+        let mut document = Document::parse("[1]").unwrap();
+        assert!(document.set_value(&[PathSegment::Index(0)], "[").is_err());
+        assert_eq!(document.to_string(), "[1]");
+    }
+}
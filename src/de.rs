@@ -0,0 +1,507 @@
+//! A serde `Deserializer` that reads straight from the token stream, without building a `Json`.
+use crate::lexer::{tokenize_spanned, Constant, NumberMode, SpannedToken, Token};
+use crate::limits::{Limit, Limits};
+use crate::parser::{Json, JsonError, JsonParseError};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use std::collections::HashMap;
+use std::fmt;
+
+impl de::Error for JsonError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        JsonError::Serde(msg.to_string())
+    }
+}
+
+/// Deserializes a `T` from JSON text. Unlike `parser::parse`, any value is accepted at the root.
+/// Nesting is limited to the default `Limits::max_depth`.
+pub fn from_str<T: DeserializeOwned>(json: &str) -> Result<T, JsonError> {
+    let tokens = tokenize_spanned(json, NumberMode::Native).map_err(JsonError::JsonLexError)?;
+    let mut deserializer = Deserializer {
+        tokens: tokens.into_iter(),
+        peeked: None,
+        depth: 0,
+        max_depth: Limits::default().max_depth,
+    };
+    let value = T::deserialize(&mut deserializer)?;
+    match deserializer.next() {
+        Some(token) => Err(JsonParseError::UnexpectedToken(token).into()),
+        None => Ok(value),
+    }
+}
+
+pub struct Deserializer {
+    tokens: std::vec::IntoIter<SpannedToken>,
+    peeked: Option<Token>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Deserializer {
+    fn peek(&mut self) -> Option<&Token> {
+        if self.peeked.is_none() {
+            self.peeked = self.tokens.next().map(|spanned| spanned.token);
+        }
+        self.peeked.as_ref()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.peek();
+        self.peeked.take()
+    }
+
+    fn next_or_eof(&mut self) -> Result<Token, JsonError> {
+        self.next().ok_or(JsonError::JsonParseError(
+            JsonParseError::UnexpectedEndOfInput,
+        ))
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), JsonError> {
+        match self.next_or_eof()? {
+            token if token == expected => Ok(()),
+            token => Err(JsonParseError::UnexpectedToken(token).into()),
+        }
+    }
+
+    /// Runs `f` one container deeper. The opening token has already been consumed, the closing
+    /// one is consumed here.
+    fn nested<T>(
+        &mut self,
+        close: Token,
+        f: impl FnOnce(&mut Self) -> Result<T, JsonError>,
+    ) -> Result<T, JsonError> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return Err(JsonError::LimitExceeded(Limit::Depth, self.max_depth));
+        }
+        let value = f(self)?;
+        self.expect(close)?;
+        self.depth -= 1;
+        Ok(value)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer {
+    type Error = JsonError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, JsonError> {
+        match self.next_or_eof()? {
+            Token::Constant(Constant::Null) => visitor.visit_unit(),
+            Token::Constant(Constant::Boolean(x)) => visitor.visit_bool(x),
+            Token::Constant(Constant::Int(x)) => visitor.visit_i64(x),
+            Token::Constant(Constant::UInt(x)) => visitor.visit_u64(x),
+            Token::Constant(Constant::Float(x)) => visitor.visit_f64(x),
+            Token::Constant(Constant::Number(x)) => match x.as_i64() {
+                Some(i) => visitor.visit_i64(i),
+                None => visitor.visit_f64(x.as_f64()),
+            },
+            Token::Constant(Constant::StringLiteral(x)) => visitor.visit_string(x),
+            Token::StartOfList => {
+                self.nested(Token::EndOfList, |de| visitor.visit_seq(Elements::new(de)))
+            }
+            Token::StartOfDict => {
+                self.nested(Token::EndOfDict, |de| visitor.visit_map(Members::new(de)))
+            }
+            token => Err(JsonParseError::UnexpectedToken(token).into()),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, JsonError> {
+        match self.peek() {
+            Some(Token::Constant(Constant::Null)) => {
+                self.next();
+                visitor.visit_none()
+            }
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, JsonError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Externally tagged like serde_json: `"Variant"` or `{"Variant": content}`.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, JsonError> {
+        match self.next_or_eof()? {
+            Token::Constant(Constant::StringLiteral(variant)) => {
+                visitor.visit_enum(variant.into_deserializer())
+            }
+            Token::StartOfDict => {
+                self.nested(Token::EndOfDict, |de| visitor.visit_enum(Variant { de }))
+            }
+            token => Err(JsonParseError::UnexpectedToken(token).into()),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Elements of an array, trailing commas are accepted like in `parser::parse`.
+struct Elements<'a> {
+    de: &'a mut Deserializer,
+    first: bool,
+}
+
+impl<'a> Elements<'a> {
+    fn new(de: &'a mut Deserializer) -> Self {
+        Self { de, first: true }
+    }
+}
+
+impl<'de> SeqAccess<'de> for Elements<'_> {
+    type Error = JsonError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, JsonError> {
+        if !self.first && self.de.peek() != Some(&Token::EndOfList) {
+            self.de.expect(Token::Comma)?;
+        }
+        self.first = false;
+        if self.de.peek() == Some(&Token::EndOfList) {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+}
+
+struct Members<'a> {
+    de: &'a mut Deserializer,
+    first: bool,
+}
+
+impl<'a> Members<'a> {
+    fn new(de: &'a mut Deserializer) -> Self {
+        Self { de, first: true }
+    }
+}
+
+impl<'de> MapAccess<'de> for Members<'_> {
+    type Error = JsonError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, JsonError> {
+        if !self.first && self.de.peek() != Some(&Token::EndOfDict) {
+            self.de.expect(Token::Comma)?;
+        }
+        self.first = false;
+        match self.de.peek() {
+            Some(Token::EndOfDict) => Ok(None),
+            Some(Token::Constant(Constant::StringLiteral(_))) => {
+                let Ok(Token::Constant(Constant::StringLiteral(key))) = self.de.next_or_eof()
+                else {
+                    unreachable!("just peeked a string");
+                };
+                seed.deserialize(MapKey(key)).map(Some)
+            }
+            Some(_) => Err(JsonParseError::UnexpectedToken(self.de.next_or_eof()?).into()),
+            None => Err(JsonParseError::UnexpectedEndOfInput.into()),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, JsonError> {
+        self.de.expect(Token::Colon)?;
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+/// Object keys are strings, but like in serde_json they may be read as integers, so that e.g.
+/// a `BTreeMap<u8, _>` written by `ser::to_string` reads back.
+struct MapKey(String);
+
+macro_rules! integer_key {
+    ($($method:ident => $visit:ident: $t:ty),*) => {
+        $(fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, JsonError> {
+            match self.0.parse::<$t>() {
+                Ok(x) => visitor.$visit(x),
+                Err(_) => Err(de::Error::invalid_type(de::Unexpected::Str(&self.0), &visitor)),
+            }
+        })*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for MapKey {
+    type Error = JsonError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, JsonError> {
+        visitor.visit_string(self.0)
+    }
+
+    integer_key!(
+        deserialize_i8 => visit_i8: i8, deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32, deserialize_i64 => visit_i64: i64,
+        deserialize_i128 => visit_i128: i128, deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16, deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64, deserialize_u128 => visit_u128: u128
+    );
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, JsonError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, JsonError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, JsonError> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        bool f32 f64 char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
+
+/// The single member of an object holding an enum variant with content.
+struct Variant<'a> {
+    de: &'a mut Deserializer,
+}
+
+impl<'de, 'a> EnumAccess<'de> for Variant<'a> {
+    type Error = JsonError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), JsonError> {
+        let variant = seed.deserialize(&mut *self.de)?;
+        self.de.expect(Token::Colon)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Variant<'_> {
+    type Error = JsonError;
+
+    fn unit_variant(self) -> Result<(), JsonError> {
+        de::Deserialize::deserialize(self.de)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, JsonError> {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, JsonError> {
+        de::Deserializer::deserialize_seq(self.de, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, JsonError> {
+        de::Deserializer::deserialize_map(self.de, visitor)
+    }
+}
+
+struct JsonVisitor;
+
+impl<'de> Visitor<'de> for JsonVisitor {
+    type Value = Json;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_bool<E>(self, x: bool) -> Result<Json, E> {
+        Ok(Json::from(x))
+    }
+
+    fn visit_i64<E>(self, x: i64) -> Result<Json, E> {
+        Ok(Json::from(x))
+    }
+
+    fn visit_u64<E>(self, x: u64) -> Result<Json, E> {
+        Ok(Json::from(x))
+    }
+
+    fn visit_f64<E>(self, x: f64) -> Result<Json, E> {
+        Ok(Json::from(x))
+    }
+
+    fn visit_str<E>(self, x: &str) -> Result<Json, E> {
+        Ok(Json::from(x))
+    }
+
+    fn visit_string<E>(self, x: String) -> Result<Json, E> {
+        Ok(Json::from(x))
+    }
+
+    fn visit_unit<E>(self) -> Result<Json, E> {
+        Ok(Json::Value(Constant::Null))
+    }
+
+    fn visit_none<E>(self) -> Result<Json, E> {
+        Ok(Json::Value(Constant::Null))
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Json, D::Error> {
+        de::Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Json, A::Error> {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(Json::List(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Json, A::Error> {
+        let mut values = HashMap::new();
+        while let Some((key, value)) = map.next_entry()? {
+            values.insert(key, value);
+        }
+        Ok(Json::Dict(values))
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Json {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(JsonVisitor)
+    }
+}
+
+/// Accepts the scalars only, arrays and objects are an error.
+impl<'de> de::Deserialize<'de> for Constant {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match deserializer.deserialize_any(JsonVisitor)? {
+            Json::Value(constant) => Ok(constant),
+            Json::List(_) => Err(de::Error::invalid_type(
+                de::Unexpected::Seq,
+                &"a JSON scalar",
+            )),
+            Json::Dict(_) => Err(de::Error::invalid_type(
+                de::Unexpected::Map,
+                &"a JSON scalar",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect { w: u32, h: u32 },
+        Line(i8, i8),
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Event {
+        id: u64,
+        name: String,
+        tags: Vec<String>,
+        parent: Option<Box<Event>>,
+        shapes: Vec<Shape>,
+        extra: BTreeMap<String, Json>,
+        #[serde(default)]
+        missing: bool,
+    }
+
+    #[test]
+    fn test_from_str() {
+        let json = r#"{
+            "id": 18446744073709551615, "name": "a\"b", "tags": ["x", "y",],
+            "parent": {"id": 1, "name": "", "tags": [], "parent": null, "shapes": [], "extra": {}},
+            "shapes": ["Point", {"Circle": 1.5}, {"Rect": {"w": 1, "h": 2}}, {"Line": [-1, 1]}],
+            "extra": {"k": [1, {"n": null}]},
+            "ignored": {"deep": [true]}
+        }"#;
+        let event: Event = from_str(json).expect("should not error");
+        assert_eq!(event.id, u64::MAX);
+        assert_eq!(event.name, "a\"b");
+        assert_eq!(event.tags, vec!["x", "y"]);
+        assert_eq!(event.parent.unwrap().id, 1);
+        assert_eq!(
+            event.shapes,
+            vec![
+                Shape::Point,
+                Shape::Circle(1.5),
+                Shape::Rect { w: 1, h: 2 },
+                Shape::Line(-1, 1)
+            ]
+        );
+        assert_eq!(event.extra["k"], json!([1, {"n": null}]));
+        assert!(!event.missing);
+
+        assert_eq!(from_str::<i32>(" -5 ").unwrap(), -5);
+        assert_eq!(from_str::<Option<String>>("null").unwrap(), None);
+    }
+
+    #[test]
+    fn test_from_str_errors() {
+        assert!(matches!(
+            from_str::<Vec<u8>>("[1, 2"),
+            Err(JsonError::JsonParseError(
+                JsonParseError::UnexpectedEndOfInput
+            ))
+        ));
+        assert!(matches!(
+            from_str::<Vec<u8>>("[1 2]"),
+            Err(JsonError::JsonParseError(JsonParseError::UnexpectedToken(
+                _
+            )))
+        ));
+        assert!(matches!(
+            from_str::<Vec<u8>>("[1] 2"),
+            Err(JsonError::JsonParseError(JsonParseError::UnexpectedToken(
+                _
+            )))
+        ));
+        assert!(matches!(
+            from_str::<Vec<u8>>("[256]"),
+            Err(JsonError::Serde(_))
+        ));
+        let deep = format!("{}{}", "[".repeat(1000), "]".repeat(1000));
+        assert!(matches!(
+            from_str::<Json>(&deep),
+            Err(JsonError::LimitExceeded(Limit::Depth, 128))
+        ));
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let json = r#"{"a": [1, -2, 2.5, "s", null, true], "b": {"c": 18446744073709551615}}"#;
+        assert_eq!(from_str::<Json>(json).unwrap(), parse(json).unwrap());
+        assert_eq!(from_str::<Constant>("1.5").unwrap(), Constant::Float(1.5));
+        assert!(from_str::<Constant>("[]").is_err());
+    }
+}
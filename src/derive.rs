@@ -0,0 +1,329 @@
+//! `ToJson` and `FromJson`, the traits behind `#[derive(ToJson, FromJson)]` of the
+//! `rusty_json_derive` crate (enabled with the `derive` feature).
+use crate::convert::{mismatch, ConversionError};
+use crate::lexer::Constant;
+use crate::parser::Json;
+use std::collections::{BTreeMap, HashMap};
+
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+/// A type whose JSON is always an object. `#[json(flatten)]` fields and the newtype variants of
+/// internally tagged enums need this, so that their members can be merged into the parent.
+pub trait ToJsonObject: ToJson {
+    fn to_json_object(&self) -> HashMap<String, Json>;
+}
+
+pub trait FromJson: Sized {
+    fn from_json(json: &Json) -> Result<Self, ConversionError>;
+
+    /// The value of a missing object member, if there is one. `Option` uses this so that absent
+    /// members read as `None`.
+    #[doc(hidden)]
+    fn from_missing() -> Option<Self> {
+        None
+    }
+
+    /// Reads a flattened field out of the members its parent object has left, removing the ones
+    /// it uses. Derived structs take their own members, anything else (maps) takes all of them.
+    #[doc(hidden)]
+    fn from_flattened(rest: &mut HashMap<String, Json>) -> Result<Self, ConversionError> {
+        Self::from_json(&Json::Dict(std::mem::take(rest)))
+    }
+}
+
+macro_rules! via_conversions {
+    ($($t:ty),*) => {
+        $(impl ToJson for $t {
+            fn to_json(&self) -> Json {
+                Json::from(self.clone())
+            }
+        }
+
+        impl FromJson for $t {
+            fn from_json(json: &Json) -> Result<Self, ConversionError> {
+                Self::try_from(json)
+            }
+        })*
+    };
+}
+
+via_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64, bool, String);
+
+impl ToJson for str {
+    fn to_json(&self) -> Json {
+        Json::from(self)
+    }
+}
+
+impl ToJson for Json {
+    fn to_json(&self) -> Json {
+        self.clone()
+    }
+}
+
+impl FromJson for Json {
+    fn from_json(json: &Json) -> Result<Self, ConversionError> {
+        Ok(json.clone())
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn to_json(&self) -> Json {
+        (**self).to_json()
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for Box<T> {
+    fn to_json(&self) -> Json {
+        (**self).to_json()
+    }
+}
+
+impl<T: ToJsonObject + ?Sized> ToJsonObject for &T {
+    fn to_json_object(&self) -> HashMap<String, Json> {
+        (**self).to_json_object()
+    }
+}
+
+impl<T: ToJsonObject + ?Sized> ToJsonObject for Box<T> {
+    fn to_json_object(&self) -> HashMap<String, Json> {
+        (**self).to_json_object()
+    }
+}
+
+impl<T: FromJson> FromJson for Box<T> {
+    fn from_json(json: &Json) -> Result<Self, ConversionError> {
+        T::from_json(json).map(Box::new)
+    }
+
+    fn from_flattened(rest: &mut HashMap<String, Json>) -> Result<Self, ConversionError> {
+        T::from_flattened(rest).map(Box::new)
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        match self {
+            Some(x) => x.to_json(),
+            None => Json::Value(Constant::Null),
+        }
+    }
+}
+
+/// `None` has no members, so a flattened `None` adds nothing.
+impl<T: ToJsonObject> ToJsonObject for Option<T> {
+    fn to_json_object(&self) -> HashMap<String, Json> {
+        self.as_ref()
+            .map(ToJsonObject::to_json_object)
+            .unwrap_or_default()
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(json: &Json) -> Result<Self, ConversionError> {
+        match json {
+            Json::Value(Constant::Null) => Ok(None),
+            _ => T::from_json(json).map(Some),
+        }
+    }
+
+    fn from_missing() -> Option<Self> {
+        Some(None)
+    }
+
+    /// `None` when the members don't make up a `T`, which then leaves them to the other fields.
+    fn from_flattened(rest: &mut HashMap<String, Json>) -> Result<Self, ConversionError> {
+        let mut members = rest.clone();
+        match T::from_flattened(&mut members) {
+            Ok(x) => {
+                *rest = members;
+                Ok(Some(x))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> Json {
+        Json::List(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        self.as_slice().to_json()
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(json: &Json) -> Result<Self, ConversionError> {
+        match json {
+            Json::List(x) => x
+                .iter()
+                .enumerate()
+                .map(|(i, value)| T::from_json(value).map_err(|e| e.within(&i.to_string())))
+                .collect(),
+            _ => Err(mismatch(json, "array")),
+        }
+    }
+}
+
+macro_rules! maps {
+    ($($map:ident),*) => {
+        $(impl<T: ToJson> ToJson for $map<String, T> {
+            fn to_json(&self) -> Json {
+                Json::Dict(self.to_json_object())
+            }
+        }
+
+        impl<T: ToJson> ToJsonObject for $map<String, T> {
+            fn to_json_object(&self) -> HashMap<String, Json> {
+                self.iter().map(|(k, v)| (k.clone(), v.to_json())).collect()
+            }
+        }
+
+        impl<T: FromJson> FromJson for $map<String, T> {
+            fn from_json(json: &Json) -> Result<Self, ConversionError> {
+                match json {
+                    Json::Dict(x) => x
+                        .iter()
+                        .map(|(k, v)| Ok((k.clone(), T::from_json(v).map_err(|e| e.within(k))?)))
+                        .collect(),
+                    _ => Err(mismatch(json, "object")),
+                }
+            }
+        })*
+    };
+}
+
+maps!(HashMap, BTreeMap);
+
+/// Helpers for the code generated by `rusty_json_derive`, not a stable API.
+#[doc(hidden)]
+pub mod __private {
+    use super::*;
+    use crate::pointer::escape_pointer_token;
+
+    pub type Object = HashMap<String, Json>;
+
+    pub fn object(json: &Json) -> Result<&Object, ConversionError> {
+        match json {
+            Json::Dict(x) => Ok(x),
+            _ => Err(mismatch(json, "object")),
+        }
+    }
+
+    pub fn array(json: &Json, len: usize) -> Result<&[Json], ConversionError> {
+        match json {
+            Json::List(x) if x.len() == len => Ok(x),
+            Json::List(x) => Err(ConversionError::InvalidValue(
+                String::new(),
+                format!("expected {} elements, found {}", len, x.len()),
+            )),
+            _ => Err(mismatch(json, "array")),
+        }
+    }
+
+    pub fn mismatch(json: &Json, expected: &'static str) -> ConversionError {
+        crate::convert::mismatch(json, expected)
+    }
+
+    pub fn null(json: &Json) -> Result<(), ConversionError> {
+        match json {
+            Json::Value(Constant::Null) => Ok(()),
+            _ => Err(mismatch(json, "null")),
+        }
+    }
+
+    fn missing(key: &str) -> ConversionError {
+        ConversionError::MissingMember(format!("/{}", escape_pointer_token(key)))
+    }
+
+    pub fn member<'a>(object: &'a Object, key: &str) -> Result<&'a Json, ConversionError> {
+        object.get(key).ok_or_else(|| missing(key))
+    }
+
+    /// Removes the members at `keys`, returning them.
+    pub fn take(object: &mut Object, keys: &[&str]) -> Object {
+        keys.iter()
+            .filter_map(|key| object.remove_entry(*key))
+            .collect()
+    }
+
+    pub fn field<T: FromJson>(object: &Object, key: &str) -> Result<T, ConversionError> {
+        match object.get(key) {
+            Some(value) => T::from_json(value).map_err(|e| e.within(key)),
+            None => T::from_missing().ok_or_else(|| missing(key)),
+        }
+    }
+
+    pub fn field_or<T: FromJson>(
+        object: &Object,
+        key: &str,
+        default: impl FnOnce() -> T,
+    ) -> Result<T, ConversionError> {
+        match object.get(key) {
+            Some(value) => T::from_json(value).map_err(|e| e.within(key)),
+            None => Ok(default()),
+        }
+    }
+
+    pub fn element<T: FromJson>(items: &[Json], i: usize) -> Result<T, ConversionError> {
+        T::from_json(&items[i]).map_err(|e| e.within(&i.to_string()))
+    }
+
+    pub fn tag<'a>(object: &'a Object, key: &str) -> Result<&'a str, ConversionError> {
+        match member(object, key)? {
+            Json::Value(Constant::StringLiteral(x)) => Ok(x),
+            other => Err(mismatch(other, "string").within(key)),
+        }
+    }
+
+    pub fn unknown_variant(name: &str, variants: &[&str]) -> ConversionError {
+        ConversionError::InvalidValue(
+            String::new(),
+            format!("unknown variant {:?}, expected one of {:?}", name, variants),
+        )
+    }
+
+    pub fn no_variant_matched(name: &str) -> ConversionError {
+        ConversionError::InvalidValue(
+            String::new(),
+            format!("data did not match any variant of {}", name),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_std_impls() {
+        let mut map = BTreeMap::new();
+        map.insert("a".to_string(), vec![Some(1u8), None]);
+        let json = map.to_json();
+        assert_eq!(json, json!({"a": [1, null]}));
+        assert_eq!(
+            BTreeMap::<String, Vec<Option<u8>>>::from_json(&json),
+            Ok(map)
+        );
+        assert_eq!(
+            Vec::<Box<String>>::from_json(&json!(["x", 1])),
+            Err(ConversionError::TypeMismatch(
+                "/1".to_string(),
+                "string",
+                "number"
+            ))
+        );
+        assert_eq!(Option::<u8>::from_missing(), Some(None));
+        assert_eq!(u8::from_missing(), None);
+        assert_eq!("s".to_json(), json!("s"));
+        assert!(Option::<HashMap<String, u8>>::None
+            .to_json_object()
+            .is_empty());
+    }
+}
@@ -0,0 +1,361 @@
+//! Structural diff of two documents as a JSON Patch.
+use crate::parser::Json;
+use crate::patch::{equal, Patch, PatchOperation};
+use crate::pointer::escape_pointer_token;
+
+/// A patch that turns `from` into `to`. Object members are compared by key, arrays with a
+/// longest common subsequence, so that insertions, removals and moves of elements show up as
+/// such instead of replacing everything after them. Arrays too large for that are compared by
+/// position once their common prefix and suffix are cut off.
+pub fn diff(from: &Json, to: &Json) -> Patch {
+    let mut operations = Vec::new();
+    diff_into(from, to, "", &mut operations);
+    Patch(operations)
+}
+
+fn diff_into(from: &Json, to: &Json, path: &str, operations: &mut Vec<PatchOperation>) {
+    match (from, to) {
+        (Json::Dict(a), Json::Dict(b)) => {
+            // sorted, so that the patch doesn't depend on the hash map order
+            let mut removed = a.keys().filter(|k| !b.contains_key(*k)).collect::<Vec<_>>();
+            removed.sort();
+            for key in removed {
+                operations.push(PatchOperation::Remove {
+                    path: member(path, key),
+                });
+            }
+            let mut keys = b.keys().collect::<Vec<_>>();
+            keys.sort();
+            for key in keys {
+                match a.get(key) {
+                    Some(old) => diff_into(old, &b[key], &member(path, key), operations),
+                    None => operations.push(PatchOperation::Add {
+                        path: member(path, key),
+                        value: b[key].clone(),
+                    }),
+                }
+            }
+        }
+        (Json::List(a), Json::List(b)) => diff_lists(a, b, path, operations),
+        _ if equal(from, to) => {}
+        _ => operations.push(PatchOperation::Replace {
+            path: path.to_string(),
+            value: to.clone(),
+        }),
+    }
+}
+
+fn member(path: &str, key: &str) -> String {
+    format!("{}/{}", path, escape_pointer_token(key))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Keep,
+    Delete(usize),
+    Insert(usize),
+}
+
+/// The most cells of the LCS table, and of the deletions times insertions checked for moves.
+/// Beyond that, the quadratic time and memory aren't worth the smaller patch.
+const MAX_CELLS: usize = 1 << 22;
+
+/// The edits turning `a` into `b` along a longest common subsequence. Within a gap, deletions
+/// come before insertions.
+fn edit_script(a: &[Json], b: &[Json]) -> Vec<Edit> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| equal(x, y)).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| equal(x, y))
+        .count();
+    let (n, m) = (a.len() - prefix - suffix, b.len() - prefix - suffix);
+    let mut script = vec![Edit::Keep; prefix];
+    if n.saturating_mul(m) > MAX_CELLS {
+        // pair the elements up by position, `steps` turns each pair into a change
+        for k in 0..n.max(m) {
+            if k < n {
+                script.push(Edit::Delete(prefix + k));
+            }
+            if k < m {
+                script.push(Edit::Insert(prefix + k));
+            }
+        }
+        script.extend(vec![Edit::Keep; suffix]);
+        return script;
+    }
+    // lengths[i * (m + 1) + j] is the length of the LCS of a[prefix + i..] and b[prefix + j..]
+    let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i * (m + 1) + j] = if equal(&a[prefix + i], &b[prefix + j]) {
+                lengths[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                lengths[(i + 1) * (m + 1) + j].max(lengths[i * (m + 1) + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && equal(&a[prefix + i], &b[prefix + j]) {
+            script.push(Edit::Keep);
+            i += 1;
+            j += 1;
+        } else if i < n
+            && (j == m || lengths[(i + 1) * (m + 1) + j] >= lengths[i * (m + 1) + j + 1])
+        {
+            script.push(Edit::Delete(prefix + i));
+            i += 1;
+        } else {
+            script.push(Edit::Insert(prefix + j));
+            j += 1;
+        }
+    }
+    script.extend(vec![Edit::Keep; suffix]);
+    script
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Edit(Edit),
+    /// An element that is moved, at the position of its deletion.
+    MoveFrom(usize),
+    /// A moved element, at the position of its insertion.
+    MoveTo(usize),
+    /// A deletion directly followed by an insertion, turned into a diff of the two elements.
+    Change(usize, usize),
+}
+
+fn steps(a: &[Json], b: &[Json]) -> Vec<Step> {
+    let script = edit_script(a, b);
+    let mut steps = script
+        .iter()
+        .map(|&edit| Step::Edit(edit))
+        .collect::<Vec<_>>();
+    // an insertion of a value that is deleted elsewhere is a move
+    let mut deletions = script
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| matches!(edit, Edit::Delete(_)))
+        .map(|(position, _)| position)
+        .collect::<Vec<_>>();
+    let insertions = script
+        .iter()
+        .filter(|edit| matches!(edit, Edit::Insert(_)))
+        .count();
+    if deletions.len().saturating_mul(insertions) <= MAX_CELLS {
+        for (position, edit) in script.iter().enumerate() {
+            let Edit::Insert(j) = *edit else { continue };
+            let found = deletions.iter().position(
+                |&deletion| matches!(script[deletion], Edit::Delete(i) if equal(&a[i], &b[j])),
+            );
+            if let Some(found) = found {
+                let deletion = deletions.remove(found);
+                let Edit::Delete(i) = script[deletion] else {
+                    unreachable!()
+                };
+                steps[deletion] = Step::MoveFrom(i);
+                steps[position] = Step::MoveTo(i);
+            }
+        }
+    }
+    let mut merged = Vec::with_capacity(steps.len());
+    let mut steps = steps.into_iter().peekable();
+    while let Some(step) = steps.next() {
+        match (step, steps.peek()) {
+            (Step::Edit(Edit::Delete(i)), Some(Step::Edit(Edit::Insert(j)))) => {
+                merged.push(Step::Change(i, *j));
+                steps.next();
+            }
+            _ => merged.push(step),
+        }
+    }
+    merged
+}
+
+/// A Fenwick tree of the steps that hold an element of the array, counting those before a step
+/// in O(log n).
+struct Occupied(Vec<isize>);
+
+impl Occupied {
+    fn new(steps: usize) -> Occupied {
+        Occupied(vec![0; steps + 1])
+    }
+
+    fn add(&mut self, step: usize, count: isize) {
+        let mut i = step + 1;
+        while i < self.0.len() {
+            self.0[i] += count;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// The index in the array of an element at `step`.
+    fn before(&self, step: usize) -> usize {
+        let (mut i, mut count) = (step, 0);
+        while i > 0 {
+            count += self.0[i];
+            i &= i - 1;
+        }
+        count as usize
+    }
+}
+
+fn diff_lists(a: &[Json], b: &[Json], path: &str, operations: &mut Vec<PatchOperation>) {
+    let at = |k: usize| format!("{}/{}", path, k);
+    let steps = steps(a, b);
+    // the array stays in the order of the steps: elements of `a` at the step that takes them,
+    // elements of `b` at the step that puts them in place
+    let mut occupied = Occupied::new(steps.len());
+    let mut moved_from = vec![0; a.len()];
+    for (position, step) in steps.iter().enumerate() {
+        match *step {
+            Step::Edit(Edit::Insert(_)) | Step::MoveTo(_) => continue,
+            Step::MoveFrom(i) => moved_from[i] = position,
+            _ => {}
+        }
+        occupied.add(position, 1);
+    }
+    for (position, step) in steps.into_iter().enumerate() {
+        let k = occupied.before(position);
+        match step {
+            Step::Edit(Edit::Keep) | Step::MoveFrom(_) => {}
+            Step::Edit(Edit::Delete(_)) => {
+                operations.push(PatchOperation::Remove { path: at(k) });
+                occupied.add(position, -1);
+            }
+            Step::Edit(Edit::Insert(j)) => {
+                operations.push(PatchOperation::Add {
+                    path: at(k),
+                    value: b[j].clone(),
+                });
+                occupied.add(position, 1);
+            }
+            Step::Change(i, j) => diff_into(&a[i], &b[j], &at(k), operations),
+            Step::MoveTo(i) => {
+                let from = occupied.before(moved_from[i]);
+                occupied.add(moved_from[i], -1);
+                // removing an element before `k` shifts the target
+                let to = occupied.before(position);
+                if from != to {
+                    operations.push(PatchOperation::Move {
+                        from: at(from),
+                        path: at(to),
+                    });
+                }
+                occupied.add(position, 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roundtrip(from: Json, to: Json) -> Patch {
+        let patch = diff(&from, &to);
+        let mut patched = from;
+        patch.apply(&mut patched).unwrap();
+        assert!(equal(&patched, &to), "{:?} != {:?}", patched, to);
+        patch
+    }
+
+    #[test]
+    fn test_minimal_patches() {
+        let patch = roundtrip(
+            json!({"a": 1, "b": [1, 2, 3], "c": {"d": true}, "x/y": 0}),
+            json!({"a": 1.0, "b": [1, 3, 4], "c": {"d": false}, "e": null}),
+        );
+        assert_eq!(
+            Json::from(&patch),
+            json!([
+                {"op": "remove", "path": "/x~1y"},
+                {"op": "remove", "path": "/b/1"},
+                {"op": "add", "path": "/b/2", "value": 4},
+                {"op": "replace", "path": "/c/d", "value": false},
+                {"op": "add", "path": "/e", "value": null},
+            ])
+        );
+        let moves = [
+            (
+                json!([1, 2, 3, 4]),
+                json!([4, 1, 2, 3]),
+                json!({"from": "/3", "path": "/0"}),
+            ),
+            (
+                json!([4, 1, 2, 3]),
+                json!([1, 2, 3, 4]),
+                json!({"from": "/0", "path": "/3"}),
+            ),
+        ];
+        for (from, to, mut expected) in moves {
+            if let Json::Dict(x) = &mut expected {
+                x.insert("op".to_string(), json!("move"));
+            }
+            assert_eq!(Json::from(&roundtrip(from, to)), json!([expected]));
+        }
+        let patch = roundtrip(json!([{"id": 1, "v": "a"}]), json!([{"id": 1, "v": "b"}]));
+        assert_eq!(
+            Json::from(&patch),
+            json!([{"op": "replace", "path": "/0/v", "value": "b"}])
+        );
+        assert_eq!(roundtrip(json!([1, 2]), json!({"a": 1})).0.len(), 1);
+        assert_eq!(
+            diff(&json!({"a": [1]}), &json!({"a": [1]})),
+            Patch::default()
+        );
+    }
+
+    #[test]
+    fn test_roundtrips() {
+        let lists = [
+            json!([]),
+            json!([1, 2, 3, 4, 5]),
+            json!([5, 4, 3, 2, 1]),
+            json!([3, 1, 4, 1, 5, 9, 2, 6]),
+            json!([1, [2, 3], {"a": 4}, "5", null]),
+            json!([{"a": 4}, 1, 1, [2, 3], true]),
+            json!([2, 7, 1, 8, 2, 8]),
+        ];
+        for from in &lists {
+            for to in &lists {
+                roundtrip(from.clone(), to.clone());
+                roundtrip(
+                    json!({"x": from.clone(), "y": 1}),
+                    json!({"x": to.clone(), "z": [to.clone()]}),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_large_arrays() {
+        // too large for an LCS table, so the elements are compared by position
+        let from = Json::List((0..20_000).map(Json::from).collect());
+        let to = Json::List((0..20_001).map(|x| Json::from(x * 2)).collect());
+        let patch = roundtrip(from, to);
+        assert_eq!(patch.0.len(), 20_000);
+        assert!(matches!(
+            &patch.0[0],
+            PatchOperation::Replace { path, .. } if path == "/1"
+        ));
+        // small enough for the table, so the rotation is a single move
+        let from = Json::List((0..2000).map(Json::from).collect());
+        let mut to = from.clone();
+        if let Json::List(x) = &mut to {
+            x.rotate_left(1);
+        }
+        assert_eq!(roundtrip(from, to).0.len(), 1);
+        // every removal is at the front. Only diffed, as applying each removal shifts the rest
+        // of the array.
+        let from = Json::List((0..200_000).map(Json::from).collect());
+        let patch = diff(&from, &json!([]));
+        assert_eq!(patch.0.len(), 200_000);
+        assert!(patch
+            .0
+            .iter()
+            .all(|operation| matches!(operation, PatchOperation::Remove { path } if path == "/0")));
+    }
+}
@@ -0,0 +1,237 @@
+//! Shortest round-trip formatting of `f64`.
+//!
+//! The digits come from the standard library's `{:e}` formatting, which already produces the
+//! shortest digit string that parses back to the same bits. This module only decides how to lay
+//! those digits out as a JSON number and what to do with values JSON can't represent.
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FloatFormatError {
+    #[error("FloatFormatError: {0} can not be written as JSON")]
+    Rejected(f64),
+}
+
+/// What to write for a float that plain JSON can't express faithfully.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialFloat {
+    /// Fail with `FloatFormatError::Rejected`.
+    Reject,
+    /// Write `null`.
+    Null,
+    /// Write the JSON5 literal: `NaN`, `Infinity`, `-Infinity` or `-0.0`.
+    Literal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloatOptions {
+    /// Policy for NaN and the infinities.
+    pub non_finite: SpecialFloat,
+    /// Policy for -0.0. Its literal `-0.0` is valid JSON as well, so it is the default.
+    pub negative_zero: SpecialFloat,
+}
+
+impl Default for FloatOptions {
+    fn default() -> Self {
+        Self {
+            non_finite: SpecialFloat::Null,
+            negative_zero: SpecialFloat::Literal,
+        }
+    }
+}
+
+/// The shortest decimal digits `d1 d2 ... dk` and exponent `n` with `|x| = 0.d1d2...dk * 10^n`.
+/// `x` has to be finite and non-zero.
+pub(crate) fn shortest_digits(x: f64) -> (String, i32) {
+    let formatted = format!("{:e}", x.abs());
+    let (mantissa, exponent) = formatted
+        .split_once('e')
+        .expect("`{:e}` always writes an exponent");
+    let exponent: i32 = exponent.parse().expect("exponent is an integer");
+    (mantissa.replace('.', ""), exponent + 1)
+}
+
+pub fn format_f64(x: f64, options: &FloatOptions) -> Result<String, FloatFormatError> {
+    let mut out = String::new();
+    write_f64(&mut out, x, options)?;
+    Ok(out)
+}
+
+pub fn write_f64(out: &mut String, x: f64, options: &FloatOptions) -> Result<(), FloatFormatError> {
+    if !x.is_finite() {
+        let literal = if x.is_nan() {
+            "NaN"
+        } else if x > 0.0 {
+            "Infinity"
+        } else {
+            "-Infinity"
+        };
+        return write_special(out, x, literal, options.non_finite);
+    }
+    if x == 0.0 {
+        if x.is_sign_negative() {
+            return write_special(out, x, "-0.0", options.negative_zero);
+        }
+        out.push_str("0.0");
+        return Ok(());
+    }
+    if x < 0.0 {
+        out.push('-');
+    }
+    let (digits, n) = shortest_digits(x);
+    let k = digits.len() as i32;
+    if k <= n && n <= 21 {
+        // integral, the `.0` keeps it a float when parsed again
+        out.push_str(&digits);
+        out.extend(std::iter::repeat_n('0', (n - k) as usize));
+        out.push_str(".0");
+    } else if 0 < n && n <= 21 {
+        out.push_str(&digits[..n as usize]);
+        out.push('.');
+        out.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        out.push_str("0.");
+        out.extend(std::iter::repeat_n('0', -n as usize));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        out.push_str(&format!(
+            "e{}{}",
+            if n > 0 { "+" } else { "-" },
+            (n - 1).abs()
+        ));
+    }
+    Ok(())
+}
+
+fn write_special(
+    out: &mut String,
+    x: f64,
+    literal: &str,
+    policy: SpecialFloat,
+) -> Result<(), FloatFormatError> {
+    match policy {
+        SpecialFloat::Reject => return Err(FloatFormatError::Rejected(x)),
+        SpecialFloat::Null => out.push_str("null"),
+        SpecialFloat::Literal => out.push_str(literal),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::Constant;
+    use std::str::FromStr;
+
+    fn format(x: f64) -> String {
+        format_f64(x, &FloatOptions::default()).expect("should not error")
+    }
+
+    fn assert_roundtrips(x: f64) {
+        let written = format(x);
+        match Constant::from_str(&written) {
+            Ok(Constant::Float(parsed)) => assert_eq!(
+                parsed.to_bits(),
+                x.to_bits(),
+                "{} was written as {}",
+                x,
+                written
+            ),
+            other => panic!("{} was written as {}, parsed as {:?}", x, written, other),
+        }
+    }
+
+    #[test]
+    fn test_shortest_output() {
+        assert_eq!(format(0.1), "0.1");
+        assert_eq!(format(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(format(1.0), "1.0");
+        assert_eq!(format(-2.5), "-2.5");
+        assert_eq!(format(100.0), "100.0");
+        assert_eq!(format(1e20), "100000000000000000000.0");
+        assert_eq!(format(1e21), "1e+21");
+        assert_eq!(format(123456.789e3), "123456789.0");
+        assert_eq!(format(0.000001), "0.000001");
+        assert_eq!(format(1e-7), "1e-7");
+        assert_eq!(format(-1.5e-10), "-1.5e-10");
+        assert_eq!(format(f64::MAX), "1.7976931348623157e+308");
+        assert_eq!(format(f64::MIN_POSITIVE), "2.2250738585072014e-308");
+        assert_eq!(format(5e-324), "5e-324");
+        assert_eq!(format(0.0), "0.0");
+    }
+
+    #[test]
+    fn test_special_values() {
+        let literal = FloatOptions {
+            non_finite: SpecialFloat::Literal,
+            negative_zero: SpecialFloat::Literal,
+        };
+        assert_eq!(format_f64(f64::NAN, &literal).unwrap(), "NaN");
+        assert_eq!(format_f64(f64::INFINITY, &literal).unwrap(), "Infinity");
+        assert_eq!(
+            format_f64(f64::NEG_INFINITY, &literal).unwrap(),
+            "-Infinity"
+        );
+        assert_eq!(format_f64(-0.0, &literal).unwrap(), "-0.0");
+
+        assert_eq!(format(f64::NAN), "null");
+        assert_eq!(format(f64::INFINITY), "null");
+        assert_roundtrips(-0.0);
+
+        let reject = FloatOptions {
+            non_finite: SpecialFloat::Reject,
+            negative_zero: SpecialFloat::Null,
+        };
+        assert!(matches!(
+            format_f64(f64::NEG_INFINITY, &reject),
+            Err(FloatFormatError::Rejected(_))
+        ));
+        assert_eq!(format_f64(-0.0, &reject).unwrap(), "null");
+    }
+
+    #[test]
+    fn test_roundtrip_edge_cases() {
+        let mut values = vec![
+            f64::MAX,
+            f64::MIN,
+            f64::MIN_POSITIVE,
+            f64::EPSILON,
+            5e-324,
+            -5e-324,
+            f64::from_bits(0x000f_ffff_ffff_ffff), // largest subnormal
+            9007199254740993.0,
+            0.1,
+            1.0 / 3.0,
+        ];
+        for exponent in -324..=308 {
+            let power = format!("1e{}", exponent).parse::<f64>().unwrap();
+            values.push(power);
+            values.push(f64::from_bits(power.to_bits() + 1));
+            values.push(f64::from_bits(power.to_bits().saturating_sub(1)));
+        }
+        for x in values {
+            assert_roundtrips(x);
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_random_bits() {
+        // xorshift, so the test doesn't need a rand dependency and stays reproducible
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut checked = 0;
+        while checked < 200_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let x = f64::from_bits(state);
+            if x.is_finite() {
+                assert_roundtrips(x);
+                checked += 1;
+            }
+        }
+    }
+}
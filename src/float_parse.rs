@@ -0,0 +1,441 @@
+//! Correctly rounded conversion of JSON number literals to `f64`.
+//!
+//! Short inputs take Clinger's fast path: if the significant digits fit into 53 bits and the
+//! power of ten is exactly representable, a single IEEE multiplication or division already
+//! rounds correctly. Everything else is computed exactly with big integers and rounded half to
+//! even, so long mantissas, subnormals and extreme exponents come out right as well.
+use crate::lexer::scan_number;
+use std::cmp::Ordering;
+
+/// Significant digits beyond these can't change the rounding, except through whether any of
+/// them is non-zero. Halfway points between two floats have at most 767 significant digits.
+const MAX_DIGITS: usize = 768;
+
+/// Powers of ten that are exactly representable as f64.
+const POWERS_OF_TEN: [f64; 23] = [
+    1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15, 1e16,
+    1e17, 1e18, 1e19, 1e20, 1e21, 1e22,
+];
+
+/// Parses a literal following the JSON number grammar, `None` for anything else. Values too
+/// large for f64 become infinite, values too small become zero.
+pub fn parse_f64(s: &str) -> Option<f64> {
+    let bytes = s.as_bytes();
+    if bytes.is_empty() || scan_number(bytes, 0) != Some(bytes.len()) {
+        return None;
+    }
+    let negative = bytes[0] == b'-';
+    let (digits, exponent) = decompose(&bytes[negative as usize..]);
+    let magnitude = to_f64(&digits, exponent);
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+/// Splits a valid, unsigned number literal into its significant digits (as values 0-9, without
+/// leading or trailing zeros, at most `MAX_DIGITS + 1` of them) and the power of ten the last
+/// digit stands for.
+fn decompose(bytes: &[u8]) -> (Vec<u8>, i64) {
+    let mut digits = Vec::new();
+    let mut exponent: i64 = 0;
+    let mut in_fraction = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'.' => in_fraction = true,
+            b'e' | b'E' => break,
+            c => {
+                if !(digits.is_empty() && c == b'0') {
+                    digits.push(c - b'0');
+                }
+                if in_fraction {
+                    exponent -= 1;
+                }
+            }
+        }
+        i += 1;
+    }
+    if i < bytes.len() {
+        let (sign, start) = match bytes.get(i + 1) {
+            Some(b'-') => (-1, i + 2),
+            Some(b'+') => (1, i + 2),
+            _ => (1, i + 1),
+        };
+        // anything beyond this is infinite or zero anyway, so saturate instead of overflowing
+        let mut explicit: i64 = 0;
+        for &c in &bytes[start..] {
+            explicit = (explicit * 10 + (c - b'0') as i64).min(1 << 32);
+        }
+        exponent += sign * explicit;
+    }
+    while digits.last() == Some(&0) {
+        digits.pop();
+        exponent += 1;
+    }
+    if digits.len() > MAX_DIGITS {
+        // the last digit isn't zero, so the dropped ones aren't all zero. A single 1 after the kept
+        // digits stands in for them: it is enough to tell the value from a halfway point.
+        exponent += (digits.len() - MAX_DIGITS - 1) as i64;
+        digits.truncate(MAX_DIGITS);
+        digits.push(1);
+    }
+    (digits, exponent)
+}
+
+fn to_f64(digits: &[u8], exponent: i64) -> f64 {
+    if digits.is_empty() {
+        return 0.0;
+    }
+    let magnitude = digits.len() as i64 + exponent; // value < 10^magnitude
+    if magnitude > 310 {
+        return f64::INFINITY;
+    }
+    if magnitude <= -324 {
+        // below half of the smallest subnormal
+        return 0.0;
+    }
+    if digits.len() <= 19 {
+        let mantissa = digits.iter().fold(0u64, |m, &d| m * 10 + d as u64);
+        if mantissa <= 1 << 53 && (-22..=22).contains(&exponent) {
+            let power = POWERS_OF_TEN[exponent.unsigned_abs() as usize];
+            return if exponent < 0 {
+                mantissa as f64 / power
+            } else {
+                mantissa as f64 * power
+            };
+        }
+    }
+    slow_path(digits, exponent)
+}
+
+/// Exact conversion: with `value = num / den`, find the 53 bit quotient `q = num * 2^k / den`
+/// and round it using the remainder.
+fn slow_path(digits: &[u8], exponent: i64) -> f64 {
+    let mut num = Big::from_digits(digits);
+    let mut den = Big::from_u64(1);
+    if exponent >= 0 {
+        num.mul_pow10(exponent as u32);
+    } else {
+        den.mul_pow10(exponent.unsigned_abs() as u32);
+    }
+    // with this k the quotient has 53 or 54 bits. Subnormals have a fixed quantum of 2^-1074,
+    // so k never needs to be larger.
+    let mut k = (53 - (num.bit_len() as i64 - den.bit_len() as i64)).min(1074);
+    let (mut q, mut half, exact) = divide(&num, &den, k);
+    if q >= 1 << 53 {
+        // drop the extra bit, it decides whether the rest is below, at or above one half
+        half = match (q & 1, exact) {
+            (0, _) => Ordering::Less,
+            (_, true) => Ordering::Equal,
+            (_, false) => Ordering::Greater,
+        };
+        q >>= 1;
+        k -= 1;
+    }
+    if half == Ordering::Greater || (half == Ordering::Equal && q & 1 == 1) {
+        q += 1;
+        if q == 1 << 53 {
+            q >>= 1;
+            k -= 1;
+        }
+    }
+    compose(q, k)
+}
+
+/// `q * 2^-k` as f64, where `q < 2^53` and `q >= 2^52` unless `k == 1074`.
+fn compose(q: u64, k: i64) -> f64 {
+    if q < 1 << 52 {
+        return f64::from_bits(q); // subnormal
+    }
+    let biased_exponent = 52 - k + 1023;
+    if biased_exponent >= 0x7ff {
+        return f64::INFINITY;
+    }
+    f64::from_bits(((biased_exponent as u64) << 52) | (q - (1 << 52)))
+}
+
+/// `floor(num * 2^k / den)` together with how twice the remainder compares to `den` and whether
+/// the remainder is zero. The quotient has to be smaller than 2^55.
+fn divide(num: &Big, den: &Big, k: i64) -> (u64, Ordering, bool) {
+    let mut remainder = num.clone();
+    let mut divisor = den.clone();
+    if k >= 0 {
+        remainder.shl(k as usize);
+    } else {
+        divisor.shl(k.unsigned_abs() as usize);
+    }
+    let mut shifted = divisor.clone();
+    shifted.shl(54);
+    let mut q = 0u64;
+    for bit in (0..=54).rev() {
+        if remainder.cmp(&shifted) != Ordering::Less {
+            remainder.sub_assign(&shifted);
+            q |= 1 << bit;
+        }
+        shifted.shr1();
+    }
+    let exact = remainder.limbs.is_empty();
+    remainder.shl(1);
+    (q, remainder.cmp(&divisor), exact)
+}
+
+/// Just enough of an unsigned big integer for `slow_path`. Little endian, without leading zero
+/// limbs.
+#[derive(Debug, Clone, PartialEq)]
+struct Big {
+    limbs: Vec<u32>,
+}
+
+impl Big {
+    fn from_u64(x: u64) -> Big {
+        let mut big = Big {
+            limbs: vec![x as u32, (x >> 32) as u32],
+        };
+        big.trim();
+        big
+    }
+
+    fn from_digits(digits: &[u8]) -> Big {
+        let mut big = Big::from_u64(0);
+        for chunk in digits.chunks(9) {
+            big.mul_small(10u32.pow(chunk.len() as u32));
+            big.add_small(chunk.iter().fold(0, |acc, &d| acc * 10 + d as u32));
+        }
+        big
+    }
+
+    fn trim(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+
+    fn mul_small(&mut self, factor: u32) {
+        let mut carry = 0u64;
+        for limb in self.limbs.iter_mut() {
+            let product = *limb as u64 * factor as u64 + carry;
+            *limb = product as u32;
+            carry = product >> 32;
+        }
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+        self.trim();
+    }
+
+    fn add_small(&mut self, summand: u32) {
+        let mut carry = summand as u64;
+        for limb in self.limbs.iter_mut() {
+            if carry == 0 {
+                return;
+            }
+            let sum = *limb as u64 + carry;
+            *limb = sum as u32;
+            carry = sum >> 32;
+        }
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+    }
+
+    fn mul_pow10(&mut self, mut exponent: u32) {
+        while exponent >= 9 {
+            self.mul_small(1_000_000_000);
+            exponent -= 9;
+        }
+        self.mul_small(10u32.pow(exponent));
+    }
+
+    fn bit_len(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    fn shl(&mut self, bits: usize) {
+        if self.limbs.is_empty() {
+            return;
+        }
+        let (words, bits) = (bits / 32, bits % 32);
+        if bits > 0 {
+            let mut carry = 0;
+            for limb in self.limbs.iter_mut() {
+                let next_carry = *limb >> (32 - bits);
+                *limb = (*limb << bits) | carry;
+                carry = next_carry;
+            }
+            if carry > 0 {
+                self.limbs.push(carry);
+            }
+        }
+        self.limbs.splice(0..0, std::iter::repeat_n(0, words));
+    }
+
+    fn shr1(&mut self) {
+        let mut carry = 0;
+        for limb in self.limbs.iter_mut().rev() {
+            let next_carry = *limb << 31;
+            *limb = (*limb >> 1) | carry;
+            carry = next_carry;
+        }
+        self.trim();
+    }
+
+    fn cmp(&self, other: &Big) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+
+    /// `self -= other`, requires `self >= other`.
+    fn sub_assign(&mut self, other: &Big) {
+        let mut borrow = 0i64;
+        for (i, limb) in self.limbs.iter_mut().enumerate() {
+            let difference = *limb as i64 - *other.limbs.get(i).unwrap_or(&0) as i64 - borrow;
+            *limb = difference.rem_euclid(1 << 32) as u32;
+            borrow = (difference < 0) as i64;
+        }
+        self.trim();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_matches_std(s: &str) {
+        let expected: f64 = s.parse().unwrap();
+        let parsed = parse_f64(s).unwrap_or_else(|| panic!("{} should parse", s));
+        assert_eq!(
+            parsed.to_bits(),
+            expected.to_bits(),
+            "{} parsed as {:e} instead of {:e}",
+            s,
+            parsed,
+            expected
+        );
+    }
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_grammar() {
+        for s in [
+            "", "-", "01", "1.", ".5", "1e", "1e+", "+1", "0x10", "1.5e3.2", "NaN",
+        ] {
+            assert_eq!(parse_f64(s), None, "{:?}", s);
+        }
+        assert_eq!(parse_f64("-0").map(f64::to_bits), Some((-0.0f64).to_bits()));
+    }
+
+    #[test]
+    fn test_hard_cases() {
+        let cases = [
+            "0.1",
+            "0.1000000000000000055511151231257827",
+            "2.2250738585072011e-308",
+            "2.2250738585072014e-308",
+            "4.9406564584124654e-324",
+            "2.4703282292062327e-324",
+            "2.4703282292062328e-324",
+            "1.7976931348623157e308",
+            "1.7976931348623158e308",
+            "1.7976931348623159e308",
+            "9007199254740993",
+            "9007199254740995",
+            "1e23",
+            "8.98846567431158e307",
+            "1e-400",
+            "1e400",
+            "123456789012345678901234567890e-10",
+            // exactly halfway between 1 and the next float, ties to even
+            "1.00000000000000011102230246251565404236316680908203125",
+            "1.00000000000000011102230246251565404236316680908203125000000000000000000001",
+            "1.00000000000000011102230246251565404236316680908203124999999999999999999999",
+            "0e99999999999999999999",
+            "1e-99999999999999999999",
+            "1e99999999999999999999",
+        ];
+        for s in cases {
+            assert_matches_std(s);
+        }
+        let long = format!("0.{}1e330", "0".repeat(700));
+        assert_matches_std(&long);
+    }
+
+    #[test]
+    fn test_long_mantissas() {
+        // halfway between 1 and the next float, decided by a digit far past the kept ones
+        let half = "1.00000000000000011102230246251565404236316680908203125";
+        for tail in ["", "1", "0000000001"] {
+            let s = format!("{}{}{}", half, "0".repeat(1_000_000), tail);
+            let start = std::time::Instant::now();
+            let json = crate::parser::parse(&format!("[{}]", s)).unwrap();
+            assert!(start.elapsed().as_secs() < 1, "took {:?}", start.elapsed());
+            let expected: f64 = s.parse().unwrap();
+            assert_eq!(json, crate::parser::Json::from(vec![expected]));
+        }
+        let below = format!(
+            "1.00000000000000011102230246251565404236316680908203124{}",
+            "9".repeat(1_000_000)
+        );
+        assert_matches_std(&below);
+        // std overflows on the exponent of this one, so compare with a shorter spelling
+        let big = format!("{}e-999990", "7".repeat(1_000_000));
+        assert_eq!(parse_f64(&big), "7777777777.777777777777".parse().ok());
+    }
+
+    #[test]
+    fn test_corpus_of_random_floats() {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        let mut checked = 0;
+        while checked < 30_000 {
+            let x = f64::from_bits(rng.next());
+            if !x.is_finite() {
+                continue;
+            }
+            assert_matches_std(&format!("{:e}", x));
+            // more digits than needed exercises the slow path
+            assert_matches_std(&format!("{:.25e}", x));
+            checked += 1;
+        }
+    }
+
+    #[test]
+    fn test_corpus_of_random_literals() {
+        let mut rng = XorShift(0x1234_5678_9abc_def1);
+        for _ in 0..30_000 {
+            let len = 1 + (rng.next() % 30) as usize;
+            let mut s: String = (0..len)
+                .map(|_| (b'0' + (rng.next() % 10) as u8) as char)
+                .collect();
+            s = s.trim_start_matches('0').to_string();
+            if s.is_empty() {
+                s.push('0');
+            }
+            if rng.next().is_multiple_of(2) && s.len() > 1 {
+                let point = 1 + (rng.next() as usize % (s.len() - 1));
+                s.insert(point, '.');
+            }
+            let exponent = (rng.next() % 700) as i64 - 350;
+            assert_matches_std(&format!("{}e{}", s, exponent));
+        }
+    }
+
+    #[test]
+    fn test_fast_path_short_decimals() {
+        for i in 0..10_000u32 {
+            assert_matches_std(&format!("{}.{:02}", i, i % 100));
+            assert_matches_std(&format!("-{}e-{}", i, i % 23));
+        }
+    }
+}
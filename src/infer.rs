@@ -0,0 +1,247 @@
+//! Inferring a JSON Schema from sample documents, e.g. the objects of an API response.
+use crate::lexer::Constant;
+use crate::parser::Json;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InferOptions {
+    /// Strings become an `enum` if there are at most this many distinct values and each of them
+    /// was seen twice on average. Zero never infers an enum.
+    pub max_enum_values: usize,
+}
+
+impl Default for InferOptions {
+    fn default() -> Self {
+        Self { max_enum_values: 8 }
+    }
+}
+
+/// What was seen in the samples at one place in the documents: how many values of each type,
+/// and the merged shapes of their elements and members.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Shape {
+    pub nulls: usize,
+    pub booleans: usize,
+    pub integers: usize,
+    /// Numbers with a fraction or an exponent, which widen the integers to numbers.
+    pub floats: usize,
+    pub strings: usize,
+    /// The distinct strings, `None` once there are too many for an enum.
+    pub string_values: Option<BTreeSet<String>>,
+    pub arrays: usize,
+    /// The merged shape of the elements of every array, `None` if they were all empty.
+    pub items: Option<Box<Shape>>,
+    pub objects: usize,
+    /// The merged shape of each member. A member is optional if it was seen less often than
+    /// there are objects.
+    pub properties: BTreeMap<String, Shape>,
+}
+
+impl Shape {
+    /// Merges the samples into one shape.
+    pub fn infer<'a>(samples: impl IntoIterator<Item = &'a Json>, options: &InferOptions) -> Shape {
+        let mut shape = Shape::default();
+        for sample in samples {
+            shape.add(sample, options);
+        }
+        shape
+    }
+
+    pub fn add(&mut self, sample: &Json, options: &InferOptions) {
+        match sample {
+            Json::Value(Constant::Null) => self.nulls += 1,
+            Json::Value(Constant::Boolean(_)) => self.booleans += 1,
+            Json::Value(Constant::Int(_) | Constant::UInt(_)) => self.integers += 1,
+            Json::Value(Constant::Float(_)) => self.floats += 1,
+            Json::Value(Constant::Number(x)) if x.is_integer() => self.integers += 1,
+            Json::Value(Constant::Number(_)) => self.floats += 1,
+            Json::Value(Constant::StringLiteral(x)) => {
+                if self.strings == 0 {
+                    self.string_values = Some(BTreeSet::new());
+                }
+                self.strings += 1;
+                if let Some(values) = &mut self.string_values {
+                    values.insert(x.clone());
+                    if values.len() > options.max_enum_values {
+                        self.string_values = None;
+                    }
+                }
+            }
+            Json::List(items) => {
+                self.arrays += 1;
+                for item in items {
+                    self.items.get_or_insert_default().add(item, options);
+                }
+            }
+            Json::Dict(members) => {
+                self.objects += 1;
+                for (key, value) in members {
+                    self.properties
+                        .entry(key.clone())
+                        .or_default()
+                        .add(value, options);
+                }
+            }
+        }
+    }
+
+    /// The number of values merged into the shape.
+    pub fn count(&self) -> usize {
+        self.nulls
+            + self.booleans
+            + self.integers
+            + self.floats
+            + self.strings
+            + self.arrays
+            + self.objects
+    }
+
+    /// The strings, if they look like an enum: few distinct values that repeat.
+    pub fn enum_values(&self) -> Option<&BTreeSet<String>> {
+        self.string_values
+            .as_ref()
+            .filter(|values| !values.is_empty() && self.strings >= 2 * values.len())
+    }
+
+    /// The members seen in every object.
+    pub fn required(&self) -> impl Iterator<Item = &str> {
+        self.properties
+            .iter()
+            .filter(|(_, shape)| shape.count() == self.objects)
+            .map(|(key, _)| key.as_str())
+    }
+
+    /// The JSON Schema type names of the values, in the order of the schema specification.
+    pub fn type_names(&self) -> Vec<&'static str> {
+        let number = match self.floats {
+            0 => "integer",
+            _ => "number",
+        };
+        [
+            (self.nulls, "null"),
+            (self.booleans, "boolean"),
+            (self.integers + self.floats, number),
+            (self.strings, "string"),
+            (self.arrays, "array"),
+            (self.objects, "object"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(_, name)| name)
+        .collect()
+    }
+
+    /// A schema that every merged value is valid against. If nothing was seen, anything is.
+    pub fn to_schema(&self) -> Json {
+        let mut schema = BTreeMap::new();
+        let names = self.type_names();
+        match &names[..] {
+            [] => {}
+            [name] => {
+                schema.insert("type".to_string(), Json::from(*name));
+            }
+            names => {
+                let names = names.iter().map(|name| Json::from(*name)).collect();
+                schema.insert("type".to_string(), Json::List(names));
+            }
+        }
+        // the enum restricts every type, so it's only used for strings that may be null
+        let only_strings = self.count() == self.strings + self.nulls;
+        if let Some(values) = self.enum_values().filter(|_| only_strings) {
+            let mut values: Vec<Json> = values.iter().map(|x| Json::from(x.as_str())).collect();
+            if self.nulls > 0 {
+                values.push(Json::Value(Constant::Null));
+            }
+            schema.insert("enum".to_string(), Json::List(values));
+        }
+        if let Some(items) = &self.items {
+            schema.insert("items".to_string(), items.to_schema());
+        }
+        if self.objects > 0 {
+            let properties = self
+                .properties
+                .iter()
+                .map(|(key, shape)| (key.clone(), shape.to_schema()))
+                .collect::<BTreeMap<_, _>>();
+            let required = self.required().map(Json::from).collect::<Vec<_>>();
+            schema.insert("properties".to_string(), Json::from(properties));
+            if !required.is_empty() {
+                schema.insert("required".to_string(), Json::List(required));
+            }
+        }
+        Json::from(schema)
+    }
+}
+
+/// A draft 2020-12 schema for the samples, inferred with the default options.
+pub fn infer_schema<'a>(samples: impl IntoIterator<Item = &'a Json>) -> Json {
+    infer_schema_with(samples, &InferOptions::default())
+}
+
+pub fn infer_schema_with<'a>(
+    samples: impl IntoIterator<Item = &'a Json>,
+    options: &InferOptions,
+) -> Json {
+    let mut schema = Shape::infer(samples, options).to_schema();
+    if let Json::Dict(members) = &mut schema {
+        let dialect = "https://json-schema.org/draft/2020-12/schema";
+        members.insert("$schema".to_string(), Json::from(dialect));
+    }
+    schema
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::schema::Schema;
+
+    #[test]
+    fn test_infer_schema() {
+        let samples = json!([
+            {"id": 1, "status": "open", "price": 10, "tags": ["a"], "owner": {"name": "x"}},
+            {"id": 2, "status": "closed", "price": 2.5, "tags": []},
+            {"id": 3, "status": "open", "price": 7, "tags": [], "owner": null},
+            {"id": 4, "status": "open", "price": 1, "tags": ["b", 1]}
+        ]);
+        let Json::List(samples) = samples else {
+            unreachable!()
+        };
+        let schema = infer_schema(&samples);
+        assert_eq!(
+            schema,
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "type": "object",
+                "properties": {
+                    "id": {"type": "integer"},
+                    "owner": {
+                        "type": ["null", "object"],
+                        "properties": {"name": {"type": "string"}},
+                        "required": ["name"]
+                    },
+                    "price": {"type": "number"},
+                    "status": {"type": "string", "enum": ["closed", "open"]},
+                    "tags": {"type": "array", "items": {"type": ["integer", "string"]}}
+                },
+                "required": ["id", "price", "status", "tags"]
+            })
+        );
+        let compiled = Schema::compile(&schema).unwrap();
+        assert!(samples.iter().all(|sample| compiled.is_valid(sample)));
+    }
+
+    #[test]
+    fn test_shapes() {
+        let options = InferOptions { max_enum_values: 2 };
+        let samples = ["a", "b", "a", "b", "c", "c"].map(Json::from);
+        let shape = Shape::infer(&samples, &options);
+        assert_eq!((shape.strings, shape.enum_values()), (6, None));
+        let shape = Shape::infer(&samples[..4], &options);
+        assert_eq!(shape.enum_values().unwrap().len(), 2);
+        // a single sample of each value doesn't look like an enum
+        assert_eq!(Shape::infer(&samples[..2], &options).enum_values(), None);
+        let shape = Shape::infer(&[json!([]), json!(null)], &options);
+        assert_eq!(shape.to_schema(), json!({"type": ["null", "array"]}));
+        assert_eq!(Shape::default().to_schema(), json!({}));
+    }
+}
@@ -0,0 +1,1190 @@
+//! JMESPath queries, e.g. ``people[?age > `20`].name | sort(@)``.
+use crate::float_parse::parse_f64;
+use crate::lexer::Constant;
+use crate::parser::{parse, Json};
+use crate::patch::{compare_numbers, equal};
+use crate::writer::to_string;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The error kinds of the JMESPath specification. Syntax errors carry the byte offset in the
+/// expression, unknown functions and wrong arities are reported when parsing.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum JmesPathError {
+    #[error("JmesPathError: Syntax error at {0}: {1}")]
+    Syntax(usize, String),
+    #[error("JmesPathError: Unknown function {0}()")]
+    UnknownFunction(String),
+    #[error("JmesPathError: Invalid arity: {0}")]
+    InvalidArity(String),
+    #[error("JmesPathError: Invalid type: {0}")]
+    InvalidType(String),
+    #[error("JmesPathError: Invalid value: {0}")]
+    InvalidValue(String),
+}
+
+/// A parsed JMESPath expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JmesPath {
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    /// The right side of a projection that doesn't continue.
+    Identity,
+    /// `@`
+    Current,
+    Field(String),
+    Literal(Json),
+    Index(i64),
+    Slice(Option<i64>, Option<i64>, Option<i64>),
+    Subexpression(Box<Expr>, Box<Expr>),
+    /// Applies the right side to each element of the left side if it is an array.
+    Projection(Box<Expr>, Box<Expr>),
+    /// Applies the right side to each member value of the left side if it is an object.
+    ValueProjection(Box<Expr>, Box<Expr>),
+    /// `left[?condition] right`
+    FilterProjection(Box<Expr>, Box<Expr>, Box<Expr>),
+    Flatten(Box<Expr>),
+    Comparison(Box<Expr>, CompareOp, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Pipe(Box<Expr>, Box<Expr>),
+    MultiSelectList(Vec<Expr>),
+    MultiSelectHash(Vec<(String, Expr)>),
+    Function(&'static Signature, Vec<Expr>),
+    /// `&expr`, only meaningful as a function argument.
+    ExpRef(Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    QuotedIdentifier(String),
+    Literal(Json),
+    Number(i64),
+    Dot,
+    Star,
+    /// `[]`
+    Flatten,
+    /// `[?`
+    Filter,
+    LeftBracket,
+    RightBracket,
+    LeftBrace,
+    RightBrace,
+    LeftParen,
+    RightParen,
+    Comma,
+    Colon,
+    Pipe,
+    Or,
+    And,
+    Not,
+    Compare(CompareOp),
+    Current,
+    ExpRef,
+    End,
+}
+
+impl Token {
+    fn binding_power(&self) -> u8 {
+        match self {
+            Token::Pipe => 1,
+            Token::Or => 2,
+            Token::And => 3,
+            Token::Compare(_) => 5,
+            Token::Flatten => 9,
+            Token::Star => 20,
+            Token::Filter => 21,
+            Token::Dot => 40,
+            Token::Not => 45,
+            Token::LeftBrace => 50,
+            Token::LeftBracket => 55,
+            Token::LeftParen => 60,
+            _ => 0,
+        }
+    }
+}
+
+/// Tokens with a binding power below this end the right side of a projection.
+const PROJECTION_STOP: u8 = 10;
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, JmesPathError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut followed_by = |next: char| chars.next_if(|(_, c)| *c == next).is_some();
+        let token = match c {
+            ' ' | '\t' | '\n' | '\r' => continue,
+            '.' => Token::Dot,
+            '*' => Token::Star,
+            ']' => Token::RightBracket,
+            '{' => Token::LeftBrace,
+            '}' => Token::RightBrace,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '@' => Token::Current,
+            '[' if followed_by(']') => Token::Flatten,
+            '[' if followed_by('?') => Token::Filter,
+            '[' => Token::LeftBracket,
+            '|' if followed_by('|') => Token::Or,
+            '|' => Token::Pipe,
+            '&' if followed_by('&') => Token::And,
+            '&' => Token::ExpRef,
+            '!' if followed_by('=') => Token::Compare(CompareOp::Ne),
+            '!' => Token::Not,
+            '=' if followed_by('=') => Token::Compare(CompareOp::Eq),
+            '<' if followed_by('=') => Token::Compare(CompareOp::Le),
+            '<' => Token::Compare(CompareOp::Lt),
+            '>' if followed_by('=') => Token::Compare(CompareOp::Ge),
+            '>' => Token::Compare(CompareOp::Gt),
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let mut end = start + 1;
+                while let Some((i, _)) =
+                    chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    end = i + 1;
+                }
+                Token::Identifier(input[start..end].to_string())
+            }
+            '-' | '0'..='9' => {
+                let mut end = start + 1;
+                while let Some((i, _)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                    end = i + 1;
+                }
+                let number = input[start..end]
+                    .parse()
+                    .map_err(|_| JmesPathError::Syntax(start, "invalid number".to_string()))?;
+                Token::Number(number)
+            }
+            '"' | '\'' | '`' => {
+                // up to the first unescaped delimiter
+                let mut escaped = false;
+                let end = loop {
+                    match chars.next() {
+                        Some((i, d)) if d == c && !escaped => break i,
+                        Some((_, d)) => escaped = d == '\\' && !escaped,
+                        None => {
+                            let message = format!("unterminated `{}`", c);
+                            return Err(JmesPathError::Syntax(start, message));
+                        }
+                    }
+                };
+                let body = &input[start + 1..end];
+                match c {
+                    '"' => match parse_value(&input[start..=end]) {
+                        Some(Json::Value(Constant::StringLiteral(x))) => Token::QuotedIdentifier(x),
+                        _ => {
+                            let message = "invalid quoted identifier".to_string();
+                            return Err(JmesPathError::Syntax(start, message));
+                        }
+                    },
+                    '\'' => Token::Literal(Json::from(body.replace("\\'", "'"))),
+                    _ => {
+                        let body = body.replace("\\`", "`");
+                        // literals that aren't JSON are strings, as in earlier versions of the
+                        // specification
+                        let legacy = || parse_value(&format!("\"{}\"", body.trim_start()));
+                        match parse_value(&body).or_else(legacy) {
+                            Some(json) => Token::Literal(json),
+                            None => {
+                                let message = "invalid JSON literal".to_string();
+                                return Err(JmesPathError::Syntax(start, message));
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {
+                let message = format!("unexpected `{}`", c);
+                return Err(JmesPathError::Syntax(start, message));
+            }
+        };
+        tokens.push((start, token));
+    }
+    tokens.push((input.len(), Token::End));
+    Ok(tokens)
+}
+
+/// Parses any value, where `parse` only accepts arrays and objects.
+fn parse_value(text: &str) -> Option<Json> {
+    match parse(&format!("[{}]", text)).ok()? {
+        Json::List(mut values) if values.len() == 1 => Some(values.remove(0)),
+        _ => None,
+    }
+}
+
+/// Nesting of expressions, bounded so that parsing fails instead of overflowing the stack.
+const MAX_NESTING: usize = 128;
+
+/// A Pratt parser over the tokens.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Expressions being parsed inside each other.
+    depth: usize,
+}
+
+type ParseResult<T> = Result<T, JmesPathError>;
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        let last = self.tokens.len() - 1;
+        &self.tokens[(self.pos + offset).min(last)].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: impl Into<String>) -> JmesPathError {
+        JmesPathError::Syntax(self.tokens[self.pos].0, message.into())
+    }
+
+    fn unexpected(&self) -> JmesPathError {
+        match self.peek() {
+            Token::End => self.error("unexpected end of expression"),
+            token => self.error(format!("unexpected {:?}", token)),
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> ParseResult<()> {
+        match *self.peek() == token {
+            true => {
+                self.next();
+                Ok(())
+            }
+            false => Err(self.unexpected()),
+        }
+    }
+
+    fn program(&mut self) -> ParseResult<Expr> {
+        let expr = self.expression(0)?;
+        match self.peek() {
+            Token::End => Ok(expr),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn expression(&mut self, binding_power: u8) -> ParseResult<Expr> {
+        let depth = self.depth;
+        self.deeper()?;
+        let mut left = self.nud()?;
+        while binding_power < self.peek().binding_power() {
+            // `left` becomes an operand of the next operator, one level deeper
+            self.deeper()?;
+            left = self.led(left)?;
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    /// Counts a level of nesting, failing past `MAX_NESTING`.
+    fn deeper(&mut self) -> ParseResult<()> {
+        if self.depth >= MAX_NESTING {
+            return Err(self.error("expressions are nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// An expression starting with the next token.
+    fn nud(&mut self) -> ParseResult<Expr> {
+        let start = self.pos;
+        Ok(match self.next() {
+            Token::Literal(json) => Expr::Literal(json),
+            Token::Identifier(name) => Expr::Field(name),
+            Token::QuotedIdentifier(name) => match self.peek() {
+                Token::LeftParen => return Err(self.error("function names can't be quoted")),
+                _ => Expr::Field(name),
+            },
+            Token::Star => {
+                let right = self.projection_rhs(Token::Star.binding_power())?;
+                Expr::ValueProjection(Box::new(Expr::Identity), Box::new(right))
+            }
+            Token::Filter => self.filter(Expr::Identity)?,
+            Token::LeftBrace => self.multi_select_hash()?,
+            Token::LeftParen => {
+                let expr = self.expression(0)?;
+                self.expect(Token::RightParen)?;
+                expr
+            }
+            Token::Flatten => self.flatten(Expr::Identity)?,
+            Token::Not => Expr::Not(Box::new(self.expression(Token::Not.binding_power())?)),
+            Token::LeftBracket => match (self.peek(), self.peek_at(1)) {
+                (Token::Number(_) | Token::Colon, _) => {
+                    let index = self.index()?;
+                    self.project_if_slice(Expr::Identity, index)?
+                }
+                (Token::Star, Token::RightBracket) => {
+                    self.pos += 2;
+                    let right = self.projection_rhs(Token::Star.binding_power())?;
+                    Expr::Projection(Box::new(Expr::Identity), Box::new(right))
+                }
+                _ => self.multi_select_list()?,
+            },
+            Token::Current => Expr::Current,
+            Token::ExpRef => Expr::ExpRef(Box::new(self.expression(0)?)),
+            _ => {
+                self.pos = start;
+                return Err(self.unexpected());
+            }
+        })
+    }
+
+    /// An expression continuing `left` with the next token.
+    fn led(&mut self, left: Expr) -> ParseResult<Expr> {
+        let token = self.next();
+        let binding_power = token.binding_power();
+        let (left, right) = (Box::new(left), |expr| Ok(Box::new(expr)));
+        Ok(match token {
+            Token::Dot if *self.peek() == Token::Star => {
+                self.next();
+                Expr::ValueProjection(left, right(self.projection_rhs(binding_power)?)?)
+            }
+            Token::Dot => Expr::Subexpression(left, right(self.dot_rhs(binding_power)?)?),
+            Token::Pipe => Expr::Pipe(left, right(self.expression(binding_power)?)?),
+            Token::Or => Expr::Or(left, right(self.expression(binding_power)?)?),
+            Token::And => Expr::And(left, right(self.expression(binding_power)?)?),
+            Token::Compare(op) => {
+                Expr::Comparison(left, op, right(self.expression(binding_power)?)?)
+            }
+            Token::LeftParen => {
+                let Expr::Field(name) = *left else {
+                    self.pos -= 1;
+                    return Err(self.error("only names can be called"));
+                };
+                self.function(name)?
+            }
+            Token::Filter => self.filter(*left)?,
+            Token::Flatten => self.flatten(*left)?,
+            Token::LeftBracket => match self.peek() {
+                Token::Number(_) | Token::Colon => {
+                    let index = self.index()?;
+                    self.project_if_slice(*left, index)?
+                }
+                _ => {
+                    self.expect(Token::Star)?;
+                    self.expect(Token::RightBracket)?;
+                    let right = self.projection_rhs(Token::Star.binding_power())?;
+                    Expr::Projection(left, Box::new(right))
+                }
+            },
+            _ => {
+                self.pos -= 1;
+                return Err(self.unexpected());
+            }
+        })
+    }
+
+    fn projection_rhs(&mut self, binding_power: u8) -> ParseResult<Expr> {
+        match self.peek() {
+            token if token.binding_power() < PROJECTION_STOP => Ok(Expr::Identity),
+            Token::LeftBracket | Token::Filter => self.expression(binding_power),
+            Token::Dot => {
+                self.next();
+                self.dot_rhs(binding_power)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// What may follow a `.`: a name, `*`, or a multi-select.
+    fn dot_rhs(&mut self, binding_power: u8) -> ParseResult<Expr> {
+        match self.peek() {
+            Token::Identifier(_) | Token::QuotedIdentifier(_) | Token::Star => {
+                self.expression(binding_power)
+            }
+            Token::LeftBracket => {
+                self.next();
+                self.multi_select_list()
+            }
+            Token::LeftBrace => {
+                self.next();
+                self.multi_select_hash()
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// An index or slice after `[`, up to and including `]`.
+    fn index(&mut self) -> ParseResult<Expr> {
+        let mut parts = vec![None];
+        loop {
+            match self.next() {
+                Token::Number(n) if parts.last() == Some(&None) => {
+                    *parts.last_mut().expect("not empty") = Some(n);
+                }
+                Token::Colon if parts.len() < 3 => parts.push(None),
+                Token::RightBracket => break,
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.unexpected());
+                }
+            }
+        }
+        Ok(match parts[..] {
+            [Some(i)] => Expr::Index(i),
+            [_, _, Some(0)] => return Err(JmesPathError::InvalidValue("slice step is 0".into())),
+            _ => {
+                parts.resize(3, None);
+                Expr::Slice(parts[0], parts[1], parts[2])
+            }
+        })
+    }
+
+    fn project_if_slice(&mut self, left: Expr, index: Expr) -> ParseResult<Expr> {
+        let projected = matches!(index, Expr::Slice(..));
+        let left = Expr::Subexpression(Box::new(left), Box::new(index));
+        match projected {
+            true => {
+                let right = self.projection_rhs(Token::Star.binding_power())?;
+                Ok(Expr::Projection(Box::new(left), Box::new(right)))
+            }
+            false => Ok(left),
+        }
+    }
+
+    fn filter(&mut self, left: Expr) -> ParseResult<Expr> {
+        let condition = self.expression(0)?;
+        self.expect(Token::RightBracket)?;
+        let right = match self.peek() {
+            Token::Flatten => Expr::Identity,
+            _ => self.projection_rhs(Token::Filter.binding_power())?,
+        };
+        Ok(Expr::FilterProjection(
+            Box::new(left),
+            Box::new(right),
+            Box::new(condition),
+        ))
+    }
+
+    fn flatten(&mut self, left: Expr) -> ParseResult<Expr> {
+        let right = self.projection_rhs(Token::Flatten.binding_power())?;
+        Ok(Expr::Projection(
+            Box::new(Expr::Flatten(Box::new(left))),
+            Box::new(right),
+        ))
+    }
+
+    /// `[a, b]` after the `[`.
+    fn multi_select_list(&mut self) -> ParseResult<Expr> {
+        let mut items = vec![self.expression(0)?];
+        while *self.peek() == Token::Comma {
+            self.next();
+            items.push(self.expression(0)?);
+        }
+        self.expect(Token::RightBracket)?;
+        Ok(Expr::MultiSelectList(items))
+    }
+
+    /// `{a: b, c: d}` after the `{`.
+    fn multi_select_hash(&mut self) -> ParseResult<Expr> {
+        let mut members = Vec::new();
+        loop {
+            let key = match self.next() {
+                Token::Identifier(key) | Token::QuotedIdentifier(key) => key,
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected a key"));
+                }
+            };
+            self.expect(Token::Colon)?;
+            members.push((key, self.expression(0)?));
+            match self.next() {
+                Token::Comma => {}
+                Token::RightBrace => return Ok(Expr::MultiSelectHash(members)),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.unexpected());
+                }
+            }
+        }
+    }
+
+    /// A call of `name` after the `(`.
+    fn function(&mut self, name: String) -> ParseResult<Expr> {
+        let mut args = Vec::new();
+        while *self.peek() != Token::RightParen {
+            if !args.is_empty() {
+                self.expect(Token::Comma)?;
+            }
+            args.push(self.expression(0)?);
+        }
+        self.next();
+        let signature = SIGNATURES
+            .iter()
+            .find(|signature| signature.name == name)
+            .ok_or(JmesPathError::UnknownFunction(name))?;
+        let arity = signature.params.len();
+        let valid = match signature.variadic {
+            true => args.len() >= arity,
+            false => args.len() == arity,
+        };
+        if !valid {
+            return Err(JmesPathError::InvalidArity(format!(
+                "{}() takes {}{} arguments, got {}",
+                signature.name,
+                if signature.variadic { "at least " } else { "" },
+                arity,
+                args.len()
+            )));
+        }
+        Ok(Expr::Function(signature, args))
+    }
+}
+
+/// Argument types of the built-in functions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Any,
+    Number,
+    String,
+    Array,
+    Object,
+    ExpRef,
+    /// An array of numbers.
+    Numbers,
+    /// An array of strings.
+    Strings,
+}
+
+#[derive(Debug, PartialEq)]
+struct Signature {
+    name: &'static str,
+    /// The accepted kinds of each parameter.
+    params: &'static [&'static [Kind]],
+    /// Whether the last parameter can be repeated.
+    variadic: bool,
+}
+
+const fn signature(name: &'static str, params: &'static [&'static [Kind]]) -> Signature {
+    Signature {
+        name,
+        params,
+        variadic: false,
+    }
+}
+
+const SIGNATURES: &[Signature] = &[
+    signature("abs", &[&[Kind::Number]]),
+    signature("avg", &[&[Kind::Numbers]]),
+    signature("ceil", &[&[Kind::Number]]),
+    signature("contains", &[&[Kind::Array, Kind::String], &[Kind::Any]]),
+    signature("ends_with", &[&[Kind::String], &[Kind::String]]),
+    signature("floor", &[&[Kind::Number]]),
+    signature("join", &[&[Kind::String], &[Kind::Strings]]),
+    signature("keys", &[&[Kind::Object]]),
+    signature("length", &[&[Kind::String, Kind::Array, Kind::Object]]),
+    signature("map", &[&[Kind::ExpRef], &[Kind::Array]]),
+    signature("max", &[&[Kind::Numbers, Kind::Strings]]),
+    signature("max_by", &[&[Kind::Array], &[Kind::ExpRef]]),
+    Signature {
+        name: "merge",
+        params: &[&[Kind::Object]],
+        variadic: true,
+    },
+    signature("min", &[&[Kind::Numbers, Kind::Strings]]),
+    signature("min_by", &[&[Kind::Array], &[Kind::ExpRef]]),
+    Signature {
+        name: "not_null",
+        params: &[&[Kind::Any]],
+        variadic: true,
+    },
+    signature("reverse", &[&[Kind::Array, Kind::String]]),
+    signature("sort", &[&[Kind::Numbers, Kind::Strings]]),
+    signature("sort_by", &[&[Kind::Array], &[Kind::ExpRef]]),
+    signature("starts_with", &[&[Kind::String], &[Kind::String]]),
+    signature("sum", &[&[Kind::Numbers]]),
+    signature("to_array", &[&[Kind::Any]]),
+    signature("to_number", &[&[Kind::Any]]),
+    signature("to_string", &[&[Kind::Any]]),
+    signature("type", &[&[Kind::Any]]),
+    signature("values", &[&[Kind::Object]]),
+];
+
+/// A function argument: a value, or an expression for `&expr`.
+enum Arg<'e> {
+    Value(Json),
+    ExpRef(&'e Expr),
+}
+
+impl Kind {
+    fn accepts(self, arg: &Arg) -> bool {
+        let items = |f: fn(&Json) -> bool| match arg {
+            Arg::Value(Json::List(x)) => x.iter().all(f),
+            _ => false,
+        };
+        match (self, arg) {
+            (Kind::ExpRef, arg) => matches!(arg, Arg::ExpRef(_)),
+            (_, Arg::ExpRef(_)) => false,
+            (Kind::Any, _) => true,
+            (Kind::Number, Arg::Value(x)) => float(x).is_some(),
+            (Kind::String, Arg::Value(x)) => string(x).is_some(),
+            (Kind::Array, Arg::Value(x)) => matches!(x, Json::List(_)),
+            (Kind::Object, Arg::Value(x)) => matches!(x, Json::Dict(_)),
+            (Kind::Numbers, _) => items(|x| float(x).is_some()),
+            (Kind::Strings, _) => items(|x| string(x).is_some()),
+        }
+    }
+}
+
+fn truthy(json: &Json) -> bool {
+    match json {
+        Json::Dict(x) => !x.is_empty(),
+        Json::List(x) => !x.is_empty(),
+        Json::Value(Constant::StringLiteral(x)) => !x.is_empty(),
+        Json::Value(Constant::Null) | Json::Value(Constant::Boolean(false)) => false,
+        Json::Value(_) => true,
+    }
+}
+
+fn type_name(json: &Json) -> &'static str {
+    match json {
+        Json::Dict(_) => "object",
+        Json::List(_) => "array",
+        Json::Value(Constant::Null) => "null",
+        Json::Value(Constant::Boolean(_)) => "boolean",
+        Json::Value(Constant::StringLiteral(_)) => "string",
+        Json::Value(_) => "number",
+    }
+}
+
+fn float(json: &Json) -> Option<f64> {
+    match json {
+        Json::Value(Constant::Int(x)) => Some(*x as f64),
+        Json::Value(Constant::UInt(x)) => Some(*x as f64),
+        Json::Value(Constant::Float(x)) => Some(*x),
+        Json::Value(Constant::Number(x)) => Some(x.as_f64()),
+        _ => None,
+    }
+}
+
+/// A computed number, as an integer if it is one that a float represents exactly.
+fn number(x: f64) -> Json {
+    match x.fract() == 0.0 && x.abs() < 9007199254740992.0 {
+        true => Json::from(x as i64),
+        false => Json::from(x),
+    }
+}
+
+fn string(json: &Json) -> Option<&str> {
+    match json {
+        Json::Value(Constant::StringLiteral(x)) => Some(x),
+        _ => None,
+    }
+}
+
+/// Orders numbers and strings, `None` for anything else or a mix of both.
+fn compare(a: &Json, b: &Json) -> Option<Ordering> {
+    match (a, b) {
+        (Json::Value(Constant::StringLiteral(x)), Json::Value(Constant::StringLiteral(y))) => {
+            Some(x.cmp(y))
+        }
+        (Json::Value(x), Json::Value(y)) => compare_numbers(x, y),
+        _ => None,
+    }
+}
+
+fn slice(items: &[Json], start: Option<i64>, end: Option<i64>, step: Option<i64>) -> Vec<Json> {
+    let len = items.len() as i64;
+    let step = step.unwrap_or(1);
+    // bounds are clamped to where a step in the direction of `step` can start and end
+    let (low, high) = if step > 0 { (0, len) } else { (-1, len - 1) };
+    let bound = |x: Option<i64>, default| match x {
+        None => default,
+        Some(x) if x < 0 => (x + len).max(low),
+        Some(x) => x.min(high),
+    };
+    let (start, end) = match step > 0 {
+        true => (bound(start, low), bound(end, high)),
+        false => (bound(start, high), bound(end, low)),
+    };
+    let mut selected = Vec::new();
+    let mut i = start;
+    while (step > 0 && i < end) || (step < 0 && i > end) {
+        selected.push(items[i as usize].clone());
+        i += step;
+    }
+    selected
+}
+
+impl Expr {
+    fn eval(&self, input: &Json) -> Result<Json, JmesPathError> {
+        let null = Json::Value(Constant::Null);
+        Ok(match self {
+            Expr::Identity | Expr::Current => input.clone(),
+            Expr::Field(name) => match input {
+                Json::Dict(x) => x.get(name).cloned().unwrap_or(null),
+                _ => null,
+            },
+            Expr::Literal(json) => json.clone(),
+            Expr::Index(i) => match input {
+                Json::List(x) => {
+                    let i = if *i < 0 { *i + x.len() as i64 } else { *i };
+                    usize::try_from(i)
+                        .ok()
+                        .and_then(|i| x.get(i))
+                        .cloned()
+                        .unwrap_or(null)
+                }
+                _ => null,
+            },
+            Expr::Slice(start, end, step) => match input {
+                Json::List(x) => Json::List(slice(x, *start, *end, *step)),
+                _ => null,
+            },
+            Expr::Subexpression(left, right) | Expr::Pipe(left, right) => {
+                right.eval(&left.eval(input)?)?
+            }
+            Expr::Projection(left, right) => match left.eval(input)? {
+                Json::List(x) => project(x.iter(), right)?,
+                _ => null,
+            },
+            Expr::ValueProjection(left, right) => match left.eval(input)? {
+                Json::Dict(x) => {
+                    let mut keys = x.keys().collect::<Vec<_>>();
+                    keys.sort();
+                    project(keys.into_iter().map(|key| &x[key]), right)?
+                }
+                _ => null,
+            },
+            Expr::FilterProjection(left, right, condition) => match left.eval(input)? {
+                Json::List(x) => {
+                    let mut selected = Vec::new();
+                    for item in x {
+                        if truthy(&condition.eval(&item)?) {
+                            selected.push(item);
+                        }
+                    }
+                    project(selected.iter(), right)?
+                }
+                _ => null,
+            },
+            Expr::Flatten(inner) => match inner.eval(input)? {
+                Json::List(x) => Json::List(
+                    x.into_iter()
+                        .flat_map(|item| match item {
+                            Json::List(items) => items,
+                            item => vec![item],
+                        })
+                        .collect(),
+                ),
+                _ => null,
+            },
+            Expr::Comparison(left, op, right) => {
+                let (left, right) = (left.eval(input)?, right.eval(input)?);
+                match op {
+                    CompareOp::Eq => Json::from(equal(&left, &right)),
+                    CompareOp::Ne => Json::from(!equal(&left, &right)),
+                    // only numbers are ordered
+                    _ if float(&left).is_none() || float(&right).is_none() => null,
+                    _ => {
+                        let order = compare(&left, &right);
+                        Json::from(match op {
+                            CompareOp::Lt => order == Some(Ordering::Less),
+                            CompareOp::Le => order.is_some_and(Ordering::is_le),
+                            CompareOp::Gt => order == Some(Ordering::Greater),
+                            _ => order.is_some_and(Ordering::is_ge),
+                        })
+                    }
+                }
+            }
+            Expr::Or(left, right) => match left.eval(input)? {
+                left if truthy(&left) => left,
+                _ => right.eval(input)?,
+            },
+            Expr::And(left, right) => match left.eval(input)? {
+                left if !truthy(&left) => left,
+                _ => right.eval(input)?,
+            },
+            Expr::Not(inner) => Json::from(!truthy(&inner.eval(input)?)),
+            Expr::Function(signature, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Expr::ExpRef(expr) => Ok(Arg::ExpRef(expr)),
+                        arg => Ok(Arg::Value(arg.eval(input)?)),
+                    })
+                    .collect::<Result<Vec<_>, JmesPathError>>()?;
+                call(signature, &args)?
+            }
+            // multi-selects of null are null
+            _ if *input == null => null,
+            Expr::MultiSelectList(items) => Json::List(
+                items
+                    .iter()
+                    .map(|item| item.eval(input))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::MultiSelectHash(members) => Json::Dict(
+                members
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), value.eval(input)?)))
+                    .collect::<Result<_, JmesPathError>>()?,
+            ),
+            Expr::ExpRef(_) => null,
+        })
+    }
+}
+
+/// The non-null results of `right` for each value.
+fn project<'a>(
+    values: impl Iterator<Item = &'a Json>,
+    right: &Expr,
+) -> Result<Json, JmesPathError> {
+    let mut projected = Vec::new();
+    for value in values {
+        match right.eval(value)? {
+            Json::Value(Constant::Null) => {}
+            x => projected.push(x),
+        }
+    }
+    Ok(Json::List(projected))
+}
+
+fn call(signature: &Signature, args: &[Arg]) -> Result<Json, JmesPathError> {
+    for (i, arg) in args.iter().enumerate() {
+        let kinds = signature.params[i.min(signature.params.len() - 1)];
+        if !kinds.iter().any(|kind| kind.accepts(arg)) {
+            let actual = match arg {
+                Arg::Value(x) => type_name(x),
+                Arg::ExpRef(_) => "expression",
+            };
+            return Err(JmesPathError::InvalidType(format!(
+                "argument {} of {}() can't be {}",
+                i + 1,
+                signature.name,
+                actual
+            )));
+        }
+    }
+    let values = args
+        .iter()
+        .map(|arg| match arg {
+            Arg::Value(x) => Some(x),
+            Arg::ExpRef(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let null = Json::Value(Constant::Null);
+    let value = |i: usize| values[i].expect("checked to be a value");
+    let items = |i: usize| match value(i) {
+        Json::List(x) => x,
+        _ => unreachable!("checked to be an array"),
+    };
+    let members = |i: usize| match value(i) {
+        Json::Dict(x) => x,
+        _ => unreachable!("checked to be an object"),
+    };
+    let text = |i: usize| string(value(i)).expect("checked to be a string");
+    let numbers = |i: usize| items(i).iter().filter_map(float);
+    let expref = |i: usize| match &args[i] {
+        Arg::ExpRef(expr) => *expr,
+        Arg::Value(_) => unreachable!("checked to be an expression"),
+    };
+    Ok(match signature.name {
+        "abs" => number(float(value(0)).expect("checked").abs()),
+        "avg" => match items(0).len() {
+            0 => null,
+            n => Json::from(numbers(0).sum::<f64>() / n as f64),
+        },
+        "ceil" => number(float(value(0)).expect("checked").ceil()),
+        "floor" => number(float(value(0)).expect("checked").floor()),
+        "contains" => Json::from(match value(0) {
+            Json::List(x) => x.iter().any(|item| equal(item, value(1))),
+            subject => string(value(1))
+                .is_some_and(|search| string(subject).expect("checked").contains(search)),
+        }),
+        "ends_with" => Json::from(text(0).ends_with(text(1))),
+        "starts_with" => Json::from(text(0).starts_with(text(1))),
+        "join" => Json::from(
+            items(1)
+                .iter()
+                .map(|x| string(x).expect("checked"))
+                .collect::<Vec<_>>()
+                .join(text(0)),
+        ),
+        "keys" | "values" => {
+            let mut keys = members(0).keys().collect::<Vec<_>>();
+            keys.sort();
+            Json::List(match signature.name {
+                "keys" => keys
+                    .into_iter()
+                    .map(|key| Json::from(key.as_str()))
+                    .collect(),
+                _ => keys
+                    .into_iter()
+                    .map(|key| members(0)[key].clone())
+                    .collect(),
+            })
+        }
+        "length" => Json::from(match value(0) {
+            Json::Dict(x) => x.len(),
+            Json::List(x) => x.len(),
+            x => string(x).expect("checked").chars().count(),
+        }),
+        "map" => Json::List(
+            items(1)
+                .iter()
+                .map(|item| expref(0).eval(item))
+                .collect::<Result<_, _>>()?,
+        ),
+        "max" | "min" => {
+            let best = items(0).iter().reduce(|best, x| {
+                let order = compare(x, best).unwrap_or(Ordering::Equal);
+                match (signature.name, order) {
+                    ("max", Ordering::Greater) | ("min", Ordering::Less) => x,
+                    _ => best,
+                }
+            });
+            best.cloned().unwrap_or(null)
+        }
+        "max_by" | "min_by" | "sort_by" => {
+            let keyed = sort_keys(items(0), expref(1), signature.name)?;
+            match signature.name {
+                "max_by" => keyed.into_iter().next_back(),
+                "min_by" => keyed.into_iter().next(),
+                _ => return Ok(Json::List(keyed.into_iter().map(|(_, x)| x).collect())),
+            }
+            .map_or(null, |(_, x)| x)
+        }
+        "merge" => {
+            let mut merged = HashMap::new();
+            for i in 0..values.len() {
+                merged.extend(members(i).iter().map(|(k, v)| (k.clone(), v.clone())));
+            }
+            Json::Dict(merged)
+        }
+        "not_null" => values
+            .iter()
+            .flatten()
+            .find(|x| ***x != null)
+            .map_or(null, |x| (*x).clone()),
+        "reverse" => match value(0) {
+            Json::List(x) => Json::List(x.iter().rev().cloned().collect()),
+            x => Json::from(
+                string(x)
+                    .expect("checked")
+                    .chars()
+                    .rev()
+                    .collect::<String>(),
+            ),
+        },
+        "sort" => {
+            let mut sorted = items(0).clone();
+            sorted.sort_by(|a, b| compare(a, b).unwrap_or(Ordering::Equal));
+            Json::List(sorted)
+        }
+        "sum" => number(numbers(0).sum()),
+        "to_array" => match value(0) {
+            Json::List(_) => value(0).clone(),
+            x => Json::List(vec![x.clone()]),
+        },
+        "to_number" => match value(0) {
+            Json::Value(Constant::StringLiteral(x)) => match x.parse::<i64>() {
+                Ok(x) => Json::from(x),
+                Err(_) => parse_f64(x).map_or(null, Json::from),
+            },
+            x if float(x).is_some() => x.clone(),
+            _ => null,
+        },
+        "to_string" => match value(0) {
+            Json::Value(Constant::StringLiteral(_)) => value(0).clone(),
+            x => Json::from(to_string(x)),
+        },
+        "type" => Json::from(type_name(value(0))),
+        _ => unreachable!("functions are checked when parsing"),
+    })
+}
+
+/// The items with their keys from `expr`, stably sorted by the keys, which must be all numbers
+/// or all strings.
+fn sort_keys(items: &[Json], expr: &Expr, name: &str) -> Result<Vec<(Json, Json)>, JmesPathError> {
+    let mut keyed = Vec::with_capacity(items.len());
+    for item in items {
+        let key = expr.eval(item)?;
+        let consistent = match keyed.first() {
+            Some((first, _)) => type_name(first) == type_name(&key),
+            None => true,
+        };
+        if !consistent || !matches!(type_name(&key), "number" | "string") {
+            return Err(JmesPathError::InvalidType(format!(
+                "{}() keys must be all numbers or all strings, got {}",
+                name,
+                type_name(&key)
+            )));
+        }
+        keyed.push((key, item.clone()));
+    }
+    keyed.sort_by(|(a, _), (b, _)| compare(a, b).unwrap_or(Ordering::Equal));
+    Ok(keyed)
+}
+
+impl JmesPath {
+    pub fn parse(expression: &str) -> Result<JmesPath, JmesPathError> {
+        let tokens = tokenize(expression)?;
+        let expr = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        }
+        .program()?;
+        Ok(JmesPath { expr })
+    }
+
+    /// Evaluates the expression against `json`. Object members are projected in key order.
+    pub fn search(&self, json: &Json) -> Result<Json, JmesPathError> {
+        self.expr.eval(json)
+    }
+}
+
+impl FromStr for JmesPath {
+    type Err = JmesPathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        JmesPath::parse(s)
+    }
+}
+
+impl Json {
+    /// The result of the JMESPath `expression`, e.g. ``people[?age > `20`].name``.
+    pub fn search(&self, expression: &str) -> Result<Json, JmesPathError> {
+        JmesPath::parse(expression)?.search(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn people() -> Json {
+        json!({"people": [
+            {"name": "ann", "age": 31, "tags": ["a", "b"]},
+            {"name": "bob", "age": 19, "tags": []},
+            {"name": "cy", "age": 25, "tags": ["b"]}
+        ]})
+    }
+
+    #[test]
+    fn test_search() {
+        let json = people();
+        let cases = [
+            ("people[?age > `20`].name | sort(@)", json!(["ann", "cy"])),
+            ("people[0].tags[-1]", json!("b")),
+            ("people[*].tags[]", json!(["a", "b", "b"])),
+            (
+                "people[1:].{n: name, old: age >= `21`}",
+                json!([
+                    {"n": "bob", "old": false},
+                    {"n": "cy", "old": true}
+                ]),
+            ),
+            (
+                "people[::-2].[name, length(tags)]",
+                json!([["cy", 1], ["ann", 2]]),
+            ),
+            ("max_by(people, &age).name", json!("ann")),
+            ("sort_by(people, &name)[-1].name", json!("cy")),
+            ("map(&join('-', tags), people)", json!(["a-b", "", "b"])),
+            ("people[?!tags].name || 'none'", json!(["bob"])),
+            ("people[?contains(tags, 'a')] | [0].age", json!(31)),
+            ("avg(people[].age)", json!(25.0)),
+            ("missing.{a: a}", json!(null)),
+            ("`{\"a\": [1, 2]}`.a[1]", json!(2)),
+        ];
+        for (expression, expected) in cases {
+            let actual = json.search(expression).unwrap();
+            assert!(equal(&actual, &expected), "{}: {}", expression, actual);
+        }
+        let path = JmesPath::from_str("people[].name").unwrap();
+        assert_eq!(path.search(&json).unwrap(), json!(["ann", "bob", "cy"]));
+    }
+
+    #[test]
+    fn test_errors() {
+        let json = people();
+        let cases = [
+            ("people[", "Syntax error at 7: unexpected end of expression"),
+            (
+                "people.`1`",
+                "Syntax error at 7: unexpected Literal(Value(Int(1)))",
+            ),
+            ("a & b", "Syntax error at 2: unexpected ExpRef"),
+            ("'abc", "Syntax error at 0: unterminated `'`"),
+            ("people[::0]", "Invalid value: slice step is 0"),
+            ("foo(@)", "Unknown function foo()"),
+            (
+                "length(@, @)",
+                "Invalid arity: length() takes 1 arguments, got 2",
+            ),
+            (
+                "not_null()",
+                "Invalid arity: not_null() takes at least 1 arguments, got 0",
+            ),
+            (
+                "abs(people)",
+                "Invalid type: argument 1 of abs() can't be array",
+            ),
+            (
+                "sort_by(people, name)",
+                "Invalid type: argument 2 of sort_by() can't be null",
+            ),
+            (
+                "sort_by(people, &tags)",
+                "Invalid type: sort_by() keys must be all numbers or all strings, got array",
+            ),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(
+                json.search(expression).unwrap_err().to_string(),
+                format!("JmesPathError: {}", expected),
+                "{}",
+                expression
+            );
+        }
+        let nested = |open: &str, close: &str, n| open.repeat(n) + "a" + &close.repeat(n);
+        for (expression, position) in [
+            (nested("(", ")", 100000), MAX_NESTING),
+            (nested("!", "", 100000), MAX_NESTING),
+            (nested("[", "]", 100000), MAX_NESTING),
+            (vec!["a"; 100000].join("."), 2 * MAX_NESTING - 2),
+        ] {
+            assert_eq!(
+                JmesPath::parse(&expression),
+                Err(JmesPathError::Syntax(
+                    position,
+                    "expressions are nested too deeply".to_string()
+                ))
+            );
+        }
+        let json = json!({"a": 1});
+        assert_eq!(json.search(&nested("(", ")", 100)).unwrap(), json!(1));
+        let chain = vec!["a"; 100].join(".");
+        assert_eq!(json.search(&chain).unwrap(), json!(null));
+    }
+}
@@ -0,0 +1,3762 @@
+//! A jq-style language for reshaping documents, e.g.
+//! `.items | map(select(.price > 10) | {name, total: (.price * .count)})`.
+//!
+//! A program turns its input into a stream of results. Object members are visited in sorted key
+//! order, since `Json` objects don't remember the order of their keys.
+//!
+//! Path expressions, which `path(f)`, `del(f)` and the left side of `|=`, `=`, `+=` and the other
+//! assignments take, are built from `.`, indexing, slices, `.[]`, `|`, `,`, `//`, `if`, `try`,
+//! `as`, `getpath`, `limit`, `recurse` and functions of those, such as `select`, `first(f)` and
+//! `..`. `label`, the destructuring alternative `?//` and modules are not supported.
+use crate::lexer::Constant;
+use crate::parser::{parse, Json};
+use crate::patch::compare_numbers;
+use crate::writer::to_string;
+use regex::{Captures, Regex};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::iter::once;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use thiserror::Error;
+
+/// Syntax errors carry the byte offset in the program, runtime errors the value they were raised
+/// with, which is the message for the built-in ones.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum JqError {
+    #[error("JqError: Syntax error at {0}: {1}")]
+    Syntax(usize, String),
+    #[error("JqError: {}", message(.0))]
+    Runtime(Json),
+}
+
+fn message(value: &Json) -> String {
+    match value {
+        Json::Value(Constant::StringLiteral(x)) => x.clone(),
+        _ => format!("{} (not a string)", to_string(value)),
+    }
+}
+
+/// A parsed program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    body: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Identity,
+    Literal(Json),
+    /// A string with interpolations, formatted with `@format` if given.
+    Str(Option<String>, Vec<StrPart>),
+    /// `@format` on its own, applied to the input.
+    Format(String),
+    Var(String),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>),
+    Iterate(Box<Expr>),
+    /// `try body catch handler`, `body?` without a handler.
+    Try(Box<Expr>, Option<Box<Expr>>),
+    Pipe(Box<Expr>, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Alternative(Box<Expr>, Box<Expr>),
+    /// `paths op value`, see `Assignment`.
+    Assign(Box<Expr>, Assignment, Box<Expr>),
+    Array(Option<Box<Expr>>),
+    Object(Vec<(Expr, Expr)>),
+    /// `elif` is a nested `If`, a missing `else` is `Identity`.
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `reduce source as $var (init; update)`
+    Reduce(Box<Expr>, Pattern, Box<Expr>, Box<Expr>),
+    /// `foreach source as $var (init; update; extract)`
+    Foreach(Box<Expr>, Pattern, Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    /// `source as $var | body`
+    Bind(Box<Expr>, Pattern, Box<Expr>),
+    Call(String, Vec<Expr>),
+    /// `def ...; rest`
+    Def(Box<FuncDef>, Box<Expr>),
+}
+
+/// What `as` binds the value to.
+#[derive(Debug, Clone, PartialEq)]
+enum Pattern {
+    Variable(String),
+    /// `[$a, $b]` or `{a: $a, $b}`: patterns for members, by key. `{$b: pattern}` is `$b` and
+    /// `b: pattern`.
+    Destructure(Vec<(Expr, Pattern)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum StrPart {
+    Literal(String),
+    Expr(Expr),
+}
+
+/// Operators parsed by `Parser::operation`.
+#[derive(Clone, Copy)]
+enum Operator {
+    Or,
+    And,
+    Binary(BinaryOp),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// How an assignment changes the values at the paths on its left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assignment {
+    /// `|=`: to the first output of the right side for the old value, or deletes it if there
+    /// is none.
+    Update,
+    /// `=`: to a value of the right side, which sees the input.
+    Set,
+    /// `+=` and the like: to the old value combined with a value of the right side.
+    Arithmetic(BinaryOp),
+    /// `//=`: to a value of the right side if the old value is `false` or `null`.
+    Alternative,
+}
+
+/// A function definition. Value parameters (`$x`) are filter parameters whose outputs the body
+/// binds to variables.
+#[derive(Debug, Clone, PartialEq)]
+struct FuncDef {
+    name: String,
+    params: Vec<String>,
+    body: Expr,
+}
+
+/// Functions implemented in Rust, by name and arity.
+const BUILTINS: &[(&str, usize)] = &[
+    ("empty", 0),
+    ("error", 0),
+    ("error", 1),
+    ("not", 0),
+    ("length", 0),
+    ("utf8bytelength", 0),
+    ("keys", 0),
+    ("keys_unsorted", 0),
+    ("has", 1),
+    ("contains", 1),
+    ("type", 0),
+    ("tostring", 0),
+    ("tojson", 0),
+    ("fromjson", 0),
+    ("tonumber", 0),
+    ("ascii_downcase", 0),
+    ("ascii_upcase", 0),
+    ("explode", 0),
+    ("implode", 0),
+    ("ltrimstr", 1),
+    ("rtrimstr", 1),
+    ("startswith", 1),
+    ("endswith", 1),
+    ("split", 1),
+    ("split", 2),
+    ("join", 1),
+    ("trim", 0),
+    ("ltrim", 0),
+    ("rtrim", 0),
+    ("test", 2),
+    ("match", 2),
+    ("sub", 3),
+    ("indices", 1),
+    ("pow", 2),
+    ("infinite", 0),
+    ("nan", 0),
+    ("isinfinite", 0),
+    ("isnan", 0),
+    ("isnormal", 0),
+    ("sort_by", 1),
+    ("group_by", 1),
+    ("unique_by", 1),
+    ("min_by", 1),
+    ("max_by", 1),
+    ("reverse", 0),
+    ("flatten", 0),
+    ("flatten", 1),
+    ("range", 2),
+    ("range", 3),
+    ("limit", 2),
+    ("until", 2),
+    ("while", 2),
+    ("recurse", 1),
+    ("map_values", 1),
+    ("getpath", 1),
+    ("setpath", 2),
+    ("delpaths", 1),
+    ("path", 1),
+];
+
+type MathFunction = fn(f64) -> f64;
+
+/// Math functions of the input.
+const MATH: &[(&str, MathFunction)] = &[
+    ("floor", f64::floor),
+    ("ceil", f64::ceil),
+    ("round", f64::round),
+    ("trunc", f64::trunc),
+    ("sqrt", f64::sqrt),
+    ("fabs", f64::abs),
+    ("log", f64::ln),
+    ("log2", f64::log2),
+    ("log10", f64::log10),
+    ("exp", f64::exp),
+    ("exp2", f64::exp2),
+    ("exp10", |x| 10f64.powf(x)),
+    ("sin", f64::sin),
+    ("cos", f64::cos),
+    ("tan", f64::tan),
+    ("asin", f64::asin),
+    ("acos", f64::acos),
+    ("atan", f64::atan),
+];
+
+const FORMATS: &[&str] = &[
+    "text", "json", "csv", "tsv", "html", "uri", "sh", "base64", "base64d",
+];
+
+/// The rest of the standard library, in the language itself.
+const PRELUDE: &str = r#"
+def select(f): if f then . else empty end;
+def recurse(f; cond): recurse(f | select(cond));
+def recurse: recurse(.[]?);
+def repeat(f): recurse(f);
+def map(f): [.[] | f];
+def values: select(. != null);
+def nulls: select(. == null);
+def booleans: select(type == "boolean");
+def numbers: select(type == "number");
+def strings: select(type == "string");
+def arrays: select(type == "array");
+def objects: select(type == "object");
+def iterables: select(type | . == "array" or . == "object");
+def scalars: select(type | . != "array" and . != "object");
+def finites: select(isinfinite or isnan | not);
+def to_entries: [keys_unsorted[] as $k | {key: $k, value: .[$k]}];
+def from_entries: reduce .[] as $x ({};
+    . + {($x | if .key == null then .k // .name // .Name // .K // .Key else .key end
+              | if type == "string" then . else tojson end):
+         ($x | if has("value") then .value else .v end)});
+def with_entries(f): to_entries | map(f) | from_entries;
+def add(f): reduce f as $x (null; . + $x);
+def add: add(.[]);
+def first: .[0];
+def last: .[-1];
+def first(f): limit(1; f);
+def last(f): reduce f as $x (null; $x);
+def nth($n): .[$n];
+def nth($n; f):
+    if $n < 0 then error("Out of bounds negative array index") else last(limit($n + 1; f)) end;
+def isempty(g): first((g | false), true);
+def any(generator; condition): isempty(first(generator | condition or empty)) | not;
+def all(generator; condition): isempty(first(generator | condition and empty));
+def any(condition): any(.[]; condition);
+def all(condition): all(.[]; condition);
+def any: any(.);
+def all: all(.);
+def in(xs): . as $x | xs | has($x);
+def inside(xs): . as $x | xs | contains($x);
+def IN(s): any(s == .; .);
+def IN(source; s): any(source == s; .);
+def INDEX(stream; idx_expr): reduce stream as $row ({}; . + {($row | idx_expr | tostring): $row});
+def INDEX(idx_expr): INDEX(.[]; idx_expr);
+def sort: sort_by(.);
+def unique: unique_by(.);
+def min: min_by(.);
+def max: max_by(.);
+def range($upto): range(0; $upto);
+def walk(f): def w: if type == "object" then map_values(w) elif type == "array" then map(w)
+    else . end | f; w;
+def index($i): indices($i) | .[0];
+def rindex($i): indices($i) | .[-1:][0];
+def match($re): match($re; null);
+def test($re): test($re; null);
+def capture($re; $flags):
+    match($re; $flags) | [.captures[] | select(.name != null) | {key: .name, value: .string}]
+    | from_entries;
+def capture($re): capture($re; null);
+def scan($re; $flags):
+    match($re; "g" + $flags)
+    | if (.captures | length) > 0 then [.captures[].string] else .string end;
+def scan($re): scan($re; null);
+def splits($re; $flags): split($re; $flags) | .[];
+def splits($re): splits($re; null);
+def sub($re; str): sub($re; str; "");
+def gsub($re; str; $flags): sub($re; str; $flags + "g");
+def gsub($re; str): sub($re; str; "g");
+def abs: if type == "number" and . < 0 then -. else . end;
+def toarray: if type == "array" then . else [.] end;
+def transpose: [range(0; map(length) | max // 0) as $i | [.[][$i]]];
+def combinations:
+    if length == 0 then [] else .[0][] as $x | (.[1:] | combinations) as $w | [$x] + $w end;
+def combinations(n): . as $dot | [range(n)] | map($dot) | combinations;
+def paths: path(..) | select(length > 0);
+def paths(node_filter): . as $dot | paths | select(. as $p | $dot | getpath($p) | node_filter);
+def leaf_paths: paths(scalars);
+def del(f): delpaths([path(f)]);
+def pick(pathexps): . as $top | reduce path(pathexps) as $p (null; setpath($p; $top | getpath($p)));
+"#;
+
+fn prelude() -> &'static [FuncDef] {
+    static DEFINITIONS: OnceLock<Vec<FuncDef>> = OnceLock::new();
+    DEFINITIONS.get_or_init(|| {
+        let mut parser = Parser::new(PRELUDE, &[]);
+        let mut definitions = Vec::new();
+        while parser.keyword("def") {
+            let def = parser.definition().expect("the prelude is valid");
+            parser.functions.push((def.name.clone(), def.params.len()));
+            definitions.push(def);
+        }
+        parser.skip_blank();
+        assert!(parser.rest().is_empty(), "the prelude only has definitions");
+        definitions
+    })
+}
+
+const KEYWORDS: &[&str] = &[
+    "def", "if", "then", "elif", "else", "end", "as", "reduce", "foreach", "try", "catch", "label",
+    "import", "include", "and", "or", "__loc__",
+];
+
+/// Longer operators first, so that the first match is the whole operator.
+const OPERATORS: &[&str] = &[
+    "//=", "|=", "+=", "-=", "*=", "/=", "%=", "==", "!=", "<=", ">=", "//", "|", "+", "-", "*",
+    "/", "%", "=", "<", ">",
+];
+
+/// Nesting of expressions in a program, bounded so that parsing fails instead of overflowing the
+/// stack.
+const MAX_NESTING: usize = 256;
+
+struct Parser<'s> {
+    input: &'s str,
+    pos: usize,
+    /// Expressions being parsed inside each other, see `deeper`.
+    depth: usize,
+    /// Whether `,` continues the expression. Not in object values, where it separates members.
+    comma: bool,
+    /// Functions in scope by name and arity, besides the library and the builtins.
+    functions: Vec<(String, usize)>,
+    variables: Vec<String>,
+    library: &'s [FuncDef],
+}
+
+type ParseResult<T> = Result<T, JqError>;
+
+impl<'s> Parser<'s> {
+    fn new(input: &'s str, library: &'s [FuncDef]) -> Self {
+        Parser {
+            input,
+            pos: 0,
+            depth: 0,
+            comma: true,
+            functions: Vec::new(),
+            variables: Vec::new(),
+            library,
+        }
+    }
+
+    fn rest(&self) -> &'s str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        let found = self.rest().starts_with(s);
+        if found {
+            self.pos += s.len();
+        }
+        found
+    }
+
+    fn error(&self, message: impl Into<String>) -> JqError {
+        JqError::Syntax(self.pos, message.into())
+    }
+
+    /// Skips whitespace and `#` comments.
+    fn skip_blank(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if !trimmed.starts_with('#') {
+                break;
+            }
+            self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+
+    /// Eats `s` after any blanks.
+    fn symbol(&mut self, s: &str) -> bool {
+        self.skip_blank();
+        self.eat(s)
+    }
+
+    fn expect(&mut self, s: &str) -> ParseResult<()> {
+        match self.symbol(s) {
+            true => Ok(()),
+            false => Err(self.error(format!("expected `{}`", s))),
+        }
+    }
+
+    /// Eats the operator `op` unless it is only the start of a longer one.
+    fn operator(&mut self, op: &str) -> bool {
+        self.skip_blank();
+        match OPERATORS.iter().find(|o| self.rest().starts_with(*o)) {
+            Some(found) if *found == op => {
+                self.pos += op.len();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn ident(&mut self) -> Option<&'s str> {
+        let rest = self.rest();
+        if !rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return None;
+        }
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        self.pos += len;
+        Some(&rest[..len])
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        self.skip_blank();
+        let start = self.pos;
+        match self.ident() {
+            Some(found) if found == word => true,
+            _ => {
+                self.pos = start;
+                false
+            }
+        }
+    }
+
+    fn expect_keyword(&mut self, word: &str) -> ParseResult<()> {
+        match self.keyword(word) {
+            true => Ok(()),
+            false => Err(self.error(format!("expected `{}`", word))),
+        }
+    }
+
+    /// Parses a delimited part of the program, in which `,` is an operator again.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+        let comma = std::mem::replace(&mut self.comma, true);
+        let result = parse(self);
+        self.comma = comma;
+        result
+    }
+
+    /// Parses an expression nested in the current one.
+    fn deeper<T>(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+        if self.depth >= MAX_NESTING {
+            return Err(self.error("expressions are nested too deeply"));
+        }
+        self.depth += 1;
+        let result = with_stack(|| parse(self));
+        self.depth -= 1;
+        result
+    }
+
+    fn program(&mut self) -> ParseResult<Expr> {
+        self.skip_blank();
+        let body = match self.rest().is_empty() {
+            true => Expr::Identity,
+            false => self.pipe()?,
+        };
+        self.skip_blank();
+        match self.peek() {
+            None => Ok(body),
+            Some(c) => Err(self.error(format!("unexpected `{}`", c))),
+        }
+    }
+
+    fn pipe(&mut self) -> ParseResult<Expr> {
+        if self.keyword("def") {
+            let def = self.definition()?;
+            self.functions.push((def.name.clone(), def.params.len()));
+            let rest = self.deeper(Self::pipe)?;
+            self.functions.pop();
+            return Ok(Expr::Def(Box::new(def), Box::new(rest)));
+        }
+        let left = self.comma()?;
+        match self.operator("|") {
+            true => Ok(Expr::Pipe(
+                Box::new(left),
+                Box::new(self.deeper(Self::pipe)?),
+            )),
+            false => Ok(left),
+        }
+    }
+
+    /// After `def`, up to and including the `;`.
+    fn definition(&mut self) -> ParseResult<FuncDef> {
+        self.skip_blank();
+        let name = match self.ident() {
+            Some(name) if !KEYWORDS.contains(&name) => name.to_string(),
+            _ => return Err(self.error("expected a function name")),
+        };
+        // parameter names, and whether they are value parameters
+        let mut params = Vec::new();
+        if self.symbol("(") {
+            loop {
+                let value = self.symbol("$");
+                let param = self
+                    .ident()
+                    .ok_or_else(|| self.error("expected a parameter name"))?;
+                params.push((param.to_string(), value));
+                if !self.symbol(";") {
+                    break;
+                }
+            }
+            self.expect(")")?;
+        }
+        self.expect(":")?;
+        let scope = (self.functions.len(), self.variables.len());
+        self.functions.push((name.clone(), params.len()));
+        for (param, value) in &params {
+            self.functions.push((param.clone(), 0));
+            if *value {
+                self.variables.push(param.clone());
+            }
+        }
+        let body = self.nested(Self::pipe)?;
+        self.expect(";")?;
+        self.functions.truncate(scope.0);
+        self.variables.truncate(scope.1);
+        // `def f($a): body` is `def f(a): a as $a | body`
+        let body =
+            params
+                .iter()
+                .rev()
+                .filter(|(_, value)| *value)
+                .fold(body, |body, (param, _)| {
+                    let source = Expr::Call(param.clone(), Vec::new());
+                    let pattern = Pattern::Variable(param.clone());
+                    Expr::Bind(Box::new(source), pattern, Box::new(body))
+                });
+        Ok(FuncDef {
+            name,
+            params: params.into_iter().map(|(param, _)| param).collect(),
+            body,
+        })
+    }
+
+    fn comma(&mut self) -> ParseResult<Expr> {
+        let mut left = self.alternative()?;
+        while self.comma && self.symbol(",") {
+            left = Expr::Comma(Box::new(left), Box::new(self.alternative()?));
+        }
+        Ok(left)
+    }
+
+    /// `//` and, binding more tightly, assignments.
+    fn alternative(&mut self) -> ParseResult<Expr> {
+        let mut left = self.operation(1)?;
+        if let Some(op) = self.assignment() {
+            let right = self.operation(1)?;
+            left = Expr::Assign(Box::new(left), op, Box::new(right));
+            if self.assignment().is_some() {
+                return Err(self.error("assignments can't be chained"));
+            }
+        }
+        match self.operator("//") {
+            true => Ok(Expr::Alternative(
+                Box::new(left),
+                Box::new(self.deeper(Self::alternative)?),
+            )),
+            false => Ok(left),
+        }
+    }
+
+    /// The assignment operator at the current position, which is then consumed.
+    fn assignment(&mut self) -> Option<Assignment> {
+        let operators = [
+            ("|=", Assignment::Update),
+            ("=", Assignment::Set),
+            ("+=", Assignment::Arithmetic(BinaryOp::Add)),
+            ("-=", Assignment::Arithmetic(BinaryOp::Sub)),
+            ("*=", Assignment::Arithmetic(BinaryOp::Mul)),
+            ("/=", Assignment::Arithmetic(BinaryOp::Div)),
+            ("%=", Assignment::Arithmetic(BinaryOp::Mod)),
+            ("//=", Assignment::Alternative),
+        ];
+        let (_, op) = operators
+            .into_iter()
+            .find(|(symbol, _)| self.operator(symbol))?;
+        Some(op)
+    }
+
+    /// The binary operator at the current position if it binds at least as tightly as
+    /// `min_precedence`, which is then consumed.
+    fn binary_operator(&mut self, min_precedence: u8) -> Option<(Operator, u8)> {
+        let start = self.pos;
+        let found = match () {
+            _ if self.keyword("or") => (Operator::Or, 1),
+            _ if self.keyword("and") => (Operator::And, 2),
+            _ => {
+                let operators = [
+                    ("==", BinaryOp::Eq, 3),
+                    ("!=", BinaryOp::Ne, 3),
+                    ("<=", BinaryOp::Le, 3),
+                    (">=", BinaryOp::Ge, 3),
+                    ("<", BinaryOp::Lt, 3),
+                    (">", BinaryOp::Gt, 3),
+                    ("+", BinaryOp::Add, 4),
+                    ("-", BinaryOp::Sub, 4),
+                    ("*", BinaryOp::Mul, 5),
+                    ("/", BinaryOp::Div, 5),
+                    ("%", BinaryOp::Mod, 5),
+                ];
+                let (_, op, precedence) = operators
+                    .into_iter()
+                    .find(|(symbol, _, _)| self.operator(symbol))?;
+                (Operator::Binary(op), precedence)
+            }
+        };
+        if found.1 < min_precedence {
+            self.pos = start;
+            return None;
+        }
+        Some(found)
+    }
+
+    /// `or`, `and`, comparisons and arithmetic, by precedence climbing so that the nesting of
+    /// parentheses and not the number of precedence levels determines the depth of recursion.
+    /// Comparisons don't chain: `1 < 2 < 3` is an error.
+    fn operation(&mut self, min_precedence: u8) -> ParseResult<Expr> {
+        let mut left = self.unary()?;
+        let mut compared = false;
+        while let Some((op, precedence)) = self.binary_operator(min_precedence) {
+            if precedence == 3 && std::mem::replace(&mut compared, true) {
+                return Err(self.error("comparisons can't be chained"));
+            }
+            let right = Box::new(self.operation(precedence + 1)?);
+            let left_box = Box::new(left);
+            left = match op {
+                Operator::Or => Expr::Or(left_box, right),
+                Operator::And => Expr::And(left_box, right),
+                Operator::Binary(op) => Expr::Binary(left_box, op, right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> ParseResult<Expr> {
+        match self.operator("-") {
+            true => Ok(Expr::Neg(Box::new(self.deeper(Self::unary)?))),
+            false => self.postfix(true),
+        }
+    }
+
+    /// A term with its suffixes (`.name`, `[...]`, `?`), and if `bind` is set, `as $x | body`.
+    /// Every parenthesized or bracketed expression is parsed inside of one.
+    fn postfix(&mut self, bind: bool) -> ParseResult<Expr> {
+        self.deeper(|parser| parser.suffixes(bind))
+    }
+
+    fn suffixes(&mut self, bind: bool) -> ParseResult<Expr> {
+        let mut expr = self.term()?;
+        loop {
+            self.skip_blank();
+            let rest = self.rest();
+            if self.eat("?") {
+                expr = Expr::Try(Box::new(expr), None);
+            } else if self.eat("[") {
+                expr = self.bracket(expr)?;
+            } else if rest.starts_with('.')
+                && rest[1..].starts_with(|c: char| {
+                    c.is_ascii_alphabetic() || c == '_' || c == '"' || c == '['
+                })
+            {
+                self.pos += 1;
+                expr = self.member(expr)?;
+            } else {
+                break;
+            }
+        }
+        match bind && self.keyword("as") {
+            true => self.bind(expr),
+            false => Ok(expr),
+        }
+    }
+
+    /// After `source as`, up to the end of the body.
+    fn bind(&mut self, source: Expr) -> ParseResult<Expr> {
+        let scope = self.variables.len();
+        let pattern = self.pattern()?;
+        let body = match self.operator("|") {
+            true => self.pipe(),
+            false => Err(self.error("expected `|`")),
+        };
+        self.variables.truncate(scope);
+        Ok(Expr::Bind(Box::new(source), pattern, Box::new(body?)))
+    }
+
+    /// After the `.` of `target.name` or `target.[...]`.
+    fn member(&mut self, target: Expr) -> ParseResult<Expr> {
+        match self.peek() {
+            Some('[') => {
+                self.pos += 1;
+                self.bracket(target)
+            }
+            _ => {
+                let key = self.field()?;
+                Ok(Expr::Index(Box::new(target), Box::new(key)))
+            }
+        }
+    }
+
+    /// The pattern after `as`. Its variables are in scope from where they are bound on.
+    fn pattern(&mut self) -> ParseResult<Pattern> {
+        self.skip_blank();
+        let mut members = Vec::new();
+        if self.eat("[") {
+            loop {
+                let key = Expr::Literal(Json::from(members.len()));
+                members.push((key, self.deeper(Self::pattern)?));
+                if !self.symbol(",") {
+                    break;
+                }
+            }
+            self.expect("]")?;
+            return Ok(Pattern::Destructure(members));
+        }
+        if !self.eat("{") {
+            let name = self.variable()?;
+            self.variables.push(name.clone());
+            return Ok(Pattern::Variable(name));
+        }
+        loop {
+            self.skip_blank();
+            // the key, and whether it is a variable, which needs no pattern of its own
+            let (key, bound) = match self.peek() {
+                Some('$') => {
+                    let name = self.variable()?;
+                    self.variables.push(name.clone());
+                    let key = Expr::Literal(Json::from(name.as_str()));
+                    members.push((key.clone(), Pattern::Variable(name)));
+                    (key, true)
+                }
+                Some('"') => (self.string(None)?, false),
+                Some('(') => {
+                    self.pos += 1;
+                    let key = self.nested(Self::pipe)?;
+                    self.expect(")")?;
+                    (key, false)
+                }
+                _ => match self.ident() {
+                    Some(name) => (Expr::Literal(Json::from(name)), false),
+                    None => return Err(self.error("expected an object key")),
+                },
+            };
+            if self.symbol(":") {
+                members.push((key, self.deeper(Self::pattern)?));
+            } else if !bound {
+                return Err(self.error("expected `:`"));
+            }
+            if !self.symbol(",") {
+                break;
+            }
+        }
+        self.expect("}")?;
+        Ok(Pattern::Destructure(members))
+    }
+
+    /// `$name`, returning the name.
+    fn variable(&mut self) -> ParseResult<String> {
+        self.expect("$")?;
+        match self.ident() {
+            Some(name) => Ok(name.to_string()),
+            None => Err(self.error("expected a variable name")),
+        }
+    }
+
+    /// The name or string after `.`, as the key to index with.
+    fn field(&mut self) -> ParseResult<Expr> {
+        match self.peek() {
+            Some('"') => self.string(None),
+            _ => match self.ident() {
+                Some(name) => Ok(Expr::Literal(Json::from(name))),
+                None => Err(self.error("expected a field name")),
+            },
+        }
+    }
+
+    /// After `[`: an iteration, index or slice of `target`.
+    fn bracket(&mut self, target: Expr) -> ParseResult<Expr> {
+        let target = Box::new(target);
+        if self.symbol("]") {
+            return Ok(Expr::Iterate(target));
+        }
+        if self.symbol(":") {
+            let to = self.nested(Self::pipe)?;
+            self.expect("]")?;
+            return Ok(Expr::Slice(target, None, Some(Box::new(to))));
+        }
+        let index = Box::new(self.nested(Self::pipe)?);
+        if !self.symbol(":") {
+            self.expect("]")?;
+            return Ok(Expr::Index(target, index));
+        }
+        let to = match self.symbol("]") {
+            true => None,
+            false => {
+                let to = self.nested(Self::pipe)?;
+                self.expect("]")?;
+                Some(Box::new(to))
+            }
+        };
+        Ok(Expr::Slice(target, Some(index), to))
+    }
+
+    fn term(&mut self) -> ParseResult<Expr> {
+        self.skip_blank();
+        let start = self.pos;
+        let Some(c) = self.peek() else {
+            return Err(self.error("unexpected end of program"));
+        };
+        match c {
+            '.' if self.eat("..") => Ok(Expr::Call("recurse".to_string(), Vec::new())),
+            '.' => {
+                self.pos += 1;
+                match self.peek() {
+                    Some(c) if c.is_ascii_digit() => {
+                        self.pos = start;
+                        self.number()
+                    }
+                    Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '"' => {
+                        let key = self.field()?;
+                        Ok(Expr::Index(Box::new(Expr::Identity), Box::new(key)))
+                    }
+                    _ => Ok(Expr::Identity),
+                }
+            }
+            '0'..='9' => self.number(),
+            '"' => self.string(None),
+            '@' => {
+                self.pos += 1;
+                let format = self
+                    .ident()
+                    .filter(|name| FORMATS.contains(name))
+                    .ok_or_else(|| JqError::Syntax(start, "unknown format".to_string()))?;
+                self.skip_blank();
+                match self.peek() {
+                    Some('"') => self.string(Some(format.to_string())),
+                    _ => Ok(Expr::Format(format.to_string())),
+                }
+            }
+            '$' => {
+                self.pos += 1;
+                let name = self
+                    .ident()
+                    .ok_or_else(|| self.error("expected a variable name"))?;
+                match self.variables.iter().any(|v| v == name) {
+                    true => Ok(Expr::Var(name.to_string())),
+                    false => Err(JqError::Syntax(start, format!("${} is not defined", name))),
+                }
+            }
+            '(' => {
+                self.pos += 1;
+                let expr = self.nested(Self::pipe)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            '[' => {
+                self.pos += 1;
+                if self.symbol("]") {
+                    return Ok(Expr::Array(None));
+                }
+                let expr = self.nested(Self::pipe)?;
+                self.expect("]")?;
+                Ok(Expr::Array(Some(Box::new(expr))))
+            }
+            '{' => {
+                self.pos += 1;
+                self.nested(Self::object)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => self.named(),
+            c => Err(self.error(format!("unexpected `{}`", c))),
+        }
+    }
+
+    fn number(&mut self) -> ParseResult<Expr> {
+        let start = self.pos;
+        let digits = |parser: &mut Self| {
+            let rest = parser.rest();
+            let count = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            parser.pos += count;
+            count
+        };
+        let mut integer = digits(self) > 0;
+        if self.eat(".") {
+            digits(self);
+            integer = false;
+        }
+        if self.eat("e") || self.eat("E") {
+            let _ = self.eat("+") || self.eat("-");
+            if digits(self) == 0 {
+                return Err(self.error("expected digits"));
+            }
+            integer = false;
+        }
+        let text = &self.input[start..self.pos];
+        let value = match text.parse::<i64>() {
+            Ok(x) if integer => Json::from(x),
+            _ => number(
+                text.parse::<f64>()
+                    .map_err(|_| JqError::Syntax(start, "invalid number".to_string()))?,
+            ),
+        };
+        Ok(Expr::Literal(value))
+    }
+
+    /// A string literal with `\(...)` interpolations, formatted with `@format` if given.
+    fn string(&mut self, format: Option<String>) -> ParseResult<Expr> {
+        self.pos += 1; // the opening quote
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        loop {
+            let c = match self.next() {
+                Some('"') => break,
+                Some('\\') => match self.next() {
+                    Some('(') => {
+                        if !literal.is_empty() {
+                            parts.push(StrPart::Literal(std::mem::take(&mut literal)));
+                        }
+                        parts.push(StrPart::Expr(self.nested(Self::pipe)?));
+                        self.expect(")")?;
+                        continue;
+                    }
+                    Some(c @ ('"' | '\\' | '/')) => c,
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => self.unicode_escape()?,
+                    _ => return Err(self.error("invalid escape sequence")),
+                },
+                Some(c) => c,
+                None => return Err(self.error("unterminated string")),
+            };
+            literal.push(c);
+        }
+        if !literal.is_empty() {
+            parts.push(StrPart::Literal(literal));
+        }
+        if format.is_none() {
+            match parts.as_slice() {
+                [] => return Ok(Expr::Literal(Json::from(""))),
+                [StrPart::Literal(s)] => return Ok(Expr::Literal(Json::from(s.as_str()))),
+                _ => {}
+            }
+        }
+        Ok(Expr::Str(format, parts))
+    }
+
+    /// The character of a `\uXXXX` escape (after the `\u`), which may be a surrogate pair.
+    fn unicode_escape(&mut self) -> ParseResult<char> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xD800..=0xDBFF if self.eat("\\u") => match self.hex4()? {
+                low @ 0xDC00..=0xDFFF => 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00),
+                _ => return Err(self.error("expected a low surrogate")),
+            },
+            _ => high,
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid code point"))
+    }
+
+    fn hex4(&mut self) -> ParseResult<u32> {
+        let hex = self
+            .rest()
+            .get(..4)
+            .filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("expected 4 hex digits"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(hex, 16).expect("checked digits"))
+    }
+
+    /// After `{`, up to and including the `}`.
+    fn object(&mut self) -> ParseResult<Expr> {
+        let mut entries = Vec::new();
+        if self.symbol("}") {
+            return Ok(Expr::Object(entries));
+        }
+        loop {
+            self.skip_blank();
+            // the key, and the value if there's no `:` (`{a}` is `{a: .a}`)
+            let (key, shorthand) = match self.peek() {
+                Some('$') => match self.term()? {
+                    Expr::Var(name) => (
+                        Expr::Literal(Json::from(name.as_str())),
+                        Some(Expr::Var(name)),
+                    ),
+                    _ => unreachable!("`$` starts a variable"),
+                },
+                Some('"') => {
+                    let key = self.string(None)?;
+                    let value = Expr::Index(Box::new(Expr::Identity), Box::new(key.clone()));
+                    (key, Some(value))
+                }
+                Some('(') => {
+                    self.pos += 1;
+                    let key = self.pipe()?;
+                    self.expect(")")?;
+                    (key, None)
+                }
+                _ => match self.ident() {
+                    Some(name) => {
+                        let key = Expr::Literal(Json::from(name));
+                        let value = Expr::Index(Box::new(Expr::Identity), Box::new(key.clone()));
+                        (key, Some(value))
+                    }
+                    None => return Err(self.error("expected an object key")),
+                },
+            };
+            let value = match self.symbol(":") {
+                true => {
+                    let comma = std::mem::replace(&mut self.comma, false);
+                    let value = self.pipe();
+                    self.comma = comma;
+                    value?
+                }
+                false => shorthand.ok_or_else(|| self.error("expected `:`"))?,
+            };
+            entries.push((key, value));
+            if !self.symbol(",") {
+                self.expect("}")?;
+                return Ok(Expr::Object(entries));
+            }
+        }
+    }
+
+    /// Keywords, literals and function calls.
+    fn named(&mut self) -> ParseResult<Expr> {
+        let start = self.pos;
+        let name = self.ident().expect("called at an identifier");
+        match name {
+            "true" => Ok(Expr::Literal(Json::from(true))),
+            "false" => Ok(Expr::Literal(Json::from(false))),
+            "null" => Ok(Expr::Literal(Json::Value(Constant::Null))),
+            "if" => self.conditional(),
+            "try" => {
+                let body = Box::new(self.postfix(false)?);
+                let handler = match self.keyword("catch") {
+                    true => Some(Box::new(self.postfix(false)?)),
+                    false => None,
+                };
+                Ok(Expr::Try(body, handler))
+            }
+            "reduce" | "foreach" => self.fold(name == "reduce"),
+            "label" | "import" | "include" | "__loc__" => Err(JqError::Syntax(
+                start,
+                format!("`{}` is not supported", name),
+            )),
+            _ if KEYWORDS.contains(&name) => {
+                Err(JqError::Syntax(start, format!("unexpected `{}`", name)))
+            }
+            _ => {
+                let mut args = Vec::new();
+                if self.symbol("(") {
+                    loop {
+                        args.push(self.nested(Self::pipe)?);
+                        if !self.symbol(";") {
+                            break;
+                        }
+                    }
+                    self.expect(")")?;
+                }
+                let defined = self
+                    .functions
+                    .iter()
+                    .any(|(f, n)| f == name && *n == args.len())
+                    || self
+                        .library
+                        .iter()
+                        .any(|def| def.name == name && def.params.len() == args.len())
+                    || BUILTINS.contains(&(name, args.len()))
+                    || (args.is_empty() && MATH.iter().any(|(f, _)| *f == name));
+                match defined {
+                    true => Ok(Expr::Call(name.to_string(), args)),
+                    false => Err(JqError::Syntax(
+                        start,
+                        format!("{}/{} is not defined", name, args.len()),
+                    )),
+                }
+            }
+        }
+    }
+
+    /// After `if` or `elif`, up to and including the `end`.
+    fn conditional(&mut self) -> ParseResult<Expr> {
+        let condition = self.nested(Self::pipe)?;
+        self.expect_keyword("then")?;
+        let then = self.nested(Self::pipe)?;
+        let otherwise = if self.keyword("elif") {
+            self.deeper(Self::conditional)?
+        } else if self.keyword("else") {
+            let otherwise = self.nested(Self::pipe)?;
+            self.expect_keyword("end")?;
+            otherwise
+        } else {
+            self.expect_keyword("end")?;
+            Expr::Identity
+        };
+        Ok(Expr::If(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// After `reduce` or `foreach`. The variables are only in scope after the initial value.
+    fn fold(&mut self, reduce: bool) -> ParseResult<Expr> {
+        let source = Box::new(self.postfix(false)?);
+        self.expect_keyword("as")?;
+        let scope = self.variables.len();
+        let pattern = self.pattern()?;
+        let bound = self.variables.split_off(scope);
+        self.expect("(")?;
+        let init = Box::new(self.nested(Self::pipe)?);
+        self.expect(";")?;
+        self.variables.extend(bound);
+        let update = Box::new(self.nested(Self::pipe)?);
+        let extract = match !reduce && self.symbol(";") {
+            true => Some(Box::new(self.nested(Self::pipe)?)),
+            false => None,
+        };
+        self.variables.truncate(scope);
+        self.expect(")")?;
+        Ok(match reduce {
+            true => Expr::Reduce(source, pattern, init, update),
+            false => Expr::Foreach(source, pattern, init, update, extract),
+        })
+    }
+}
+
+/// Why evaluation stopped early: an error, or a `limit` that has seen enough outputs.
+enum Stop {
+    Error(Json),
+    Break(usize),
+}
+
+type Eval = Result<(), Stop>;
+
+/// Where the outputs of an expression go.
+type Sink<'a> = &'a mut dyn FnMut(Json) -> Eval;
+
+/// The keys leading from the input to a value.
+type Path = Vec<Json>;
+
+/// Where the outputs of a path expression go, with the values at the paths.
+type PathSink<'a> = &'a mut dyn FnMut((Path, Json)) -> Eval;
+
+fn error(message: impl Into<String>) -> Stop {
+    Stop::Error(Json::from(message.into()))
+}
+
+/// Bindings in scope, innermost first.
+#[derive(Clone, Default)]
+struct Env<'e>(Option<Rc<Scope<'e>>>);
+
+struct Scope<'e> {
+    binding: Binding<'e>,
+    parent: Env<'e>,
+}
+
+enum Binding<'e> {
+    Variable(&'e str, Json),
+    Function(&'e FuncDef),
+    /// A filter argument, with the bindings of the call.
+    Argument(&'e str, &'e Expr, Env<'e>),
+}
+
+enum Callable<'e> {
+    /// A definition with the bindings it sees, itself included.
+    Function(&'e FuncDef, Env<'e>),
+    Argument(&'e Expr, Env<'e>),
+}
+
+impl<'e> Env<'e> {
+    fn bind(&self, binding: Binding<'e>) -> Env<'e> {
+        Env(Some(Rc::new(Scope {
+            binding,
+            parent: self.clone(),
+        })))
+    }
+
+    fn variable(&self, name: &str) -> Option<&Json> {
+        let mut env = self;
+        while let Some(scope) = &env.0 {
+            match &scope.binding {
+                Binding::Variable(var, value) if *var == name => return Some(value),
+                _ => env = &scope.parent,
+            }
+        }
+        None
+    }
+
+    fn function(&self, name: &str, arity: usize) -> Option<Callable<'e>> {
+        let mut env = self;
+        while let Some(scope) = &env.0 {
+            match &scope.binding {
+                Binding::Function(def) if def.name == name && def.params.len() == arity => {
+                    return Some(Callable::Function(def, env.clone()))
+                }
+                Binding::Argument(param, body, closure) if *param == name && arity == 0 => {
+                    return Some(Callable::Argument(body, closure.clone()))
+                }
+                _ => env = &scope.parent,
+            }
+        }
+        None
+    }
+}
+
+/// The longest string, in bytes, that multiplying a string by a number may produce.
+const MAX_REPEAT_LENGTH: usize = 1 << 30;
+
+fn library_function(name: &str, arity: usize) -> Option<&'static FuncDef> {
+    prelude()
+        .iter()
+        .find(|def| def.name == name && def.params.len() == arity)
+}
+
+/// The function or filter argument a call refers to, `None` for builtins.
+fn resolve<'e>(name: &str, arity: usize, env: &Env<'e>) -> Option<Callable<'e>> {
+    env.function(name, arity).or_else(|| {
+        library_function(name, arity).map(|def| Callable::Function(def, Env::default()))
+    })
+}
+
+/// `scope` with the parameters of `def` bound to the filters `args` of a call in `env`.
+fn arguments<'e>(def: &'e FuncDef, scope: Env<'e>, args: &'e [Expr], env: &Env<'e>) -> Env<'e> {
+    def.params
+        .iter()
+        .zip(args)
+        .fold(scope, |scope, (param, arg)| {
+            scope.bind(Binding::Argument(param, arg, env.clone()))
+        })
+}
+
+/// Nesting of function calls, bounded so that runaway recursion fails instead of using up
+/// memory.
+const MAX_CALL_DEPTH: usize = 512;
+
+/// Runs `f`, first moving to a new stack segment on the heap if the current stack is nearly used
+/// up. Parsing and evaluation recurse with the program, and each level takes kilobytes of stack,
+/// tens of them in debug builds, so the stack of the calling thread isn't always enough.
+fn with_stack<T>(f: impl FnOnce() -> T) -> T {
+    stacker::maybe_grow(256 << 10, 4 << 20, f)
+}
+
+#[derive(Default)]
+struct Interpreter {
+    regexes: RefCell<HashMap<String, Regex>>,
+    /// Function calls being evaluated.
+    depth: Cell<usize>,
+    /// The number of `limit`s so far, to tell whose `Stop::Break` is whose.
+    limits: Cell<usize>,
+}
+
+/// A step of a recursive generator such as `recurse(f)`, which is run with a stack instead of
+/// recursing.
+enum Task<T> {
+    Emit(T),
+    Visit(T),
+}
+
+/// What a step of a recursive generator leads to, in order.
+type Steps<T> = Result<Vec<Task<T>>, Stop>;
+
+impl Interpreter {
+    fn eval<'e>(&self, expr: &'e Expr, input: &Json, env: &Env<'e>, out: Sink) -> Eval {
+        with_stack(|| self.dispatch(expr, input, env, out))
+    }
+
+    /// Dispatches on `expr`. Anything that needs locals is evaluated by a function of its own,
+    /// so that recursion through `eval` takes little stack.
+    fn dispatch<'e>(&self, expr: &'e Expr, input: &Json, env: &Env<'e>, out: Sink) -> Eval {
+        match expr {
+            Expr::Identity => out(input.clone()),
+            Expr::Literal(x) => out(x.clone()),
+            Expr::Str(format, parts) => {
+                self.interpolate(format.as_deref(), parts, String::new(), input, env, out)
+            }
+            Expr::Format(name) => self.format(name, input, out),
+            Expr::Var(name) => out(env
+                .variable(name)
+                .expect("variables are checked when parsing")
+                .clone()),
+            Expr::Index(target, key) => self.index(target, key, input, env, out),
+            Expr::Slice(target, from, to) => {
+                self.slice(target, from.as_deref(), to.as_deref(), input, env, out)
+            }
+            Expr::Iterate(target) => self.iterate(target, input, env, out),
+            Expr::Try(body, handler) => self.try_catch(body, handler.as_deref(), input, env, out),
+            Expr::Pipe(left, right) => self.pipe(left, right, input, env, out),
+            Expr::Comma(left, right) => self.comma(left, right, input, env, out),
+            Expr::Neg(operand) => self.negate(operand, input, env, out),
+            Expr::Binary(left, op, right) => self.binary(left, *op, right, input, env, out),
+            Expr::And(left, right) => self.and(left, right, input, env, out),
+            Expr::Or(left, right) => self.or(left, right, input, env, out),
+            Expr::Alternative(left, right) => self.alternative(left, right, input, env, out),
+            Expr::Assign(paths, op, value) => self.assign(paths, *op, value, input, env, out),
+            Expr::Array(None) => out(Json::List(Vec::new())),
+            Expr::Array(Some(items)) => self.array(items, input, env, out),
+            Expr::Object(entries) => self.object(entries, input, env, &HashMap::new(), out),
+            Expr::If(condition, then, otherwise) => {
+                self.conditional(condition, then, otherwise, input, env, out)
+            }
+            Expr::Reduce(source, pattern, init, update) => {
+                self.reduce(source, pattern, init, update, input, env, out)
+            }
+            Expr::Foreach(source, pattern, init, update, extract) => {
+                let extract = extract.as_deref();
+                self.foreach(source, pattern, init, update, extract, input, env, out)
+            }
+            Expr::Bind(source, pattern, body) => self.bind(source, pattern, body, input, env, out),
+            Expr::Call(name, args) => self.call(name, args, input, env, out),
+            Expr::Def(def, rest) => self.define(def, rest, input, env, out),
+        }
+    }
+
+    fn format(&self, name: &str, input: &Json, out: Sink) -> Eval {
+        out(Json::from(apply_format(name, input)?))
+    }
+
+    fn index<'e>(
+        &self,
+        target: &'e Expr,
+        key: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        self.eval(target, input, env, &mut |target| {
+            self.eval(key, input, env, &mut |key| out(index(&target, &key)?))
+        })
+    }
+
+    fn slice<'e>(
+        &self,
+        target: &'e Expr,
+        from: Option<&'e Expr>,
+        to: Option<&'e Expr>,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        let null = Expr::Literal(Json::Value(Constant::Null));
+        self.eval(target, input, env, &mut |target| {
+            self.eval(from.unwrap_or(&null), input, env, &mut |from| {
+                self.eval(to.unwrap_or(&null), input, env, &mut |to| {
+                    out(slice(&target, &from, &to)?)
+                })
+            })
+        })
+    }
+
+    fn iterate<'e>(&self, target: &'e Expr, input: &Json, env: &Env<'e>, out: Sink) -> Eval {
+        self.eval(target, input, env, &mut |target| {
+            iterate(&target)?.into_iter().try_for_each(&mut *out)
+        })
+    }
+
+    fn try_catch<'e>(
+        &self,
+        body: &'e Expr,
+        handler: Option<&'e Expr>,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        match self.attempt(|out| self.eval(body, input, env, out), out)? {
+            Err(e) => match handler {
+                Some(handler) => self.eval(handler, &e, env, out),
+                None => Ok(()),
+            },
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn pipe<'e>(
+        &self,
+        left: &'e Expr,
+        right: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        self.eval(left, input, env, &mut |x| self.eval(right, &x, env, out))
+    }
+
+    fn comma<'e>(
+        &self,
+        left: &'e Expr,
+        right: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        self.eval(left, input, env, out)?;
+        self.eval(right, input, env, out)
+    }
+
+    fn negate<'e>(&self, operand: &'e Expr, input: &Json, env: &Env<'e>, out: Sink) -> Eval {
+        self.eval(operand, input, env, &mut |x| out(negate(&x)?))
+    }
+
+    fn binary<'e>(
+        &self,
+        left: &'e Expr,
+        op: BinaryOp,
+        right: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        // like jq, the right operand varies slowest
+        self.eval(right, input, env, &mut |b| {
+            self.eval(left, input, env, &mut |a| out(binary(op, &a, &b)?))
+        })
+    }
+
+    fn and<'e>(
+        &self,
+        left: &'e Expr,
+        right: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        self.eval(left, input, env, &mut |a| match truthy(&a) {
+            true => self.eval(right, input, env, &mut |b| out(Json::from(truthy(&b)))),
+            false => out(Json::from(false)),
+        })
+    }
+
+    fn or<'e>(
+        &self,
+        left: &'e Expr,
+        right: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        self.eval(left, input, env, &mut |a| match truthy(&a) {
+            true => out(Json::from(true)),
+            false => self.eval(right, input, env, &mut |b| out(Json::from(truthy(&b)))),
+        })
+    }
+
+    fn alternative<'e>(
+        &self,
+        left: &'e Expr,
+        right: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        // errors on the left count as no output
+        let mut any = false;
+        let _ = self.attempt(
+            |out| self.eval(left, input, env, out),
+            &mut |x| match truthy(&x) {
+                true => {
+                    any = true;
+                    out(x)
+                }
+                false => Ok(()),
+            },
+        )?;
+        match any {
+            true => Ok(()),
+            false => self.eval(right, input, env, out),
+        }
+    }
+
+    fn assign<'e>(
+        &self,
+        paths: &'e Expr,
+        op: Assignment,
+        value: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        if op == Assignment::Update {
+            // like jq, deletions wait until the end so that they don't move later paths
+            let (mut result, mut deleted) = (input.clone(), Vec::new());
+            for path in self.collect_paths(paths, input, env)? {
+                let old = getpath(&result, &path)?;
+                match self.first(value, &old, env)? {
+                    Some(new) => setpath(&mut result, &path, new)?,
+                    None => deleted.push(Json::List(path)),
+                }
+            }
+            delpaths(&mut result, deleted)?;
+            return out(result);
+        }
+        self.eval(value, input, env, &mut |value| {
+            let mut result = input.clone();
+            for path in self.collect_paths(paths, input, env)? {
+                let new = match op {
+                    Assignment::Arithmetic(op) => binary(op, &getpath(&result, &path)?, &value)?,
+                    Assignment::Alternative => {
+                        let old = getpath(&result, &path)?;
+                        match truthy(&old) {
+                            true => old,
+                            false => value.clone(),
+                        }
+                    }
+                    _ => value.clone(),
+                };
+                setpath(&mut result, &path, new)?;
+            }
+            out(result)
+        })
+    }
+
+    fn array<'e>(&self, items: &'e Expr, input: &Json, env: &Env<'e>, out: Sink) -> Eval {
+        out(Json::List(self.collect(items, input, env)?))
+    }
+
+    fn conditional<'e>(
+        &self,
+        condition: &'e Expr,
+        then: &'e Expr,
+        otherwise: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        self.eval(condition, input, env, &mut |x| match truthy(&x) {
+            true => self.eval(then, input, env, out),
+            false => self.eval(otherwise, input, env, out),
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn reduce<'e>(
+        &self,
+        source: &'e Expr,
+        pattern: &'e Pattern,
+        init: &'e Expr,
+        update: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        self.eval(init, input, env, &mut |mut state| {
+            self.eval(source, input, env, &mut |x| {
+                self.destructure(pattern, &x, input, env.clone(), &mut |env| {
+                    let mut last = Json::Value(Constant::Null);
+                    self.eval(update, &state, &env, &mut |y| {
+                        last = y;
+                        Ok(())
+                    })?;
+                    state = last;
+                    Ok(())
+                })
+            })?;
+            out(state)
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn foreach<'e>(
+        &self,
+        source: &'e Expr,
+        pattern: &'e Pattern,
+        init: &'e Expr,
+        update: &'e Expr,
+        extract: Option<&'e Expr>,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        self.eval(init, input, env, &mut |mut state| {
+            self.eval(source, input, env, &mut |x| {
+                self.destructure(pattern, &x, input, env.clone(), &mut |env| {
+                    let updates = self.collect(update, &state, &env)?;
+                    for y in &updates {
+                        match extract {
+                            Some(extract) => self.eval(extract, y, &env, out)?,
+                            None => out(y.clone())?,
+                        }
+                    }
+                    if let Some(y) = updates.into_iter().last() {
+                        state = y;
+                    }
+                    Ok(())
+                })
+            })
+        })
+    }
+
+    fn bind<'e>(
+        &self,
+        source: &'e Expr,
+        pattern: &'e Pattern,
+        body: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        self.eval(source, input, env, &mut |x| {
+            self.destructure(pattern, &x, input, env.clone(), &mut |env| {
+                self.eval(body, input, &env, out)
+            })
+        })
+    }
+
+    /// Calls `f` with `env` and the variables of `pattern` bound to the parts of `value`, once
+    /// for every combination of the outputs of the keys, which see `input`.
+    fn destructure<'e>(
+        &self,
+        pattern: &'e Pattern,
+        value: &Json,
+        input: &Json,
+        env: Env<'e>,
+        f: &mut dyn FnMut(Env<'e>) -> Eval,
+    ) -> Eval {
+        match pattern {
+            Pattern::Variable(name) => f(env.bind(Binding::Variable(name, value.clone()))),
+            Pattern::Destructure(members) => self.members(members, value, input, env, f),
+        }
+    }
+
+    fn members<'e>(
+        &self,
+        members: &'e [(Expr, Pattern)],
+        value: &Json,
+        input: &Json,
+        env: Env<'e>,
+        f: &mut dyn FnMut(Env<'e>) -> Eval,
+    ) -> Eval {
+        let Some(((key, pattern), members)) = members.split_first() else {
+            return f(env);
+        };
+        self.eval(key, input, &env, &mut |key| {
+            let member = index(value, &key)?;
+            self.destructure(pattern, &member, input, env.clone(), &mut |env| {
+                self.members(members, value, input, env, f)
+            })
+        })
+    }
+
+    fn define<'e>(
+        &self,
+        def: &'e FuncDef,
+        rest: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        self.eval(rest, input, &env.bind(Binding::Function(def)), out)
+    }
+
+    fn collect<'e>(&self, expr: &'e Expr, input: &Json, env: &Env<'e>) -> Result<Vec<Json>, Stop> {
+        let mut results = Vec::new();
+        self.eval(expr, input, env, &mut |x| {
+            results.push(x);
+            Ok(())
+        })?;
+        Ok(results)
+    }
+
+    /// The first output of `expr`, if any.
+    fn first<'e>(&self, expr: &'e Expr, input: &Json, env: &Env<'e>) -> Result<Option<Json>, Stop> {
+        let mut first = None;
+        self.limit(1.0, |out| self.eval(expr, input, env, out), &mut |x| {
+            first = Some(x);
+            Ok(())
+        })?;
+        Ok(first)
+    }
+
+    /// The paths `expr` points to.
+    fn collect_paths<'e>(
+        &self,
+        expr: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+    ) -> Result<Vec<Path>, Stop> {
+        let mut paths = Vec::new();
+        self.path(expr, input, env, &mut |(path, _)| {
+            paths.push(path);
+            Ok(())
+        })?;
+        Ok(paths)
+    }
+
+    /// Runs `run`, returning the error it raised itself as `Ok(Err(..))`. Errors raised by `out`
+    /// for later stages of the pipeline are passed on instead.
+    fn attempt<T>(
+        &self,
+        run: impl FnOnce(&mut dyn FnMut(T) -> Eval) -> Eval,
+        out: &mut dyn FnMut(T) -> Eval,
+    ) -> Result<Result<(), Json>, Stop> {
+        let mut downstream = None;
+        let result = run(&mut |x| {
+            out(x).map_err(|stop| {
+                downstream = Some(stop);
+                Stop::Break(usize::MAX)
+            })
+        });
+        if let Some(stop) = downstream {
+            return Err(stop);
+        }
+        match result {
+            Err(Stop::Error(e)) => Ok(Err(e)),
+            result => result.map(Ok),
+        }
+    }
+
+    /// The string for `parts`, followed by `suffix`. The last interpolation varies slowest.
+    fn interpolate<'e>(
+        &self,
+        format: Option<&str>,
+        parts: &'e [StrPart],
+        suffix: String,
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        let Some((last, parts)) = parts.split_last() else {
+            return out(Json::from(suffix));
+        };
+        match last {
+            StrPart::Literal(s) => {
+                self.interpolate(format, parts, format!("{}{}", s, suffix), input, env, out)
+            }
+            StrPart::Expr(expr) => self.eval(expr, input, env, &mut |x| {
+                let text = match format {
+                    Some(format) => apply_format(format, &x)?,
+                    None => tostring(&x),
+                };
+                self.interpolate(
+                    format,
+                    parts,
+                    format!("{}{}", text, suffix),
+                    input,
+                    env,
+                    out,
+                )
+            }),
+        }
+    }
+
+    /// Every combination of the entries' keys and values, added to `object`.
+    fn object<'e>(
+        &self,
+        entries: &'e [(Expr, Expr)],
+        input: &Json,
+        env: &Env<'e>,
+        object: &HashMap<String, Json>,
+        out: Sink,
+    ) -> Eval {
+        let Some(((key, value), entries)) = entries.split_first() else {
+            return out(Json::Dict(object.clone()));
+        };
+        self.eval(key, input, env, &mut |key| {
+            let Json::Value(Constant::StringLiteral(key)) = key else {
+                return Err(error(format!(
+                    "Object keys must be strings, not {}",
+                    describe(&key)
+                )));
+            };
+            self.eval(value, input, env, &mut |value| {
+                let mut object = object.clone();
+                object.insert(key.clone(), value);
+                self.object(entries, input, env, &object, out)
+            })
+        })
+    }
+
+    fn call<'e>(
+        &self,
+        name: &'e str,
+        args: &'e [Expr],
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        match resolve(name, args.len(), env) {
+            Some(Callable::Argument(body, scope)) => self.eval(body, input, &scope, out),
+            Some(Callable::Function(def, scope)) => {
+                let env = arguments(def, scope, args, env);
+                self.enter(|| self.eval(&def.body, input, &env, out))
+            }
+            None => self.native(name, args, input, env, out),
+        }
+    }
+
+    /// Runs the body of a function call, one level deeper.
+    fn enter(&self, body: impl FnOnce() -> Eval) -> Eval {
+        let depth = self.depth.get();
+        if depth >= MAX_CALL_DEPTH {
+            return Err(error("Maximum call depth exceeded"));
+        }
+        self.depth.set(depth + 1);
+        let result = body();
+        self.depth.set(depth);
+        result
+    }
+
+    /// Passes on the first `n` outputs of `run`.
+    fn limit<T>(
+        &self,
+        n: f64,
+        run: impl FnOnce(&mut dyn FnMut(T) -> Eval) -> Eval,
+        out: &mut dyn FnMut(T) -> Eval,
+    ) -> Eval {
+        if n <= 0.0 {
+            return Ok(());
+        }
+        let id = self.limits.get();
+        self.limits.set(id + 1);
+        let mut count = 0.0;
+        let result = run(&mut |x| {
+            count += 1.0;
+            out(x)?;
+            match count < n {
+                true => Ok(()),
+                false => Err(Stop::Break(id)),
+            }
+        });
+        match result {
+            Err(Stop::Break(stop)) if stop == id => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Runs a recursive generator: `step` turns a value into the outputs and values to visit
+    /// next, in order.
+    fn unfold<T>(
+        &self,
+        input: T,
+        out: &mut dyn FnMut(T) -> Eval,
+        step: &mut dyn FnMut(&T) -> Steps<T>,
+    ) -> Eval {
+        let mut stack = vec![Task::Visit(input)];
+        while let Some(task) = stack.pop() {
+            match task {
+                Task::Emit(x) => out(x)?,
+                Task::Visit(x) => stack.extend(step(&x)?.into_iter().rev()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with every combination of the outputs of `args`, the first varying slowest.
+    fn with_values<'e>(
+        &self,
+        args: &'e [Expr],
+        input: &Json,
+        env: &Env<'e>,
+        values: &mut Vec<Json>,
+        f: &mut dyn FnMut(&[Json]) -> Eval,
+    ) -> Eval {
+        let Some((first, rest)) = args.split_first() else {
+            return f(values);
+        };
+        self.eval(first, input, env, &mut |x| {
+            values.push(x);
+            let result = self.with_values(rest, input, env, values, f);
+            values.pop();
+            result
+        })
+    }
+}
+
+/// Path expressions, which point to parts of their input: `path(f)` and the left side of an
+/// assignment.
+impl Interpreter {
+    fn path<'e>(&self, expr: &'e Expr, input: &Json, env: &Env<'e>, out: PathSink) -> Eval {
+        with_stack(|| self.dispatch_path(expr, input, env, out))
+    }
+
+    /// Like `dispatch`. Expressions that don't point to their input fail with their first output.
+    fn dispatch_path<'e>(
+        &self,
+        expr: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: PathSink,
+    ) -> Eval {
+        match expr {
+            Expr::Identity => out((Vec::new(), input.clone())),
+            Expr::Index(target, key) => self.index_path(target, key, input, env, out),
+            Expr::Slice(target, from, to) => {
+                self.slice_path(target, from.as_deref(), to.as_deref(), input, env, out)
+            }
+            Expr::Iterate(target) => self.iterate_path(target, input, env, out),
+            Expr::Try(body, handler) => {
+                match self.attempt(|out| self.path(body, input, env, out), out)? {
+                    Err(e) => match handler {
+                        Some(handler) => self.path(handler, &e, env, out),
+                        None => Ok(()),
+                    },
+                    Ok(()) => Ok(()),
+                }
+            }
+            Expr::Pipe(left, right) => self.pipe_path(left, right, input, env, out),
+            Expr::Comma(left, right) => {
+                self.path(left, input, env, out)?;
+                self.path(right, input, env, out)
+            }
+            Expr::Alternative(left, right) => self.alternative_path(left, right, input, env, out),
+            Expr::If(condition, then, otherwise) => {
+                self.eval(condition, input, env, &mut |x| match truthy(&x) {
+                    true => self.path(then, input, env, out),
+                    false => self.path(otherwise, input, env, out),
+                })
+            }
+            Expr::Bind(source, pattern, body) => self.eval(source, input, env, &mut |x| {
+                self.destructure(pattern, &x, input, env.clone(), &mut |env| {
+                    self.path(body, input, &env, out)
+                })
+            }),
+            Expr::Call(name, args) => self.call_path(name, args, input, env, out),
+            Expr::Def(def, rest) => self.path(rest, input, &env.bind(Binding::Function(def)), out),
+            _ => self.eval(expr, input, env, &mut |x| Err(invalid_path(&x))),
+        }
+    }
+
+    fn index_path<'e>(
+        &self,
+        target: &'e Expr,
+        key: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: PathSink,
+    ) -> Eval {
+        self.path(target, input, env, &mut |(path, target)| {
+            self.eval(key, input, env, &mut |key| {
+                let value = index(&target, &key)?;
+                out((appended(&path, key), value))
+            })
+        })
+    }
+
+    fn slice_path<'e>(
+        &self,
+        target: &'e Expr,
+        from: Option<&'e Expr>,
+        to: Option<&'e Expr>,
+        input: &Json,
+        env: &Env<'e>,
+        out: PathSink,
+    ) -> Eval {
+        let null = Expr::Literal(Json::Value(Constant::Null));
+        self.path(target, input, env, &mut |(path, target)| {
+            self.eval(from.unwrap_or(&null), input, env, &mut |from| {
+                self.eval(to.unwrap_or(&null), input, env, &mut |to| {
+                    let value = slice(&target, &from, &to)?;
+                    let mut key = HashMap::new();
+                    key.insert("start".to_string(), from.clone());
+                    key.insert("end".to_string(), to);
+                    out((appended(&path, Json::Dict(key)), value))
+                })
+            })
+        })
+    }
+
+    fn iterate_path<'e>(
+        &self,
+        target: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: PathSink,
+    ) -> Eval {
+        self.path(target, input, env, &mut |(path, target)| match &target {
+            Json::List(x) => x
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, value)| out((appended(&path, Json::from(i)), value.clone()))),
+            Json::Dict(x) => {
+                let mut keys = x.keys().collect::<Vec<_>>();
+                keys.sort();
+                keys.into_iter().try_for_each(|key| {
+                    out((appended(&path, Json::from(key.as_str())), x[key].clone()))
+                })
+            }
+            _ => Err(error(format!("Cannot iterate over {}", describe(&target)))),
+        })
+    }
+
+    fn pipe_path<'e>(
+        &self,
+        left: &'e Expr,
+        right: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: PathSink,
+    ) -> Eval {
+        self.path(left, input, env, &mut |(path, value)| {
+            self.path(right, &value, env, &mut |(rest, value)| {
+                out(([path.as_slice(), &rest].concat(), value))
+            })
+        })
+    }
+
+    fn alternative_path<'e>(
+        &self,
+        left: &'e Expr,
+        right: &'e Expr,
+        input: &Json,
+        env: &Env<'e>,
+        out: PathSink,
+    ) -> Eval {
+        let mut any = false;
+        let _ = self.attempt(|out| self.path(left, input, env, out), &mut |(
+            path,
+            value,
+        )| {
+            match truthy(&value) {
+                true => {
+                    any = true;
+                    out((path, value))
+                }
+                false => Ok(()),
+            }
+        })?;
+        match any {
+            true => Ok(()),
+            false => self.path(right, input, env, out),
+        }
+    }
+
+    fn call_path<'e>(
+        &self,
+        name: &'e str,
+        args: &'e [Expr],
+        input: &Json,
+        env: &Env<'e>,
+        out: PathSink,
+    ) -> Eval {
+        match resolve(name, args.len(), env) {
+            Some(Callable::Argument(body, scope)) => self.path(body, input, &scope, out),
+            Some(Callable::Function(def, scope)) => {
+                let env = arguments(def, scope, args, env);
+                self.enter(|| self.path(&def.body, input, &env, out))
+            }
+            None => self.native_path(name, args, input, env, out),
+        }
+    }
+
+    fn native_path<'e>(
+        &self,
+        name: &str,
+        args: &'e [Expr],
+        input: &Json,
+        env: &Env<'e>,
+        out: PathSink,
+    ) -> Eval {
+        match (name, args) {
+            ("getpath", [path]) => self.eval(path, input, env, &mut |path| {
+                let path = path_keys(&path)?;
+                out((path.to_vec(), getpath(input, path)?))
+            }),
+            ("limit", [n, f]) => self.eval(n, input, env, &mut |n| {
+                let n = float(&n).ok_or_else(|| error("Invalid limit: not a number"))?;
+                self.limit(n, |out| self.path(f, input, env, out), out)
+            }),
+            ("recurse", [f]) => {
+                self.unfold((Vec::new(), input.clone()), out, &mut |(path, value)| {
+                    let mut tasks = vec![Task::Emit((path.clone(), value.clone()))];
+                    self.path(f, value, env, &mut |(rest, child)| {
+                        tasks.push(Task::Visit(([path.as_slice(), &rest].concat(), child)));
+                        Ok(())
+                    })?;
+                    Ok(tasks)
+                })
+            }
+            _ => self.native(name, args, input, env, &mut |x| Err(invalid_path(&x))),
+        }
+    }
+}
+
+impl Interpreter {
+    fn native<'e>(
+        &self,
+        name: &str,
+        args: &'e [Expr],
+        input: &Json,
+        env: &Env<'e>,
+        out: Sink,
+    ) -> Eval {
+        match (name, args) {
+            ("empty", []) => Ok(()),
+            ("error", []) => Err(Stop::Error(input.clone())),
+            ("limit", [n, f]) => self.eval(n, input, env, &mut |n| {
+                let n = float(&n).ok_or_else(|| error("Invalid limit: not a number"))?;
+                self.limit(n, |out| self.eval(f, input, env, out), out)
+            }),
+            ("path", [f]) => self.path(f, input, env, &mut |(path, _)| out(Json::List(path))),
+            ("recurse", [f]) => self.unfold(input.clone(), out, &mut |x| {
+                let children = self.collect(f, x, env)?.into_iter().map(Task::Visit);
+                Ok(once(Task::Emit(x.clone())).chain(children).collect())
+            }),
+            ("until" | "while", [condition, update]) => self.unfold(input.clone(), out, &mut |x| {
+                let mut tasks = Vec::new();
+                for holds in self.collect(condition, x, env)? {
+                    // `until` emits once the condition holds and stops there, `while` emits and
+                    // goes on as long as it holds
+                    match (name, truthy(&holds)) {
+                        ("until", true) => {
+                            tasks.push(Task::Emit(x.clone()));
+                            continue;
+                        }
+                        ("while", true) => tasks.push(Task::Emit(x.clone())),
+                        ("while", false) => continue,
+                        _ => {}
+                    }
+                    let next = self.collect(update, x, env)?;
+                    tasks.extend(next.into_iter().map(Task::Visit));
+                }
+                Ok(tasks)
+            }),
+            ("sort_by" | "group_by" | "unique_by" | "min_by" | "max_by", [f]) => {
+                out(self.by(name, f, input, env)?)
+            }
+            ("map_values", [f]) => {
+                let first = |value: &Json| -> Result<Option<Json>, Stop> {
+                    Ok(self.collect(f, value, env)?.into_iter().next())
+                };
+                let mapped = match input {
+                    Json::Dict(x) => {
+                        let mut mapped = HashMap::new();
+                        for (key, value) in x {
+                            if let Some(value) = first(value)? {
+                                mapped.insert(key.clone(), value);
+                            }
+                        }
+                        Json::Dict(mapped)
+                    }
+                    Json::List(x) => Json::List(
+                        x.iter()
+                            .map(first)
+                            .collect::<Result<Vec<_>, _>>()?
+                            .into_iter()
+                            .flatten()
+                            .collect(),
+                    ),
+                    _ => return Err(error(format!("Cannot iterate over {}", describe(input)))),
+                };
+                out(mapped)
+            }
+            ("sub", [re, replacement, flags]) => self.eval(re, input, env, &mut |re| {
+                self.eval(flags, input, env, &mut |flags| {
+                    let text = matched(input)?;
+                    let (regex, global, skip_empty) = self.regex(&re, &flags)?;
+                    // the text before each match, and what replacements see of the match
+                    let (mut pieces, mut captures, mut end) = (Vec::new(), Vec::new(), 0);
+                    for groups in regex.captures_iter(text) {
+                        let whole = groups.get(0).expect("the whole match is a group");
+                        if skip_empty && whole.is_empty() {
+                            continue;
+                        }
+                        pieces.push(&text[end..whole.start()]);
+                        captures.push(capture_object(&regex, &groups));
+                        end = whole.end();
+                        if !global {
+                            break;
+                        }
+                    }
+                    let tail = &text[end..];
+                    self.substitute(replacement, env, &pieces, &captures, "", tail, out)
+                })
+            }),
+            ("match", [_, _]) => self.with_values(args, input, env, &mut Vec::new(), &mut |v| {
+                let text = matched(input)?;
+                let (regex, global, skip_empty) = self.regex(&v[0], &v[1])?;
+                for groups in regex.captures_iter(text) {
+                    let whole = groups.get(0).expect("the whole match is a group");
+                    if skip_empty && whole.is_empty() {
+                        continue;
+                    }
+                    out(match_object(text, &regex, &groups))?;
+                    if !global {
+                        break;
+                    }
+                }
+                Ok(())
+            }),
+            ("range", _) => self.with_values(args, input, env, &mut Vec::new(), &mut |values| {
+                let bounds = values.iter().map(float).collect::<Option<Vec<_>>>();
+                let Some(&[from, upto, by]) = bounds
+                    .map(|mut bounds| {
+                        bounds.resize(3, 1.0);
+                        bounds
+                    })
+                    .as_deref()
+                else {
+                    return Err(error("Range bounds must be numeric"));
+                };
+                let mut i = 0.0;
+                loop {
+                    let x = from + i * by;
+                    if by == 0.0 || (by > 0.0 && x >= upto) || (by < 0.0 && x <= upto) {
+                        return Ok(());
+                    }
+                    out(number(x))?;
+                    i += 1.0;
+                }
+            }),
+            _ => self.with_values(args, input, env, &mut Vec::new(), &mut |values| {
+                out(self.function(name, input, values)?)
+            }),
+        }
+    }
+
+    /// Builtins of the input and the values of their arguments, with a single output.
+    fn function(&self, name: &str, input: &Json, args: &[Json]) -> Result<Json, Stop> {
+        Ok(match (name, args) {
+            ("error", [message]) => return Err(Stop::Error(message.clone())),
+            ("not", []) => Json::from(!truthy(input)),
+            ("length", []) => match input {
+                Json::Dict(x) => Json::from(x.len()),
+                Json::List(x) => Json::from(x.len()),
+                Json::Value(Constant::StringLiteral(x)) => Json::from(x.chars().count()),
+                Json::Value(Constant::Null) => Json::from(0),
+                _ => match float(input) {
+                    Some(x) => number(x.abs()),
+                    None => return Err(error(format!("{} has no length", describe(input)))),
+                },
+            },
+            ("utf8bytelength", []) => Json::from(string_input(input, name)?.len()),
+            ("keys" | "keys_unsorted", []) => match input {
+                Json::Dict(x) => {
+                    let mut keys = x.keys().cloned().collect::<Vec<_>>();
+                    keys.sort();
+                    Json::from(keys)
+                }
+                Json::List(x) => Json::from((0..x.len()).collect::<Vec<_>>()),
+                _ => return Err(error(format!("{} has no keys", describe(input)))),
+            },
+            ("has", [key]) => match (input, key, float(key)) {
+                (Json::Dict(x), Json::Value(Constant::StringLiteral(key)), _) => {
+                    Json::from(x.contains_key(key))
+                }
+                (Json::List(x), _, Some(i)) => Json::from(i >= 0.0 && i < x.len() as f64),
+                _ => {
+                    return Err(error(format!(
+                        "Cannot check whether {} has a {} key",
+                        type_name(input),
+                        type_name(key)
+                    )))
+                }
+            },
+            ("contains", [other]) => Json::from(contains(input, other)?),
+            ("type", []) => Json::from(type_name(input)),
+            ("tostring", []) => Json::from(tostring(input)),
+            ("tojson", []) => Json::from(to_string(input)),
+            ("fromjson", []) => {
+                let text = string_input(input, name)?;
+                parse_value(text).map_err(|e| error(format!("{} (while parsing '{}')", e, text)))?
+            }
+            ("tonumber", []) => match input {
+                Json::Value(Constant::StringLiteral(text)) => parse_value(text)
+                    .ok()
+                    .filter(|x| float(x).is_some())
+                    .ok_or_else(|| error(format!("Cannot parse '{}' as a number", text)))?,
+                _ if float(input).is_some() => input.clone(),
+                _ => {
+                    return Err(error(format!(
+                        "{} cannot be parsed as a number",
+                        describe(input)
+                    )))
+                }
+            },
+            ("ascii_downcase", []) => Json::from(string_input(input, name)?.to_ascii_lowercase()),
+            ("ascii_upcase", []) => Json::from(string_input(input, name)?.to_ascii_uppercase()),
+            ("explode", []) => Json::from(
+                string_input(input, name)?
+                    .chars()
+                    .map(u32::from)
+                    .collect::<Vec<_>>(),
+            ),
+            ("implode", []) => {
+                let Json::List(codes) = input else {
+                    return Err(error("implode input must be an array"));
+                };
+                let text = codes
+                    .iter()
+                    .map(|code| {
+                        integer(code)
+                            .and_then(|code| u32::try_from(code).ok())
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| error("Invalid codepoint literal"))
+                    })
+                    .collect::<Result<String, _>>()?;
+                Json::from(text)
+            }
+            ("ltrimstr" | "rtrimstr", [affix]) => match (string(input), string(affix)) {
+                (Some(text), Some(affix)) => match name {
+                    "ltrimstr" => text.strip_prefix(affix),
+                    _ => text.strip_suffix(affix),
+                }
+                .map_or_else(|| input.clone(), Json::from),
+                _ => input.clone(),
+            },
+            ("startswith" | "endswith", [affix]) => match (string(input), string(affix)) {
+                (Some(text), Some(affix)) => Json::from(match name {
+                    "startswith" => text.starts_with(affix),
+                    _ => text.ends_with(affix),
+                }),
+                _ => return Err(error(format!("{}() requires string inputs", name))),
+            },
+            ("split", [separator]) => match (string(input), string(separator)) {
+                (Some(text), Some(separator)) => split(text, separator),
+                _ => return Err(error("split input and separator must be strings")),
+            },
+            ("join", [separator]) => join(input, separator)?,
+            ("trim" | "ltrim" | "rtrim", []) => {
+                let text = string_input(input, name)?;
+                Json::from(match name {
+                    "trim" => text.trim(),
+                    "ltrim" => text.trim_start(),
+                    _ => text.trim_end(),
+                })
+            }
+            ("test", [re, flags]) => {
+                let text = matched(input)?;
+                Json::from(self.regex(re, flags)?.0.is_match(text))
+            }
+            ("split", [re, flags]) => {
+                let text = matched(input)?;
+                Json::List(
+                    self.regex(re, flags)?
+                        .0
+                        .split(text)
+                        .map(Json::from)
+                        .collect(),
+                )
+            }
+            ("indices", [i]) => indices(input, i)?,
+            ("pow", [a, b]) => match (float(a), float(b)) {
+                (Some(a), Some(b)) => number(a.powf(b)),
+                _ => return Err(error("pow requires numbers")),
+            },
+            ("infinite", []) => Json::from(f64::INFINITY),
+            ("nan", []) => Json::from(f64::NAN),
+            ("isinfinite" | "isnan" | "isnormal", []) => {
+                let x = float(input)
+                    .ok_or_else(|| error(format!("{} number required", describe(input))))?;
+                Json::from(match name {
+                    "isinfinite" => x.is_infinite(),
+                    "isnan" => x.is_nan(),
+                    _ => x.is_normal(),
+                })
+            }
+            ("reverse", []) => match input {
+                Json::List(x) => Json::List(x.iter().rev().cloned().collect()),
+                Json::Value(Constant::StringLiteral(x)) => {
+                    Json::from(x.chars().rev().collect::<String>())
+                }
+                Json::Value(Constant::Null) => Json::List(Vec::new()),
+                _ => return Err(error(format!("Cannot reverse {}", describe(input)))),
+            },
+            ("flatten", []) => flatten(input, f64::INFINITY)?,
+            ("flatten", [depth]) => match float(depth) {
+                Some(depth) if depth >= 0.0 => flatten(input, depth)?,
+                _ => return Err(error("flatten depth must not be negative")),
+            },
+            ("getpath", [path]) => getpath(input, path_keys(path)?)?,
+            ("setpath", [path, value]) => {
+                let mut result = input.clone();
+                setpath(&mut result, path_keys(path)?, value.clone())?;
+                result
+            }
+            ("delpaths", [paths]) => {
+                let Json::List(paths) = paths else {
+                    return Err(error("Paths must be specified as an array"));
+                };
+                let mut result = input.clone();
+                delpaths(&mut result, paths.clone())?;
+                result
+            }
+            (_, []) => {
+                let (_, f) = MATH
+                    .iter()
+                    .find(|(math, _)| *math == name)
+                    .expect("functions are checked when parsing");
+                let x = float(input)
+                    .ok_or_else(|| error(format!("{} number required", describe(input))))?;
+                number(f(x))
+            }
+            _ => unreachable!("functions are checked when parsing"),
+        })
+    }
+
+    /// `sort_by` and the other functions of an array ordered by keys from `f`.
+    fn by<'e>(&self, name: &str, f: &'e Expr, input: &Json, env: &Env<'e>) -> Result<Json, Stop> {
+        let Json::List(items) = input else {
+            return Err(error(format!(
+                "{} cannot be sorted, as it is not an array",
+                describe(input)
+            )));
+        };
+        let mut keyed = items
+            .iter()
+            .map(|item| Ok((self.collect(f, item, env)?, item.clone())))
+            .collect::<Result<Vec<_>, Stop>>()?;
+        // stable, so that `min_by` finds the first minimum and `max_by` the last maximum
+        keyed.sort_by(|(a, _), (b, _)| compare_lists(a, b));
+        let groups = keyed.chunk_by(|(a, _), (b, _)| compare_lists(a, b) == Ordering::Equal);
+        Ok(match name {
+            "group_by" => Json::List(
+                groups
+                    .map(|group| Json::List(group.iter().map(|(_, x)| x.clone()).collect()))
+                    .collect(),
+            ),
+            "unique_by" => Json::List(groups.map(|group| group[0].1.clone()).collect()),
+            "min_by" => Json::from(keyed.first().map(|(_, x)| x.clone())),
+            "max_by" => Json::from(keyed.last().map(|(_, x)| x.clone())),
+            _ => Json::List(keyed.into_iter().map(|(_, x)| x).collect()),
+        })
+    }
+
+    /// The regex for `pattern` with jq's `flags`, and whether the flags ask for all matches
+    /// (`g`) and for skipping empty ones (`n`).
+    fn regex(&self, pattern: &Json, flags: &Json) -> Result<(Regex, bool, bool), Stop> {
+        let pattern = string(pattern).ok_or_else(|| {
+            error(format!(
+                "{} cannot be matched, as it is not a string",
+                describe(pattern)
+            ))
+        })?;
+        let flags = match flags {
+            Json::Value(Constant::Null) => "",
+            _ => string(flags)
+                .ok_or_else(|| error(format!("{} is not a string", describe(flags))))?,
+        };
+        let (mut modifiers, mut global, mut skip_empty) = (String::new(), false, false);
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'n' => skip_empty = true,
+                'i' | 'x' => modifiers.push(flag),
+                // Oniguruma's single line mode is the default here, its multi line mode is `s`
+                'p' => modifiers.push('s'),
+                's' | 'l' => {}
+                _ => return Err(error(format!("{} is not a valid modifier string", flags))),
+            }
+        }
+        let source = match modifiers.is_empty() {
+            true => pattern.to_string(),
+            false => format!("(?{}){}", modifiers, pattern),
+        };
+        let mut regexes = self.regexes.borrow_mut();
+        if let Some(regex) = regexes.get(&source) {
+            return Ok((regex.clone(), global, skip_empty));
+        }
+        let regex = Regex::new(&source)
+            .map_err(|e| error(format!("{} is not a valid regex: {}", pattern, e)))?;
+        regexes.insert(source, regex.clone());
+        Ok((regex, global, skip_empty))
+    }
+
+    /// Every string made by replacing the matches with outputs of `replacement`, the first match
+    /// varying slowest.
+    #[allow(clippy::too_many_arguments)]
+    fn substitute<'e>(
+        &self,
+        replacement: &'e Expr,
+        env: &Env<'e>,
+        pieces: &[&str],
+        captures: &[Json],
+        prefix: &str,
+        tail: &str,
+        out: Sink,
+    ) -> Eval {
+        let (Some((piece, pieces)), Some((capture, captures))) =
+            (pieces.split_first(), captures.split_first())
+        else {
+            return out(Json::from(format!("{}{}", prefix, tail)));
+        };
+        self.eval(replacement, capture, env, &mut |x| match string(&x) {
+            Some(x) => {
+                let prefix = format!("{}{}{}", prefix, piece, x);
+                self.substitute(replacement, env, pieces, captures, &prefix, tail, out)
+            }
+            None => Err(error(format!(
+                "{} cannot be added to a string",
+                describe(&x)
+            ))),
+        })
+    }
+}
+
+/// jq's match object, with offsets and lengths in code points.
+fn match_object(text: &str, regex: &Regex, groups: &Captures) -> Json {
+    let located = |start: usize, matched: &str| {
+        let mut object = HashMap::new();
+        object.insert(
+            "offset".to_string(),
+            Json::from(text[..start].chars().count()),
+        );
+        object.insert("length".to_string(), Json::from(matched.chars().count()));
+        object.insert("string".to_string(), Json::from(matched));
+        object
+    };
+    let whole = groups.get(0).expect("the whole match is a group");
+    let mut object = located(whole.start(), whole.as_str());
+    let captures = regex
+        .capture_names()
+        .enumerate()
+        .skip(1)
+        .map(|(i, name)| {
+            let mut capture = match groups.get(i) {
+                Some(group) => located(group.start(), group.as_str()),
+                None => {
+                    let mut capture = HashMap::new();
+                    capture.insert("offset".to_string(), Json::from(-1));
+                    capture.insert("length".to_string(), Json::from(0));
+                    capture.insert("string".to_string(), Json::Value(Constant::Null));
+                    capture
+                }
+            };
+            capture.insert("name".to_string(), Json::from(name));
+            Json::Dict(capture)
+        })
+        .collect();
+    object.insert("captures".to_string(), Json::List(captures));
+    Json::Dict(object)
+}
+
+/// The named groups of a match, as `sub` replacements see them.
+fn capture_object(regex: &Regex, groups: &Captures) -> Json {
+    let captures = regex
+        .capture_names()
+        .flatten()
+        .map(|name| {
+            let value = groups.name(name).map(|group| group.as_str());
+            (name.to_string(), Json::from(value))
+        })
+        .collect::<HashMap<_, _>>();
+    Json::Dict(captures)
+}
+
+fn truthy(json: &Json) -> bool {
+    !matches!(
+        json,
+        Json::Value(Constant::Null) | Json::Value(Constant::Boolean(false))
+    )
+}
+
+fn type_name(json: &Json) -> &'static str {
+    match json {
+        Json::Dict(_) => "object",
+        Json::List(_) => "array",
+        Json::Value(Constant::Null) => "null",
+        Json::Value(Constant::Boolean(_)) => "boolean",
+        Json::Value(Constant::StringLiteral(_)) => "string",
+        Json::Value(_) => "number",
+    }
+}
+
+/// The type and a shortened text of a value, for error messages, e.g. `number (1)`.
+fn describe(json: &Json) -> String {
+    let text = to_string(json);
+    match text.char_indices().nth(11) {
+        Some((end, _)) => format!("{} ({}...)", type_name(json), &text[..end]),
+        None => format!("{} ({})", type_name(json), text),
+    }
+}
+
+/// Parses any value, where `parse` only accepts arrays and objects.
+fn parse_value(text: &str) -> Result<Json, String> {
+    match parse(&format!("[{}]", text)).map_err(|e| e.to_string())? {
+        Json::List(mut values) if values.len() == 1 => Ok(values.remove(0)),
+        _ => Err("Expected a single value".to_string()),
+    }
+}
+
+fn string(json: &Json) -> Option<&str> {
+    match json {
+        Json::Value(Constant::StringLiteral(x)) => Some(x),
+        _ => None,
+    }
+}
+
+fn string_input<'a>(input: &'a Json, function: &str) -> Result<&'a str, Stop> {
+    string(input).ok_or_else(|| error(format!("{} input must be a string", function)))
+}
+
+fn matched(input: &Json) -> Result<&str, Stop> {
+    string(input).ok_or_else(|| {
+        error(format!(
+            "{} cannot be matched, as it is not a string",
+            describe(input)
+        ))
+    })
+}
+
+/// Strings as they are, anything else as JSON.
+fn tostring(json: &Json) -> String {
+    match json {
+        Json::Value(Constant::StringLiteral(x)) => x.clone(),
+        _ => to_string(json),
+    }
+}
+
+fn float(json: &Json) -> Option<f64> {
+    match json {
+        Json::Value(Constant::Int(x)) => Some(*x as f64),
+        Json::Value(Constant::UInt(x)) => Some(*x as f64),
+        Json::Value(Constant::Float(x)) => Some(*x),
+        Json::Value(Constant::Number(x)) => Some(x.as_f64()),
+        _ => None,
+    }
+}
+
+/// The value of an integer that isn't written as a float.
+fn integer(json: &Json) -> Option<i64> {
+    match json {
+        Json::Value(Constant::Int(x)) => Some(*x),
+        Json::Value(Constant::UInt(x)) => i64::try_from(*x).ok(),
+        Json::Value(Constant::Number(x)) => x.as_i64(),
+        _ => None,
+    }
+}
+
+/// A computed number, as an integer if it is one that a float represents exactly.
+fn number(x: f64) -> Json {
+    match x.fract() == 0.0 && x.abs() < 9007199254740992.0 {
+        true => Json::from(x as i64),
+        false => Json::from(x),
+    }
+}
+
+/// Applies `int` to integers, `float` to other numbers and on overflow.
+fn arithmetic(
+    a: &Json,
+    b: &Json,
+    int: fn(i64, i64) -> Option<i64>,
+    float_op: fn(f64, f64) -> f64,
+) -> Option<Json> {
+    if let Some(x) = integer(a).zip(integer(b)).and_then(|(a, b)| int(a, b)) {
+        return Some(Json::from(x));
+    }
+    Some(number(float_op(float(a)?, float(b)?)))
+}
+
+fn negate(x: &Json) -> Result<Json, Stop> {
+    match integer(x).and_then(i64::checked_neg) {
+        Some(x) => Ok(Json::from(x)),
+        None => float(x)
+            .map(|x| number(-x))
+            .ok_or_else(|| error(format!("{} cannot be negated", describe(x)))),
+    }
+}
+
+fn binary(op: BinaryOp, a: &Json, b: &Json) -> Result<Json, Stop> {
+    let failed = |what: &str| {
+        error(format!(
+            "{} and {} cannot be {}",
+            describe(a),
+            describe(b),
+            what
+        ))
+    };
+    let order = || compare(a, b);
+    Ok(match op {
+        BinaryOp::Add => match (a, b) {
+            (Json::Value(Constant::Null), x) | (x, Json::Value(Constant::Null)) => x.clone(),
+            (Json::List(x), Json::List(y)) => Json::List(x.iter().chain(y).cloned().collect()),
+            (Json::Dict(x), Json::Dict(y)) => {
+                let mut merged = x.clone();
+                merged.extend(y.iter().map(|(k, v)| (k.clone(), v.clone())));
+                Json::Dict(merged)
+            }
+            (Json::Value(Constant::StringLiteral(x)), Json::Value(Constant::StringLiteral(y))) => {
+                Json::from(format!("{}{}", x, y))
+            }
+            _ => arithmetic(a, b, i64::checked_add, |x, y| x + y).ok_or_else(|| failed("added"))?,
+        },
+        BinaryOp::Sub => match (a, b) {
+            (Json::List(x), Json::List(y)) => Json::List(
+                x.iter()
+                    .filter(|x| !y.iter().any(|y| compare(x, y) == Ordering::Equal))
+                    .cloned()
+                    .collect(),
+            ),
+            _ => arithmetic(a, b, i64::checked_sub, |x, y| x - y)
+                .ok_or_else(|| failed("subtracted"))?,
+        },
+        BinaryOp::Mul => match (a, b) {
+            (Json::Dict(x), Json::Dict(y)) => Json::Dict(deep_merge(x, y)),
+            (Json::Value(Constant::StringLiteral(text)), n)
+            | (n, Json::Value(Constant::StringLiteral(text)))
+                if float(n).is_some() =>
+            {
+                match float(n).expect("checked") {
+                    n if n < 1.0 => Json::Value(Constant::Null),
+                    n if n * text.len() as f64 > MAX_REPEAT_LENGTH as f64 => {
+                        return Err(error("Repeat string result too long"))
+                    }
+                    n => Json::from(text.repeat(n as usize)),
+                }
+            }
+            _ => arithmetic(a, b, i64::checked_mul, |x, y| x * y)
+                .ok_or_else(|| failed("multiplied"))?,
+        },
+        BinaryOp::Div => match (a, b) {
+            (Json::Value(Constant::StringLiteral(x)), Json::Value(Constant::StringLiteral(y))) => {
+                split(x, y)
+            }
+            _ => match (float(a), float(b)) {
+                (Some(_), Some(0.0)) => return Err(failed("divided because the divisor is zero")),
+                (Some(x), Some(y)) => number(x / y),
+                _ => return Err(failed("divided")),
+            },
+        },
+        BinaryOp::Mod => match (float(a), float(b)) {
+            (Some(_), Some(y)) if y as i64 == 0 => {
+                return Err(failed("divided because the divisor is zero"))
+            }
+            (Some(x), Some(y)) => Json::from((x as i64).checked_rem(y as i64).unwrap_or(0)),
+            _ => return Err(failed("divided")),
+        },
+        BinaryOp::Eq => Json::from(order() == Ordering::Equal),
+        BinaryOp::Ne => Json::from(order() != Ordering::Equal),
+        BinaryOp::Lt => Json::from(order() == Ordering::Less),
+        BinaryOp::Le => Json::from(order() != Ordering::Greater),
+        BinaryOp::Gt => Json::from(order() == Ordering::Greater),
+        BinaryOp::Ge => Json::from(order() != Ordering::Less),
+    })
+}
+
+/// Object multiplication: members that are objects on both sides are merged recursively.
+fn deep_merge(a: &HashMap<String, Json>, b: &HashMap<String, Json>) -> HashMap<String, Json> {
+    let mut merged = a.clone();
+    for (key, value) in b {
+        let value = match (merged.get(key), value) {
+            (Some(Json::Dict(x)), Json::Dict(y)) => Json::Dict(deep_merge(x, y)),
+            _ => value.clone(),
+        };
+        merged.insert(key.clone(), value);
+    }
+    merged
+}
+
+/// jq's order: null, false, true, numbers, strings, arrays, objects. NaN is less than any number.
+fn compare(a: &Json, b: &Json) -> Ordering {
+    let rank = |json: &Json| match json {
+        Json::Value(Constant::Null) => 0,
+        Json::Value(Constant::Boolean(false)) => 1,
+        Json::Value(Constant::Boolean(true)) => 2,
+        Json::Value(Constant::StringLiteral(_)) => 4,
+        Json::Value(_) => 3,
+        Json::List(_) => 5,
+        Json::Dict(_) => 6,
+    };
+    match (a, b) {
+        _ if rank(a) != rank(b) || rank(a) < 3 => rank(a).cmp(&rank(b)),
+        (Json::Value(Constant::StringLiteral(x)), Json::Value(Constant::StringLiteral(y))) => {
+            x.cmp(y)
+        }
+        (Json::Value(x), Json::Value(y)) => {
+            compare_numbers(x, y).unwrap_or_else(|| match float(a).is_some_and(f64::is_nan) {
+                true => Ordering::Less,
+                false => Ordering::Greater,
+            })
+        }
+        (Json::List(x), Json::List(y)) => compare_lists(x, y),
+        (Json::Dict(x), Json::Dict(y)) => {
+            let mut keys = x.keys().collect::<Vec<_>>();
+            let mut other = y.keys().collect::<Vec<_>>();
+            keys.sort();
+            other.sort();
+            keys.cmp(&other).then_with(|| {
+                let values = keys.iter().map(|key| compare(&x[*key], &y[*key]));
+                values.fold(Ordering::Equal, Ordering::then)
+            })
+        }
+        _ => unreachable!("same rank, same variant"),
+    }
+}
+
+fn compare_lists(a: &[Json], b: &[Json]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| compare(x, y))
+        .find(|order| order.is_ne())
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+fn contains(a: &Json, b: &Json) -> Result<bool, Stop> {
+    Ok(match (a, b) {
+        (Json::Dict(x), Json::Dict(y)) => {
+            for (key, value) in y {
+                match x.get(key) {
+                    Some(other) if contains(other, value)? => {}
+                    _ => return Ok(false),
+                }
+            }
+            true
+        }
+        (Json::List(x), Json::List(y)) => {
+            for value in y {
+                let mut found = false;
+                for other in x {
+                    if contains(other, value)? {
+                        found = true;
+                        break;
+                    }
+                }
+                if !found {
+                    return Ok(false);
+                }
+            }
+            true
+        }
+        (Json::Value(Constant::StringLiteral(x)), Json::Value(Constant::StringLiteral(y))) => {
+            x.contains(y.as_str())
+        }
+        _ if type_name(a) == type_name(b) => compare(a, b) == Ordering::Equal,
+        _ => {
+            return Err(error(format!(
+                "{} and {} cannot have their containment checked",
+                describe(a),
+                describe(b)
+            )))
+        }
+    })
+}
+
+/// `target[key]`. An object key `{"start": from, "end": to}` is a slice, as in paths.
+fn index(target: &Json, key: &Json) -> Result<Json, Stop> {
+    let null = Json::Value(Constant::Null);
+    match (target, key, float(key)) {
+        (Json::Dict(x), Json::Value(Constant::StringLiteral(key)), _) => {
+            Ok(x.get(key).cloned().unwrap_or(null))
+        }
+        (Json::List(x), _, Some(i)) => Ok(position(x.len(), i).map_or(null, |i| x[i].clone())),
+        (Json::List(_) | Json::Value(Constant::Null), Json::Dict(range), _) => {
+            let bound = |name| range.get(name).unwrap_or(&null);
+            slice(target, bound("start"), bound("end"))
+        }
+        (Json::Value(Constant::Null), Json::Value(Constant::StringLiteral(_)), _)
+        | (Json::Value(Constant::Null), _, Some(_)) => Ok(null),
+        _ => Err(cannot_index(target, key)),
+    }
+}
+
+fn cannot_index(target: &Json, key: &Json) -> Stop {
+    match key {
+        Json::Value(Constant::StringLiteral(key)) => error(format!(
+            "Cannot index {} with \"{}\"",
+            type_name(target),
+            key
+        )),
+        _ => error(format!(
+            "Cannot index {} with {}",
+            type_name(target),
+            type_name(key)
+        )),
+    }
+}
+
+/// The item at index `i` of an array of length `len`, counting from the end if `i` is negative.
+fn position(len: usize, i: f64) -> Option<usize> {
+    let i = i.floor();
+    let i = if i < 0.0 { i + len as f64 } else { i };
+    (i >= 0.0 && i < len as f64).then_some(i as usize)
+}
+
+/// The range of the slice `[from:to]` of something of length `len`.
+fn slice_range(len: usize, from: &Json, to: &Json) -> Result<(usize, usize), Stop> {
+    let bound = |x: &Json, default: f64, round: fn(f64) -> f64| match x {
+        Json::Value(Constant::Null) => Ok(default),
+        _ => float(x)
+            .map(round)
+            .ok_or_else(|| error("Start and end indices of a slice must be numbers")),
+    };
+    let len = len as f64;
+    let clamp = |x: f64| (if x < 0.0 { x + len } else { x }).clamp(0.0, len) as usize;
+    let start = clamp(bound(from, 0.0, f64::floor)?);
+    let end = clamp(bound(to, len, f64::ceil)?);
+    Ok((start, end.max(start)))
+}
+
+fn slice(target: &Json, from: &Json, to: &Json) -> Result<Json, Stop> {
+    match target {
+        Json::Value(Constant::Null) => Ok(Json::Value(Constant::Null)),
+        Json::List(x) => {
+            let (start, end) = slice_range(x.len(), from, to)?;
+            Ok(Json::List(x[start..end].to_vec()))
+        }
+        Json::Value(Constant::StringLiteral(x)) => {
+            let (start, end) = slice_range(x.chars().count(), from, to)?;
+            Ok(Json::from(
+                x.chars().skip(start).take(end - start).collect::<String>(),
+            ))
+        }
+        _ => Err(error(format!(
+            "Cannot index {} with object",
+            type_name(target)
+        ))),
+    }
+}
+
+fn invalid_path(result: &Json) -> Stop {
+    error(format!(
+        "Invalid path expression with result {}",
+        describe(result)
+    ))
+}
+
+fn appended(path: &[Json], key: Json) -> Path {
+    let mut path = path.to_vec();
+    path.push(key);
+    path
+}
+
+/// The keys of a path given as a value.
+fn path_keys(path: &Json) -> Result<&[Json], Stop> {
+    match path {
+        Json::List(keys) => Ok(keys),
+        _ => Err(error("Path must be specified as an array")),
+    }
+}
+
+/// The largest array index that assigning to may pad an array up to.
+const MAX_ARRAY_INDEX: usize = 1 << 20;
+
+/// The value at `path`, `null` if it runs into a `null`.
+fn getpath(target: &Json, path: &[Json]) -> Result<Json, Stop> {
+    let mut node = target;
+    for (i, key) in path.iter().enumerate() {
+        node = match (node, key, float(key)) {
+            (Json::Value(Constant::Null), _, _) => break,
+            (Json::Dict(x), Json::Value(Constant::StringLiteral(key)), _) => match x.get(key) {
+                Some(value) => value,
+                None => return Ok(Json::Value(Constant::Null)),
+            },
+            (Json::List(x), _, Some(i)) => match position(x.len(), i) {
+                Some(i) => &x[i],
+                None => return Ok(Json::Value(Constant::Null)),
+            },
+            // slices aren't part of the target, so they are looked up by value
+            _ => return with_stack(|| getpath(&index(node, key)?, &path[i + 1..])),
+        };
+    }
+    Ok(node.clone())
+}
+
+/// Sets the value at `path`, creating the objects and arrays on the way where there is `null`.
+fn setpath(target: &mut Json, path: &[Json], value: Json) -> Result<(), Stop> {
+    let mut node = target;
+    for (i, key) in path.iter().enumerate() {
+        if let Json::Dict(range) = key {
+            let rest = &path[i + 1..];
+            return with_stack(|| splice(node, key, range, |part| setpath(part, rest, value)));
+        }
+        if *node == Json::Value(Constant::Null) {
+            match (key, float(key)) {
+                (Json::Value(Constant::StringLiteral(_)), _) => *node = Json::Dict(HashMap::new()),
+                (_, Some(_)) => *node = Json::List(Vec::new()),
+                _ => {}
+            }
+        }
+        node = match (node, key, float(key)) {
+            (Json::Dict(x), Json::Value(Constant::StringLiteral(key)), _) => {
+                x.entry(key.clone()).or_insert(Json::Value(Constant::Null))
+            }
+            (Json::List(x), _, Some(i)) => {
+                let i = i.floor();
+                let i = if i < 0.0 { i + x.len() as f64 } else { i };
+                if i < 0.0 {
+                    return Err(error("Out of bounds negative array index"));
+                }
+                if i > MAX_ARRAY_INDEX as f64 {
+                    return Err(error("Array index too large"));
+                }
+                let i = i as usize;
+                if i >= x.len() {
+                    x.resize(i + 1, Json::Value(Constant::Null));
+                }
+                &mut x[i]
+            }
+            (node, key, _) => return Err(cannot_index(node, key)),
+        };
+    }
+    *node = value;
+    Ok(())
+}
+
+/// Deletes the value at `path` if there is one.
+fn delpath(target: &mut Json, path: &[Json]) -> Result<(), Stop> {
+    let Some((last, parents)) = path.split_last() else {
+        *target = Json::Value(Constant::Null);
+        return Ok(());
+    };
+    let mut node = target;
+    for (i, key) in parents.iter().enumerate() {
+        node = match (node, key, float(key)) {
+            (Json::Value(Constant::Null), _, _) => return Ok(()),
+            (node, Json::Dict(range), _) => {
+                let rest = &path[i + 1..];
+                return with_stack(|| splice(node, key, range, |part| delpath(part, rest)));
+            }
+            (Json::Dict(x), Json::Value(Constant::StringLiteral(key)), _) => match x.get_mut(key) {
+                Some(value) => value,
+                None => return Ok(()),
+            },
+            (Json::List(x), _, Some(i)) => match position(x.len(), i) {
+                Some(i) => &mut x[i],
+                None => return Ok(()),
+            },
+            (node, key, _) => return Err(cannot_index(node, key)),
+        };
+    }
+    match (node, last, float(last)) {
+        (Json::Value(Constant::Null), _, _) => {}
+        (node, Json::Dict(range), _) => splice(node, last, range, |part| {
+            *part = Json::List(Vec::new());
+            Ok(())
+        })?,
+        (Json::Dict(x), Json::Value(Constant::StringLiteral(key)), _) => {
+            x.remove(key);
+        }
+        (Json::List(x), _, Some(i)) => {
+            if let Some(i) = position(x.len(), i) {
+                x.remove(i);
+            }
+        }
+        (node, key, _) => return Err(cannot_index(node, key)),
+    }
+    Ok(())
+}
+
+/// Deletes the values at `paths`, last first so that deleting array items doesn't move the
+/// others.
+fn delpaths(target: &mut Json, paths: Vec<Json>) -> Result<(), Stop> {
+    let mut paths = paths
+        .iter()
+        .map(|path| Ok(absolute(target, path_keys(path)?)))
+        .collect::<Result<Vec<_>, Stop>>()?;
+    paths.sort_by(|a, b| compare_lists(a, b));
+    paths.dedup();
+    let parent = |path: &Path| path.split_last().map(|(_, parent)| parent.to_vec());
+    for siblings in paths.chunk_by(|a, b| parent(a) == parent(b)).rev() {
+        if siblings.len() > 1 && delete_items(target, siblings) {
+            continue;
+        }
+        for path in siblings.iter().rev() {
+            delpath(target, path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the array items at `paths`, which have the same parent, in one pass over the array.
+/// `false` if they aren't all items of an array.
+fn delete_items(target: &mut Json, paths: &[Path]) -> bool {
+    let Some((_, parent)) = paths[0].split_last() else {
+        return false;
+    };
+    let node = parent
+        .iter()
+        .try_fold(target, |node, key| match (node, key, float(key)) {
+            (Json::Dict(x), Json::Value(Constant::StringLiteral(key)), _) => x.get_mut(key),
+            (Json::List(x), _, Some(i)) => position(x.len(), i).map(|i| &mut x[i]),
+            _ => None,
+        });
+    let Some(Json::List(items)) = node else {
+        return false;
+    };
+    let mut deleted = vec![false; items.len()];
+    for path in paths {
+        match path
+            .last()
+            .and_then(float)
+            .and_then(|i| position(items.len(), i))
+        {
+            Some(i) => deleted[i] = true,
+            None => return false,
+        }
+    }
+    let mut deleted = deleted.into_iter();
+    items.retain(|_| !deleted.next().expect("a flag for every item"));
+    true
+}
+
+/// `path` with negative array indices counted from the end, as far as it leads into `target`.
+fn absolute(target: &Json, path: &[Json]) -> Path {
+    let mut path = path.to_vec();
+    let mut node = Some(target);
+    for key in &mut path {
+        node = match (node, float(key)) {
+            (Some(Json::Dict(x)), _) => string(key).and_then(|key| x.get(key)),
+            (Some(Json::List(x)), Some(i)) => {
+                let found = position(x.len(), i);
+                if let Some(i) = found {
+                    *key = Json::from(i);
+                }
+                found.map(|i| &x[i])
+            }
+            _ => None,
+        };
+    }
+    path
+}
+
+/// Runs `f` on the part of the array `node` that the slice key `range` selects, and puts what
+/// `f` leaves there, which has to be an array, in its place.
+fn splice(
+    node: &mut Json,
+    key: &Json,
+    range: &HashMap<String, Json>,
+    f: impl FnOnce(&mut Json) -> Result<(), Stop>,
+) -> Result<(), Stop> {
+    if *node == Json::Value(Constant::Null) {
+        *node = Json::List(Vec::new());
+    }
+    let Json::List(items) = node else {
+        return Err(cannot_index(node, key));
+    };
+    let null = Json::Value(Constant::Null);
+    let bound = |name| range.get(name).unwrap_or(&null);
+    let (start, end) = slice_range(items.len(), bound("start"), bound("end"))?;
+    let mut part = Json::List(items[start..end].to_vec());
+    f(&mut part)?;
+    let Json::List(part) = part else {
+        return Err(error(
+            "A slice of an array can only be assigned another array",
+        ));
+    };
+    items.splice(start..end, part);
+    Ok(())
+}
+
+/// The values of an array or object, object members in key order.
+fn iterate(json: &Json) -> Result<Vec<Json>, Stop> {
+    match json {
+        Json::List(x) => Ok(x.clone()),
+        Json::Dict(x) => {
+            let mut keys = x.keys().collect::<Vec<_>>();
+            keys.sort();
+            Ok(keys.into_iter().map(|key| x[key].clone()).collect())
+        }
+        _ => Err(error(format!("Cannot iterate over {}", describe(json)))),
+    }
+}
+
+fn split(text: &str, separator: &str) -> Json {
+    match (text, separator) {
+        ("", _) => Json::List(Vec::new()),
+        (_, "") => Json::List(text.chars().map(|c| Json::from(c.to_string())).collect()),
+        _ => Json::List(text.split(separator).map(Json::from).collect()),
+    }
+}
+
+fn join(input: &Json, separator: &Json) -> Result<Json, Stop> {
+    let (Json::List(items), Some(separator)) = (input, string(separator)) else {
+        return Err(error(format!("Cannot join {}", describe(input))));
+    };
+    let parts = items
+        .iter()
+        .map(|item| match item {
+            Json::Value(Constant::Null) => Ok(String::new()),
+            Json::Value(_) => Ok(tostring(item)),
+            _ => Err(error(format!("Cannot join with {}", type_name(item)))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json::from(parts.join(separator)))
+}
+
+/// Positions of `i` in a string (in code points, overlapping) or an array.
+fn indices(input: &Json, i: &Json) -> Result<Json, Stop> {
+    let found: Vec<usize> = match (input, i) {
+        (Json::Value(Constant::Null), _) => return Ok(Json::Value(Constant::Null)),
+        (Json::Value(Constant::StringLiteral(text)), Json::Value(Constant::StringLiteral(i))) => {
+            if i.is_empty() {
+                return Ok(Json::Value(Constant::Null));
+            }
+            (0..text.len())
+                .filter(|&start| {
+                    text.is_char_boundary(start) && text[start..].starts_with(i.as_str())
+                })
+                .map(|start| text[..start].chars().count())
+                .collect()
+        }
+        (Json::List(items), Json::List(i)) => {
+            if i.is_empty() {
+                return Ok(Json::Value(Constant::Null));
+            }
+            (0..items.len())
+                .filter(|&start| {
+                    items[start..].len() >= i.len()
+                        && compare_lists(&items[start..start + i.len()], i) == Ordering::Equal
+                })
+                .collect()
+        }
+        (Json::List(items), _) => (0..items.len())
+            .filter(|&start| compare(&items[start], i) == Ordering::Equal)
+            .collect(),
+        _ => {
+            return Err(error(format!(
+                "Cannot determine the indices of {} in {}",
+                describe(i),
+                describe(input)
+            )))
+        }
+    };
+    Ok(Json::from(found))
+}
+
+fn flatten(input: &Json, depth: f64) -> Result<Json, Stop> {
+    fn flatten_into(items: &[Json], depth: f64, flat: &mut Vec<Json>) {
+        for item in items {
+            match item {
+                Json::List(x) if depth > 0.0 => flatten_into(x, depth - 1.0, flat),
+                _ => flat.push(item.clone()),
+            }
+        }
+    }
+    let Json::List(items) = input else {
+        return Err(error(format!("Cannot flatten {}", describe(input))));
+    };
+    let mut flat = Vec::new();
+    flatten_into(items, depth, &mut flat);
+    Ok(Json::List(flat))
+}
+
+/// Applies `@csv` and the other formats to a value.
+fn apply_format(format: &str, value: &Json) -> Result<String, Stop> {
+    Ok(match format {
+        "json" => to_string(value),
+        "html" => {
+            let replacements = [
+                ('&', "&amp;"),
+                ('<', "&lt;"),
+                ('>', "&gt;"),
+                ('\'', "&#39;"),
+                ('"', "&quot;"),
+            ];
+            tostring(value)
+                .chars()
+                .map(|c| match replacements.iter().find(|(from, _)| *from == c) {
+                    Some((_, to)) => to.to_string(),
+                    None => c.to_string(),
+                })
+                .collect()
+        }
+        "uri" => tostring(value)
+            .bytes()
+            .map(
+                |b| match b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+                    true => (b as char).to_string(),
+                    false => format!("%{:02X}", b),
+                },
+            )
+            .collect(),
+        "csv" | "tsv" => {
+            let Json::List(items) = value else {
+                return Err(error(format!(
+                    "{} cannot be {}-formatted, only an array can be",
+                    describe(value),
+                    format
+                )));
+            };
+            let cells = items
+                .iter()
+                .map(|item| match item {
+                    Json::Value(Constant::StringLiteral(x)) if format == "csv" => {
+                        Ok(format!("\"{}\"", x.replace('"', "\"\"")))
+                    }
+                    Json::Value(Constant::StringLiteral(x)) => Ok(x
+                        .replace('\\', "\\\\")
+                        .replace('\t', "\\t")
+                        .replace('\n', "\\n")
+                        .replace('\r', "\\r")),
+                    Json::Value(Constant::Null) => Ok(String::new()),
+                    Json::Value(_) => Ok(to_string(item)),
+                    _ => Err(error(format!(
+                        "{} is not valid in a {} row",
+                        describe(item),
+                        format
+                    ))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            cells.join(if format == "csv" { "," } else { "\t" })
+        }
+        "sh" => {
+            let quote = |item: &Json| match item {
+                Json::Value(Constant::StringLiteral(x)) => {
+                    Ok(format!("'{}'", x.replace('\'', "'\\''")))
+                }
+                Json::Value(_) => Ok(to_string(item)),
+                _ => Err(error(format!(
+                    "{} can not be escaped for shell",
+                    describe(item)
+                ))),
+            };
+            match value {
+                Json::List(items) => items
+                    .iter()
+                    .map(quote)
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(" "),
+                _ => quote(value)?,
+            }
+        }
+        "base64" => base64_encode(tostring(value).as_bytes()),
+        "base64d" => {
+            let text = tostring(value);
+            let bytes = base64_decode(&text)
+                .ok_or_else(|| error(format!("{} is not valid base64 data", describe(value))))?;
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        _ => tostring(value),
+    })
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(BASE64[(n >> (18 - 6 * i)) as usize & 63] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes base64 with or without padding.
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let digits = text
+        .trim_end_matches('=')
+        .bytes()
+        .map(|b| BASE64.iter().position(|x| *x == b).map(|x| x as u32))
+        .collect::<Option<Vec<_>>>()?;
+    if digits.len() % 4 == 1 {
+        return None;
+    }
+    let mut decoded = Vec::new();
+    for chunk in digits.chunks(4) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, digit)| n | digit << (18 - 6 * i));
+        decoded.extend((0..chunk.len() - 1).map(|i| (n >> (16 - 8 * i)) as u8));
+    }
+    Some(decoded)
+}
+
+impl Program {
+    pub fn parse(program: &str) -> Result<Program, JqError> {
+        let body = Parser::new(program, prelude()).program()?;
+        Ok(Program { body })
+    }
+
+    /// Runs the program with `input` as `.`, collecting its results. Function calls nest at most
+    /// 512 deep.
+    pub fn run(&self, input: &Json) -> Result<Vec<Json>, JqError> {
+        let mut results = Vec::new();
+        let result = Interpreter::default().eval(&self.body, input, &Env::default(), &mut |x| {
+            results.push(x);
+            Ok(())
+        });
+        match result {
+            Ok(()) => Ok(results),
+            Err(Stop::Error(e)) => Err(JqError::Runtime(e)),
+            Err(Stop::Break(_)) => unreachable!("a `limit` catches its own breaks"),
+        }
+    }
+}
+
+impl FromStr for Program {
+    type Err = JqError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Program::parse(s)
+    }
+}
+
+impl Json {
+    /// Runs the jq program `program` on this document, e.g. `.items[] | select(.done) | .name`.
+    pub fn jq(&self, program: &str) -> Result<Vec<Json>, JqError> {
+        Program::parse(program)?.run(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn jq(json: &Json, program: &str) -> Vec<Json> {
+        json.jq(program)
+            .unwrap_or_else(|e| panic!("{}: {}", program, e))
+    }
+
+    fn runtime_error(json: &Json, program: &str) -> String {
+        json.jq(program).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_language() {
+        let json = json!({"items": [
+            {"name": "a", "done": true, "n": 3},
+            {"name": "b", "done": false, "n": 1},
+            {"name": "c", "done": true, "n": 2}
+        ]});
+        let cases = [
+            (
+                ".items[] | select(.done) | .name",
+                vec![json!("a"), json!("c")],
+            ),
+            (
+                ".items[1:][0].name, .items[-1].n",
+                vec![json!("b"), json!(2)],
+            ),
+            (
+                ".missing.deep, .[\"items\"][5]",
+                vec![json!(null), json!(null)],
+            ),
+            (
+                "{a: (1, 2), b: (3, 4)} | [.a, .b]",
+                vec![json!([1, 3]), json!([1, 4]), json!([2, 3]), json!([2, 4])],
+            ),
+            (
+                ".items[0] | {name, (.name): .n}",
+                vec![json!({"name": "a", "a": 3})],
+            ),
+            (".x // .items[2].n // 5", vec![json!(2)]),
+            (
+                "1, 2 | if . == 1 then \"one\" elif . == 2 then \"two\" else . end",
+                vec![json!("one"), json!("two")],
+            ),
+            ("reduce .items[] as $x (0; . + $x.n)", vec![json!(6)]),
+            (
+                "[foreach (1, 2, 3) as $x (0; . + $x; [$x, .])]",
+                vec![json!([[1, 1], [2, 3], [3, 6]])],
+            ),
+            (".items[0].n as $n | [$n, $n * 2]", vec![json!([3, 6])]),
+            (
+                "def fac: if . <= 1 then 1 else . * (. - 1 | fac) end; [range(1; 6) | fac]",
+                vec![json!([1, 2, 6, 24, 120])],
+            ),
+            (
+                "def twice(f): f | f; def add($a; $b): $a + $b; 3 | twice(. * 2), add(1; 2)",
+                vec![json!(12), json!(3)],
+            ),
+            (
+                "\"\\(.items | length) items # not a comment\" # a comment",
+                vec![json!("3 items # not a comment")],
+            ),
+            (
+                "[.items[].name[]?], [..] | length",
+                vec![json!(0), json!(14)],
+            ),
+            ("[1, 2] | .[0] + .[1] * 10 - -4 / 2 % 3", vec![json!(23)]),
+            (
+                "1 < 2, \"b\" > \"a\", [] > {}, null < false, 1 == 1.0",
+                vec![
+                    json!(true),
+                    json!(true),
+                    json!(false),
+                    json!(true),
+                    json!(true),
+                ],
+            ),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(jq(&json, program), expected, "{}", program);
+        }
+        assert_eq!(jq(&json!(null), ""), [json!(null)]);
+        let program = Program::from_str(".[] | . * 2").unwrap();
+        assert_eq!(program.run(&json!([1, 2])).unwrap(), [json!(2), json!(4)]);
+    }
+
+    #[test]
+    fn test_builtins() {
+        let json = json!({"a": 1, "b": [3, 1, 2], "s": "Hello, World"});
+        let cases = [
+            (
+                "[.b | map(. + 1), sort, unique, add, min, max, length]",
+                json!([[4, 2, 3], [1, 2, 3], [1, 2, 3], 6, 1, 3, 3]),
+            ),
+            (
+                "{a, c: 2} | to_entries",
+                json!([
+                    {"key": "a", "value": 1},
+                    {"key": "c", "value": 2}
+                ]),
+            ),
+            (
+                "{a, b: 2} | with_entries(select(.value > 1)) | keys",
+                json!(["b"]),
+            ),
+            (
+                "[{x: 2, y: 1}, {x: 1, y: 2}, {x: 2, y: 3}] | [sort_by(.x)[].y, \
+                 (group_by(.x) | map(length)), (unique_by(.x) | length), max_by(.y).y]",
+                json!([2, 1, 3, [1, 2], 2, 3]),
+            ),
+            (
+                "[limit(3; range(10)), first(range(5; 10)), last(range(3))]",
+                json!([0, 1, 2, 5, 2]),
+            ),
+            (
+                "[.b | any(. > 2), all(. > 2), contains([1]), index(1), has(5)]",
+                json!([true, false, true, 1, false]),
+            ),
+            (
+                "[9007199254740993 + 1, 1 / 3, 7 % 3, (1.5 | floor)]",
+                json!([9007199254740994i64, 0.3333333333333333, 1, 1]),
+            ),
+            (
+                "[.s | ascii_downcase, split(\", \"), .[7:], test(\"world\"; \"i\"), \
+              ltrimstr(\"Hello\"), indices(\"o\")]",
+                json!([
+                    "hello, world",
+                    ["Hello", "World"],
+                    "World",
+                    true,
+                    ", World",
+                    [4, 8]
+                ]),
+            ),
+            (
+                r#"[.s | capture("(?<first>\\w+), (?<second>\\w+)"), sub("(?<x>o)"; "[\(.x)]"),
+                gsub("l"; "L"), [scan("o.")]]"#,
+                json!([
+                    {"first": "Hello", "second": "World"},
+                    "Hell[o], World",
+                    "HeLLo, WorLd",
+                    ["o,", "or"]
+                ]),
+            ),
+            (
+                r#"[[[1, "a,\"b", null] | @csv, @json], ["hi" | @base64, (@base64 | @base64d)]]"#,
+                json!([["1,\"a,\"\"b\",", "[1,\"a,\\\"b\",null]"], ["aGk=", "hi"]]),
+            ),
+            (
+                "[walk(if type == \"number\" then . * 10 else . end) | .b]",
+                json!([[30, 10, 20]]),
+            ),
+            (
+                "[[[1, 2], [3]] | transpose, flatten, (0 | until(. > 4; . + 3)), tojson]",
+                json!([[[1, 3], [2, null]], [1, 2, 3], 6, "[[1,2],[3]]"]),
+            ),
+            (
+                "[(1 | [while(. < 100; . * 2)]), (200 | [while(. < 100; . + 1)])]",
+                json!([[1, 2, 4, 8, 16, 32, 64], []]),
+            ),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(jq(&json, program), [expected], "{}", program);
+        }
+    }
+
+    #[test]
+    fn test_paths_and_assignments() {
+        let json = json!({"a": 1, "b": [1, 2, 3], "c": {"d": null}});
+        let cases = [
+            (
+                "[paths]",
+                json!([["a"], ["b"], ["b", 0], ["b", 1], ["b", 2], ["c"], ["c", "d"]]),
+            ),
+            (
+                "[path(.b[1:], (.b[] | select(. > 1)), .x // .a, first(.b[]))]",
+                json!([
+                    ["b", {"start": 1, "end": null}],
+                    ["b", 1],
+                    ["b", 2],
+                    ["a"],
+                    ["b", 0]
+                ]),
+            ),
+            (
+                "[[leaf_paths], [paths(type == \"array\")]]",
+                json!([[["a"], ["b", 0], ["b", 1], ["b", 2]], [["b"]]]),
+            ),
+            ("{a} | with_entries(.value += 1)", json!({"a": 2})),
+            (
+                ".a |= . * 10 | .b[] += 1 | .c.d //= \"x\" | .e.f[1] = true",
+                json!({
+                    "a": 10,
+                    "b": [2, 3, 4],
+                    "c": {"d": "x"},
+                    "e": {"f": [null, true]}
+                }),
+            ),
+            (
+                "[.b |= map(select(. != 2)), (.b[] |= empty) | .b]",
+                json!([[1, 3], []]),
+            ),
+            ("[.b[1:] = [\"x\"], .b[:-1] |= reverse | .b]", json!([[1, "x"], [2, 1, 3]])),
+            (
+                "[(del(.a, .c) | keys), (del(.b[0, 2], .b[-1]) | .b), (del(.b[1:]) | .b)]",
+                json!([["b"], [2], [1]]),
+            ),
+            (
+                "[getpath([\"c\", \"d\", \"e\"]), setpath([\"b\", -1]; 0).b, \
+                 delpaths([[\"a\"], [\"b\"], [\"c\"]])]",
+                json!([null, [1, 2, 0], {}]),
+            ),
+            ("pick(.b[1], .c.x)", json!({"b": [null, 2], "c": {"x": null}})),
+            (
+                ". as {a: $x, b: [$first, $second], $c, c: {d: $d}} | [$x, $first, $second, $c, $d]",
+                json!([1, 1, 2, {"d": null}, null]),
+            ),
+            (
+                "[reduce ([1, 2], [3, 4]) as [$a, $b] (0; . + $a * $b), \
+                 (. as {(\"a\", \"c\"): $v} | $v)]",
+                json!([14, 1, {"d": null}]),
+            ),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(jq(&json, program), [expected], "{}", program);
+        }
+        // the items of an array are deleted in one pass over it
+        let items = Json::List((0..100_000i64).map(Json::from).collect());
+        let program = "del(.[] | select(. % 2 == 0)) | [length, .[-1]]";
+        assert_eq!(jq(&items, program), [json!([50_000, 99_999])]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let json = json!({"a": [1, 2]});
+        let syntax = [
+            (
+                ".a |",
+                "JqError: Syntax error at 4: unexpected end of program",
+            ),
+            ("foo(1)", "JqError: Syntax error at 0: foo/1 is not defined"),
+            (
+                ".a = .b |= 1",
+                "JqError: Syntax error at 10: assignments can't be chained",
+            ),
+            (". as {a} | .", "JqError: Syntax error at 7: expected `:`"),
+            ("[.a)", "JqError: Syntax error at 3: expected `]`"),
+            ("$x", "JqError: Syntax error at 0: $x is not defined"),
+        ];
+        for (program, expected) in syntax {
+            assert_eq!(runtime_error(&json, program), expected, "{}", program);
+        }
+        let runtime = [
+            (".a.b", "Cannot index array with \"b\""),
+            (".a[] | keys", "number (1) has no keys"),
+            ("true | .[]", "Cannot iterate over boolean (true)"),
+            ("error(\"custom\")", "custom"),
+            ("error({code: 1})", "{\"code\":1} (not a string)"),
+            (
+                ".a | implode | tonumber",
+                "Cannot parse '\u{1}\u{2}' as a number",
+            ),
+            ("\"x\" * 1e19", "Repeat string result too long"),
+            ("path(1)", "Invalid path expression with result number (1)"),
+            (".a[-3] = 1", "Out of bounds negative array index"),
+            (
+                ".a[1:] = 1",
+                "A slice of an array can only be assigned another array",
+            ),
+            (". as [$x] | $x", "Cannot index object with number"),
+            (
+                "[.a[] | 1 / (. - 1)]",
+                "number (1) and number (0) cannot be divided because \
+              the divisor is zero",
+            ),
+        ];
+        for (program, expected) in runtime {
+            assert_eq!(
+                runtime_error(&json, program),
+                format!("JqError: {}", expected),
+                "{}",
+                program
+            );
+        }
+        assert_eq!(
+            jq(
+                &json,
+                "[.a[] | try error(\"x\\(.)\") catch ., (.b?), (1 | .[0])?]"
+            ),
+            [json!(["x1", "x2"])]
+        );
+        // nesting and recursion as deep as the limits allow, on the test thread's small stack
+        let nested = |open: &str, close: &str, n| open.repeat(n) + "." + &close.repeat(n);
+        assert_eq!(
+            Program::parse(&nested("(", ")", 5000)),
+            Err(JqError::Syntax(
+                256,
+                "expressions are nested too deeply".to_string()
+            ))
+        );
+        for program in [
+            nested("-", "", 5000),
+            nested(".|", "", 5000),
+            nested("[", "]", 5000),
+        ] {
+            assert!(matches!(Program::parse(&program), Err(JqError::Syntax(..))));
+        }
+        for program in [nested("(", ")", 255), nested("[", "]", 255)] {
+            assert_eq!(
+                Program::parse(&program).unwrap().run(&json).unwrap().len(),
+                1
+            );
+        }
+        let deep = (0..128).fold(json!([]), |x, _| Json::List(vec![x]));
+        assert_eq!(jq(&deep, "walk(.)"), [deep]);
+        let program = "def f: if . == 0 then 0 else . - 1 | f end; 500 | f";
+        assert_eq!(jq(&json, program), [json!(0)]);
+        for program in ["def f: f; f", "def f: 1 + f; f", "def f: [f]; f"] {
+            assert_eq!(
+                runtime_error(&json, program),
+                "JqError: Maximum call depth exceeded"
+            );
+        }
+        // errors after the body of a `try` aren't caught by it
+        assert_eq!(
+            runtime_error(&json, "try .a[] catch 0 | error"),
+            "JqError: 1 (not a string)"
+        );
+    }
+}
@@ -0,0 +1,1235 @@
+//! JSONPath (RFC 9535) queries.
+use crate::lexer::{Constant, Number};
+use crate::parser::Json;
+use crate::patch::{compare_numbers, equal};
+use regex::Regex;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Errors carry the byte offset in the query.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum JsonPathError {
+    #[error("JsonPathError: Syntax error at {0}: {1}")]
+    Syntax(usize, String),
+    /// A well-formed expression that isn't well-typed, e.g. `?length(@.a)` without a comparison.
+    #[error("JsonPathError: Type error at {0}: {1}")]
+    Type(usize, String),
+}
+
+/// A parsed JSONPath query, e.g. `$.store.book[?@.price < 10].title`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Segment {
+    /// `..[...]` instead of `[...]`
+    descendant: bool,
+    selectors: Vec<Selector>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Name(String),
+    Wildcard,
+    Index(i64),
+    Slice(Option<i64>, Option<i64>, Option<i64>),
+    Filter(LogicalExpr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum LogicalExpr {
+    Or(Vec<LogicalExpr>),
+    And(Vec<LogicalExpr>),
+    Not(Box<LogicalExpr>),
+    Compare(Operand, CompareOp, Operand),
+    /// A query that selects at least one node.
+    Exists(Query),
+    Function(FunctionExpr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A query within a filter, relative to the current node (`@`) or the root (`$`).
+#[derive(Debug, Clone, PartialEq)]
+struct Query {
+    relative: bool,
+    segments: Vec<Segment>,
+}
+
+/// Comparison sides and function arguments.
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Literal(Json),
+    Query(Query),
+    Function(FunctionExpr),
+    Logical(Box<LogicalExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct FunctionExpr {
+    function: Function,
+    args: Vec<Operand>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Length,
+    Count,
+    Match,
+    Search,
+    Value,
+}
+
+/// The types of the function extension type system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Value,
+    Logical,
+    Nodes,
+}
+
+impl Function {
+    fn signature(self) -> (&'static [Type], Type) {
+        match self {
+            Function::Length => (&[Type::Value], Type::Value),
+            Function::Count => (&[Type::Nodes], Type::Value),
+            Function::Match | Function::Search => (&[Type::Value, Type::Value], Type::Logical),
+            Function::Value => (&[Type::Nodes], Type::Value),
+        }
+    }
+}
+
+impl Query {
+    /// Whether the query selects at most one node, using only name and index selectors.
+    fn is_singular(&self) -> bool {
+        self.segments.iter().all(|segment| {
+            !segment.descendant
+                && matches!(
+                    segment.selectors.as_slice(),
+                    [Selector::Name(_) | Selector::Index(_)]
+                )
+        })
+    }
+}
+
+/// I-JSON integers, the range of indices and slice bounds.
+const MAX_INT: i64 = (1 << 53) - 1;
+
+/// Nesting of logical expressions in a query, bounded so that parsing fails instead of
+/// overflowing the stack.
+const MAX_NESTING: usize = 128;
+
+struct Parser<'s> {
+    input: &'s str,
+    pos: usize,
+    /// Logical expressions being parsed inside each other.
+    depth: usize,
+}
+
+type ParseResult<T> = Result<T, JsonPathError>;
+
+impl<'s> Parser<'s> {
+    fn rest(&self) -> &'s str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        let found = self.rest().starts_with(s);
+        if found {
+            self.pos += s.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, s: &str) -> ParseResult<()> {
+        match self.eat(s) {
+            true => Ok(()),
+            false => Err(self.error(format!("expected `{}`", s))),
+        }
+    }
+
+    fn skip_blank(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn error(&self, message: impl Into<String>) -> JsonPathError {
+        JsonPathError::Syntax(self.pos, message.into())
+    }
+
+    fn query(&mut self) -> ParseResult<JsonPath> {
+        self.expect("$")?;
+        let segments = self.segments()?;
+        if self.pos < self.input.len() {
+            return Err(self.error("expected a segment"));
+        }
+        Ok(JsonPath { segments })
+    }
+
+    fn segments(&mut self) -> ParseResult<Vec<Segment>> {
+        let mut segments = Vec::new();
+        loop {
+            let start = self.pos;
+            self.skip_blank();
+            let segment = if self.eat("..") {
+                match self.peek() {
+                    Some('[') => self.bracketed(true)?,
+                    _ => Segment {
+                        descendant: true,
+                        selectors: vec![self.shorthand()?],
+                    },
+                }
+            } else if self.eat(".") {
+                Segment {
+                    descendant: false,
+                    selectors: vec![self.shorthand()?],
+                }
+            } else if self.peek() == Some('[') {
+                self.bracketed(false)?
+            } else {
+                self.pos = start;
+                return Ok(segments);
+            };
+            segments.push(segment);
+        }
+    }
+
+    /// A wildcard or member name after `.` or `..`.
+    fn shorthand(&mut self) -> ParseResult<Selector> {
+        if self.eat("*") {
+            return Ok(Selector::Wildcard);
+        }
+        let start = self.pos;
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' || !c.is_ascii() => {}
+            _ => return Err(self.error("expected a member name or `*`")),
+        }
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_' || !c.is_ascii()) {
+                break;
+            }
+            self.next();
+        }
+        Ok(Selector::Name(self.input[start..self.pos].to_string()))
+    }
+
+    fn bracketed(&mut self, descendant: bool) -> ParseResult<Segment> {
+        self.expect("[")?;
+        let mut selectors = Vec::new();
+        loop {
+            self.skip_blank();
+            selectors.push(self.selector()?);
+            self.skip_blank();
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect("]")?;
+        Ok(Segment {
+            descendant,
+            selectors,
+        })
+    }
+
+    fn selector(&mut self) -> ParseResult<Selector> {
+        match self.peek() {
+            Some('\'' | '"') => Ok(Selector::Name(self.string()?)),
+            Some('*') => {
+                self.next();
+                Ok(Selector::Wildcard)
+            }
+            Some('?') => {
+                self.next();
+                self.skip_blank();
+                Ok(Selector::Filter(self.logical_or()?))
+            }
+            _ => {
+                let start = self.int()?;
+                let before_colon = self.pos;
+                self.skip_blank();
+                if !self.eat(":") {
+                    self.pos = before_colon;
+                    return start
+                        .map(Selector::Index)
+                        .ok_or_else(|| self.error("expected a selector"));
+                }
+                self.skip_blank();
+                let end = self.int()?;
+                let before_colon = self.pos;
+                self.skip_blank();
+                let step = if self.eat(":") {
+                    self.skip_blank();
+                    self.int()?
+                } else {
+                    self.pos = before_colon;
+                    None
+                };
+                Ok(Selector::Slice(start, end, step))
+            }
+        }
+    }
+
+    /// An index or slice bound, `None` if there is none.
+    fn int(&mut self) -> ParseResult<Option<i64>> {
+        let start = self.pos;
+        let negative = self.eat("-");
+        let digits = self.rest().len()
+            - self
+                .rest()
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .len();
+        if digits == 0 {
+            return match negative {
+                true => Err(JsonPathError::Syntax(start, "expected digits".to_string())),
+                false => Ok(None),
+            };
+        }
+        let text = &self.input[start..start + negative as usize + digits];
+        let leading_zero = text.trim_start_matches('-').starts_with('0');
+        let error = |message: &str| Err(JsonPathError::Syntax(start, message.to_string()));
+        if leading_zero && (digits > 1 || negative) {
+            return error("integers can't have leading zeros or be -0");
+        }
+        match text.parse::<i64>() {
+            Ok(x) if (-MAX_INT..=MAX_INT).contains(&x) => {
+                self.pos = start + text.len();
+                Ok(Some(x))
+            }
+            _ => error("integer out of range"),
+        }
+    }
+
+    fn string(&mut self) -> ParseResult<String> {
+        let quote = self.next().expect("called at a quote");
+        let mut string = String::new();
+        loop {
+            let c = match self.next() {
+                Some(c) if c == quote => return Ok(string),
+                Some('\\') => match self.next() {
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some(c @ ('/' | '\\')) => c,
+                    Some(c) if c == quote => c,
+                    Some('u') => self.unicode_escape()?,
+                    _ => return Err(self.error("invalid escape sequence")),
+                },
+                Some(c) if c < '\u{20}' => {
+                    return Err(self.error("control characters have to be escaped"))
+                }
+                Some(c) => c,
+                None => return Err(self.error("unterminated string")),
+            };
+            string.push(c);
+        }
+    }
+
+    /// The character of a `\uXXXX` escape (after the `\u`), which may be a surrogate pair.
+    fn unicode_escape(&mut self) -> ParseResult<char> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xD800..=0xDBFF => {
+                if !self.eat("\\u") {
+                    return Err(self.error("expected a low surrogate"));
+                }
+                match self.hex4()? {
+                    low @ 0xDC00..=0xDFFF => 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00),
+                    _ => return Err(self.error("expected a low surrogate")),
+                }
+            }
+            0xDC00..=0xDFFF => return Err(self.error("unpaired low surrogate")),
+            _ => high,
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid code point"))
+    }
+
+    fn hex4(&mut self) -> ParseResult<u32> {
+        let hex = self.rest().get(..4).unwrap_or_default();
+        match hex.chars().all(|c| c.is_ascii_hexdigit()) && hex.len() == 4 {
+            true => {
+                self.pos += 4;
+                Ok(u32::from_str_radix(hex, 16).expect("checked digits"))
+            }
+            false => Err(self.error("expected 4 hex digits")),
+        }
+    }
+
+    fn number(&mut self) -> ParseResult<Json> {
+        let start = self.pos;
+        self.eat("-");
+        let digits = |parser: &mut Self| {
+            let rest = parser.rest();
+            let count = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            parser.pos += count;
+            count
+        };
+        let int_start = self.pos;
+        match digits(self) {
+            0 => return Err(self.error("expected digits")),
+            n if n > 1 && self.input[int_start..].starts_with('0') => {
+                return Err(JsonPathError::Syntax(int_start, "leading zero".to_string()))
+            }
+            _ => {}
+        }
+        if self.eat(".") && digits(self) == 0 {
+            return Err(self.error("expected digits"));
+        }
+        if self.eat("e") || self.eat("E") {
+            let _ = self.eat("+") || self.eat("-");
+            if digits(self) == 0 {
+                return Err(self.error("expected digits"));
+            }
+        }
+        let number = Number::from_str(&self.input[start..self.pos])
+            .map_err(|_| JsonPathError::Syntax(start, "invalid number".to_string()))?;
+        Ok(Json::Value(Constant::Number(number)))
+    }
+
+    fn logical_or(&mut self) -> ParseResult<LogicalExpr> {
+        if self.depth >= MAX_NESTING {
+            return Err(self.error("expressions are nested too deeply"));
+        }
+        self.depth += 1;
+        let result = self.disjunction();
+        self.depth -= 1;
+        result
+    }
+
+    fn disjunction(&mut self) -> ParseResult<LogicalExpr> {
+        let mut operands = vec![self.logical_and()?];
+        while self.eat_operator("||") {
+            operands.push(self.logical_and()?);
+        }
+        Ok(match operands.len() {
+            1 => operands.pop().expect("one operand"),
+            _ => LogicalExpr::Or(operands),
+        })
+    }
+
+    fn logical_and(&mut self) -> ParseResult<LogicalExpr> {
+        let mut operands = vec![self.basic()?];
+        while self.eat_operator("&&") {
+            operands.push(self.basic()?);
+        }
+        Ok(match operands.len() {
+            1 => operands.pop().expect("one operand"),
+            _ => LogicalExpr::And(operands),
+        })
+    }
+
+    /// Eats `operator` and the blanks around it, or nothing if it doesn't follow.
+    fn eat_operator(&mut self, operator: &str) -> bool {
+        let start = self.pos;
+        self.skip_blank();
+        if self.eat(operator) {
+            self.skip_blank();
+            true
+        } else {
+            self.pos = start;
+            false
+        }
+    }
+
+    fn comparison_op(&mut self) -> Option<CompareOp> {
+        let operators = [
+            ("==", CompareOp::Eq),
+            ("!=", CompareOp::Ne),
+            ("<=", CompareOp::Le),
+            (">=", CompareOp::Ge),
+            ("<", CompareOp::Lt),
+            (">", CompareOp::Gt),
+        ];
+        operators
+            .into_iter()
+            .find(|(s, _)| self.eat_operator(s))
+            .map(|(_, op)| op)
+    }
+
+    fn basic(&mut self) -> ParseResult<LogicalExpr> {
+        if self.eat("!") {
+            self.skip_blank();
+            let negated = match self.peek() {
+                Some('(') => self.parenthesized()?,
+                _ => {
+                    let start = self.pos;
+                    let operand = self.operand()?;
+                    self.test(operand, start)?
+                }
+            };
+            return Ok(LogicalExpr::Not(Box::new(negated)));
+        }
+        if self.peek() == Some('(') {
+            return self.parenthesized();
+        }
+        let start = self.pos;
+        let left = self.operand()?;
+        let Some(op) = self.comparison_op() else {
+            return self.test(left, start);
+        };
+        let right_start = self.pos;
+        let right = self.operand()?;
+        Ok(LogicalExpr::Compare(
+            self.comparable(left, start)?,
+            op,
+            self.comparable(right, right_start)?,
+        ))
+    }
+
+    fn parenthesized(&mut self) -> ParseResult<LogicalExpr> {
+        self.expect("(")?;
+        self.skip_blank();
+        let expr = self.logical_or()?;
+        self.skip_blank();
+        self.expect(")")?;
+        Ok(expr)
+    }
+
+    /// A literal, query or function call.
+    fn operand(&mut self) -> ParseResult<Operand> {
+        match self.peek() {
+            Some(c @ ('@' | '$')) => {
+                self.next();
+                Ok(Operand::Query(Query {
+                    relative: c == '@',
+                    segments: self.segments()?,
+                }))
+            }
+            Some('\'' | '"') => Ok(Operand::Literal(Json::from(self.string()?))),
+            Some(c) if c == '-' || c.is_ascii_digit() => Ok(Operand::Literal(self.number()?)),
+            Some(c) if c.is_ascii_lowercase() => {
+                let start = self.pos;
+                let rest = self.rest();
+                let name_length = rest.len()
+                    - rest
+                        .trim_start_matches(|c: char| {
+                            c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
+                        })
+                        .len();
+                let name = &rest[..name_length];
+                self.pos += name_length;
+                if self.peek() == Some('(') {
+                    return Ok(Operand::Function(self.function(name, start)?));
+                }
+                Ok(Operand::Literal(match name {
+                    "true" => Json::from(true),
+                    "false" => Json::from(false),
+                    "null" => Json::Value(Constant::Null),
+                    _ => {
+                        return Err(JsonPathError::Syntax(
+                            start,
+                            "expected a literal".to_string(),
+                        ))
+                    }
+                }))
+            }
+            _ => Err(self.error("expected a query, literal or function")),
+        }
+    }
+
+    fn function(&mut self, name: &str, start: usize) -> ParseResult<FunctionExpr> {
+        let function = match name {
+            "length" => Function::Length,
+            "count" => Function::Count,
+            "match" => Function::Match,
+            "search" => Function::Search,
+            "value" => Function::Value,
+            _ => {
+                let message = format!("unknown function {}()", name);
+                return Err(JsonPathError::Syntax(start, message));
+            }
+        };
+        self.expect("(")?;
+        self.skip_blank();
+        let (params, _) = function.signature();
+        let mut args = Vec::new();
+        if !self.eat(")") {
+            loop {
+                let arg_start = self.pos;
+                let arg = self.argument()?;
+                let Some(param) = params.get(args.len()) else {
+                    let message = format!("too many arguments for {}()", name);
+                    return Err(JsonPathError::Type(arg_start, message));
+                };
+                args.push(self.typed(arg, *param, arg_start)?);
+                if !self.eat_operator(",") {
+                    break;
+                }
+            }
+            self.skip_blank();
+            self.expect(")")?;
+        }
+        if args.len() < params.len() {
+            let message = format!("too few arguments for {}()", name);
+            return Err(JsonPathError::Type(start, message));
+        }
+        Ok(FunctionExpr { function, args })
+    }
+
+    fn argument(&mut self) -> ParseResult<Operand> {
+        let start = self.pos;
+        if !matches!(self.peek(), Some('!' | '(')) {
+            let operand = self.operand()?;
+            let end = self.pos;
+            let is_logical = self.comparison_op().is_some()
+                || self.eat_operator("&&")
+                || self.eat_operator("||");
+            if !is_logical {
+                self.pos = end;
+                return Ok(operand);
+            }
+            self.pos = start;
+        }
+        Ok(Operand::Logical(Box::new(self.logical_or()?)))
+    }
+
+    /// Checks that `operand` can be used where a `param` is expected.
+    fn typed(&self, operand: Operand, param: Type, start: usize) -> ParseResult<Operand> {
+        let valid = match (&operand, param) {
+            (Operand::Literal(_), Type::Value) => true,
+            (Operand::Query(query), Type::Value) => query.is_singular(),
+            (Operand::Query(_), Type::Nodes | Type::Logical) => true,
+            (Operand::Function(f), _) => {
+                let (_, result) = f.function.signature();
+                result == param || (result == Type::Nodes && param == Type::Logical)
+            }
+            (Operand::Logical(_), Type::Logical) => true,
+            _ => false,
+        };
+        match valid {
+            true => Ok(operand),
+            false => Err(JsonPathError::Type(
+                start,
+                format!("argument is not of type {:?}", param),
+            )),
+        }
+    }
+
+    fn comparable(&self, operand: Operand, start: usize) -> ParseResult<Operand> {
+        self.typed(operand, Type::Value, start)
+            .map_err(|_| JsonPathError::Type(start, "can't be compared".to_string()))
+    }
+
+    /// An operand without a comparison: the existence of nodes or a logical function result.
+    fn test(&self, operand: Operand, start: usize) -> ParseResult<LogicalExpr> {
+        match operand {
+            Operand::Query(query) => Ok(LogicalExpr::Exists(query)),
+            Operand::Function(f) if f.function.signature().1 != Type::Value => {
+                Ok(LogicalExpr::Function(f))
+            }
+            _ => Err(JsonPathError::Type(start, "has to be compared".to_string())),
+        }
+    }
+}
+
+/// The location of a node as a linked list up to the root, cheap to extend.
+type Path<'a> = Option<Rc<Link<'a>>>;
+
+struct Link<'a> {
+    parent: Path<'a>,
+    element: Element<'a>,
+}
+
+enum Element<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+#[derive(Clone)]
+struct Node<'a> {
+    value: &'a Json,
+    path: Path<'a>,
+}
+
+impl<'a> Node<'a> {
+    fn child(&self, value: &'a Json, element: Element<'a>) -> Node<'a> {
+        let parent = self.path.clone();
+        Node {
+            value,
+            path: Some(Rc::new(Link { parent, element })),
+        }
+    }
+
+    /// Object members (sorted by key, so that results are deterministic) or array elements.
+    fn children(&self) -> Vec<Node<'a>> {
+        match self.value {
+            Json::Dict(x) => {
+                let mut members = x.iter().collect::<Vec<_>>();
+                members.sort_by(|a, b| a.0.cmp(b.0));
+                members
+                    .into_iter()
+                    .map(|(key, value)| self.child(value, Element::Key(key)))
+                    .collect()
+            }
+            Json::List(x) => x
+                .iter()
+                .enumerate()
+                .map(|(i, value)| self.child(value, Element::Index(i)))
+                .collect(),
+            Json::Value(_) => Vec::new(),
+        }
+    }
+
+    /// The node and all of its descendants, parents before children.
+    fn descendants(&self, nodes: &mut Vec<Node<'a>>) {
+        nodes.push(self.clone());
+        for child in self.children() {
+            child.descendants(nodes);
+        }
+    }
+
+    /// The normalized path, e.g. `$['store']['book'][0]`.
+    fn normalized_path(&self) -> String {
+        let mut elements = Vec::new();
+        let mut link = self.path.as_deref();
+        while let Some(current) = link {
+            elements.push(&current.element);
+            link = current.parent.as_deref();
+        }
+        let mut path = String::from("$");
+        for element in elements.into_iter().rev() {
+            match element {
+                Element::Key(key) => {
+                    path.push_str("['");
+                    for c in key.chars() {
+                        match c {
+                            '\u{8}' => path.push_str("\\b"),
+                            '\u{c}' => path.push_str("\\f"),
+                            '\n' => path.push_str("\\n"),
+                            '\r' => path.push_str("\\r"),
+                            '\t' => path.push_str("\\t"),
+                            '\'' | '\\' => {
+                                path.push('\\');
+                                path.push(c);
+                            }
+                            c if c < '\u{20}' => path.push_str(&format!("\\u{:04x}", c as u32)),
+                            c => path.push(c),
+                        }
+                    }
+                    path.push_str("']");
+                }
+                Element::Index(i) => path.push_str(&format!("[{}]", i)),
+            }
+        }
+        path
+    }
+}
+
+/// State of an evaluation: the document and compiled regular expressions.
+struct Context<'a> {
+    root: &'a Json,
+    regexes: RefCell<HashMap<String, Option<Regex>>>,
+}
+
+impl<'a> Context<'a> {
+    /// Whether `text` matches the I-Regexp `pattern`, entirely or anywhere. Invalid patterns match
+    /// nothing.
+    fn is_match(&self, pattern: &str, text: &str, entirely: bool) -> bool {
+        let Some(mut regex) = translate(pattern) else {
+            return false;
+        };
+        if entirely {
+            regex = format!("\\A(?:{})\\z", regex);
+        }
+        self.regexes
+            .borrow_mut()
+            .entry(regex)
+            .or_insert_with_key(|regex| Regex::new(regex).ok())
+            .as_ref()
+            .is_some_and(|regex| regex.is_match(text))
+    }
+}
+
+/// The `regex` crate syntax of an I-Regexp (RFC 9485), `None` if it uses escapes that I-Regexp
+/// doesn't define, such as `\d` or `\b`.
+fn translate(pattern: &str) -> Option<String> {
+    let mut regex = String::new();
+    let mut in_class = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let escaped = chars.next()?;
+                if !"()*+-.?[\\]^{|}nrtpP".contains(escaped) {
+                    return None;
+                }
+                regex.push(c);
+                regex.push(escaped);
+            }
+            '[' if !in_class => {
+                in_class = true;
+                regex.push(c);
+            }
+            ']' if in_class => {
+                in_class = false;
+                regex.push(c);
+            }
+            // the `regex` crate has set operations in classes
+            '&' | '~' if in_class => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            // I-Regexp has no anchors, outside classes these are ordinary characters
+            '^' | '$' if !in_class => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            // I-Regexp's `.` doesn't match line breaks
+            '.' if !in_class => regex.push_str("[^\\n\\r]"),
+            _ => regex.push(c),
+        }
+    }
+    Some(regex)
+}
+
+fn apply<'a>(segments: &[Segment], cx: &Context<'a>, mut nodes: Vec<Node<'a>>) -> Vec<Node<'a>> {
+    for segment in segments {
+        let mut selected = Vec::new();
+        for node in &nodes {
+            let mut inputs = Vec::new();
+            match segment.descendant {
+                true => node.descendants(&mut inputs),
+                false => inputs.push(node.clone()),
+            }
+            for input in &inputs {
+                for selector in &segment.selectors {
+                    selector.select(cx, input, &mut selected);
+                }
+            }
+        }
+        nodes = selected;
+    }
+    nodes
+}
+
+impl Selector {
+    fn select<'a>(&self, cx: &Context<'a>, node: &Node<'a>, selected: &mut Vec<Node<'a>>) {
+        match (self, node.value) {
+            (Selector::Name(name), Json::Dict(x)) => {
+                if let Some((key, value)) = x.get_key_value(name) {
+                    selected.push(node.child(value, Element::Key(key)));
+                }
+            }
+            (Selector::Wildcard, _) => selected.extend(node.children()),
+            (Selector::Index(i), Json::List(x)) => {
+                let i = if *i < 0 { x.len() as i64 + i } else { *i };
+                if (0..x.len() as i64).contains(&i) {
+                    selected.push(node.child(&x[i as usize], Element::Index(i as usize)));
+                }
+            }
+            (Selector::Slice(start, end, step), Json::List(x)) => {
+                for i in slice(*start, *end, *step, x.len() as i64) {
+                    selected.push(node.child(&x[i], Element::Index(i)));
+                }
+            }
+            (Selector::Filter(expr), _) => selected.extend(
+                node.children()
+                    .into_iter()
+                    .filter(|child| expr.test(cx, child.value)),
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// The indices selected by a slice of an array of length `len`.
+fn slice(start: Option<i64>, end: Option<i64>, step: Option<i64>, len: i64) -> Vec<usize> {
+    let step = step.unwrap_or(1);
+    let normalize = |i: i64| if i >= 0 { i } else { len + i };
+    let mut indices = Vec::new();
+    if step > 0 {
+        let lower = normalize(start.unwrap_or(0)).clamp(0, len);
+        let upper = normalize(end.unwrap_or(len)).clamp(0, len);
+        let mut i = lower;
+        while i < upper {
+            indices.push(i as usize);
+            i += step;
+        }
+    } else if step < 0 {
+        let upper = normalize(start.unwrap_or(len - 1)).clamp(-1, len - 1);
+        let lower = normalize(end.unwrap_or(-len - 1)).clamp(-1, len - 1);
+        let mut i = upper;
+        while lower < i {
+            indices.push(i as usize);
+            i += step;
+        }
+    }
+    indices
+}
+
+impl Query {
+    fn select<'x>(&'x self, cx: &Context<'x>, current: &'x Json) -> Vec<&'x Json> {
+        let start = Node {
+            value: if self.relative { current } else { cx.root },
+            path: None,
+        };
+        apply(&self.segments, cx, vec![start])
+            .into_iter()
+            .map(|node| node.value)
+            .collect()
+    }
+}
+
+impl LogicalExpr {
+    fn test<'x>(&'x self, cx: &Context<'x>, current: &'x Json) -> bool {
+        match self {
+            LogicalExpr::Or(operands) => operands.iter().any(|x| x.test(cx, current)),
+            LogicalExpr::And(operands) => operands.iter().all(|x| x.test(cx, current)),
+            LogicalExpr::Not(x) => !x.test(cx, current),
+            LogicalExpr::Compare(left, op, right) => {
+                let left = left.value(cx, current);
+                let right = right.value(cx, current);
+                compare(left.as_deref(), *op, right.as_deref())
+            }
+            LogicalExpr::Exists(query) => !query.select(cx, current).is_empty(),
+            LogicalExpr::Function(f) => f.test(cx, current),
+        }
+    }
+}
+
+fn compare(left: Option<&Json>, op: CompareOp, right: Option<&Json>) -> bool {
+    let eq = || match (left, right) {
+        (Some(a), Some(b)) => equal(a, b),
+        (None, None) => true,
+        _ => false,
+    };
+    let lt = |a: Option<&Json>, b: Option<&Json>| match (a, b) {
+        (Some(Json::Value(a)), Some(Json::Value(b))) => match (a, b) {
+            (Constant::StringLiteral(a), Constant::StringLiteral(b)) => a < b,
+            _ => compare_numbers(a, b).is_some_and(|order| order.is_lt()),
+        },
+        _ => false,
+    };
+    match op {
+        CompareOp::Eq => eq(),
+        CompareOp::Ne => !eq(),
+        CompareOp::Lt => lt(left, right),
+        CompareOp::Le => lt(left, right) || eq(),
+        CompareOp::Gt => lt(right, left),
+        CompareOp::Ge => lt(right, left) || eq(),
+    }
+}
+
+impl Operand {
+    /// The value of an operand of type `Value`, `None` for "Nothing".
+    fn value<'x>(&'x self, cx: &Context<'x>, current: &'x Json) -> Option<Cow<'x, Json>> {
+        match self {
+            Operand::Literal(x) => Some(Cow::Borrowed(x)),
+            Operand::Query(query) => query
+                .select(cx, current)
+                .first()
+                .copied()
+                .map(Cow::Borrowed),
+            Operand::Function(f) => f.value(cx, current),
+            Operand::Logical(_) => unreachable!("type checked"),
+        }
+    }
+
+    fn nodes<'x>(&'x self, cx: &Context<'x>, current: &'x Json) -> Vec<&'x Json> {
+        match self {
+            Operand::Query(query) => query.select(cx, current),
+            _ => unreachable!("type checked"),
+        }
+    }
+}
+
+impl FunctionExpr {
+    fn value<'x>(&'x self, cx: &Context<'x>, current: &'x Json) -> Option<Cow<'x, Json>> {
+        let length = match self.function {
+            Function::Length => match self.args[0].value(cx, current)?.as_ref() {
+                Json::Value(Constant::StringLiteral(x)) => x.chars().count(),
+                Json::List(x) => x.len(),
+                Json::Dict(x) => x.len(),
+                Json::Value(_) => return None,
+            },
+            Function::Count => self.args[0].nodes(cx, current).len(),
+            Function::Value => {
+                return match self.args[0].nodes(cx, current).as_slice() {
+                    [node] => Some(Cow::Borrowed(*node)),
+                    _ => None,
+                }
+            }
+            Function::Match | Function::Search => unreachable!("type checked"),
+        };
+        Some(Cow::Owned(Json::from(length)))
+    }
+
+    fn test<'x>(&'x self, cx: &Context<'x>, current: &'x Json) -> bool {
+        let text = self.args[0].value(cx, current);
+        let pattern = self.args[1].value(cx, current);
+        match (text.as_deref(), pattern.as_deref()) {
+            (
+                Some(Json::Value(Constant::StringLiteral(text))),
+                Some(Json::Value(Constant::StringLiteral(pattern))),
+            ) => cx.is_match(pattern, text, self.function == Function::Match),
+            _ => false,
+        }
+    }
+}
+
+impl JsonPath {
+    pub fn parse(query: &str) -> Result<JsonPath, JsonPathError> {
+        Parser {
+            input: query,
+            pos: 0,
+            depth: 0,
+        }
+        .query()
+    }
+
+    fn nodes<'a>(&self, root: &'a Json) -> Vec<Node<'a>> {
+        let cx = Context {
+            root,
+            regexes: RefCell::new(HashMap::new()),
+        };
+        apply(
+            &self.segments,
+            &cx,
+            vec![Node {
+                value: root,
+                path: None,
+            }],
+        )
+    }
+
+    /// The selected nodes. Object members are visited in key order.
+    pub fn query<'a>(&self, root: &'a Json) -> Vec<&'a Json> {
+        self.nodes(root)
+            .into_iter()
+            .map(|node| node.value)
+            .collect()
+    }
+
+    /// The selected nodes with their normalized paths, e.g. `$['book'][0]`.
+    pub fn query_located<'a>(&self, root: &'a Json) -> Vec<(String, &'a Json)> {
+        self.nodes(root)
+            .into_iter()
+            .map(|node| (node.normalized_path(), node.value))
+            .collect()
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = JsonPathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        JsonPath::parse(s)
+    }
+}
+
+impl Json {
+    /// The nodes selected by the JSONPath `query`, e.g. `$.store.book[?@.price < 10].title`.
+    pub fn query(&self, query: &str) -> Result<Vec<&Json>, JsonPathError> {
+        Ok(JsonPath::parse(query)?.query(self))
+    }
+
+    /// Like `query`, with the normalized path of each node.
+    pub fn query_located(&self, query: &str) -> Result<Vec<(String, &Json)>, JsonPathError> {
+        Ok(JsonPath::parse(query)?.query_located(self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn store() -> Json {
+        json!({"store": {
+            "book": [
+                {"category": "reference", "author": "Nigel Rees",
+                 "title": "Sayings of the Century", "price": 8.95},
+                {"category": "fiction", "author": "Evelyn Waugh",
+                 "title": "Sword of Honour", "price": 12.99},
+                {"category": "fiction", "author": "Herman Melville",
+                 "title": "Moby Dick", "isbn": "0-553-21311-3", "price": 8.99},
+                {"category": "fiction", "author": "J. R. R. Tolkien",
+                 "title": "The Lord of the Rings", "isbn": "0-395-19395-8", "price": 22.99}
+            ],
+            "bicycle": {"color": "red", "price": 399}
+        }})
+    }
+
+    fn paths(json: &Json, query: &str) -> Vec<String> {
+        let located = json.query_located(query).unwrap();
+        located.into_iter().map(|(path, _)| path).collect()
+    }
+
+    #[test]
+    fn test_rfc_9535_examples() {
+        let json = store();
+        assert_eq!(
+            json.query("$.store.book[*].author").unwrap(),
+            [
+                &json!("Nigel Rees"),
+                &json!("Evelyn Waugh"),
+                &json!("Herman Melville"),
+                &json!("J. R. R. Tolkien")
+            ]
+        );
+        assert_eq!(json.query("$..author").unwrap().len(), 4);
+        assert_eq!(json.query("$.store.*").unwrap().len(), 2);
+        assert_eq!(json.query("$.store..price").unwrap().len(), 5);
+        assert_eq!(
+            json.query("$..book[2].author").unwrap(),
+            [&json!("Herman Melville")]
+        );
+        assert_eq!(
+            json.query("$..book[2].publisher").unwrap(),
+            Vec::<&Json>::new()
+        );
+        assert_eq!(paths(&json, "$..book[-1]"), ["$['store']['book'][3]"]);
+        assert_eq!(paths(&json, "$..book[0,1]"), paths(&json, "$..book[:2]"));
+        assert_eq!(
+            paths(&json, "$..book[?@.isbn]"),
+            ["$['store']['book'][2]", "$['store']['book'][3]"]
+        );
+        assert_eq!(
+            json.query("$..book[?@.price<10].title").unwrap(),
+            [&json!("Sayings of the Century"), &json!("Moby Dick")]
+        );
+        assert_eq!(json.query("$..*").unwrap().len(), 27);
+        assert_eq!(
+            json.query("$.store.book[?@.price < 10 && !(@.category == 'reference')].title")
+                .unwrap(),
+            [&json!("Moby Dick")]
+        );
+    }
+
+    #[test]
+    fn test_selectors_and_functions() {
+        let json = json!({
+            "a": [3, 5, 1, 2, 4, 6, {"b": "j"}, {"b": "k"}, {"b": {}}, {"b": "kilo"}],
+            "o": {"p": 1, "q": 2, "r": [7], "it's": 0, "\n": 1}
+        });
+        let values = |query: &str| -> Vec<Json> {
+            json.query(query).unwrap().into_iter().cloned().collect()
+        };
+        assert_eq!(values("$.a[1:3]"), [json!(5), json!(1)]);
+        assert_eq!(values("$.a[5:1:-2]"), [json!(6), json!(2)]);
+        assert_eq!(
+            values("$.a[::-4]"),
+            [json!({"b": "kilo"}), json!(6), json!(5)]
+        );
+        assert_eq!(values("$.a[-20:2]"), [json!(3), json!(5)]);
+        assert_eq!(values("$.a[1:2:0]"), Vec::<Json>::new());
+        assert_eq!(values("$.a[?@ > 3 && @ <= 5]"), [json!(5), json!(4)]);
+        assert_eq!(values("$.a[?@.b == 'k']"), [json!({"b": "k"})]);
+        assert_eq!(
+            values("$.a[?match(@.b, 'k.*')].b"),
+            [json!("k"), json!("kilo")]
+        );
+        assert_eq!(
+            values("$.a[?search(@.b, '[jl]')].b"),
+            [json!("j"), json!("kilo")]
+        );
+        assert_eq!(values("$.a[?length(@.b) == 0]"), [json!({"b": {}})]);
+        assert_eq!(values("$.o[?count(@.*) == 1]"), [json!([7])]);
+        assert_eq!(values("$.o[?value(@..*) == 7]"), [json!([7])]);
+        assert_eq!(values("$[?$.o.p == 1.0].p"), [json!(1)]);
+        assert_eq!(values("$.o[?@ == $.o.q]"), [json!(2)]);
+        assert_eq!(values(r#"$.o["p", 'q']"#), [json!(1), json!(2)]);
+        assert_eq!(values("$.o[?@ < 'x' || @ == 2]"), [json!(2)]);
+        assert_eq!(values("$.o[?@.missing == @.other]").len(), 5);
+        assert_eq!(
+            paths(&json, "$.o[?@ == 0 || @ == 1]"),
+            ["$['o']['\\n']", "$['o']['it\\'s']", "$['o']['p']"]
+        );
+    }
+
+    #[test]
+    fn test_i_regexp() {
+        let json = json!(null);
+        let cx = Context {
+            root: &json,
+            regexes: RefCell::new(HashMap::new()),
+        };
+        assert!(cx.is_match("a^b$", "a^b$", true));
+        assert!(!cx.is_match("^b", "b", false));
+        assert!(cx.is_match("[^a]\\.[$^]", "b.^", true));
+        assert!(cx.is_match("\\p{Nd}+", "\u{663}", true));
+        for pattern in ["\\d", "\\w", "\\b", "a\\"] {
+            assert!(!cx.is_match(pattern, "1", false), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_invalid_queries() {
+        let syntax = [
+            ("", 0),
+            ("$.", 2),
+            ("$ ", 1),
+            ("$[01]", 2),
+            ("$[-0]", 2),
+            ("$[9007199254740992]", 2),
+            ("$['a'", 5),
+            ("$['\\x']", 5),
+            ("$[?@.a == 'b' &]", 14),
+            ("$[?foo(@)]", 3),
+            ("$[?@.a = 1]", 7),
+        ];
+        for (query, position) in syntax {
+            match JsonPath::parse(query) {
+                Err(JsonPathError::Syntax(pos, _)) => assert_eq!(pos, position, "{}", query),
+                other => panic!("{}: {:?}", query, other),
+            }
+        }
+        let nested =
+            |open: &str, close: &str, n| format!("$[?{}@.a{}]", open.repeat(n), close.repeat(n));
+        for (open, close) in [("(", ")"), ("!(", ")"), ("@[?", "]")] {
+            assert_eq!(
+                JsonPath::parse(&nested(open, close, 10000)),
+                Err(JsonPathError::Syntax(
+                    3 + open.len() * MAX_NESTING,
+                    "expressions are nested too deeply".to_string()
+                )),
+                "{}",
+                open
+            );
+        }
+        let json = json!([{"a": 1}]);
+        assert_eq!(json.query(&nested("(", ")", 100)).unwrap().len(), 1);
+        assert_eq!(json.query(&nested("@[?", "]", 100)).unwrap().len(), 0);
+        let types = [
+            "$[?length(@)]",
+            "$[?@.* == 1]",
+            "$[?1]",
+            "$[?count(1) == 1]",
+            "$[?length(@.*) == 1]",
+            "$[?match(@.a) == true]",
+            "$[?match(@.a, 'a') == true]",
+            "$[?value(@.a, 1) == 1]",
+        ];
+        for query in types {
+            assert!(
+                matches!(JsonPath::parse(query), Err(JsonPathError::Type(..))),
+                "{}",
+                query
+            );
+        }
+    }
+}
@@ -1,3 +1,7 @@
+use crate::float_parse::parse_f64;
+use crate::limits::{Limit, Limits};
+use crate::parser::JsonError;
+use std::ops::Range;
 use std::str::FromStr;
 use thiserror::Error;
 
@@ -6,17 +10,148 @@ pub enum JsonLexError {
     /// Not a valid token
     #[error("Invalid syntax in token: {0}")]
     InvalidSyntax(String),
+    /// Backslash sequence inside a string literal that JSON doesn't define
+    #[error("Invalid escape sequence in string: {0}")]
+    InvalidEscape(String),
+    /// Integer literal that fits neither i64 nor u64
+    #[error("Integer out of range: {0}")]
+    IntegerOverflow(String),
+}
+
+/// How number literals are turned into constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberMode {
+    /// Integers become `Int`/`UInt`, everything else `Float`.
+    #[default]
+    Native,
+    /// Every number becomes a `Number` that keeps the literal exactly as written.
+    Preserve,
+}
+
+/// A number literal kept exactly as it was written, e.g. `0.1000000000000000055511151231257827`.
+/// It is converted to a native type only on demand.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Number(String);
+
+impl Number {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether the literal has neither a fraction nor an exponent.
+    pub fn is_integer(&self) -> bool {
+        is_integer_literal(&self.0)
+    }
+
+    /// `None` if the number isn't an integer or doesn't fit.
+    pub fn as_i64(&self) -> Option<i64> {
+        self.0.parse().ok()
+    }
+
+    /// `None` if the number isn't an integer or doesn't fit.
+    pub fn as_u64(&self) -> Option<u64> {
+        self.0.parse().ok()
+    }
+
+    /// `None` if the number isn't an integer or doesn't fit.
+    pub fn as_i128(&self) -> Option<i128> {
+        self.0.parse().ok()
+    }
+
+    /// The closest f64, which may be infinite for huge exponents.
+    pub fn as_f64(&self) -> f64 {
+        parse_f64(&self.0).expect("checked against the JSON number grammar on construction")
+    }
+}
+
+impl FromStr for Number {
+    type Err = JsonLexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.is_empty() && scan_number(s.as_bytes(), 0) == Some(s.len()) {
+            Ok(Number(s.to_string()))
+        } else {
+            Err(JsonLexError::InvalidSyntax(s.to_string()))
+        }
+    }
+}
+
+impl std::fmt::Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Returns the position right after the number starting at `start`, or `None` if it doesn't
+/// follow the JSON grammar `-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?`.
+pub(crate) fn scan_number(bytes: &[u8], start: usize) -> Option<usize> {
+    let digits = |mut pos: usize| {
+        while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+            pos += 1;
+        }
+        pos
+    };
+    let mut pos = start;
+    if bytes[pos] == b'-' {
+        pos += 1;
+    }
+    match bytes.get(pos) {
+        Some(b'0') => {
+            pos += 1;
+            if bytes.get(pos).is_some_and(u8::is_ascii_digit) {
+                return None;
+            }
+        }
+        Some(b'1'..=b'9') => pos = digits(pos),
+        _ => return None,
+    }
+    if bytes.get(pos) == Some(&b'.') {
+        let end = digits(pos + 1);
+        if end == pos + 1 {
+            return None;
+        }
+        pos = end;
+    }
+    if matches!(bytes.get(pos), Some(b'e' | b'E')) {
+        pos += 1;
+        if matches!(bytes.get(pos), Some(b'+' | b'-')) {
+            pos += 1;
+        }
+        let end = digits(pos);
+        if end == pos {
+            return None;
+        }
+        pos = end;
+    }
+    Some(pos)
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Constant {
     Float(f64),
-    Int(isize),
+    Int(i64),
+    /// Only used for integers above `i64::MAX`, everything else is an `Int`.
+    UInt(u64),
+    /// Only produced with `NumberMode::Preserve`.
+    Number(Number),
+    /// The text without quotes and with the escapes resolved, so `"a\nb"` holds a newline.
     StringLiteral(String),
     Null,
     Boolean(bool),
 }
 
+impl Constant {
+    pub fn parse_with(s: &str, number_mode: NumberMode) -> Result<Self, JsonLexError> {
+        let s = s.trim();
+        let is_number = s.starts_with(|c: char| c == '-' || c.is_ascii_digit());
+        if number_mode == NumberMode::Preserve && is_number {
+            Ok(Self::Number(Number::from_str(s)?))
+        } else {
+            Self::from_str(s)
+        }
+    }
+}
+
 impl FromStr for Constant {
     type Err = JsonLexError;
 
@@ -27,14 +162,22 @@ impl FromStr for Constant {
                 // 2 seperate quotes
                 Err(Self::Err::InvalidSyntax(s.to_string()))
             } else {
-                Ok(Self::StringLiteral(s[1..s.len() - 1].to_string())) // cut off quotes
+                Ok(Self::StringLiteral(unescape(&s[1..s.len() - 1])?)) // cut off quotes
             }
         } else if s == "null" {
             Ok(Self::Null)
-        } else if let Ok(integer) = s.parse::<isize>() {
-            Ok(Self::Int(integer))
-        } else if let Ok(float) = s.parse::<f64>() {
-            Ok(Self::Float(float))
+        } else if s.starts_with(|c: char| c == '-' || c.is_ascii_digit()) {
+            let number = Number::from_str(s)?; // checks the JSON number grammar
+            if let Some(integer) = number.as_i64() {
+                Ok(Self::Int(integer))
+            } else if let Some(integer) = number.as_u64() {
+                Ok(Self::UInt(integer))
+            } else if number.is_integer() {
+                // don't silently fall back to a float and lose precision
+                Err(Self::Err::IntegerOverflow(s.to_string()))
+            } else {
+                Ok(Self::Float(number.as_f64()))
+            }
         } else if s == "true" || s == "false" {
             Ok(Self::Boolean(s == "true"))
         } else {
@@ -43,6 +186,83 @@ impl FromStr for Constant {
     }
 }
 
+fn is_integer_literal(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    !digits.is_empty() && digits.bytes().all(|c| c.is_ascii_digit())
+}
+
+/// Decodes the backslash escapes of a string literal (without the surrounding quotes).
+pub(crate) fn unescape(s: &str) -> Result<String, JsonLexError> {
+    if !s.contains('\\') {
+        return Ok(s.to_string());
+    }
+    let invalid = || JsonLexError::InvalidEscape(s.to_string());
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next().ok_or_else(invalid)? {
+            '"' => result.push('"'),
+            '\\' => result.push('\\'),
+            '/' => result.push('/'),
+            'b' => result.push('\u{8}'),
+            'f' => result.push('\u{c}'),
+            'n' => result.push('\n'),
+            'r' => result.push('\r'),
+            't' => result.push('\t'),
+            'u' => {
+                let high = read_hex4(&mut chars).ok_or_else(invalid)?;
+                let code = if (0xD800..0xDC00).contains(&high) {
+                    // surrogate pair, the low half has to follow right away
+                    if chars.next() != Some('\\') || chars.next() != Some('u') {
+                        return Err(invalid());
+                    }
+                    let low = read_hex4(&mut chars).ok_or_else(invalid)?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(invalid());
+                    }
+                    0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                } else {
+                    high
+                };
+                result.push(char::from_u32(code).ok_or_else(invalid)?);
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(result)
+}
+
+fn read_hex4(chars: &mut std::str::Chars) -> Option<u32> {
+    let mut code = 0;
+    for _ in 0..4 {
+        code = code * 16 + chars.next()?.to_digit(16)?;
+    }
+    Some(code)
+}
+
+/// Inverse of `unescape`: turns arbitrary text into the content of a JSON string literal.
+pub(crate) fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            '\u{8}' => result.push_str("\\b"),
+            '\u{c}' => result.push_str("\\f"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     StartOfDict,
@@ -54,12 +274,28 @@ pub enum Token {
     Comma,
 }
 
+/// A token together with the byte range it was lexed from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Range<usize>,
+}
+
 impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
+impl Token {
+    pub fn parse_with(s: &str, number_mode: NumberMode) -> Result<Self, JsonLexError> {
+        match s.trim() {
+            "{" | "}" | "[" | "]" | ":" | "," => Self::from_str(s),
+            s => Ok(Self::Constant(Constant::parse_with(s, number_mode)?)),
+        }
+    }
+}
+
 impl FromStr for Token {
     type Err = JsonLexError;
 
@@ -83,15 +319,23 @@ enum LexStateType {
     Numeric, // this is either float or int (in the case of int, we never know if there is comma
     // coming at some point, so there is no state for int because when we could conclude with
     // certainty that it's the correct state, we don't need the state anymore as we can also just
-    // parse it right away)
-    Float,
+    // parse it right away). Whether the collected characters actually form a valid number is
+    // checked against the JSON grammar when the token is converted.
     String,
-    Null,
+    Literal, // null, true or false
     Invalid,
     Symbol, // Includes { } [ ] , : ... those are all single character, meaning any character
             // terminates them.
 }
 
+fn is_number_char(c: char) -> bool {
+    c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-')
+}
+
+fn is_literal_prefix(s: &str) -> bool {
+    ["null", "true", "false"].iter().any(|literal| literal.starts_with(s))
+}
+
 struct LexState {
     state: LexStateType,
     buffer: String,
@@ -124,14 +368,12 @@ impl LexState {
         self.buffer.push(c);
         match self.state {
             LexStateType::Any => {
-                if c.is_numeric() {
+                if c.is_ascii_digit() || c == '-' || c == '.' {
                     self.state = LexStateType::Numeric;
-                } else if c == '.' {
-                    self.state = LexStateType::Float;
                 } else if c == '"' {
                     self.state = LexStateType::String;
-                } else if c == 'n' {
-                    self.state = LexStateType::Null;
+                } else if c == 'n' || c == 't' || c == 'f' {
+                    self.state = LexStateType::Literal;
                 } else if c == ' ' || c == '\n' || c == '\t' {
                     self.state = LexStateType::Any;
                 } else if c == '{' || c == '}' || c == '[' || c == ']' || c == ':' || c == ',' {
@@ -141,45 +383,18 @@ impl LexState {
                 }
             }
             LexStateType::Numeric => {
-                if c == '.' {
-                    self.state = LexStateType::Float;
-                } else if c.is_numeric() {
+                if is_number_char(c) {
                     self.state = LexStateType::Numeric;
                 } else {
                     self.state = LexStateType::Invalid;
                 }
             }
-            LexStateType::Float => {
-                if c.is_numeric() {
-                    self.state = LexStateType::Float;
-                } else if c == '.' {
-                    let prev_option = self.buffer.chars().last();
-                    if let Some(prev) = prev_option {
-                        if prev == '.' {
-                            self.state = LexStateType::Invalid;
-                        } else {
-                            self.state = LexStateType::Float;
-                        }
-                    } else {
-                        // Self { state: LexStateType::Invalid, buffer }
-                        panic!("State had type Float even though buffer was empty, so there was no way to infer this information.")
-                    }
-                } else {
-                    self.state = LexStateType::Invalid;
-                }
-            }
             LexStateType::String => {
                 self.state = LexStateType::String;
             }
-            LexStateType::Null => {
-                if self.buffer.is_empty() {
-                    panic!("State had type Null even though self.buffer was empty, so there was no way to infer this information.")
-                } else if self.buffer == "null"
-                    || self.buffer == "nul"
-                    || self.buffer == "nu"
-                    || self.buffer == "n"
-                {
-                    self.state = LexStateType::Null;
+            LexStateType::Literal => {
+                if is_literal_prefix(&self.buffer) {
+                    self.state = LexStateType::Literal;
                 } else {
                     self.state = LexStateType::Invalid;
                 }
@@ -198,31 +413,9 @@ impl LexState {
     fn is_terminated_by(&self, c: char) -> bool {
         match self.state {
             LexStateType::Any => false,
-            LexStateType::Numeric => !(c.is_numeric() || c == '.'),
-            LexStateType::Float => !c.is_numeric(),
-            LexStateType::String => {
-                // The following checks if the previous character is " &
-                // before it is not a backslash
-                self.buffer
-                    .chars()
-                    .last()
-                    .expect("Must not be empty because it is in state String")
-                    == '"'
-                    && self
-                        .buffer
-                        .chars()
-                        .take(self.buffer.len() - 1)
-                        .last()
-                        .unwrap_or(' ')
-                        != '\\'
-            }
-            LexStateType::Null => match self.buffer.as_str() {
-                "null" => c != ' ',
-                "nul" => c != 'l',
-                "nu" => c != 'l',
-                "n" => c != 'u',
-                _ => true,
-            },
+            LexStateType::Numeric => !is_number_char(c),
+            LexStateType::String => self.is_closed_string(),
+            LexStateType::Literal => !self.allows(c),
             LexStateType::Invalid => {
                 panic!("Invalid state reached, so this should never be called.")
             }
@@ -230,33 +423,25 @@ impl LexState {
         }
     }
 
+    /// Whether the buffer holds a complete string literal, i.e. it ends in a quote that isn't the
+    /// opening one and isn't escaped. Backslashes before it escape each other in pairs.
+    fn is_closed_string(&self) -> bool {
+        let Some(body) = self.buffer.strip_prefix('"') else {
+            return false;
+        };
+        let Some(body) = body.strip_suffix('"') else {
+            return false;
+        };
+        let backslashes = body.chars().rev().take_while(|&c| c == '\\').count();
+        backslashes % 2 == 0
+    }
+
     fn allows(&self, c: char) -> bool {
         match self.state {
             LexStateType::Any => true,
-            LexStateType::Numeric => c.is_numeric() || c == '.',
-            LexStateType::Float => c.is_numeric(),
-            LexStateType::String => {
-                self.buffer
-                    .chars()
-                    .take(self.buffer.len() - 1)
-                    .last()
-                    .unwrap_or('\\')  // unwrap_or('\\') so that if there is no character before it
-                // (meaning we are checking the string opening quotes), it should not terminate
-                    == '\\'
-                    || self
-                        .buffer
-                        .chars()
-                        .last()
-                        .expect("Must not be empty because of state String")
-                        != '"'
-            } // string allows any character apart from " (except if there is a \ before it)
-            LexStateType::Null => match self.buffer.as_str() {
-                "null" => c == ' ',
-                "nul" => c == 'l',
-                "nu" => c == 'l',
-                "n" => c == 'u',
-                _ => false,
-            },
+            LexStateType::Numeric => is_number_char(c),
+            LexStateType::String => !self.is_closed_string(),
+            LexStateType::Literal => is_literal_prefix(&format!("{}{}", self.buffer, c)),
             LexStateType::Invalid => {
                 panic!("Invalid state reached, so this should never be called.")
             }
@@ -265,12 +450,14 @@ impl LexState {
     }
 }
 
+#[allow(dead_code)]
 pub(crate) struct Tokenizer<'a> {
     json: std::iter::Peekable<std::str::Chars<'a>>,
     state: LexState,
 }
 
 impl<'a> Tokenizer<'a> {
+    #[allow(dead_code)]
     fn new(json: &'a str) -> Self {
         Self {
             json: json.chars().peekable(),
@@ -319,27 +506,146 @@ impl<'a> Iterator for Tokenizer<'a> {
 
 // not actually needed (because of Tokenizer), but won't delete it either.
 pub fn tokenize(json: &str) -> Result<Vec<Token>, JsonLexError> {
-    let mut tokens: Vec<Token> = vec![];
-    let mut state = LexState::new();
-    for c in json.chars() {
-        if state.is_invalid() {
-            return Err(Token::from_str(&state.buffer).unwrap_err());
-        } else if state.allows(c) {
-            state.encorporate(c);
-        } else if state.is_terminated_by(c) {
-            tokens.push(Token::from_str(&state.buffer)?);
-            state = LexState::new();
-            state.encorporate(c);
-        } else {
-            state.buffer.push(c); // push the invalid character so that the conversion to token will
-                                  // fail with appropriate error.
-            return Err(Token::from_str(&state.buffer).unwrap_err());
+    tokenize_with(json, NumberMode::Native)
+}
+
+pub fn tokenize_with(json: &str, number_mode: NumberMode) -> Result<Vec<Token>, JsonLexError> {
+    Ok(tokenize_spanned(json, number_mode)?
+        .into_iter()
+        .map(|spanned| spanned.token)
+        .collect())
+}
+
+pub fn tokenize_spanned(
+    json: &str,
+    number_mode: NumberMode,
+) -> Result<Vec<SpannedToken>, JsonLexError> {
+    Lexer::new(json, number_mode, &Limits::unlimited())
+        .map(|token| {
+            token.map_err(|e| match e {
+                JsonError::JsonLexError(e) => e,
+                e => unreachable!("unlimited lexing failed with {}", e),
+            })
+        })
+        .collect()
+}
+
+/// Lexes one token at a time. The length limits of strings and numbers are checked while they
+/// are scanned, so an over-long literal fails before it is buffered completely.
+pub(crate) struct Lexer<'a> {
+    json: &'a str,
+    chars: std::str::CharIndices<'a>,
+    number_mode: NumberMode,
+    max_string_length: usize,
+    max_number_length: usize,
+    state: LexState,
+    /// Where the token in `state` starts.
+    start: usize,
+    done: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub(crate) fn new(json: &'a str, number_mode: NumberMode, limits: &Limits) -> Self {
+        Self {
+            json,
+            chars: json.char_indices(),
+            number_mode,
+            max_string_length: limits.max_string_length,
+            max_number_length: limits.max_number_length,
+            state: LexState::new(),
+            start: 0,
+            done: false,
         }
     }
-    if state.state != LexStateType::Any {
-        tokens.push(Token::from_str(&state.buffer)?);
+
+    fn lex(&mut self) -> Result<Option<SpannedToken>, JsonError> {
+        while let Some((pos, c)) = self.chars.next() {
+            if self.state.is_invalid() {
+                return Err(Token::from_str(&self.state.buffer).unwrap_err().into());
+            } else if self.state.allows(c) {
+                if self.state.is_any() && !self.state.ignores(c) {
+                    self.start = pos;
+                }
+                self.state.encorporate(c);
+                self.check_buffer()?;
+            } else if self.state.is_terminated_by(c) {
+                let token = self.token(pos)?;
+                self.state = LexState::new();
+                self.start = pos;
+                self.state.encorporate(c);
+                return Ok(Some(token));
+            } else {
+                self.state.buffer.push(c); // push the invalid character so that the conversion to
+                                           // token will fail with appropriate error.
+                return Err(Token::from_str(&self.state.buffer).unwrap_err().into());
+            }
+        }
+        if self.state.is_any() {
+            return Ok(None);
+        }
+        let token = self.token(self.json.len())?;
+        self.state = LexState::new();
+        Ok(Some(token))
+    }
+
+    /// Fails as soon as the literal being scanned can't fit its limit. An escape is at most six
+    /// bytes per byte of the unescaped string.
+    fn check_buffer(&self) -> Result<(), JsonError> {
+        let length = self.state.buffer.len();
+        match self.state.state {
+            LexStateType::Numeric if length > self.max_number_length => Err(
+                JsonError::LimitExceeded(Limit::NumberLength, self.max_number_length),
+            ),
+            LexStateType::String
+                if length > self.max_string_length.saturating_mul(6).saturating_add(2) =>
+            {
+                Err(JsonError::LimitExceeded(
+                    Limit::StringLength,
+                    self.max_string_length,
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The token in `state`, which ends at `end`.
+    fn token(&self, end: usize) -> Result<SpannedToken, JsonError> {
+        let token = Token::parse_with(&self.state.buffer, self.number_mode)?;
+        let checked = match &token {
+            Token::Constant(Constant::StringLiteral(s)) => {
+                Some((Limit::StringLength, s.len(), self.max_string_length))
+            }
+            Token::Constant(Constant::Null | Constant::Boolean(_)) => None,
+            Token::Constant(_) => Some((
+                Limit::NumberLength,
+                end - self.start,
+                self.max_number_length,
+            )),
+            _ => None,
+        };
+        if let Some((limit, length, max)) = checked {
+            if length > max {
+                return Err(JsonError::LimitExceeded(limit, max));
+            }
+        }
+        Ok(SpannedToken {
+            token,
+            span: self.start..end,
+        })
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<SpannedToken, JsonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.lex();
+        self.done = !matches!(result, Ok(Some(_)));
+        result.transpose()
     }
-    Ok(tokens)
 }
 
 #[cfg(test)]
@@ -397,11 +703,92 @@ mod test {
         println!("**Tokens**: {:?}", tokens);
     }
 
+    #[test]
+    fn test_string_ends() {
+        let tokens = tokenize(r#"["a\\", "é\"", "\\\"", "é\\\\"]"#).expect("should not error");
+        let strings: Vec<&str> = tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::Constant(Constant::StringLiteral(s)) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(strings, vec!["a\\", "\u{e9}\"", "\\\"", "é\\\\"]);
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let json = "{ \"k\\u00e9y\" :\t[-1.5e3,null ,\"é\"]}";
+        let spans: Vec<&str> = tokenize_spanned(json, NumberMode::Native)
+            .expect("should not error")
+            .into_iter()
+            .map(|spanned| &json[spanned.span])
+            .collect();
+        assert_eq!(
+            spans,
+            vec!["{", "\"k\\u00e9y\"", ":", "[", "-1.5e3", ",", "null", ",", "\"é\"", "]", "}"]
+        );
+    }
+
+    #[test]
+    fn test_integer_ranges() {
+        assert_eq!(Constant::from_str("-42").unwrap(), Constant::Int(-42));
+        assert_eq!(
+            Constant::from_str("9223372036854775807").unwrap(),
+            Constant::Int(i64::MAX)
+        );
+        assert_eq!(
+            Constant::from_str("-9223372036854775808").unwrap(),
+            Constant::Int(i64::MIN)
+        );
+        assert_eq!(
+            Constant::from_str("18446744073709551615").unwrap(),
+            Constant::UInt(u64::MAX)
+        );
+        assert!(matches!(
+            Constant::from_str("18446744073709551616"),
+            Err(JsonLexError::IntegerOverflow(_))
+        ));
+        assert!(matches!(
+            Constant::from_str("-9223372036854775809"),
+            Err(JsonLexError::IntegerOverflow(_))
+        ));
+        assert!(Constant::from_str("-").is_err());
+        let tokens = tokenize("[-1, 2]").expect("should not error");
+        assert_eq!(tokens[1], Token::Constant(Constant::Int(-1)));
+    }
+
+    #[test]
+    fn test_number_grammar() {
+        let tokens = tokenize("[-1.5e3, 2E-2, 0.25, true, false, null]").expect("should not error");
+        let constants: Vec<_> = tokens
+            .into_iter()
+            .filter_map(|t| match t {
+                Token::Constant(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            constants,
+            vec![
+                Constant::Float(-1500.0),
+                Constant::Float(0.02),
+                Constant::Float(0.25),
+                Constant::Boolean(true),
+                Constant::Boolean(false),
+                Constant::Null,
+            ]
+        );
+        for invalid in ["[01]", "[1.]", "[.5]", "[1e]", "[--1]", "[1-2]", "[tru]", "[nul]"] {
+            assert!(tokenize(invalid).is_err(), "{} should not lex", invalid);
+        }
+    }
+
     #[test]
     fn test_tokenizer_1() {
         let json = r#"{"key": "value"}"#;
-        let mut tokenizer = Tokenizer::new(json);
-        while let Some(token) = tokenizer.next() {
+        let tokenizer = Tokenizer::new(json);
+        for token in tokenizer {
             println!("Token: {:?}", token.expect("should not crash"));
         }
     }
@@ -420,8 +807,8 @@ mod test {
             }
         }
         "#;
-        let mut tokenizer = Tokenizer::new(json);
-        while let Some(token) = tokenizer.next() {
+        let tokenizer = Tokenizer::new(json);
+        for token in tokenizer {
             let token = match token {
                 Ok(token) => token,
                 Err(err) => panic!("Error returned: {:?}", err),
@@ -1,3 +1,38 @@
+#[macro_use]
+mod macros;
+
+pub mod canonical;
+pub mod codegen;
+pub mod convert;
+pub mod cst;
+#[cfg(feature = "serde")]
+pub mod de;
+pub mod derive;
+pub mod diff;
+pub mod float_format;
+pub mod float_parse;
+pub mod infer;
+pub mod jmespath;
+pub mod jq;
+pub mod jsonpath;
 pub mod lexer;
+pub mod limits;
+pub mod merge_patch;
 pub mod parser;
+pub mod patch;
+pub mod pointer;
+pub mod recover;
+pub mod schema;
+#[cfg(feature = "serde")]
+pub mod ser;
+pub mod source_map;
+pub mod writer;
 mod tests;
+
+#[cfg(feature = "serde")]
+pub use de::from_str;
+pub use derive::{FromJson, ToJson, ToJsonObject};
+#[cfg(feature = "derive")]
+pub use rusty_json_derive::{FromJson, ToJson};
+#[cfg(feature = "serde")]
+pub use ser::to_string;
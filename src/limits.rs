@@ -0,0 +1,79 @@
+//! Resource limits for parsing untrusted input.
+use std::fmt;
+
+/// The resource a `Limits` field restricts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Depth,
+    InputSize,
+    StringLength,
+    NumberLength,
+    ArrayLength,
+    MemberCount,
+    NodeCount,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Depth => "nesting depth",
+            Limit::InputSize => "input size",
+            Limit::StringLength => "string length",
+            Limit::NumberLength => "number literal length",
+            Limit::ArrayLength => "array length",
+            Limit::MemberCount => "object member count",
+            Limit::NodeCount => "node count",
+        })
+    }
+}
+
+/// Upper bounds checked while parsing. Sizes and lengths are in bytes, string lengths are
+/// measured after unescaping, number literals as written. Only the nesting depth is bounded by
+/// default, so that deeply nested input can't overflow the stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    pub max_depth: usize,
+    pub max_input_size: usize,
+    pub max_string_length: usize,
+    pub max_number_length: usize,
+    pub max_array_length: usize,
+    /// Members of a single object, duplicate keys included.
+    pub max_members: usize,
+    /// Values in the whole document, containers included.
+    pub max_nodes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: 128,
+            ..Self::unlimited()
+        }
+    }
+}
+
+impl Limits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_depth: usize::MAX,
+            max_input_size: usize::MAX,
+            max_string_length: usize::MAX,
+            max_number_length: usize::MAX,
+            max_array_length: usize::MAX,
+            max_members: usize::MAX,
+            max_nodes: usize::MAX,
+        }
+    }
+
+    pub fn get(&self, limit: Limit) -> usize {
+        match limit {
+            Limit::Depth => self.max_depth,
+            Limit::InputSize => self.max_input_size,
+            Limit::StringLength => self.max_string_length,
+            Limit::NumberLength => self.max_number_length,
+            Limit::ArrayLength => self.max_array_length,
+            Limit::MemberCount => self.max_members,
+            Limit::NodeCount => self.max_nodes,
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! The `json!` macro.
+
+/// Builds a `parser::Json` from JSON-like syntax. Values that aren't JSON literals are Rust
+/// expressions converted with `Into<Json>`, keys are anything that converts `Into<String>`:
+///
+/// ```
+/// use rusty_json::json;
+///
+/// let x = 5;
+/// let value = json!({"key1": 1, "key3": [1, 2, x], "nested": {"x": "y", "z": null}});
+/// assert_eq!(value.to_string(), r#"{"key1":1,"key3":[1,2,5],"nested":{"x":"y","z":null}}"#);
+/// ```
+///
+/// Malformed input, like a missing colon or value, is a compile error:
+///
+/// ```compile_fail
+/// use rusty_json::json;
+///
+/// let value = json!({"key": 1, "other"});
+/// ```
+#[macro_export]
+macro_rules! json {
+    ($($json:tt)+) => {
+        $crate::json_internal!($($json)+)
+    };
+}
+
+// Adapted from the tt-muncher in serde_json. Arrays are munched element by element into
+// `@array [elements...] rest...`, objects key token by key token into
+// `@object map (key...) (rest...) (copy of rest...)`. The copy is only used to point error
+// messages at the offending token.
+//
+// serde_json (https://github.com/serde-rs/json) is by Erick Tryzelaar and David Tolnay, licensed
+// under either of the Apache License, Version 2.0 or the MIT license, at your option. Under the
+// MIT license:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! json_internal {
+    // Done with a trailing comma.
+    (@array [$($elems:expr,)*]) => {
+        ::std::vec![$($elems,)*]
+    };
+
+    // Done without a trailing comma.
+    (@array [$($elems:expr),*]) => {
+        ::std::vec![$($elems),*]
+    };
+
+    (@array [$($elems:expr,)*] null $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!(null)] $($rest)*)
+    };
+
+    (@array [$($elems:expr,)*] true $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!(true)] $($rest)*)
+    };
+
+    (@array [$($elems:expr,)*] false $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!(false)] $($rest)*)
+    };
+
+    (@array [$($elems:expr,)*] [$($array:tt)*] $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!([$($array)*])] $($rest)*)
+    };
+
+    (@array [$($elems:expr,)*] {$($map:tt)*} $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!({$($map)*})] $($rest)*)
+    };
+
+    (@array [$($elems:expr,)*] $next:expr, $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!($next),] $($rest)*)
+    };
+
+    (@array [$($elems:expr,)*] $last:expr) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!($last)])
+    };
+
+    // Comma after the most recent element.
+    (@array [$($elems:expr),*] , $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)*] $($rest)*)
+    };
+
+    (@array [$($elems:expr),*] $unexpected:tt $($rest:tt)*) => {
+        $crate::json_unexpected!($unexpected)
+    };
+
+    // Done.
+    (@object $object:ident () () ()) => {};
+
+    // Insert the current entry followed by a trailing comma.
+    (@object $object:ident [$($key:tt)+] ($value:expr) , $($rest:tt)*) => {
+        let _ = $object.insert(($($key)+).into(), $value);
+        $crate::json_internal!(@object $object () ($($rest)*) ($($rest)*));
+    };
+
+    (@object $object:ident [$($key:tt)+] ($value:expr) $unexpected:tt $($rest:tt)*) => {
+        $crate::json_unexpected!($unexpected);
+    };
+
+    // Insert the last entry without a trailing comma.
+    (@object $object:ident [$($key:tt)+] ($value:expr)) => {
+        let _ = $object.insert(($($key)+).into(), $value);
+    };
+
+    (@object $object:ident ($($key:tt)+) (: null $($rest:tt)*) $copy:tt) => {
+        $crate::json_internal!(@object $object [$($key)+] ($crate::json_internal!(null)) $($rest)*);
+    };
+
+    (@object $object:ident ($($key:tt)+) (: true $($rest:tt)*) $copy:tt) => {
+        $crate::json_internal!(@object $object [$($key)+] ($crate::json_internal!(true)) $($rest)*);
+    };
+
+    (@object $object:ident ($($key:tt)+) (: false $($rest:tt)*) $copy:tt) => {
+        $crate::json_internal!(
+            @object $object [$($key)+] ($crate::json_internal!(false)) $($rest)*
+        );
+    };
+
+    (@object $object:ident ($($key:tt)+) (: [$($array:tt)*] $($rest:tt)*) $copy:tt) => {
+        $crate::json_internal!(
+            @object $object [$($key)+] ($crate::json_internal!([$($array)*])) $($rest)*
+        );
+    };
+
+    (@object $object:ident ($($key:tt)+) (: {$($map:tt)*} $($rest:tt)*) $copy:tt) => {
+        $crate::json_internal!(
+            @object $object [$($key)+] ($crate::json_internal!({$($map)*})) $($rest)*
+        );
+    };
+
+    (@object $object:ident ($($key:tt)+) (: $value:expr , $($rest:tt)*) $copy:tt) => {
+        $crate::json_internal!(
+            @object $object [$($key)+] ($crate::json_internal!($value)) , $($rest)*
+        );
+    };
+
+    (@object $object:ident ($($key:tt)+) (: $value:expr) $copy:tt) => {
+        $crate::json_internal!(@object $object [$($key)+] ($crate::json_internal!($value)));
+    };
+
+    // Missing value for the last entry, fails with "unexpected end of macro invocation".
+    (@object $object:ident ($($key:tt)+) (:) $copy:tt) => {
+        $crate::json_internal!();
+    };
+
+    // Missing colon and value for the last entry.
+    (@object $object:ident ($($key:tt)+) () $copy:tt) => {
+        $crate::json_internal!();
+    };
+
+    // Colon without a key.
+    (@object $object:ident () (: $($rest:tt)*) ($colon:tt $($copy:tt)*)) => {
+        $crate::json_unexpected!($colon);
+    };
+
+    // Comma inside a key.
+    (@object $object:ident ($($key:tt)*) (, $($rest:tt)*) ($comma:tt $($copy:tt)*)) => {
+        $crate::json_unexpected!($comma);
+    };
+
+    // Fully parenthesized key.
+    (@object $object:ident () (($key:expr) : $($rest:tt)*) $copy:tt) => {
+        $crate::json_internal!(@object $object ($key) (: $($rest)*) (: $($rest)*));
+    };
+
+    // The value isn't followed by a comma, so none of the value rules above matched.
+    (@object $object:ident ($($key:tt)*) (: $($unexpected:tt)+) $copy:tt) => {
+        $crate::json_expect_expr_comma!($($unexpected)+);
+    };
+
+    // Munch a token into the current key.
+    (@object $object:ident ($($key:tt)*) ($tt:tt $($rest:tt)*) $copy:tt) => {
+        $crate::json_internal!(@object $object ($($key)* $tt) ($($rest)*) ($($rest)*));
+    };
+
+    (null) => {
+        $crate::parser::Json::Value($crate::lexer::Constant::Null)
+    };
+
+    (true) => {
+        $crate::parser::Json::Value($crate::lexer::Constant::Boolean(true))
+    };
+
+    (false) => {
+        $crate::parser::Json::Value($crate::lexer::Constant::Boolean(false))
+    };
+
+    ([]) => {
+        $crate::parser::Json::List(::std::vec::Vec::new())
+    };
+
+    ([ $($tt:tt)+ ]) => {
+        $crate::parser::Json::List($crate::json_internal!(@array [] $($tt)+))
+    };
+
+    ({}) => {
+        $crate::parser::Json::Dict(::std::collections::HashMap::new())
+    };
+
+    ({ $($tt:tt)+ }) => {
+        $crate::parser::Json::Dict({
+            let mut object = ::std::collections::HashMap::new();
+            $crate::json_internal!(@object object () ($($tt)+) ($($tt)+));
+            object
+        })
+    };
+
+    // Any other expression, converted through `Into<Json>`.
+    ($other:expr) => {
+        ::std::convert::Into::<$crate::parser::Json>::into($other)
+    };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! json_unexpected {
+    () => {};
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! json_expect_expr_comma {
+    ($e:expr , $($tt:tt)*) => {};
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lexer::Constant;
+    use crate::parser::{parse, Json};
+
+    #[test]
+    fn test_json_macro() {
+        let name = "rusty".to_string();
+        let count: u64 = u64::MAX;
+        let nested = json!([1.5, false]);
+        let value = json!({
+            "name": name,
+            "count": count,
+            "nested": nested,
+            "computed": 2 * 21,
+            ("key".to_string() + "5"): -1,
+            "list": [null, true, {"x": [], "y": {}}, "s",],
+        });
+        let expected = parse(
+            r#"{"name": "rusty", "count": 18446744073709551615, "nested": [1.5, false],
+            "computed": 42, "key5": -1, "list": [null, true, {"x": [], "y": {}}, "s"]}"#,
+        )
+        .unwrap();
+        assert_eq!(value, expected);
+        assert_eq!(json!(null), Json::Value(Constant::Null));
+        assert_eq!(
+            json!("a"),
+            Json::Value(Constant::StringLiteral("a".to_string()))
+        );
+    }
+}
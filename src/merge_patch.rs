@@ -0,0 +1,153 @@
+//! JSON Merge Patch (RFC 7396).
+use crate::lexer::Constant;
+use crate::parser::Json;
+use std::collections::HashMap;
+
+/// Applies `patch` to `target`: members of an object patch that are `null` are removed, other
+/// members are merged recursively, and any patch that isn't an object replaces the target.
+pub fn merge_patch(target: &mut Json, patch: &Json) {
+    let Json::Dict(members) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !matches!(target, Json::Dict(_)) {
+        *target = Json::Dict(HashMap::new());
+    }
+    let Json::Dict(object) = target else {
+        unreachable!()
+    };
+    for (key, value) in members {
+        match value {
+            Json::Value(Constant::Null) => {
+                object.remove(key);
+            }
+            _ => merge_patch(
+                object
+                    .entry(key.clone())
+                    .or_insert(Json::Value(Constant::Null)),
+                value,
+            ),
+        }
+    }
+}
+
+/// The merge patch that turns `from` into `to`, or `None` if there is none because `to` has
+/// object members that are `null` where `from` doesn't (a merge patch can only delete those).
+/// Values are compared exactly, so `1` is replaced by `1.0`.
+pub fn merge_diff(from: &Json, to: &Json) -> Option<Json> {
+    let null = Json::Value(Constant::Null);
+    match (from, to) {
+        (Json::Dict(a), Json::Dict(b)) => {
+            let mut patch = a
+                .keys()
+                .filter(|k| !b.contains_key(*k))
+                .map(|k| (k.clone(), null.clone()))
+                .collect::<HashMap<_, _>>();
+            for (key, value) in b {
+                let value = match a.get(key) {
+                    Some(old) if old == value => continue,
+                    _ if *value == null => return None,
+                    old => merge_diff(old.unwrap_or(&null), value)?,
+                };
+                patch.insert(key.clone(), value);
+            }
+            Some(Json::Dict(patch))
+        }
+        _ if has_null_members(to) => None,
+        _ => Some(to.clone()),
+    }
+}
+
+/// Whether merging `json` into anything would drop some of it.
+fn has_null_members(json: &Json) -> bool {
+    match json {
+        Json::Dict(x) => x
+            .values()
+            .any(|value| *value == Json::Value(Constant::Null) || has_null_members(value)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rfc_7396_examples() {
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (
+                json!({"a": "b"}),
+                json!({"b": "c"}),
+                json!({"a": "b", "b": "c"}),
+            ),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (
+                json!({"a": "b", "b": "c"}),
+                json!({"a": null}),
+                json!({"b": "c"}),
+            ),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (
+                json!({"a": [{"b": "c"}]}),
+                json!({"a": [1]}),
+                json!({"a": [1]}),
+            ),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (
+                json!({"e": null}),
+                json!({"a": 1}),
+                json!({"e": null, "a": 1}),
+            ),
+            (
+                json!([1, 2]),
+                json!({"a": "b", "c": null}),
+                json!({"a": "b"}),
+            ),
+            (
+                json!({}),
+                json!({"a": {"bb": {"ccc": null}}}),
+                json!({"a": {"bb": {}}}),
+            ),
+        ];
+        for (target, patch, expected) in cases {
+            let mut merged = target.clone();
+            merge_patch(&mut merged, &patch);
+            assert_eq!(merged, expected);
+
+            let generated = merge_diff(&target, &expected).unwrap();
+            let mut merged = target;
+            merge_patch(&mut merged, &generated);
+            assert_eq!(merged, expected);
+        }
+    }
+
+    #[test]
+    fn test_merge_diff() {
+        let from = json!({"a": 1, "b": {"c": [1, 2], "d": true}, "e": "x"});
+        let to = json!({"a": 1.0, "b": {"c": [1], "d": true}, "f": {"g": 1}});
+        assert_eq!(
+            merge_diff(&from, &to),
+            Some(json!({"a": 1.0, "b": {"c": [1]}, "e": null, "f": {"g": 1}}))
+        );
+        let mut merged = from.clone();
+        merge_patch(&mut merged, &merge_diff(&from, &to).unwrap());
+        assert_eq!(merged, to);
+        assert_eq!(merge_diff(&from, &from), Some(json!({})));
+        assert_eq!(merge_diff(&json!({}), &json!({"a": null})), None);
+        assert_eq!(merge_diff(&json!(1), &json!({"a": {"b": null}})), None);
+        assert_eq!(
+            merge_diff(&json!({"a": null}), &json!({"a": null})),
+            Some(json!({}))
+        );
+    }
+}
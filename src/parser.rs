@@ -1,5 +1,8 @@
-use crate::lexer::{tokenize, Constant, JsonLexError, Token};
+use crate::lexer::{Constant, JsonLexError, Lexer, NumberMode, SpannedToken, Token};
+use crate::limits::{Limit, Limits};
+use crate::pointer::escape_pointer_token;
 use std::collections::HashMap;
+use std::ops::Range;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -8,6 +11,24 @@ pub enum JsonError {
     JsonLexError(JsonLexError),
     #[error("JsonParseError: {0}")]
     JsonParseError(JsonParseError),
+    #[error("LimitExceeded: {0} is limited to {1}")]
+    LimitExceeded(Limit, usize),
+    /// Raised by `de::from_str` and `ser::to_string`. The variant exists without the `serde`
+    /// feature too, so that matching on `JsonError` doesn't depend on the enabled features.
+    #[error("SerdeError: {0}")]
+    Serde(String),
+}
+
+impl From<JsonLexError> for JsonError {
+    fn from(e: JsonLexError) -> Self {
+        JsonError::JsonLexError(e)
+    }
+}
+
+impl From<JsonParseError> for JsonError {
+    fn from(e: JsonParseError) -> Self {
+        JsonError::JsonParseError(e)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -16,15 +37,60 @@ pub enum JsonParseError {
     UnexpectedToken(Token),
     #[error("JsonParseError: Unexpected end of input")]
     UnexpectedEndOfInput,
+    #[error("JsonParseError: Duplicate key {0}, first at bytes {1:?}, again at {2:?}")]
+    DuplicateKey(String, Range<usize>, Range<usize>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Json {
     Dict(HashMap<String, Json>),
     List(Vec<Json>),
     Value(Constant),
 }
 
+/// What to do when an object contains the same key more than once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateKeys {
+    /// Fail with `JsonParseError::DuplicateKey`, carrying the JSON Pointer of the key and the
+    /// spans of both occurrences.
+    Reject,
+    FirstWins,
+    #[default]
+    LastWins,
+    /// Multimap view: every key maps to a `Json::List` of all of its values in document order,
+    /// including keys that only occur once.
+    Collect,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    pub number_mode: NumberMode,
+    pub duplicate_keys: DuplicateKeys,
+    pub limits: Limits,
+}
+
+/// State shared by all levels of one `parse_with_options` call.
+struct Context<'a> {
+    options: &'a ParseOptions,
+    nodes: usize,
+}
+
+impl Context<'_> {
+    fn check(&self, limit: Limit, value: usize) -> Result<(), JsonError> {
+        let max = self.options.limits.get(limit);
+        if value > max {
+            return Err(JsonError::LimitExceeded(limit, max));
+        }
+        Ok(())
+    }
+
+    fn count_node(&mut self) -> Result<(), JsonError> {
+        self.nodes += 1;
+        self.check(Limit::NodeCount, self.nodes)
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
 enum DictParseState {
     // represents what was last parsed, so from this, it can be inferred what is
     // expected next. If, for example, the last thing that has been parsed was a key, a value is
@@ -40,194 +106,264 @@ enum ListParseState {
     ExpectCommaOrEnd,
 }
 
-fn parse_dict(tokens: &[Token]) -> Result<Json, JsonParseError> {
-    let mut state = DictParseState::ExpectKey;
-    let mut result_hashmap = HashMap::new();
-    let mut prev_key = String::new();
-    let mut tokens_iter = tokens.iter();
-    while let Some(token) = tokens_iter.next() {
-        match state {
-            DictParseState::ExpectKey => match token {
-                Token::Constant(Constant::StringLiteral(key)) => {
-                    prev_key = key.to_string(); // TODO this could potentially be solved more
-                                                // efficiently or at least more elegently by pasing ownership of the tokens to the
-                                                // function, so into_iter is possible and this copy is not required.
-                    state = DictParseState::ExpectColon;
+/// Collects the members of one object according to `ParseOptions::duplicate_keys`.
+struct DictBuilder<'a> {
+    policy: DuplicateKeys,
+    path: &'a str,
+    values: HashMap<String, Json>,
+    key_spans: HashMap<String, Range<usize>>,
+}
+
+impl<'a> DictBuilder<'a> {
+    fn new(policy: DuplicateKeys, path: &'a str) -> Self {
+        Self {
+            policy,
+            path,
+            values: HashMap::new(),
+            key_spans: HashMap::new(),
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: String,
+        span: Range<usize>,
+        value: Json,
+    ) -> Result<(), JsonParseError> {
+        match self.policy {
+            DuplicateKeys::LastWins => {
+                self.values.insert(key, value);
+            }
+            DuplicateKeys::FirstWins => {
+                self.values.entry(key).or_insert(value);
+            }
+            DuplicateKeys::Reject => {
+                if let Some(first) = self.key_spans.get(&key) {
+                    return Err(JsonParseError::DuplicateKey(
+                        member_path(self.path, &key),
+                        first.clone(),
+                        span,
+                    ));
                 }
-                _ => return Err(JsonParseError::UnexpectedToken(token.clone())),
-            },
-            DictParseState::ExpectColon => match token {
-                Token::Colon => {
-                    state = DictParseState::ExpectValue;
+                self.key_spans.insert(key.clone(), span);
+                self.values.insert(key, value);
+            }
+            DuplicateKeys::Collect => {
+                match self.values.entry(key).or_insert_with(|| Json::List(Vec::new())) {
+                    Json::List(values) => values.push(value),
+                    _ => unreachable!("collected values are always lists"),
                 }
-                _ => return Err(JsonParseError::UnexpectedToken(token.clone())),
-            },
-            DictParseState::ExpectValue => {
-                match token {
-                    Token::Constant(c) => {
-                        result_hashmap.insert(prev_key.clone(), Json::Value(c.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Json {
+        Json::Dict(self.values)
+    }
+}
+
+fn member_path(path: &str, key: &str) -> String {
+    format!("{}/{}", path, escape_pointer_token(key))
+}
+
+/// Pulls tokens from the lexer one at a time, so only the values built so far are held. The last
+/// token of the input is the root's closing token: it is kept back, so that containers inside the
+/// root may go unclosed.
+struct Parser<'j, 'o> {
+    lexer: Lexer<'j>,
+    /// The next token, given out once another one follows it.
+    ahead: SpannedToken,
+    context: Context<'o>,
+    /// The tokens given out so far, if they are kept for a source map.
+    taken: Option<Vec<SpannedToken>>,
+}
+
+impl Parser<'_, '_> {
+    fn next(&mut self) -> Result<Option<SpannedToken>, JsonError> {
+        let Some(following) = self.lexer.next().transpose()? else {
+            return Ok(None);
+        };
+        let token = std::mem::replace(&mut self.ahead, following);
+        if let Some(taken) = &mut self.taken {
+            taken.push(token.clone());
+        }
+        Ok(Some(token))
+    }
+
+    /// Parses the container that `open` just started.
+    fn parse_container(
+        &mut self,
+        open: &Token,
+        path: &str,
+        depth: usize,
+    ) -> Result<Json, JsonError> {
+        self.context.check(Limit::Depth, depth)?;
+        match open {
+            Token::StartOfDict => self.parse_dict(path, depth),
+            _ => self.parse_list(path, depth),
+        }
+    }
+
+    fn parse_dict(&mut self, path: &str, depth: usize) -> Result<Json, JsonError> {
+        self.context.count_node()?;
+        let mut state = DictParseState::ExpectKey;
+        let mut result = DictBuilder::new(self.context.options.duplicate_keys, path);
+        let mut members = 0;
+        let mut prev_key = String::new();
+        let mut prev_key_span = 0..0;
+        while let Some(token) = self.next()? {
+            if token.token == Token::EndOfDict {
+                // trailing commas and members without a value are tolerated
+                return Ok(result.finish());
+            }
+            match state {
+                DictParseState::ExpectKey => match token.token {
+                    Token::Constant(Constant::StringLiteral(key)) => {
+                        members += 1;
+                        self.context.check(Limit::MemberCount, members)?;
+                        prev_key = key;
+                        prev_key_span = token.span;
+                        state = DictParseState::ExpectColon;
                     }
-                    Token::StartOfDict => {
-                        // find end of dict
-                        let mut depth = 1;
-                        let mut context_tokens = Vec::new();
-                        while let Some(token) = tokens_iter.next() {
-                            match token {
-                                Token::StartOfDict => depth += 1,
-                                Token::EndOfDict => depth -= 1,
-                                _ => {}
-                            }
-                            if depth <= 0 {
-                                break;
-                            }
-                            context_tokens.push(token.clone()); // TODO avoid cloning by taking
-                                                                // ownership of tokens (this function won't need them anymore afterwards)
-                        }
-                        let sub_json = parse_dict(&context_tokens)?;
-                        result_hashmap.insert(prev_key.clone(), sub_json);
+                    token => return Err(JsonParseError::UnexpectedToken(token).into()),
+                },
+                DictParseState::ExpectColon => match token.token {
+                    Token::Colon => {
+                        state = DictParseState::ExpectValue;
                     }
-                    Token::StartOfList => {
-                        // find end of dict
-                        let mut depth = 1;
-                        let mut context_tokens = Vec::new();
-                        while let Some(token) = tokens_iter.next() {
-                            match token {
-                                Token::StartOfList => depth += 1,
-                                Token::EndOfList => depth -= 1,
-                                _ => {}
-                            }
-                            if depth <= 0 {
-                                break;
-                            }
-                            context_tokens.push(token.clone()); // TODO avoid cloning by taking
-                                                                // ownership of tokens (this function won't need them anymore afterwards)
+                    token => return Err(JsonParseError::UnexpectedToken(token).into()),
+                },
+                DictParseState::ExpectValue => {
+                    let value = match token.token {
+                        Token::Constant(c) => {
+                            self.context.count_node()?;
+                            Json::Value(c)
                         }
-                        let sub_json = parse_list(&context_tokens)?;
-                        result_hashmap.insert(prev_key.clone(), sub_json);
-                    }
-                    _ => return Err(JsonParseError::UnexpectedToken(token.clone())),
+                        open @ (Token::StartOfDict | Token::StartOfList) => {
+                            let member = member_path(path, &prev_key);
+                            self.parse_container(&open, &member, depth + 1)?
+                        }
+                        token => return Err(JsonParseError::UnexpectedToken(token).into()),
+                    };
+                    result.insert(prev_key.clone(), prev_key_span.clone(), value)?;
+                    state = DictParseState::ExpectCommaOrEnd;
                 }
-                state = DictParseState::ExpectCommaOrEnd;
+                DictParseState::ExpectCommaOrEnd => match token.token {
+                    Token::Comma => {
+                        state = DictParseState::ExpectKey;
+                    }
+                    token => return Err(JsonParseError::UnexpectedToken(token).into()),
+                },
             }
-            DictParseState::ExpectCommaOrEnd => match token {
-                Token::Comma => {
-                    state = DictParseState::ExpectKey;
-                }
-                Token::EndOfDict => return Ok(Json::Dict(result_hashmap)),
-                _ => return Err(JsonParseError::UnexpectedToken(token.clone())),
-            },
         }
+        Ok(result.finish())
     }
-    Ok(Json::Dict(result_hashmap))
-}
 
-fn parse_list(tokens: &[Token]) -> Result<Json, JsonParseError> {
-    let mut state = ListParseState::ExpectValue;
-    let mut result_vec: Vec<Json> = Vec::new();
-    let mut tokens_iter = tokens.iter();
-    while let Some(token) = tokens_iter.next() {
-        match state {
-            ListParseState::ExpectValue => {
-                match token {
-                    Token::Constant(c) => result_vec.push(Json::Value(c.clone())),
-                    Token::StartOfDict => {
-                        // find end of dict
-                        let mut depth = 1;
-                        let mut context_tokens = Vec::new();
-                        while let Some(token) = tokens_iter.next() {
-                            match token {
-                                Token::StartOfDict => depth += 1,
-                                Token::EndOfDict => depth -= 1,
-                                _ => {}
-                            }
-                            if depth <= 0 {
-                                break;
-                            }
-                            context_tokens.push(token.clone()); // TODO avoid cloning by taking
-                                                                // ownership of tokens (this function won't need them anymore afterwards)
+    fn parse_list(&mut self, path: &str, depth: usize) -> Result<Json, JsonError> {
+        self.context.count_node()?;
+        let mut state = ListParseState::ExpectValue;
+        let mut result_vec: Vec<Json> = Vec::new();
+        while let Some(token) = self.next()? {
+            if token.token == Token::EndOfList {
+                return Ok(Json::List(result_vec));
+            }
+            match state {
+                ListParseState::ExpectValue => {
+                    self.context.check(Limit::ArrayLength, result_vec.len() + 1)?;
+                    let value = match token.token {
+                        Token::Constant(c) => {
+                            self.context.count_node()?;
+                            Json::Value(c)
                         }
-                        let sub_json = parse_dict(&context_tokens)?;
-                        result_vec.push(sub_json);
-                    }
-                    Token::StartOfList => {
-                        // find end of dict
-                        let mut depth = 1;
-                        let mut context_tokens = Vec::new();
-                        while let Some(token) = tokens_iter.next() {
-                            match token {
-                                Token::StartOfList => depth += 1,
-                                Token::EndOfList => depth -= 1,
-                                _ => {}
-                            }
-                            if depth <= 0 {
-                                break;
-                            }
-                            context_tokens.push(token.clone()); // TODO avoid cloning by taking
-                                                                // ownership of tokens (this function won't need them anymore afterwards)
+                        open @ (Token::StartOfDict | Token::StartOfList) => {
+                            let element_path = format!("{}/{}", path, result_vec.len());
+                            self.parse_container(&open, &element_path, depth + 1)?
                         }
-                        let sub_json = parse_list(&context_tokens)?;
-                        result_vec.push(sub_json);
-                    }
-                    _ => return Err(JsonParseError::UnexpectedToken(token.clone())),
+                        token => return Err(JsonParseError::UnexpectedToken(token).into()),
+                    };
+                    result_vec.push(value);
+                    state = ListParseState::ExpectCommaOrEnd;
                 }
-                state = ListParseState::ExpectCommaOrEnd;
+                ListParseState::ExpectCommaOrEnd => match token.token {
+                    Token::Comma => state = ListParseState::ExpectValue,
+                    token => return Err(JsonParseError::UnexpectedToken(token).into()),
+                },
             }
-            ListParseState::ExpectCommaOrEnd => match token {
-                Token::Comma => state = ListParseState::ExpectValue,
-                _ => return Err(JsonParseError::UnexpectedToken(token.clone())),
-            },
         }
+        Ok(Json::List(result_vec))
     }
-    Ok(Json::List(result_vec))
 }
 
+/// Parses `json` with the default options. String literals are unescaped, and an escape that
+/// JSON doesn't define is a `JsonLexError::InvalidEscape`.
 pub fn parse(json: &str) -> Result<Json, JsonError> {
-    let tokens_result = tokenize(json);
-    let tokens: Vec<Token> = match tokens_result {
-        Ok(tokens) => tokens,
-        Err(e) => return Err(JsonError::JsonLexError(e)),
-    };
-    if tokens.len() < 2 {
+    parse_with_options(json, &ParseOptions::default())
+}
+
+pub fn parse_with_options(json: &str, options: &ParseOptions) -> Result<Json, JsonError> {
+    run(json, options, None).map(|(json, _)| json)
+}
+
+/// Parses `json` and also returns the tokens the parser has accepted.
+pub(crate) fn parse_tokens(
+    json: &str,
+    options: &ParseOptions,
+) -> Result<(Json, Vec<SpannedToken>), JsonError> {
+    run(json, options, Some(Vec::new()))
+        .map(|(json, tokens)| (json, tokens.expect("tokens are kept")))
+}
+
+fn run(
+    json: &str,
+    options: &ParseOptions,
+    taken: Option<Vec<SpannedToken>>,
+) -> Result<(Json, Option<Vec<SpannedToken>>), JsonError> {
+    let context = Context { options, nodes: 0 };
+    context.check(Limit::InputSize, json.len())?;
+    let mut lexer = Lexer::new(json, options.number_mode, &options.limits);
+    let (Some(first), Some(ahead)) = (lexer.next().transpose()?, lexer.next().transpose()?) else {
         // If there is only one token, there cannot even be a pair of opening and
-        // closing brackets. Therefor, must be invalid. This check is mostly there for avoiding
-        // future leg-shooting if I change something
+        // closing brackets. Therefor, must be invalid.
         return Err(JsonError::JsonParseError(
             JsonParseError::UnexpectedEndOfInput,
         ));
-    }
-    let first_token = &tokens[0]; // safe because length is checked
-    let last_token = &tokens[tokens.len() - 1]; // safe because length is checked
-    let remaining_tokens = &tokens[1..tokens.len() - 1];
-
-    match first_token {
-        Token::StartOfDict => {
-            if last_token != &Token::EndOfDict {
-                return Err(JsonError::JsonParseError(JsonParseError::UnexpectedToken(
-                    last_token.clone(),
-                )));
-            }
-            let result = parse_dict(remaining_tokens);
-            match result {
-                Ok(r) => Ok(r),
-                Err(e) => Err(JsonError::JsonParseError(e)),
-            }
-        }
-        Token::StartOfList => {
-            if last_token != &Token::EndOfList {
-                return Err(JsonError::JsonParseError(JsonParseError::UnexpectedToken(
-                    last_token.clone(),
-                )));
-            }
-            let result = parse_list(remaining_tokens);
-            match result {
-                Ok(r) => Ok(r),
-                Err(e) => Err(JsonError::JsonParseError(e)),
-            }
+    };
+    let expected_last = match first.token {
+        Token::StartOfDict => Token::EndOfDict,
+        Token::StartOfList => Token::EndOfList,
+        _ => {
+            return Err(JsonError::JsonParseError(JsonParseError::UnexpectedToken(
+                first.token,
+            )))
         }
-        _ => Err(JsonError::JsonParseError(JsonParseError::UnexpectedToken(
-            first_token.clone(),
-        ))),
+    };
+    let mut parser = Parser {
+        lexer,
+        ahead,
+        context,
+        taken: taken.map(|mut taken| {
+            taken.push(first.clone());
+            taken
+        }),
+    };
+    let result = parser.parse_container(&first.token, "", 1)?;
+    if parser.lexer.next().transpose()?.is_some() {
+        // the root was closed before the last token
+        return Err(JsonError::JsonParseError(JsonParseError::UnexpectedToken(
+            parser.ahead.token,
+        )));
     }
+    if parser.ahead.token != expected_last {
+        return Err(JsonError::JsonParseError(JsonParseError::UnexpectedToken(
+            parser.ahead.token,
+        )));
+    }
+    let taken = parser.taken.map(|mut taken| {
+        taken.push(parser.ahead);
+        taken
+    });
+    Ok((result, taken))
 }
 
 #[cfg(test)]
@@ -252,34 +388,134 @@ mod test {
             Err(e) => panic!("{:?}", e),
         };
 
-        let expected_json = {
-            let mut expected_output = HashMap::new();
-            expected_output.insert("key1".to_string(), Json::Value(Constant::Int(1)));
-            expected_output.insert(
-                "key2".to_string(),
-                Json::Value(Constant::StringLiteral("value2".to_string())),
-            );
-            expected_output.insert(
-                "key3".to_string(),
-                Json::List(vec![
-                    Json::Value(Constant::Int(1)),
-                    Json::Value(Constant::Int(2)),
-                    Json::Value(Constant::Int(3)),
-                ]),
-            );
-            let mut key4_map = HashMap::new();
-            key4_map.insert(
-                "key5".to_string(),
-                Json::Value(Constant::StringLiteral("value5".to_string())),
-            );
-            key4_map.insert("key6".to_string(), Json::Value(Constant::Int(6)));
-            expected_output.insert("key4".to_string(), Json::Dict(key4_map));
-            Json::Dict(expected_output)
-        };
+        let expected_json = json!({
+            "key1": 1,
+            "key2": "value2",
+            "key3": [1, 2, 3],
+            "key4": {
+                "key5": "value5",
+                "key6": 6
+            }
+        });
         assert_eq!(parsed, expected_json);
         println!("{:?}", parsed);
     }
 
+    #[test]
+    fn test_64_bit_integers() {
+        let parsed = parse(r#"[9007199254740993, -9007199254740993, 18446744073709551615]"#)
+            .expect("should not error");
+        assert_eq!(
+            parsed,
+            Json::List(vec![
+                Json::Value(Constant::Int(9007199254740993)),
+                Json::Value(Constant::Int(-9007199254740993)),
+                Json::Value(Constant::UInt(u64::MAX)),
+            ])
+        );
+        assert!(matches!(
+            parse("[18446744073709551616]"),
+            Err(JsonError::JsonLexError(JsonLexError::IntegerOverflow(_)))
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let json = r#"{"a": 1, "b": {"c": true, "c": false}, "a": [2]}"#;
+        let with = |duplicate_keys| {
+            let options = ParseOptions {
+                duplicate_keys,
+                ..Default::default()
+            };
+            parse_with_options(json, &options)
+        };
+        let get = |json: &Json, key: &str| match json {
+            Json::Dict(x) => x[key].to_string(),
+            _ => panic!("should be a dict"),
+        };
+
+        let last = with(DuplicateKeys::LastWins).unwrap();
+        assert_eq!(get(&last, "a"), "[2]");
+        assert_eq!(get(&last, "b"), r#"{"c":false}"#);
+        assert_eq!(parse(json).unwrap(), last);
+
+        let first = with(DuplicateKeys::FirstWins).unwrap();
+        assert_eq!(get(&first, "a"), "1");
+        assert_eq!(get(&first, "b"), r#"{"c":true}"#);
+
+        let collected = with(DuplicateKeys::Collect).unwrap();
+        assert_eq!(get(&collected, "a"), "[1,[2]]");
+        assert_eq!(get(&collected, "b"), r#"[{"c":[true,false]}]"#);
+
+        match with(DuplicateKeys::Reject) {
+            Err(JsonError::JsonParseError(JsonParseError::DuplicateKey(path, first, second))) => {
+                assert_eq!(path, "/b/c");
+                assert_eq!(&json[first], r#""c""#);
+                assert_eq!(second, 26..29);
+            }
+            other => panic!("expected a duplicate key error, got {:?}", other),
+        }
+        let nested = r#"[{"x": 0}, {"x~/": 1, "x~/": 2}]"#;
+        let options = ParseOptions {
+            duplicate_keys: DuplicateKeys::Reject,
+            ..Default::default()
+        };
+        match parse_with_options(nested, &options) {
+            Err(JsonError::JsonParseError(JsonParseError::DuplicateKey(path, first, second))) => {
+                assert_eq!(path, "/1/x~0~1");
+                assert_eq!((first, second), (12..17, 22..27));
+            }
+            other => panic!("expected a duplicate key error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let limited = |limits: Limits, json: &str| {
+            let options = ParseOptions {
+                limits,
+                ..Default::default()
+            };
+            match parse_with_options(json, &options) {
+                Err(JsonError::LimitExceeded(limit, max)) => Some((limit, max)),
+                Err(e) => panic!("unexpected error {:?}", e),
+                Ok(_) => None,
+            }
+        };
+        let deep = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert_eq!(limited(Limits::default(), &deep), Some((Limit::Depth, 128)));
+        let nested = r#"{"a": [1, {"b": "cd"}], "e": -0.5}"#;
+        assert_eq!(limited(Limits::default(), nested), None);
+
+        let with = |f: fn(&mut Limits)| {
+            let mut limits = Limits::unlimited();
+            f(&mut limits);
+            limited(limits, nested)
+        };
+        assert_eq!(with(|l| l.max_depth = 3), None);
+        assert_eq!(with(|l| l.max_depth = 2), Some((Limit::Depth, 2)));
+        assert_eq!(with(|l| l.max_input_size = 10), Some((Limit::InputSize, 10)));
+        assert_eq!(with(|l| l.max_string_length = 2), None);
+        assert_eq!(with(|l| l.max_string_length = 1), Some((Limit::StringLength, 1)));
+        assert_eq!(with(|l| l.max_number_length = 3), Some((Limit::NumberLength, 3)));
+        assert_eq!(with(|l| l.max_array_length = 1), Some((Limit::ArrayLength, 1)));
+        assert_eq!(with(|l| l.max_members = 1), Some((Limit::MemberCount, 1)));
+        assert_eq!(with(|l| l.max_nodes = 6), None);
+        assert_eq!(with(|l| l.max_nodes = 5), Some((Limit::NodeCount, 5)));
+    }
+
+    #[test]
+    fn test_container_boundaries() {
+        assert_eq!(parse("[[1], {}, 2,]").unwrap(), json!([[1], {}, 2]));
+        // containers inside the root may go unclosed
+        assert_eq!(parse(r#"[{"a": [1]]"#).unwrap(), json!([{"a": [1]}]));
+        assert!(matches!(
+            parse(r#"{"a": 1}, "b": 2}"#),
+            Err(JsonError::JsonParseError(JsonParseError::UnexpectedToken(Token::Comma)))
+        ));
+        assert!(parse(r#"[{"a": 1]}]"#).is_err());
+    }
+
     #[test]
     #[should_panic(expected="Error returned")]
     fn test_full_invalid_json_lex_error() {
@@ -299,30 +535,15 @@ mod test {
             Err(e) => panic!("Error returned: {:?}", e),
         };
 
-        let expected_json = {
-            let mut expected_output = HashMap::new();
-            expected_output.insert("key1".to_string(), Json::Value(Constant::Int(1)));
-            expected_output.insert(
-                "key2".to_string(),
-                Json::Value(Constant::StringLiteral("value2".to_string())),
-            );
-            expected_output.insert(
-                "key3".to_string(),
-                Json::List(vec![
-                    Json::Value(Constant::Int(1)),
-                    Json::Value(Constant::Int(2)),
-                    Json::Value(Constant::Int(3)),
-                ]),
-            );
-            let mut key4_map = HashMap::new();
-            key4_map.insert(
-                "key5".to_string(),
-                Json::Value(Constant::StringLiteral("value5".to_string())),
-            );
-            key4_map.insert("key6".to_string(), Json::Value(Constant::Int(6)));
-            expected_output.insert("key4".to_string(), Json::Dict(key4_map));
-            Json::Dict(expected_output)
-        };
+        let expected_json = json!({
+            "key1": 1,
+            "key2": "value2",
+            "key3": [1, 2, 3],
+            "key4": {
+                "key5": "value5",
+                "key6": 6
+            }
+        });
         assert_eq!(parsed, expected_json);
         println!("{:?}", parsed);
     }
@@ -346,30 +567,15 @@ mod test {
             Err(e) => panic!("Error returned: {:?}", e),
         };
 
-        let expected_json = {
-            let mut expected_output = HashMap::new();
-            expected_output.insert("key1".to_string(), Json::Value(Constant::Int(1)));
-            expected_output.insert(
-                "key2".to_string(),
-                Json::Value(Constant::StringLiteral("value2".to_string())),
-            );
-            expected_output.insert(
-                "key3".to_string(),
-                Json::List(vec![
-                    Json::Value(Constant::Int(1)),
-                    Json::Value(Constant::Int(2)),
-                    Json::Value(Constant::Int(3)),
-                ]),
-            );
-            let mut key4_map = HashMap::new();
-            key4_map.insert(
-                "key5".to_string(),
-                Json::Value(Constant::StringLiteral("value5".to_string())),
-            );
-            key4_map.insert("key6".to_string(), Json::Value(Constant::Int(6)));
-            expected_output.insert("key4".to_string(), Json::Dict(key4_map));
-            Json::Dict(expected_output)
-        };
+        let expected_json = json!({
+            "key1": 1,
+            "key2": "value2",
+            "key3": [1, 2, 3],
+            "key4": {
+                "key5": "value5",
+                "key6": 6
+            }
+        });
         assert_eq!(parsed, expected_json);
         println!("{:?}", parsed);
     }
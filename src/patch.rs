@@ -0,0 +1,349 @@
+//! JSON Patch (RFC 6902).
+use crate::convert::{mismatch, ConversionError};
+use crate::lexer::Constant;
+use crate::parser::{parse, Json, JsonError};
+use crate::pointer::PointerError;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchOperation {
+    Add { path: String, value: Json },
+    Remove { path: String },
+    Replace { path: String, value: Json },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Json },
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Patch(pub Vec<PatchOperation>);
+
+/// Failures to apply a patch carry the index of the operation.
+#[derive(Debug, Error)]
+pub enum PatchError {
+    #[error("PatchError: {0}")]
+    Json(#[from] JsonError),
+    #[error("PatchError: Invalid patch: {0}")]
+    Invalid(#[from] ConversionError),
+    #[error("PatchError: Operation {0} failed: {1}")]
+    Pointer(usize, PointerError),
+    #[error("PatchError: Operation {0} failed: value at \"{1}\" is not equal")]
+    TestFailed(usize, String),
+    #[error("PatchError: Operation {0} failed: \"{1}\" can't be moved into itself")]
+    MoveIntoChild(usize, String),
+}
+
+impl PatchOperation {
+    fn apply(&self, json: &mut Json, index: usize) -> Result<(), PatchError> {
+        let failed = |e| PatchError::Pointer(index, e);
+        match self {
+            Self::Add { path, value } => json.insert_at(path, value.clone()).map_err(failed),
+            Self::Remove { path } => json.remove_at(path).map(drop).map_err(failed),
+            Self::Replace { path, value } => json
+                .replace_at(path, value.clone())
+                .map(drop)
+                .map_err(failed),
+            Self::Move { from, path } => {
+                if from == path {
+                    // still has to exist
+                    return json.resolve(from).map(drop).map_err(failed);
+                }
+                if path.starts_with(from.as_str()) && path[from.len()..].starts_with('/') {
+                    return Err(PatchError::MoveIntoChild(index, from.clone()));
+                }
+                let value = json.remove_at(from).map_err(failed)?;
+                json.insert_at(path, value).map_err(failed)
+            }
+            Self::Copy { from, path } => {
+                let value = json.resolve(from).map_err(failed)?.clone();
+                json.insert_at(path, value).map_err(failed)
+            }
+            Self::Test { path, value } => match json.resolve(path).map_err(failed)? {
+                actual if equal(actual, value) => Ok(()),
+                _ => Err(PatchError::TestFailed(index, path.clone())),
+            },
+        }
+    }
+}
+
+/// Equality as defined for the `test` operation, where numbers compare by value (`1` equals
+/// `1.0`).
+pub(crate) fn equal(a: &Json, b: &Json) -> bool {
+    match (a, b) {
+        (Json::Dict(a), Json::Dict(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(k, v)| b.get(k).is_some_and(|other| equal(v, other)))
+        }
+        (Json::List(a), Json::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(a, b))
+        }
+        (Json::Value(a), Json::Value(b)) => match compare_numbers(a, b) {
+            Some(order) => order == Ordering::Equal,
+            None => a == b,
+        },
+        _ => false,
+    }
+}
+
+/// A number constant as an integer if it is one, otherwise as a float.
+fn number(constant: &Constant) -> Option<Result<i128, f64>> {
+    match constant {
+        Constant::Int(x) => Some(Ok(*x as i128)),
+        Constant::UInt(x) => Some(Ok(*x as i128)),
+        Constant::Float(x) => Some(Err(*x)),
+        Constant::Number(x) => Some(x.as_i128().ok_or_else(|| x.as_f64())),
+        _ => None,
+    }
+}
+
+/// The numeric order of two numbers, `None` if one of them isn't a number.
+pub(crate) fn compare_numbers(a: &Constant, b: &Constant) -> Option<Ordering> {
+    match (number(a)?, number(b)?) {
+        (Ok(a), Ok(b)) => Some(a.cmp(&b)),
+        (a, b) => {
+            let float = |x: Result<i128, f64>| x.map_or_else(|x| x, |x| x as f64);
+            float(a).partial_cmp(&float(b))
+        }
+    }
+}
+
+impl Patch {
+    pub fn parse(json: &str) -> Result<Patch, PatchError> {
+        Ok(Patch::try_from(&parse(json)?)?)
+    }
+
+    /// Applies all operations in order. If one of them fails, `json` is left unchanged.
+    pub fn apply(&self, json: &mut Json) -> Result<(), PatchError> {
+        let mut patched = json.clone();
+        for (i, operation) in self.0.iter().enumerate() {
+            operation.apply(&mut patched, i)?;
+        }
+        *json = patched;
+        Ok(())
+    }
+}
+
+fn member<'a>(object: &'a HashMap<String, Json>, key: &str) -> Result<&'a Json, ConversionError> {
+    object
+        .get(key)
+        .ok_or_else(|| ConversionError::MissingMember(String::new()).within(key))
+}
+
+fn string(object: &HashMap<String, Json>, key: &str) -> Result<String, ConversionError> {
+    String::try_from(member(object, key)?).map_err(|e| e.within(key))
+}
+
+impl TryFrom<&Json> for PatchOperation {
+    type Error = ConversionError;
+
+    fn try_from(json: &Json) -> Result<Self, Self::Error> {
+        let Json::Dict(object) = json else {
+            return Err(mismatch(json, "object"));
+        };
+        let path = string(object, "path")?;
+        let value = || member(object, "value").cloned();
+        Ok(match string(object, "op")?.as_str() {
+            "add" => Self::Add {
+                path,
+                value: value()?,
+            },
+            "remove" => Self::Remove { path },
+            "replace" => Self::Replace {
+                path,
+                value: value()?,
+            },
+            "move" => Self::Move {
+                from: string(object, "from")?,
+                path,
+            },
+            "copy" => Self::Copy {
+                from: string(object, "from")?,
+                path,
+            },
+            "test" => Self::Test {
+                path,
+                value: value()?,
+            },
+            other => {
+                return Err(ConversionError::InvalidValue(
+                    "/op".to_string(),
+                    format!("unknown operation {:?}", other),
+                ))
+            }
+        })
+    }
+}
+
+impl TryFrom<&Json> for Patch {
+    type Error = ConversionError;
+
+    fn try_from(json: &Json) -> Result<Self, Self::Error> {
+        let Json::List(operations) = json else {
+            return Err(mismatch(json, "array"));
+        };
+        operations
+            .iter()
+            .enumerate()
+            .map(|(i, operation)| {
+                PatchOperation::try_from(operation).map_err(|e| e.within(&i.to_string()))
+            })
+            .collect::<Result<_, _>>()
+            .map(Patch)
+    }
+}
+
+impl From<&PatchOperation> for Json {
+    fn from(operation: &PatchOperation) -> Json {
+        let (op, path, from, value) = match operation {
+            PatchOperation::Add { path, value } => ("add", path, None, Some(value)),
+            PatchOperation::Remove { path } => ("remove", path, None, None),
+            PatchOperation::Replace { path, value } => ("replace", path, None, Some(value)),
+            PatchOperation::Move { from, path } => ("move", path, Some(from), None),
+            PatchOperation::Copy { from, path } => ("copy", path, Some(from), None),
+            PatchOperation::Test { path, value } => ("test", path, None, Some(value)),
+        };
+        let mut object = HashMap::new();
+        object.insert("op".to_string(), Json::from(op));
+        object.insert("path".to_string(), Json::from(path.as_str()));
+        if let Some(from) = from {
+            object.insert("from".to_string(), Json::from(from.as_str()));
+        }
+        if let Some(value) = value {
+            object.insert("value".to_string(), value.clone());
+        }
+        Json::Dict(object)
+    }
+}
+
+impl From<&Patch> for Json {
+    fn from(patch: &Patch) -> Json {
+        Json::List(patch.0.iter().map(Json::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn apply(document: Json, patch: &str) -> Result<Json, String> {
+        let mut document = document;
+        Patch::parse(patch)
+            .and_then(|patch| patch.apply(&mut document))
+            .map_err(|e| e.to_string())?;
+        Ok(document)
+    }
+
+    #[test]
+    fn test_rfc_6902_examples() {
+        assert_eq!(
+            apply(
+                json!({"foo": "bar"}),
+                r#"[{"op": "add", "path": "/baz", "value": "qux"}]"#
+            ),
+            Ok(json!({"baz": "qux", "foo": "bar"}))
+        );
+        assert_eq!(
+            apply(
+                json!({"foo": ["bar", "baz"]}),
+                r#"[{"op": "add", "path": "/foo/1", "value": "qux"}]"#
+            ),
+            Ok(json!({"foo": ["bar", "qux", "baz"]}))
+        );
+        assert_eq!(
+            apply(
+                json!({"baz": "qux", "foo": "bar"}),
+                r#"[{"op": "remove", "path": "/baz"},
+                    {"op": "replace", "path": "/foo", "value": "boo"}]"#
+            ),
+            Ok(json!({"foo": "boo"}))
+        );
+        assert_eq!(
+            apply(
+                json!({"foo": {"bar": "baz", "waldo": "fred"}, "qux": {"corge": "grault"}}),
+                r#"[{"op": "move", "from": "/foo/waldo", "path": "/qux/thud"}]"#
+            ),
+            Ok(json!({"foo": {"bar": "baz"}, "qux": {"corge": "grault", "thud": "fred"}}))
+        );
+        assert_eq!(
+            apply(
+                json!({"foo": ["all", "grass", "cows", "eat"]}),
+                r#"[{"op": "move", "from": "/foo/1", "path": "/foo/3"}]"#
+            ),
+            Ok(json!({"foo": ["all", "cows", "eat", "grass"]}))
+        );
+        assert_eq!(
+            apply(
+                json!({"baz": "qux", "foo": ["a", 2, "c"]}),
+                r#"[{"op": "test", "path": "/baz", "value": "qux"},
+                    {"op": "test", "path": "/foo/1", "value": 2.0},
+                    {"op": "copy", "from": "/foo", "path": "/bar"}]"#
+            ),
+            Ok(json!({"baz": "qux", "foo": ["a", 2, "c"], "bar": ["a", 2, "c"]}))
+        );
+        assert_eq!(
+            apply(
+                json!({"/": 9, "~1": 10}),
+                r#"[{"op": "test", "path": "/~01", "value": "10"}]"#
+            ),
+            Err("PatchError: Operation 0 failed: value at \"/~01\" is not equal".to_string())
+        );
+    }
+
+    #[test]
+    fn test_errors_are_atomic() {
+        let document = json!({"a": [1, 2], "b": {"c": null}});
+        let cases = [
+            (
+                r#"[{"op": "remove", "path": "/a/0"}, {"op": "remove", "path": "/x"}]"#,
+                r#"Operation 1 failed: PointerError: Nothing at "/x""#,
+            ),
+            (
+                r#"[{"op": "add", "path": "/b/c/d", "value": 1}]"#,
+                r#"Operation 0 failed: PointerError: "/b/c" is a scalar, not an object or array"#,
+            ),
+            (
+                r#"[{"op": "move", "from": "/b", "path": "/b/c/d"}]"#,
+                r#"Operation 0 failed: "/b" can't be moved into itself"#,
+            ),
+            (
+                r#"[{"op": "copy", "from": "/a/5", "path": "/c"}]"#,
+                concat!(
+                    r#"Operation 0 failed: PointerError: Index at "/a/5" is out of bounds "#,
+                    "for length 2"
+                ),
+            ),
+            (
+                r#"[{"op": "add", "path": "/c"}]"#,
+                r#"Invalid patch: ConversionError: Missing member "/0/value""#,
+            ),
+            (
+                r#"[{"op": "add", "path": "/c", "value": 1}, {"op": "x", "path": ""}]"#,
+                concat!(
+                    r#"Invalid patch: ConversionError: Invalid value at "/1/op": "#,
+                    r#"unknown operation "x""#
+                ),
+            ),
+        ];
+        for (patch, expected) in cases {
+            let mut patched = document.clone();
+            let result = Patch::parse(patch).and_then(|patch| patch.apply(&mut patched));
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                format!("PatchError: {}", expected)
+            );
+            assert_eq!(patched, document);
+        }
+        // moving a value onto itself changes nothing, but it has to exist
+        let mut moved = document.clone();
+        let patch = Patch::parse(r#"[{"op": "move", "from": "/a", "path": "/a"}]"#).unwrap();
+        assert!(patch.apply(&mut moved).is_ok());
+        assert_eq!(moved, document);
+        let patch = Patch::parse(r#"[{"op": "move", "from": "/x", "path": "/x"}]"#).unwrap();
+        assert!(patch.apply(&mut moved).is_err());
+        let patch = Patch::parse(r#"[{"op": "move", "from": "/a", "path": "/b/a"}]"#).unwrap();
+        assert_eq!(Patch::try_from(&Json::from(&patch)), Ok(patch));
+    }
+}
@@ -0,0 +1,303 @@
+//! JSON Pointer (RFC 6901) lookup and mutation.
+use crate::parser::Json;
+use thiserror::Error;
+
+/// Errors carry the pointer to the node where resolving failed.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PointerError {
+    #[error("PointerError: Invalid pointer {0:?}")]
+    Syntax(String),
+    #[error("PointerError: Nothing at \"{0}\"")]
+    NotFound(String),
+    #[error("PointerError: \"{0}\" is {1}, not an object or array")]
+    WrongType(String, &'static str),
+    #[error("PointerError: \"{0}\" is not a valid array index")]
+    InvalidIndex(String),
+    #[error("PointerError: Index at \"{0}\" is out of bounds for length {1}")]
+    IndexOutOfBounds(String, usize),
+    #[error("PointerError: The root can't be removed")]
+    RemoveRoot,
+}
+
+/// Escapes a key for use as a JSON Pointer reference token.
+pub fn escape_pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Splits `pointer` into its unescaped reference tokens. `""` is the root and has none.
+pub fn parse_pointer(pointer: &str) -> Result<Vec<String>, PointerError> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(tokens) = pointer.strip_prefix('/') else {
+        return Err(PointerError::Syntax(pointer.to_string()));
+    };
+    tokens
+        .split('/')
+        .map(|token| {
+            // `~` has to be followed by 0 or 1
+            let mut rest = token.split('~');
+            let mut unescaped = rest.next().unwrap_or_default().to_string();
+            for part in rest {
+                match part.as_bytes().first() {
+                    Some(b'0') => unescaped.push('~'),
+                    Some(b'1') => unescaped.push('/'),
+                    _ => return Err(PointerError::Syntax(pointer.to_string())),
+                }
+                unescaped.push_str(&part[1..]);
+            }
+            Ok(unescaped)
+        })
+        .collect()
+}
+
+fn type_name(json: &Json) -> &'static str {
+    match json {
+        Json::Dict(_) => "an object",
+        Json::List(_) => "an array",
+        Json::Value(_) => "a scalar",
+    }
+}
+
+/// Parses an array index: digits without leading zeros. `-` (past the end) is only accepted if
+/// `allow_end` is set.
+fn array_index(
+    token: &str,
+    len: usize,
+    allow_end: bool,
+    path: &str,
+) -> Result<usize, PointerError> {
+    if token == "-" && allow_end {
+        return Ok(len);
+    }
+    let valid = !token.is_empty()
+        && token.bytes().all(|b| b.is_ascii_digit())
+        && (token == "0" || !token.starts_with('0'));
+    let index = match token.parse::<usize>() {
+        Ok(index) if valid => index,
+        _ if token == "-" || valid => usize::MAX, // out of bounds either way
+        _ => return Err(PointerError::InvalidIndex(path.to_string())),
+    };
+    let bound = if allow_end { len + 1 } else { len };
+    if index >= bound {
+        return Err(PointerError::IndexOutOfBounds(path.to_string(), len));
+    }
+    Ok(index)
+}
+
+fn child<'a>(node: &'a Json, token: &str, path: &str) -> Result<&'a Json, PointerError> {
+    match node {
+        Json::Dict(x) => x
+            .get(token)
+            .ok_or_else(|| PointerError::NotFound(path.to_string())),
+        Json::List(x) => Ok(&x[array_index(token, x.len(), false, path)?]),
+        Json::Value(_) => Err(PointerError::WrongType(parent_of(path), type_name(node))),
+    }
+}
+
+fn child_mut<'a>(
+    node: &'a mut Json,
+    token: &str,
+    path: &str,
+) -> Result<&'a mut Json, PointerError> {
+    let name = type_name(node);
+    match node {
+        Json::Dict(x) => x
+            .get_mut(token)
+            .ok_or_else(|| PointerError::NotFound(path.to_string())),
+        Json::List(x) => {
+            let index = array_index(token, x.len(), false, path)?;
+            Ok(&mut x[index])
+        }
+        Json::Value(_) => Err(PointerError::WrongType(parent_of(path), name)),
+    }
+}
+
+fn parent_of(path: &str) -> String {
+    path[..path.rfind('/').unwrap_or(0)].to_string()
+}
+
+/// Follows `tokens`, returning the node and the pointer to it.
+fn resolve_mut<'a>(
+    mut node: &'a mut Json,
+    tokens: &[String],
+) -> Result<(&'a mut Json, String), PointerError> {
+    let mut path = String::new();
+    for token in tokens {
+        path.push('/');
+        path.push_str(&escape_pointer_token(token));
+        node = child_mut(node, token, &path)?;
+    }
+    Ok((node, path))
+}
+
+impl Json {
+    /// The node at `pointer`, e.g. `/key4/key5` or `/key3/0`. `""` is the whole document.
+    pub fn pointer(&self, pointer: &str) -> Option<&Json> {
+        self.resolve(pointer).ok()
+    }
+
+    /// Like `pointer`, with the reason if there's nothing at `pointer`.
+    pub(crate) fn resolve(&self, pointer: &str) -> Result<&Json, PointerError> {
+        let mut node = self;
+        let mut path = String::new();
+        for token in parse_pointer(pointer)? {
+            path.push('/');
+            path.push_str(&escape_pointer_token(&token));
+            node = child(node, &token, &path)?;
+        }
+        Ok(node)
+    }
+
+    /// Like `pointer`, for modifying the node in place.
+    pub fn pointer_mut(&mut self, pointer: &str) -> Option<&mut Json> {
+        let tokens = parse_pointer(pointer).ok()?;
+        resolve_mut(self, &tokens).ok().map(|(node, _)| node)
+    }
+
+    /// Adds `value` at `pointer`, like the JSON Patch `add` operation: an object member is
+    /// created or overwritten, an array element is inserted before the index (`-` appends), and
+    /// `""` replaces the whole document. The parent has to exist.
+    pub fn insert_at(&mut self, pointer: &str, value: Json) -> Result<(), PointerError> {
+        let tokens = parse_pointer(pointer)?;
+        let Some((last, parents)) = tokens.split_last() else {
+            *self = value;
+            return Ok(());
+        };
+        let (parent, mut path) = resolve_mut(self, parents)?;
+        let parent_path = path.clone();
+        path.push('/');
+        path.push_str(&escape_pointer_token(last));
+        match parent {
+            Json::Dict(x) => {
+                x.insert(last.clone(), value);
+            }
+            Json::List(x) => {
+                let index = array_index(last, x.len(), true, &path)?;
+                x.insert(index, value);
+            }
+            Json::Value(_) => {
+                return Err(PointerError::WrongType(parent_path, type_name(parent)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the node at `pointer` and returns it. Array elements after it shift down.
+    pub fn remove_at(&mut self, pointer: &str) -> Result<Json, PointerError> {
+        let tokens = parse_pointer(pointer)?;
+        let Some((last, parents)) = tokens.split_last() else {
+            return Err(PointerError::RemoveRoot);
+        };
+        let (parent, mut path) = resolve_mut(self, parents)?;
+        let parent_path = path.clone();
+        path.push('/');
+        path.push_str(&escape_pointer_token(last));
+        match parent {
+            Json::Dict(x) => x.remove(last).ok_or(PointerError::NotFound(path)),
+            Json::List(x) => {
+                let index = array_index(last, x.len(), false, &path)?;
+                Ok(x.remove(index))
+            }
+            Json::Value(_) => Err(PointerError::WrongType(parent_path, type_name(parent))),
+        }
+    }
+
+    /// Replaces the existing node at `pointer` and returns the previous value.
+    pub fn replace_at(&mut self, pointer: &str, value: Json) -> Result<Json, PointerError> {
+        let tokens = parse_pointer(pointer)?;
+        let (node, _) = resolve_mut(self, &tokens)?;
+        Ok(std::mem::replace(node, value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_rfc_6901_examples() {
+        let json = parse(
+            r#"{"foo": ["bar", "baz"], "": 0, "a/b": 1, "c%d": 2, "e^f": 3, "g|h": 4,
+            "i\\j": 5, "k\"l": 6, " ": 7, "m~n": 8}"#,
+        )
+        .unwrap();
+        assert_eq!(json.pointer(""), Some(&json));
+        assert_eq!(json.pointer("/foo"), Some(&json!(["bar", "baz"])));
+        let cases = [
+            ("/foo/0", json!("bar")),
+            ("/", json!(0)),
+            ("/a~1b", json!(1)),
+            ("/c%d", json!(2)),
+            ("/e^f", json!(3)),
+            ("/g|h", json!(4)),
+            ("/i\\j", json!(5)),
+            ("/k\"l", json!(6)),
+            ("/ ", json!(7)),
+            ("/m~0n", json!(8)),
+        ];
+        for (pointer, expected) in cases {
+            assert_eq!(json.pointer(pointer), Some(&expected), "{}", pointer);
+        }
+        for pointer in [
+            "foo", "/foo/01", "/foo/-", "/foo/2", "/foo/0/x", "/m~2n", "/x",
+        ] {
+            assert_eq!(json.pointer(pointer), None, "{}", pointer);
+        }
+        assert_eq!(parse_pointer("/~01"), Ok(vec!["~1".to_string()]));
+    }
+
+    #[test]
+    fn test_mutation() {
+        let mut json = json!({"a": {"b": [1, 2]}, "s": "x"});
+        *json.pointer_mut("/a/b/0").unwrap() = json!(0);
+        json.insert_at("/a/b/-", json!(3)).unwrap();
+        json.insert_at("/a/b/1", json!(1)).unwrap();
+        json.insert_at("/a/c", json!(null)).unwrap();
+        assert_eq!(json, json!({"a": {"b": [0, 1, 2, 3], "c": null}, "s": "x"}));
+
+        assert_eq!(json.remove_at("/a/b/2"), Ok(json!(2)));
+        assert_eq!(json.replace_at("/a/c", json!(true)), Ok(json!(null)));
+        assert_eq!(json, json!({"a": {"b": [0, 1, 3], "c": true}, "s": "x"}));
+
+        json.insert_at("", json!([])).unwrap();
+        assert_eq!(json, json!([]));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut json = json!({"a": {"b": [1, 2]}, "s": "x"});
+        let before = json.clone();
+        assert_eq!(
+            json.insert_at("/x/y", json!(1)),
+            Err(PointerError::NotFound("/x".to_string()))
+        );
+        assert_eq!(
+            json.insert_at("/s/y", json!(1)),
+            Err(PointerError::WrongType("/s".to_string(), "a scalar"))
+        );
+        assert_eq!(
+            json.insert_at("/a/b/3", json!(1)),
+            Err(PointerError::IndexOutOfBounds("/a/b/3".to_string(), 2))
+        );
+        assert_eq!(
+            json.remove_at("/a/b/-"),
+            Err(PointerError::IndexOutOfBounds("/a/b/-".to_string(), 2))
+        );
+        assert_eq!(
+            json.replace_at("/a/b/01", json!(1)),
+            Err(PointerError::InvalidIndex("/a/b/01".to_string()))
+        );
+        assert_eq!(
+            json.replace_at("/a/x~1y", json!(1)),
+            Err(PointerError::NotFound("/a/x~1y".to_string()))
+        );
+        assert_eq!(json.remove_at(""), Err(PointerError::RemoveRoot));
+        assert_eq!(
+            json.remove_at("a"),
+            Err(PointerError::Syntax("a".to_string()))
+        );
+        assert_eq!(json, before);
+    }
+}
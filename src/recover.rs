@@ -0,0 +1,488 @@
+//! Error-recovering parser for editor integration.
+//!
+//! `parse_recovering` never gives up: it reports every problem it finds as a `Diagnostic`,
+//! resynchronises at commas and closing brackets and returns whatever it could make sense of.
+use crate::cst::{next_token, CstError, TokenKind};
+use crate::lexer::{unescape, Constant};
+use crate::limits::Limits;
+use crate::parser::Json;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub error: CstError,
+    /// Bytes the diagnostic refers to. Empty for things that are missing, e.g. at the end of
+    /// input.
+    pub span: Range<usize>,
+}
+
+/// Like `parser::Json`, but with `Error` nodes wherever the input was broken.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartialJson {
+    Dict(HashMap<String, PartialJson>),
+    List(Vec<PartialJson>),
+    Value(Constant),
+    Error,
+}
+
+impl PartialJson {
+    pub fn has_errors(&self) -> bool {
+        match self {
+            PartialJson::Dict(x) => x.values().any(PartialJson::has_errors),
+            PartialJson::List(x) => x.iter().any(PartialJson::has_errors),
+            PartialJson::Value(_) => false,
+            PartialJson::Error => true,
+        }
+    }
+
+    /// Converts into a `Json`, replacing error nodes with null.
+    pub fn into_json(self) -> Json {
+        match self {
+            PartialJson::Dict(x) => {
+                Json::Dict(x.into_iter().map(|(k, v)| (k, v.into_json())).collect())
+            }
+            PartialJson::List(x) => Json::List(x.into_iter().map(PartialJson::into_json).collect()),
+            PartialJson::Value(c) => Json::Value(c),
+            PartialJson::Error => Json::Value(Constant::Null),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recovered {
+    pub json: PartialJson,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tok {
+    Kind(TokenKind),
+    /// Something the lexer couldn't read. It has already been reported and stands in for a
+    /// value, so the parser doesn't complain about a missing one as well.
+    Bad,
+}
+
+struct RecoveringParser<'a> {
+    source: &'a str,
+    tokens: Vec<(Tok, Range<usize>)>,
+    pos: usize,
+    diagnostics: Vec<Diagnostic>,
+    /// Containers open around the current token, bounded like `Limits::max_depth`.
+    depth: usize,
+    max_depth: usize,
+}
+
+/// Parses `json` without stopping at the first error.
+pub fn parse_recovering(json: &str) -> Recovered {
+    let mut parser = RecoveringParser {
+        source: json,
+        tokens: Vec::new(),
+        pos: 0,
+        diagnostics: Vec::new(),
+        depth: 0,
+        max_depth: Limits::default().max_depth,
+    };
+    parser.lex();
+    let result = parser.parse_value();
+    if let Some((_, span)) = parser.tokens.get(parser.pos).cloned() {
+        parser.unexpected(span);
+    }
+    // lexer diagnostics were collected first, editors want them in document order
+    parser.diagnostics.sort_by_key(|d| d.span.start);
+    parser.diagnostics.dedup();
+    Recovered {
+        json: result,
+        diagnostics: parser.diagnostics,
+    }
+}
+
+fn is_structural(c: u8) -> bool {
+    matches!(c, b'{' | b'}' | b'[' | b']' | b',' | b':' | b'"')
+}
+
+impl<'a> RecoveringParser<'a> {
+    fn lex(&mut self) {
+        let bytes = self.source.as_bytes();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            match next_token(self.source, pos) {
+                Ok(token) => {
+                    pos = token.span.end;
+                    if !token.kind.is_trivia() {
+                        self.tokens.push((Tok::Kind(token.kind), token.span));
+                    }
+                }
+                Err(error) => {
+                    let start = pos;
+                    pos = match error {
+                        CstError::UnterminatedString(_) | CstError::UnterminatedComment(_) => {
+                            // give up on the rest of the line
+                            self.source[pos..]
+                                .find('\n')
+                                .map_or(bytes.len(), |i| pos + i)
+                        }
+                        CstError::InvalidEscape(_) => {
+                            // skip to the closing quote, ignoring the broken escape
+                            let mut end = start + 1;
+                            while end < bytes.len() && !matches!(bytes[end], b'"' | b'\n') {
+                                end += if bytes[end] == b'\\' { 2 } else { 1 };
+                            }
+                            (end + 1).min(bytes.len())
+                        }
+                        _ => {
+                            // skip the whole garbage word, e.g. `tru` or `1.2.3`
+                            let mut end = start + 1;
+                            while end < bytes.len()
+                                && !bytes[end].is_ascii_whitespace()
+                                && !is_structural(bytes[end])
+                            {
+                                end += 1;
+                            }
+                            end
+                        }
+                    };
+                    while !self.source.is_char_boundary(pos) {
+                        pos += 1;
+                    }
+                    self.diagnostics.push(Diagnostic {
+                        error,
+                        span: start..pos,
+                    });
+                    self.tokens.push((Tok::Bad, start..pos));
+                }
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<Tok> {
+        self.tokens.get(self.pos).map(|(tok, _)| *tok)
+    }
+
+    fn span(&self) -> Range<usize> {
+        match self.tokens.get(self.pos) {
+            Some((_, span)) => span.clone(),
+            None => self.source.len()..self.source.len(),
+        }
+    }
+
+    fn unexpected(&mut self, span: Range<usize>) {
+        let error = CstError::UnexpectedToken(self.source[span.clone()].to_string(), span.start);
+        self.diagnostics.push(Diagnostic { error, span });
+    }
+
+    fn end_of_input(&mut self) {
+        let span = self.source.len()..self.source.len();
+        self.diagnostics.push(Diagnostic {
+            error: CstError::UnexpectedEndOfInput,
+            span,
+        });
+    }
+
+    /// Reports the current token and skips ahead to the next comma or closing bracket on this
+    /// nesting level, without consuming it.
+    fn resync(&mut self) {
+        self.unexpected(self.span());
+        let mut depth = 0;
+        while let Some(tok) = self.peek() {
+            match tok {
+                Tok::Kind(TokenKind::StartOfDict | TokenKind::StartOfList) => depth += 1,
+                Tok::Kind(TokenKind::EndOfDict | TokenKind::EndOfList) if depth == 0 => return,
+                Tok::Kind(TokenKind::EndOfDict | TokenKind::EndOfList) => depth -= 1,
+                Tok::Kind(TokenKind::Comma) if depth == 0 => return,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// Reports a container that is nested too deeply and skips it, up to and including its
+    /// closing bracket.
+    fn skip_container(&mut self) {
+        let span = self.span();
+        self.diagnostics.push(Diagnostic {
+            error: CstError::TooDeep(self.max_depth, span.start),
+            span,
+        });
+        let mut depth = 0;
+        while let Some(tok) = self.peek() {
+            self.pos += 1;
+            match tok {
+                Tok::Kind(TokenKind::StartOfDict | TokenKind::StartOfList) => depth += 1,
+                Tok::Kind(TokenKind::EndOfDict | TokenKind::EndOfList) if depth == 1 => return,
+                Tok::Kind(TokenKind::EndOfDict | TokenKind::EndOfList) => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    /// Consumes the comma after a member or element, reporting it if the container ends right
+    /// after it.
+    fn comma(&mut self) {
+        let span = self.span();
+        self.pos += 1;
+        if let Some(Tok::Kind(TokenKind::EndOfDict | TokenKind::EndOfList)) = self.peek() {
+            self.unexpected(span);
+        }
+    }
+
+    fn starts_value(tok: Option<Tok>) -> bool {
+        matches!(
+            tok,
+            Some(
+                Tok::Bad
+                    | Tok::Kind(
+                        TokenKind::StartOfDict
+                            | TokenKind::StartOfList
+                            | TokenKind::String
+                            | TokenKind::Number
+                            | TokenKind::True
+                            | TokenKind::False
+                            | TokenKind::Null
+                    )
+            )
+        )
+    }
+
+    fn parse_value(&mut self) -> PartialJson {
+        let span = self.span();
+        match self.peek() {
+            None => {
+                self.end_of_input();
+                PartialJson::Error
+            }
+            Some(Tok::Bad) => {
+                self.pos += 1;
+                PartialJson::Error
+            }
+            Some(Tok::Kind(TokenKind::StartOfDict | TokenKind::StartOfList))
+                if self.depth == self.max_depth =>
+            {
+                self.skip_container();
+                PartialJson::Error
+            }
+            Some(Tok::Kind(kind @ (TokenKind::StartOfDict | TokenKind::StartOfList))) => {
+                self.pos += 1;
+                self.depth += 1;
+                let result = match kind {
+                    TokenKind::StartOfDict => self.parse_dict(),
+                    _ => self.parse_list(),
+                };
+                self.depth -= 1;
+                result
+            }
+            Some(tok) if Self::starts_value(Some(tok)) => {
+                self.pos += 1;
+                match Constant::from_str(&self.source[span.clone()]) {
+                    Ok(c) => PartialJson::Value(c),
+                    Err(_) => {
+                        self.unexpected(span);
+                        PartialJson::Error
+                    }
+                }
+            }
+            Some(_) => {
+                // a separator or closing bracket where a value should be. It is left for the
+                // enclosing container to deal with.
+                self.unexpected(span);
+                PartialJson::Error
+            }
+        }
+    }
+
+    fn parse_dict(&mut self) -> PartialJson {
+        let mut result = HashMap::new();
+        loop {
+            match self.peek() {
+                None => {
+                    self.end_of_input();
+                    break;
+                }
+                Some(Tok::Kind(TokenKind::EndOfDict)) => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(Tok::Kind(TokenKind::EndOfList)) => {
+                    // most likely a typo for `}`
+                    self.unexpected(self.span());
+                    self.pos += 1;
+                    break;
+                }
+                Some(Tok::Kind(TokenKind::String)) => {
+                    let span = self.span();
+                    self.pos += 1;
+                    let key = unescape(&self.source[span.start + 1..span.end - 1])
+                        .unwrap_or_else(|_| self.source[span.clone()].to_string());
+                    if self.peek() == Some(Tok::Kind(TokenKind::Colon)) {
+                        self.pos += 1;
+                    } else {
+                        self.unexpected(self.span());
+                    }
+                    let value = if Self::starts_value(self.peek()) {
+                        self.parse_value()
+                    } else {
+                        self.unexpected(self.span());
+                        PartialJson::Error
+                    };
+                    result.insert(key, value);
+                }
+                Some(_) => {
+                    self.resync();
+                }
+            }
+            match self.peek() {
+                Some(Tok::Kind(TokenKind::Comma)) => self.comma(),
+                Some(Tok::Kind(TokenKind::EndOfDict | TokenKind::EndOfList)) | None => {}
+                // a missing comma between two members
+                Some(Tok::Kind(TokenKind::String)) => self.unexpected(self.span()),
+                Some(_) => self.resync(),
+            }
+        }
+        PartialJson::Dict(result)
+    }
+
+    fn parse_list(&mut self) -> PartialJson {
+        let mut result = Vec::new();
+        loop {
+            match self.peek() {
+                None => {
+                    self.end_of_input();
+                    break;
+                }
+                Some(Tok::Kind(TokenKind::EndOfList)) => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(Tok::Kind(TokenKind::EndOfDict)) => {
+                    // most likely a typo for `]`
+                    self.unexpected(self.span());
+                    self.pos += 1;
+                    break;
+                }
+                tok if Self::starts_value(tok) => result.push(self.parse_value()),
+                Some(_) => {
+                    self.resync();
+                    result.push(PartialJson::Error);
+                }
+            }
+            match self.peek() {
+                Some(Tok::Kind(TokenKind::Comma)) => self.comma(),
+                Some(Tok::Kind(TokenKind::EndOfDict | TokenKind::EndOfList)) | None => {}
+                // a missing comma between two elements
+                tok if Self::starts_value(tok) => self.unexpected(self.span()),
+                Some(_) => self.resync(),
+            }
+        }
+        PartialJson::List(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_valid_input_has_no_diagnostics() {
+        let json = r#"{"key1": 1, "key2": "value2", "key3": [1, 2, 3], "key4": {"key5": null}}"#;
+        let recovered = parse_recovering(json);
+        assert!(recovered.diagnostics.is_empty());
+        assert!(!recovered.json.has_errors());
+        assert_eq!(recovered.json.into_json(), parse(json).unwrap());
+    }
+
+    #[test]
+    fn test_collects_every_diagnostic() {
+        let json = r#"{
+            "key1": ,
+            "key2": tru,
+            "key3": [1, 2 3, @],
+            "key4": {"key5": "value5" "key6": 6}
+        "#;
+        let recovered = parse_recovering(json);
+        let errors: Vec<_> = recovered.diagnostics.iter().map(|d| &d.error).collect();
+        assert!(matches!(errors[0], CstError::UnexpectedToken(x, _) if x == ","));
+        assert!(matches!(errors[1], CstError::UnexpectedCharacter('t', _)));
+        assert!(matches!(errors[2], CstError::UnexpectedToken(x, _) if x == "3"));
+        assert!(matches!(errors[3], CstError::UnexpectedCharacter('@', _)));
+        assert!(matches!(errors[4], CstError::UnexpectedToken(x, _) if x == "\"key6\""));
+        assert_eq!(errors[5], &CstError::UnexpectedEndOfInput);
+        assert_eq!(errors.len(), 6);
+        assert_eq!(&json[recovered.diagnostics[1].span.clone()], "tru");
+
+        let PartialJson::Dict(dict) = recovered.json else {
+            panic!("should be a dict");
+        };
+        assert_eq!(dict["key1"], PartialJson::Error);
+        assert_eq!(dict["key2"], PartialJson::Error);
+        assert_eq!(
+            dict["key3"],
+            PartialJson::List(vec![
+                PartialJson::Value(Constant::Int(1)),
+                PartialJson::Value(Constant::Int(2)),
+                PartialJson::Value(Constant::Int(3)),
+                PartialJson::Error,
+            ])
+        );
+        let PartialJson::Dict(key4) = &dict["key4"] else {
+            panic!("should be a dict");
+        };
+        assert_eq!(key4["key6"], PartialJson::Value(Constant::Int(6)));
+    }
+
+    #[test]
+    fn test_resyncs_at_closing_brackets() {
+        let recovered = parse_recovering(r#"[{"a": 1], {"b": 2}, "unterminated]"#);
+        assert_eq!(recovered.diagnostics.len(), 3);
+        assert!(matches!(
+            recovered.diagnostics[1].error,
+            CstError::UnterminatedString(_)
+        ));
+        let PartialJson::List(list) = recovered.json else {
+            panic!("should be a list");
+        };
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[2], PartialJson::Error);
+
+        let recovered = parse_recovering("}");
+        assert_eq!(recovered.diagnostics.len(), 1);
+        assert_eq!(recovered.json, PartialJson::Error);
+    }
+
+    #[test]
+    fn test_trailing_commas() {
+        for json in ["[1,]", r#"{"a": 1,}"#] {
+            let recovered = parse_recovering(json);
+            let comma = json.find(',').unwrap();
+            assert_eq!(
+                recovered.diagnostics,
+                vec![Diagnostic {
+                    error: CstError::UnexpectedToken(",".to_string(), comma),
+                    span: comma..comma + 1,
+                }]
+            );
+            assert!(!recovered.json.has_errors());
+        }
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let deep = format!("[{}{}, 1]", "[".repeat(100_000), "]".repeat(100_000));
+        let recovered = parse_recovering(&deep);
+        assert_eq!(recovered.diagnostics.len(), 1);
+        assert_eq!(recovered.diagnostics[0].error, CstError::TooDeep(128, 128));
+        let mut json = &recovered.json;
+        for _ in 0..128 {
+            let PartialJson::List(list) = json else {
+                panic!("should be a list");
+            };
+            json = &list[0];
+        }
+        assert_eq!(json, &PartialJson::Error);
+        let PartialJson::List(outer) = &recovered.json else {
+            panic!("should be a list");
+        };
+        assert_eq!(outer[1], PartialJson::Value(Constant::Int(1)));
+    }
+}
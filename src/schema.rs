@@ -0,0 +1,1263 @@
+//! JSON Schema (draft 2020-12) validation.
+//!
+//! `pattern`, `patternProperties` and the `regex` format use the syntax of the `regex` crate
+//! rather than ECMA-262. Look-around and backreferences aren't supported, so a schema with such a
+//! pattern fails to compile, and strings using them aren't valid `regex`es. Unlike in ECMA-262,
+//! `\d`, `\w` and `\s` match Unicode.
+//!
+//! `$dynamicRef`, `$dynamicAnchor`, `unevaluatedItems` and `unevaluatedProperties` aren't
+//! supported either. Schemas using them are rejected with `SchemaError::Invalid` instead of
+//! being validated as if the keywords were absent.
+use crate::float_format::shortest_digits;
+use crate::lexer::Constant;
+use crate::parser::Json;
+use crate::patch::{compare_numbers, equal};
+use crate::pointer::{escape_pointer_token, parse_pointer};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::net::Ipv6Addr;
+use std::sync::OnceLock;
+use thiserror::Error;
+
+/// A schema that can't be compiled, with the JSON pointer to the offending part of it.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SchemaError {
+    #[error("SchemaError: Invalid schema at \"{0}\": {1}")]
+    Invalid(String, String),
+    #[error("SchemaError: Can't resolve \"{1}\" at \"{0}\"")]
+    Reference(String, String),
+}
+
+/// A violation of a schema. `instance_path` points to the offending value in the document,
+/// `schema_path` to the keyword it violates, following `$ref`s as they are met.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("ValidationError at \"{instance_path}\": {message} (schema \"{schema_path}\")")]
+pub struct ValidationError {
+    pub instance_path: String,
+    pub schema_path: String,
+    pub message: String,
+}
+
+/// A compiled schema, e.g. `Schema::compile(&json!({"type": "object", "required": ["id"]}))`.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    /// Subschemas by index, the root first. `$ref`s refer to subschemas by index.
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Bool(bool),
+    Keywords(Box<Keywords>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Null,
+    Boolean,
+    Integer,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+const TYPES: &[(&str, Type)] = &[
+    ("null", Type::Null),
+    ("boolean", Type::Boolean),
+    ("integer", Type::Integer),
+    ("number", Type::Number),
+    ("string", Type::String),
+    ("array", Type::Array),
+    ("object", Type::Object),
+];
+
+/// The assertions and applicators of a schema object. Subschemas are indices into the nodes.
+#[derive(Debug, Clone, Default)]
+struct Keywords {
+    reference: Option<usize>,
+    types: Option<Vec<Type>>,
+    enumeration: Option<Vec<Json>>,
+    constant: Option<Json>,
+    multiple_of: Option<Constant>,
+    maximum: Option<Constant>,
+    exclusive_maximum: Option<Constant>,
+    minimum: Option<Constant>,
+    exclusive_minimum: Option<Constant>,
+    max_length: Option<u64>,
+    min_length: Option<u64>,
+    pattern: Option<Regex>,
+    format: Option<String>,
+    prefix_items: Vec<usize>,
+    items: Option<usize>,
+    contains: Option<usize>,
+    max_contains: Option<u64>,
+    min_contains: Option<u64>,
+    max_items: Option<u64>,
+    min_items: Option<u64>,
+    unique_items: bool,
+    properties: Vec<(String, usize)>,
+    pattern_properties: Vec<(Regex, usize)>,
+    additional_properties: Option<usize>,
+    property_names: Option<usize>,
+    max_properties: Option<u64>,
+    min_properties: Option<u64>,
+    required: Vec<String>,
+    dependent_required: Vec<(String, Vec<String>)>,
+    dependent_schemas: Vec<(String, usize)>,
+    all_of: Vec<usize>,
+    any_of: Vec<usize>,
+    one_of: Vec<usize>,
+    not: Option<usize>,
+    condition: Option<usize>,
+    then: Option<usize>,
+    otherwise: Option<usize>,
+}
+
+struct Compiler<'a> {
+    root: &'a Json,
+    nodes: Vec<Node>,
+    /// Subschemas by their JSON pointer in the root, which also ends recursion through `$ref`s.
+    compiled: HashMap<String, usize>,
+    /// Pointers to the subschemas with an `$anchor`.
+    anchors: HashMap<String, String>,
+}
+
+fn invalid(path: &str, message: impl Into<String>) -> SchemaError {
+    SchemaError::Invalid(path.to_string(), message.into())
+}
+
+fn find_anchors(json: &Json, path: &str, anchors: &mut HashMap<String, String>) {
+    match json {
+        Json::Dict(x) => {
+            if let Some(Json::Value(Constant::StringLiteral(anchor))) = x.get("$anchor") {
+                anchors.insert(anchor.clone(), path.to_string());
+            }
+            for (key, value) in x {
+                let path = format!("{}/{}", path, escape_pointer_token(key));
+                find_anchors(value, &path, anchors);
+            }
+        }
+        Json::List(x) => {
+            for (i, value) in x.iter().enumerate() {
+                find_anchors(value, &format!("{}/{}", path, i), anchors);
+            }
+        }
+        Json::Value(_) => {}
+    }
+}
+
+/// Decodes the `%XX` escapes of a URI fragment.
+pub(crate) fn percent_decode(fragment: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = fragment.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+impl<'a> Compiler<'a> {
+    /// Compiles the subschema at `path` in the root, once.
+    fn compile(&mut self, schema: &'a Json, path: &str) -> Result<usize, SchemaError> {
+        if let Some(&index) = self.compiled.get(path) {
+            return Ok(index);
+        }
+        let index = self.nodes.len();
+        self.nodes.push(Node::Bool(true));
+        self.compiled.insert(path.to_string(), index);
+        self.nodes[index] = match schema {
+            Json::Value(Constant::Boolean(x)) => Node::Bool(*x),
+            Json::Dict(members) => Node::Keywords(Box::new(self.keywords(members, path)?)),
+            _ => return Err(invalid(path, "a schema must be an object or a boolean")),
+        };
+        Ok(index)
+    }
+
+    fn resolve(&mut self, reference: &str, path: &str) -> Result<usize, SchemaError> {
+        let unresolved = || SchemaError::Reference(path.to_string(), reference.to_string());
+        let fragment = reference
+            .strip_prefix('#')
+            .and_then(percent_decode)
+            .ok_or_else(unresolved)?;
+        let pointer = match fragment.is_empty() || fragment.starts_with('/') {
+            true => fragment,
+            false => self
+                .anchors
+                .get(&fragment)
+                .cloned()
+                .ok_or_else(unresolved)?,
+        };
+        let target = self.root.pointer(&pointer).ok_or_else(unresolved)?;
+        self.compile(target, &pointer)
+    }
+
+    fn keywords(
+        &mut self,
+        members: &'a HashMap<String, Json>,
+        path: &str,
+    ) -> Result<Keywords, SchemaError> {
+        let mut keywords = Keywords::default();
+        // sorted, so that the first error doesn't depend on the hash map order
+        let mut entries = members.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|(key, _)| *key);
+        for (keyword, value) in entries {
+            let path = format!("{}/{}", path, escape_pointer_token(keyword));
+            let path = path.as_str();
+            match keyword.as_str() {
+                "$ref" => {
+                    let reference = string(value, path)?;
+                    keywords.reference = Some(self.resolve(reference, path)?);
+                }
+                "type" => {
+                    let names = match value {
+                        Json::List(names) => names.iter().collect(),
+                        name => vec![name],
+                    };
+                    let types = names
+                        .into_iter()
+                        .map(|name| {
+                            let name = string(name, path)?;
+                            TYPES
+                                .iter()
+                                .find(|(known, _)| *known == name)
+                                .map(|(_, t)| *t)
+                                .ok_or_else(|| invalid(path, format!("unknown type {:?}", name)))
+                        })
+                        .collect::<Result<_, _>>()?;
+                    keywords.types = Some(types);
+                }
+                "enum" => match value {
+                    Json::List(values) => keywords.enumeration = Some(values.clone()),
+                    _ => return Err(invalid(path, "must be an array")),
+                },
+                "const" => keywords.constant = Some(value.clone()),
+                "multipleOf" => match number(value, path)? {
+                    x if float(&x) > 0.0 => keywords.multiple_of = Some(x),
+                    _ => return Err(invalid(path, "must be greater than 0")),
+                },
+                "maximum" => keywords.maximum = Some(number(value, path)?),
+                "exclusiveMaximum" => keywords.exclusive_maximum = Some(number(value, path)?),
+                "minimum" => keywords.minimum = Some(number(value, path)?),
+                "exclusiveMinimum" => keywords.exclusive_minimum = Some(number(value, path)?),
+                "maxLength" => keywords.max_length = Some(count(value, path)?),
+                "minLength" => keywords.min_length = Some(count(value, path)?),
+                "pattern" => keywords.pattern = Some(regex(value, path)?),
+                "format" => keywords.format = Some(string(value, path)?.to_string()),
+                "prefixItems" => keywords.prefix_items = self.subschemas(value, path)?,
+                "items" => keywords.items = Some(self.compile(value, path)?),
+                "contains" => keywords.contains = Some(self.compile(value, path)?),
+                "maxContains" => keywords.max_contains = Some(count(value, path)?),
+                "minContains" => keywords.min_contains = Some(count(value, path)?),
+                "maxItems" => keywords.max_items = Some(count(value, path)?),
+                "minItems" => keywords.min_items = Some(count(value, path)?),
+                "uniqueItems" => match value {
+                    Json::Value(Constant::Boolean(x)) => keywords.unique_items = *x,
+                    _ => return Err(invalid(path, "must be a boolean")),
+                },
+                "properties" => keywords.properties = self.members(value, path)?,
+                "patternProperties" => {
+                    for (pattern, index) in self.members(value, path)? {
+                        let pattern_path = format!("{}/{}", path, escape_pointer_token(&pattern));
+                        let regex = regex(&Json::from(pattern), &pattern_path)?;
+                        keywords.pattern_properties.push((regex, index));
+                    }
+                }
+                "additionalProperties" => {
+                    keywords.additional_properties = Some(self.compile(value, path)?)
+                }
+                "propertyNames" => keywords.property_names = Some(self.compile(value, path)?),
+                "maxProperties" => keywords.max_properties = Some(count(value, path)?),
+                "minProperties" => keywords.min_properties = Some(count(value, path)?),
+                "required" => keywords.required = strings(value, path)?,
+                "dependentRequired" => {
+                    let Json::Dict(dependencies) = value else {
+                        return Err(invalid(path, "must be an object"));
+                    };
+                    for (key, value) in dependencies {
+                        let path = format!("{}/{}", path, escape_pointer_token(key));
+                        let required = strings(value, &path)?;
+                        keywords.dependent_required.push((key.clone(), required));
+                    }
+                    keywords.dependent_required.sort();
+                }
+                "dependentSchemas" => keywords.dependent_schemas = self.members(value, path)?,
+                "allOf" => keywords.all_of = self.subschemas(value, path)?,
+                "anyOf" => keywords.any_of = self.subschemas(value, path)?,
+                "oneOf" => keywords.one_of = self.subschemas(value, path)?,
+                "not" => keywords.not = Some(self.compile(value, path)?),
+                "if" => keywords.condition = Some(self.compile(value, path)?),
+                "then" => keywords.then = Some(self.compile(value, path)?),
+                "else" => keywords.otherwise = Some(self.compile(value, path)?),
+                "$dynamicRef" | "$dynamicAnchor" | "unevaluatedItems" | "unevaluatedProperties" => {
+                    return Err(invalid(path, format!("{} is not supported", keyword)));
+                }
+                "$defs" | "definitions" => {
+                    // compiled when referenced, but checked anyway
+                    self.members(value, path)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(keywords)
+    }
+
+    /// The subschemas of a non-empty array.
+    fn subschemas(&mut self, value: &'a Json, path: &str) -> Result<Vec<usize>, SchemaError> {
+        match value {
+            Json::List(schemas) if !schemas.is_empty() => schemas
+                .iter()
+                .enumerate()
+                .map(|(i, schema)| self.compile(schema, &format!("{}/{}", path, i)))
+                .collect(),
+            _ => Err(invalid(path, "must be a non-empty array")),
+        }
+    }
+
+    /// The subschemas of an object by key, sorted by key.
+    fn members(
+        &mut self,
+        value: &'a Json,
+        path: &str,
+    ) -> Result<Vec<(String, usize)>, SchemaError> {
+        let Json::Dict(schemas) = value else {
+            return Err(invalid(path, "must be an object"));
+        };
+        let mut keys = schemas.keys().collect::<Vec<_>>();
+        keys.sort();
+        keys.into_iter()
+            .map(|key| {
+                let path = format!("{}/{}", path, escape_pointer_token(key));
+                Ok((key.clone(), self.compile(&schemas[key], &path)?))
+            })
+            .collect()
+    }
+}
+
+fn string<'j>(value: &'j Json, path: &str) -> Result<&'j str, SchemaError> {
+    match value {
+        Json::Value(Constant::StringLiteral(x)) => Ok(x),
+        _ => Err(invalid(path, "must be a string")),
+    }
+}
+
+fn strings(value: &Json, path: &str) -> Result<Vec<String>, SchemaError> {
+    match value {
+        Json::List(x) => x
+            .iter()
+            .map(|x| string(x, path).map(str::to_string))
+            .collect(),
+        _ => Err(invalid(path, "must be an array of strings")),
+    }
+}
+
+fn number(value: &Json, path: &str) -> Result<Constant, SchemaError> {
+    match value {
+        Json::Value(x) if compare_numbers(x, x).is_some() => Ok(x.clone()),
+        _ => Err(invalid(path, "must be a number")),
+    }
+}
+
+/// A non-negative integer, which may be written as a float like `2.0`.
+fn count(value: &Json, path: &str) -> Result<u64, SchemaError> {
+    let x = float(&number(value, path)?);
+    match x >= 0.0 && x.fract() == 0.0 {
+        true => Ok(x as u64),
+        false => Err(invalid(path, "must be a non-negative integer")),
+    }
+}
+
+fn regex(value: &Json, path: &str) -> Result<Regex, SchemaError> {
+    let pattern = string(value, path)?;
+    Regex::new(pattern).map_err(|e| invalid(path, format!("invalid pattern: {}", e)))
+}
+
+fn float(x: &Constant) -> f64 {
+    match x {
+        Constant::Int(x) => *x as f64,
+        Constant::UInt(x) => *x as f64,
+        Constant::Float(x) => *x,
+        Constant::Number(x) => x.as_f64(),
+        _ => f64::NAN,
+    }
+}
+
+/// A number as `mantissa * 10^exponent`, exactly as it is written for integers and as the
+/// shortest decimal that rounds to it for floats.
+fn decimal(x: &Constant) -> Option<(i128, i32)> {
+    match x {
+        Constant::Int(x) => Some((*x as i128, 0)),
+        Constant::UInt(x) => Some((*x as i128, 0)),
+        Constant::Number(x) if x.as_i128().is_some() => Some((x.as_i128()?, 0)),
+        _ => {
+            let x = float(x);
+            if x == 0.0 {
+                return Some((0, 0));
+            }
+            if !x.is_finite() {
+                return None;
+            }
+            let (digits, exponent) = shortest_digits(x);
+            let mantissa = digits.parse::<i128>().ok()?;
+            let sign = if x < 0.0 { -1 } else { 1 };
+            Some((sign * mantissa, exponent - digits.len() as i32))
+        }
+    }
+}
+
+/// Whether `x` is a multiple of `m`, in decimal arithmetic so that `0.3` is a multiple of `0.1`.
+fn is_multiple(x: &Constant, m: &Constant) -> bool {
+    let exact = || {
+        let ((x, xe), (m, me)) = (decimal(x)?, decimal(m)?);
+        let e = xe.min(me);
+        let scale = |n: i128, by: i32| 10i128.checked_pow(by as u32)?.checked_mul(n);
+        Some(scale(x, xe - e)? % scale(m, me - e)? == 0)
+    };
+    exact().unwrap_or_else(|| {
+        let q = float(x) / float(m);
+        q.is_finite() && q.fract() == 0.0
+    })
+}
+
+fn type_of(json: &Json) -> Type {
+    match json {
+        Json::Dict(_) => Type::Object,
+        Json::List(_) => Type::Array,
+        Json::Value(Constant::Null) => Type::Null,
+        Json::Value(Constant::Boolean(_)) => Type::Boolean,
+        Json::Value(Constant::StringLiteral(_)) => Type::String,
+        Json::Value(x) => match float(x).fract() == 0.0 {
+            true => Type::Integer,
+            false => Type::Number,
+        },
+    }
+}
+
+fn type_name(t: Type) -> &'static str {
+    TYPES
+        .iter()
+        .find(|(_, known)| *known == t)
+        .map(|(name, _)| *name)
+        .expect("every type has a name")
+}
+
+/// Checks the formats this validator knows, anything else is valid.
+fn is_format(format: &str, text: &str) -> bool {
+    static PATTERNS: OnceLock<HashMap<&str, Regex>> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        // `[0-9]`, as `\d` matches any Unicode digit
+        let date = r"[0-9]{4}-[0-9]{2}-[0-9]{2}";
+        let time = r"[0-9]{2}:[0-9]{2}:[0-9]{2}(\.[0-9]+)?([zZ]|[+-][0-9]{2}:[0-9]{2})";
+        [
+            ("date", format!("^{}$", date)),
+            ("time", format!("^{}$", time)),
+            ("date-time", format!("^{}[tT]{}$", date, time)),
+            ("email", r"^[^\s@.]+(\.[^\s@.]+)*@[^\s@]+$".to_string()),
+            (
+                "uuid",
+                r"^[0-9a-fA-F]{8}-([0-9a-fA-F]{4}-){3}[0-9a-fA-F]{12}$".to_string(),
+            ),
+            ("uri", r"^[a-zA-Z][a-zA-Z0-9+.-]*:[^\s]*$".to_string()),
+            ("uri-reference", r"^[^\s]*$".to_string()),
+        ]
+        .into_iter()
+        .map(|(name, pattern)| (name, Regex::new(&pattern).expect("valid pattern")))
+        .collect()
+    });
+    if let Some(pattern) = patterns.get(format) {
+        if !pattern.is_match(text) {
+            return false;
+        }
+    }
+    match format {
+        "date" => is_date(text),
+        "time" => is_time(text),
+        "date-time" => text
+            .split_once(['t', 'T'])
+            .is_some_and(|(date, time)| is_date(date) && is_time(time)),
+        "hostname" => is_hostname(text),
+        "email" => text
+            .rsplit_once('@')
+            .is_some_and(|(_, host)| is_hostname(host)),
+        "ipv4" => is_ipv4(text),
+        "ipv6" => text.parse::<Ipv6Addr>().is_ok(),
+        "regex" => Regex::new(text).is_ok(),
+        "json-pointer" => parse_pointer(text).is_ok(),
+        _ => true,
+    }
+}
+
+fn is_date(text: &str) -> bool {
+    let parts = text
+        .split('-')
+        .map(|part| part.parse::<u32>().unwrap_or(0))
+        .collect::<Vec<_>>();
+    let &[year, month, day] = &parts[..] else {
+        return false;
+    };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return false,
+    };
+    (1..=days).contains(&day)
+}
+
+fn is_time(text: &str) -> bool {
+    let fields = |text: &str| {
+        text.split(':')
+            .map(|field| {
+                field
+                    .split('.')
+                    .next()
+                    .unwrap_or_default()
+                    .parse::<u32>()
+                    .ok()
+            })
+            .collect::<Option<Vec<_>>>()
+    };
+    let (time, offset) = match text.strip_suffix(['z', 'Z']) {
+        Some(time) => (time, Some(vec![0, 0])),
+        None => match text.rfind(['+', '-']) {
+            Some(i) => (&text[..i], fields(&text[i + 1..])),
+            None => return false,
+        },
+    };
+    // 60 is a leap second
+    matches!(fields(time).as_deref(), Some(&[h, m, s]) if h < 24 && m < 60 && s <= 60)
+        && matches!(offset.as_deref(), Some(&[h, m]) if h < 24 && m < 60)
+}
+
+fn is_hostname(text: &str) -> bool {
+    let text = text.strip_suffix('.').unwrap_or(text);
+    !text.is_empty()
+        && text.len() <= 253
+        && text.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+fn is_ipv4(text: &str) -> bool {
+    let octets = text.split('.').collect::<Vec<_>>();
+    octets.len() == 4
+        && octets.iter().all(|octet| {
+            !octet.is_empty()
+                && octet.len() <= 3
+                && octet.bytes().all(|b| b.is_ascii_digit())
+                && (octet.len() == 1 || !octet.starts_with('0'))
+                && octet.parse::<u8>().is_ok()
+        })
+}
+
+/// Nesting of subschemas applied to the same value is bounded, so that a long chain of `$ref`s
+/// fails instead of overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+/// Subschema evaluations allowed per value of the instance. Applicators that refer to the same
+/// subschema more than once make the work grow exponentially with the nesting of the schema, so
+/// it is bounded by the size of the instance instead.
+const EVALUATIONS_PER_VALUE: usize = 4096;
+
+/// Where validation is: the value's pointer in the document and the path through the schema.
+struct Location {
+    instance: String,
+    schema: String,
+    /// Subschemas applied to the same value so far.
+    depth: usize,
+}
+
+impl Location {
+    /// The location of a subschema applied to the same value.
+    fn keyword(&self, keyword: &str) -> Location {
+        Location {
+            instance: self.instance.clone(),
+            schema: format!("{}/{}", self.schema, keyword),
+            depth: self.depth + 1,
+        }
+    }
+
+    /// The location of a subschema applied to a member or an element of the value.
+    fn child(&self, keyword: &str, token: &str) -> Location {
+        Location {
+            instance: format!("{}/{}", self.instance, escape_pointer_token(token)),
+            schema: format!("{}/{}", self.schema, keyword),
+            depth: 0,
+        }
+    }
+
+    fn error(&self, keyword: &str, message: String) -> ValidationError {
+        ValidationError {
+            instance_path: self.instance.clone(),
+            schema_path: format!("{}/{}", self.schema, keyword),
+            message,
+        }
+    }
+
+    /// An error of the subschema itself rather than one of its keywords.
+    fn subschema_error(&self, message: &str) -> ValidationError {
+        ValidationError {
+            instance_path: self.instance.clone(),
+            schema_path: self.schema.clone(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// The state of one `validate` call.
+struct Validation {
+    errors: Vec<ValidationError>,
+    /// The subschemas being applied and the addresses of the values they are applied to. A
+    /// subschema that is applied to the same value again, like through `{"$ref": "#"}`, would
+    /// never finish.
+    active: HashSet<(usize, *const Json)>,
+    remaining: usize,
+    /// Set when `remaining` runs out, which ends validation.
+    exhausted: Option<ValidationError>,
+}
+
+/// The number of values in `json`, containers included.
+fn values(json: &Json) -> usize {
+    let mut stack = vec![json];
+    let mut count = 0;
+    while let Some(json) = stack.pop() {
+        count += 1;
+        match json {
+            Json::Dict(x) => stack.extend(x.values()),
+            Json::List(x) => stack.extend(x),
+            Json::Value(_) => {}
+        }
+    }
+    count
+}
+
+impl Schema {
+    pub fn compile(schema: &Json) -> Result<Schema, SchemaError> {
+        let mut compiler = Compiler {
+            root: schema,
+            nodes: Vec::new(),
+            compiled: HashMap::new(),
+            anchors: HashMap::new(),
+        };
+        find_anchors(schema, "", &mut compiler.anchors);
+        compiler.compile(schema, "")?;
+        Ok(Schema {
+            nodes: compiler.nodes,
+        })
+    }
+
+    /// Checks `instance` against the schema, with every violation if it isn't valid.
+    pub fn validate(&self, instance: &Json) -> Result<(), Vec<ValidationError>> {
+        let mut state = Validation {
+            errors: Vec::new(),
+            active: HashSet::new(),
+            remaining: values(instance).saturating_mul(EVALUATIONS_PER_VALUE),
+            exhausted: None,
+        };
+        let at = Location {
+            instance: String::new(),
+            schema: String::new(),
+            depth: 0,
+        };
+        self.check(0, instance, &at, &mut state);
+        if let Some(error) = state.exhausted {
+            return Err(vec![error]);
+        }
+        match state.errors.is_empty() {
+            true => Ok(()),
+            false => Err(state.errors),
+        }
+    }
+
+    pub fn is_valid(&self, instance: &Json) -> bool {
+        self.validate(instance).is_ok()
+    }
+
+    /// Whether `instance` is valid against a subschema, ignoring why not.
+    fn matches(&self, node: usize, instance: &Json, at: &Location, state: &mut Validation) -> bool {
+        let before = state.errors.len();
+        self.check(node, instance, at, state);
+        let matches = state.errors.len() == before;
+        state.errors.truncate(before);
+        matches
+    }
+
+    fn check(&self, node: usize, instance: &Json, at: &Location, state: &mut Validation) {
+        if state.exhausted.is_some() {
+            return;
+        }
+        if state.remaining == 0 {
+            let message = "validation takes too many steps";
+            state.exhausted = Some(at.subschema_error(message));
+            return;
+        }
+        state.remaining -= 1;
+        match &self.nodes[node] {
+            Node::Bool(true) => {}
+            Node::Bool(false) => {
+                let error = at.subschema_error("no value is allowed here");
+                state.errors.push(error);
+            }
+            Node::Keywords(_) if at.depth > MAX_DEPTH => {
+                let message = "subschemas are nested too deeply".to_string();
+                state.errors.push(at.error("$ref", message));
+            }
+            Node::Keywords(keywords) => {
+                let active = (node, instance as *const Json);
+                if !state.active.insert(active) {
+                    let message = "the subschema is applied to the same value again";
+                    state.errors.push(at.subschema_error(message));
+                    return;
+                }
+                self.check_value(keywords, instance, at, &mut state.errors);
+                match instance {
+                    Json::List(items) => self.check_array(keywords, items, at, state),
+                    Json::Dict(members) => self.check_object(keywords, members, at, state),
+                    Json::Value(Constant::StringLiteral(x)) => {
+                        check_string(keywords, x, at, &mut state.errors)
+                    }
+                    Json::Value(x) => check_number(keywords, x, at, &mut state.errors),
+                }
+                self.check_applicators(keywords, instance, at, state);
+                state.active.remove(&active);
+            }
+        }
+    }
+
+    /// The keywords that apply to any value.
+    fn check_value(
+        &self,
+        keywords: &Keywords,
+        instance: &Json,
+        at: &Location,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        if let Some(types) = &keywords.types {
+            let actual = type_of(instance);
+            let matches =
+                |t: &Type| *t == actual || (*t == Type::Number && actual == Type::Integer);
+            if !types.iter().any(matches) {
+                let expected = types.iter().map(|t| type_name(*t)).collect::<Vec<_>>();
+                let message = format!(
+                    "expected {}, got {}",
+                    expected.join(" or "),
+                    type_name(actual)
+                );
+                errors.push(at.error("type", message));
+            }
+        }
+        if let Some(values) = &keywords.enumeration {
+            if !values.iter().any(|value| equal(value, instance)) {
+                errors.push(at.error("enum", "not one of the allowed values".to_string()));
+            }
+        }
+        if let Some(value) = &keywords.constant {
+            if !equal(value, instance) {
+                errors.push(at.error("const", format!("expected {}", value)));
+            }
+        }
+    }
+
+    fn check_array(
+        &self,
+        keywords: &Keywords,
+        items: &[Json],
+        at: &Location,
+        state: &mut Validation,
+    ) {
+        let length = items.len() as u64;
+        if keywords.max_items.is_some_and(|max| length > max) {
+            let message = format!("expected at most {} items", keywords.max_items.unwrap());
+            state.errors.push(at.error("maxItems", message));
+        }
+        if keywords.min_items.is_some_and(|min| length < min) {
+            let message = format!("expected at least {} items", keywords.min_items.unwrap());
+            state.errors.push(at.error("minItems", message));
+        }
+        if keywords.unique_items {
+            // Each distinct item with the index of its first duplicate, bucketed by a hash that
+            // numbers equal by value share.
+            let mut seen: HashMap<u64, Vec<(usize, Option<usize>)>> = HashMap::new();
+            for (i, item) in items.iter().enumerate() {
+                let mut hasher = DefaultHasher::new();
+                hash_value(item, &mut hasher);
+                let bucket = seen.entry(hasher.finish()).or_default();
+                match bucket
+                    .iter_mut()
+                    .find(|(first, _)| equal(&items[*first], item))
+                {
+                    Some((_, duplicate)) => {
+                        duplicate.get_or_insert(i);
+                    }
+                    None => bucket.push((i, None)),
+                }
+            }
+            let duplicate = seen
+                .values()
+                .flatten()
+                .filter_map(|&(i, j)| Some((i, j?)))
+                .min();
+            if let Some((i, j)) = duplicate {
+                let message = format!("items {} and {} are equal", i, j);
+                state.errors.push(at.error("uniqueItems", message));
+            }
+        }
+        for (i, (item, &node)) in items.iter().zip(&keywords.prefix_items).enumerate() {
+            let token = i.to_string();
+            self.check(
+                node,
+                item,
+                &at.child(&format!("prefixItems/{}", i), &token),
+                state,
+            );
+        }
+        if let Some(node) = keywords.items {
+            for (i, item) in items.iter().enumerate().skip(keywords.prefix_items.len()) {
+                self.check(node, item, &at.child("items", &i.to_string()), state);
+            }
+        }
+        if let Some(node) = keywords.contains {
+            let matching = items
+                .iter()
+                .enumerate()
+                .filter(|(i, item)| {
+                    self.matches(node, item, &at.child("contains", &i.to_string()), state)
+                })
+                .count() as u64;
+            let min = keywords.min_contains.unwrap_or(1);
+            if matching < min {
+                let message = format!("expected at least {} matching items, got {}", min, matching);
+                let keyword = match keywords.min_contains {
+                    Some(_) => "minContains",
+                    None => "contains",
+                };
+                state.errors.push(at.error(keyword, message));
+            }
+            if let Some(max) = keywords.max_contains.filter(|max| matching > *max) {
+                let message = format!("expected at most {} matching items, got {}", max, matching);
+                state.errors.push(at.error("maxContains", message));
+            }
+        }
+    }
+
+    fn check_object(
+        &self,
+        keywords: &Keywords,
+        members: &HashMap<String, Json>,
+        at: &Location,
+        state: &mut Validation,
+    ) {
+        let errors = &mut state.errors;
+        let length = members.len() as u64;
+        if keywords.max_properties.is_some_and(|max| length > max) {
+            let max = keywords.max_properties.unwrap();
+            errors.push(at.error(
+                "maxProperties",
+                format!("expected at most {} properties", max),
+            ));
+        }
+        if keywords.min_properties.is_some_and(|min| length < min) {
+            let min = keywords.min_properties.unwrap();
+            let message = format!("expected at least {} properties", min);
+            errors.push(at.error("minProperties", message));
+        }
+        for name in &keywords.required {
+            if !members.contains_key(name) {
+                errors.push(at.error("required", format!("missing property {:?}", name)));
+            }
+        }
+        for (key, required) in &keywords.dependent_required {
+            if !members.contains_key(key) {
+                continue;
+            }
+            for name in required.iter().filter(|name| !members.contains_key(*name)) {
+                let message = format!("missing property {:?}, required by {:?}", name, key);
+                let keyword = format!("dependentRequired/{}", escape_pointer_token(key));
+                errors.push(at.error(&keyword, message));
+            }
+        }
+        // sorted, so that errors come in a stable order
+        let mut keys = members.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            let value = &members[key];
+            if let Some(node) = keywords.property_names {
+                let name = Json::from(key.as_str());
+                self.check(node, &name, &at.child("propertyNames", key), state);
+            }
+            let mut evaluated = false;
+            if let Ok(i) = keywords
+                .properties
+                .binary_search_by(|(name, _)| name.cmp(key))
+            {
+                let keyword = format!("properties/{}", escape_pointer_token(key));
+                self.check(
+                    keywords.properties[i].1,
+                    value,
+                    &at.child(&keyword, key),
+                    state,
+                );
+                evaluated = true;
+            }
+            for (regex, node) in &keywords.pattern_properties {
+                if regex.is_match(key) {
+                    let pattern = escape_pointer_token(regex.as_str());
+                    let keyword = format!("patternProperties/{}", pattern);
+                    self.check(*node, value, &at.child(&keyword, key), state);
+                    evaluated = true;
+                }
+            }
+            if let (false, Some(node)) = (evaluated, keywords.additional_properties) {
+                self.check(node, value, &at.child("additionalProperties", key), state);
+            }
+        }
+    }
+
+    /// The keywords that combine subschemas applied to the value itself.
+    fn check_applicators(
+        &self,
+        keywords: &Keywords,
+        instance: &Json,
+        at: &Location,
+        state: &mut Validation,
+    ) {
+        if let Some(node) = keywords.reference {
+            self.check(node, instance, &at.keyword("$ref"), state);
+        }
+        for (i, &node) in keywords.all_of.iter().enumerate() {
+            self.check(node, instance, &at.keyword(&format!("allOf/{}", i)), state);
+        }
+        if let Json::Dict(members) = instance {
+            for (key, node) in &keywords.dependent_schemas {
+                if members.contains_key(key) {
+                    let keyword = format!("dependentSchemas/{}", escape_pointer_token(key));
+                    self.check(*node, instance, &at.keyword(&keyword), state);
+                }
+            }
+        }
+        let matching = |keyword: &str, nodes: &[usize], state: &mut Validation| {
+            nodes
+                .iter()
+                .enumerate()
+                .filter(|(i, node)| {
+                    let at = at.keyword(&format!("{}/{}", keyword, i));
+                    self.matches(**node, instance, &at, state)
+                })
+                .count()
+        };
+        if !keywords.any_of.is_empty() && matching("anyOf", &keywords.any_of, state) == 0 {
+            state
+                .errors
+                .push(at.error("anyOf", "no subschema matches".to_string()));
+        }
+        if !keywords.one_of.is_empty() {
+            let count = matching("oneOf", &keywords.one_of, state);
+            if count != 1 {
+                let message = format!("expected exactly one subschema to match, got {}", count);
+                state.errors.push(at.error("oneOf", message));
+            }
+        }
+        if let Some(node) = keywords.not {
+            if self.matches(node, instance, &at.keyword("not"), state) {
+                let message = "matches a forbidden subschema".to_string();
+                state.errors.push(at.error("not", message));
+            }
+        }
+        if let Some(node) = keywords.condition {
+            let branch = match self.matches(node, instance, &at.keyword("if"), state) {
+                true => keywords.then.map(|node| (node, "then")),
+                false => keywords.otherwise.map(|node| (node, "else")),
+            };
+            if let Some((node, keyword)) = branch {
+                self.check(node, instance, &at.keyword(keyword), state);
+            }
+        }
+    }
+}
+
+fn check_string(keywords: &Keywords, text: &str, at: &Location, errors: &mut Vec<ValidationError>) {
+    let length = text.chars().count() as u64;
+    if let Some(max) = keywords.max_length.filter(|max| length > *max) {
+        errors.push(at.error("maxLength", format!("expected at most {} characters", max)));
+    }
+    if let Some(min) = keywords.min_length.filter(|min| length < *min) {
+        errors.push(at.error("minLength", format!("expected at least {} characters", min)));
+    }
+    if let Some(pattern) = keywords.pattern.as_ref().filter(|x| !x.is_match(text)) {
+        let message = format!("doesn't match the pattern {:?}", pattern.as_str());
+        errors.push(at.error("pattern", message));
+    }
+    if let Some(format) = keywords.format.as_ref().filter(|x| !is_format(x, text)) {
+        errors.push(at.error("format", format!("not a valid {}", format)));
+    }
+}
+
+fn check_number(
+    keywords: &Keywords,
+    x: &Constant,
+    at: &Location,
+    errors: &mut Vec<ValidationError>,
+) {
+    if compare_numbers(x, x).is_none() {
+        return;
+    }
+    use Ordering::*;
+    let bounds: [(_, _, _, &[Ordering]); 4] = [
+        (&keywords.maximum, "maximum", "at most", &[Less, Equal]),
+        (
+            &keywords.exclusive_maximum,
+            "exclusiveMaximum",
+            "less than",
+            &[Less],
+        ),
+        (&keywords.minimum, "minimum", "at least", &[Greater, Equal]),
+        (
+            &keywords.exclusive_minimum,
+            "exclusiveMinimum",
+            "greater than",
+            &[Greater],
+        ),
+    ];
+    for (bound, keyword, relation, allowed) in bounds {
+        let Some(bound) = bound else { continue };
+        if !compare_numbers(x, bound).is_some_and(|order| allowed.contains(&order)) {
+            let bound = Json::Value(bound.clone());
+            let message = format!("expected a number {} {}", relation, bound);
+            errors.push(at.error(keyword, message));
+        }
+    }
+    if let Some(m) = keywords.multiple_of.as_ref().filter(|m| !is_multiple(x, m)) {
+        let message = format!("expected a multiple of {}", Json::Value(m.clone()));
+        errors.push(at.error("multipleOf", message));
+    }
+}
+
+/// Hashes `value` so that values `equal` considers equal hash the same.
+fn hash_value(value: &Json, state: &mut DefaultHasher) {
+    match value {
+        Json::Dict(object) => {
+            let mut keys: Vec<_> = object.keys().collect();
+            keys.sort();
+            state.write_u8(0);
+            state.write_usize(keys.len());
+            for key in keys {
+                key.hash(state);
+                hash_value(&object[key], state);
+            }
+        }
+        Json::List(list) => {
+            state.write_u8(1);
+            state.write_usize(list.len());
+            list.iter().for_each(|item| hash_value(item, state));
+        }
+        Json::Value(constant) => {
+            let number = match constant {
+                Constant::Int(x) => *x as f64,
+                Constant::UInt(x) => *x as f64,
+                Constant::Float(x) => *x,
+                Constant::Number(x) => x.as_f64(),
+                Constant::StringLiteral(s) => {
+                    state.write_u8(2);
+                    return s.hash(state);
+                }
+                Constant::Null => return state.write_u8(3),
+                Constant::Boolean(x) => return state.write_u8(4 + *x as u8),
+            };
+            // Integers are compared exactly, but ones that are equal share their nearest float.
+            state.write_u8(6);
+            state.write_u64(if number == 0.0 { 0 } else { number.to_bits() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse;
+
+    fn paths(schema: &Json, instance: &Json) -> Vec<(String, String)> {
+        let schema = Schema::compile(schema).unwrap();
+        match schema.validate(instance) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors
+                .into_iter()
+                .map(|e| (e.instance_path, e.schema_path))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_errors_have_paths() {
+        let schema = json!({
+            "$defs": {"id": {"type": "integer", "minimum": 1}},
+            "type": "object",
+            "required": ["id", "name"],
+            "properties": {
+                "id": {"$ref": "#/$defs/id"},
+                "name": {"type": "string"},
+                "tags": {"prefixItems": [{"const": "x"}], "items": {"type": "string"}}
+            },
+            "patternProperties": {"^x-": {"type": "boolean"}},
+            "additionalProperties": false
+        });
+        let instance = json!({"id": 0, "tags": ["x", 2], "x-a": true, "other": null});
+        assert_eq!(
+            paths(&schema, &instance),
+            [
+                ("", "/required"),
+                ("/id", "/properties/id/$ref/minimum"),
+                ("/other", "/additionalProperties"),
+                ("/tags/1", "/properties/tags/items/type"),
+            ]
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+        );
+        assert!(paths(&schema, &json!({"id": 2.0, "name": "a", "x-b": false})).is_empty());
+    }
+
+    #[test]
+    fn test_applicators() {
+        let schema = json!({
+            "oneOf": [{"type": "integer"}, {"multipleOf": 0.5}],
+            "not": {"const": 3},
+            "if": {"minimum": 10},
+            "then": {"maximum": 20},
+            "else": {"exclusiveMinimum": 0}
+        });
+        assert!(paths(&schema, &json!(1.5)).is_empty());
+        assert_eq!(paths(&schema, &json!(2))[0].1, "/oneOf");
+        assert_eq!(paths(&schema, &json!(3))[1].1, "/not");
+        assert_eq!(paths(&schema, &json!(21))[1].1, "/then/maximum");
+        assert_eq!(paths(&schema, &json!(-1.5))[0].1, "/else/exclusiveMinimum");
+        let schema = json!({"dependentSchemas": {"a": {"required": ["b"]}}});
+        assert!(paths(&schema, &json!({"b": 1})).is_empty());
+        assert_eq!(
+            paths(&schema, &json!({"a": 1}))[0].1,
+            "/dependentSchemas/a/required"
+        );
+        assert!(Schema::compile(&json!({"multipleOf": 0.1}))
+            .unwrap()
+            .is_valid(&json!(0.3)));
+    }
+
+    #[test]
+    fn test_bounded_work() {
+        for keyword in ["allOf", "anyOf"] {
+            let schema = parse(&format!(
+                r##"{{"{}":[{{"$ref":"#"}},{{"$ref":"#"}}]}}"##,
+                keyword
+            ));
+            let schema = Schema::compile(&schema.unwrap()).unwrap();
+            let errors = schema.validate(&json!(1)).unwrap_err();
+            assert!(errors.len() <= 2, "{:?}", errors);
+        }
+        let schema = Schema::compile(&json!({"allOf": [{"$ref": "#"}]})).unwrap();
+        let errors = schema.validate(&json!(1)).unwrap_err();
+        assert_eq!(errors[0].schema_path, "/allOf/0/$ref");
+        assert_eq!(
+            errors[0].message,
+            "the subschema is applied to the same value again"
+        );
+        // no subschema is applied to the same value twice at once, but the work doubles with
+        // every level
+        let mut defs = HashMap::new();
+        for i in 0..40 {
+            let next = json!({"$ref": format!("#/$defs/{}", i + 1)});
+            defs.insert(i.to_string(), json!({"allOf": [next.clone(), next]}));
+        }
+        defs.insert("40".to_string(), json!({"type": "integer"}));
+        let schema = json!({"$defs": Json::Dict(defs), "$ref": "#/$defs/0"});
+        let errors = Schema::compile(&schema)
+            .unwrap()
+            .validate(&json!([1, 2]))
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "validation takes too many steps");
+        let schema = json!({
+            "items": {"$ref": "#/$defs/a"},
+            "$defs": {
+                "a": {"allOf": [{"$ref": "#/$defs/b"}, {"$ref": "#/$defs/b"}]},
+                "b": {"type": "integer"}
+            }
+        });
+        assert!(Schema::compile(&schema)
+            .unwrap()
+            .is_valid(&json!([1, 2, 3])));
+    }
+
+    #[test]
+    fn test_unique_items() {
+        let schema = Schema::compile(&json!({"uniqueItems": true})).unwrap();
+        let message = |instance: Json| match schema.validate(&instance) {
+            Ok(()) => None,
+            Err(errors) => Some(errors[0].message.clone()),
+        };
+        let duplicates = json!([{"a": [1], "b": null}, "x", "x", {"b": null, "a": [1.0]}]);
+        assert_eq!(message(duplicates).unwrap(), "items 0 and 3 are equal");
+        assert_eq!(
+            message(json!([0, -0.0])).unwrap(),
+            "items 0 and 1 are equal"
+        );
+        assert_eq!(message(json!([[1, 2], [2, 1], "1", true, 1])), None);
+        let large = i64::MAX - 1;
+        assert_eq!(message(json!([large, large + 1])), None);
+        // quadratic in the number of items this would take minutes
+        let mut items: Vec<Json> = (0..100_000).map(|i| json!({ "id": i })).collect();
+        assert_eq!(message(Json::List(items.clone())), None);
+        items.push(json!({"id": 99_999.0}));
+        assert_eq!(
+            message(Json::List(items)).unwrap(),
+            "items 99999 and 100000 are equal"
+        );
+    }
+
+    #[test]
+    fn test_formats_and_invalid_schemas() {
+        let schema = Schema::compile(&json!({"format": "date-time"})).unwrap();
+        assert!(schema.is_valid(&json!("2024-02-29T23:59:60.5+01:00")));
+        assert!(!schema.is_valid(&json!("2023-02-29T00:00:00Z")));
+        // only ASCII digits
+        assert!(!schema.is_valid(&json!("2020-01-0٣T00:00:00Z")));
+        assert!(!is_format("time", "0٠:00:00Z") && !is_format("date", "٢٠٢٠-01-01"));
+        assert!(is_format("time", "23:59:59-05:30") && !is_format("time", "12:00:00+24:00"));
+        assert!(is_format("ipv4", "192.168.0.1") && !is_format("ipv4", "192.168.0.01"));
+        assert!(is_format("email", "a.b@example.com") && !is_format("email", "a@-x.com"));
+        assert!(is_format("unknown", "anything"));
+        assert_eq!(
+            Schema::compile(&json!({"items": {"$ref": "other.json#/a"}})).unwrap_err(),
+            SchemaError::Reference("/items/$ref".to_string(), "other.json#/a".to_string())
+        );
+        let schema = json!({"properties": {"a": {}}, "unevaluatedProperties": false});
+        assert_eq!(
+            Schema::compile(&schema).unwrap_err(),
+            SchemaError::Invalid(
+                "/unevaluatedProperties".to_string(),
+                "unevaluatedProperties is not supported".to_string()
+            )
+        );
+        assert!(matches!(
+            Schema::compile(&json!({"pattern": "a(?=b)"})),
+            Err(SchemaError::Invalid(..))
+        ));
+        assert!(matches!(
+            Schema::compile(&json!({"minLength": -1})),
+            Err(SchemaError::Invalid(..))
+        ));
+    }
+}
@@ -0,0 +1,650 @@
+//! A serde `Serializer` that writes compact JSON text, without building a `Json`.
+use crate::float_format::{write_f64, FloatOptions};
+use crate::lexer::{escape, Constant};
+use crate::parser::{Json, JsonError};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq};
+use std::fmt;
+
+impl ser::Error for JsonError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        JsonError::Serde(msg.to_string())
+    }
+}
+
+/// Serializes `value` as compact JSON text. Enums are externally tagged, as in serde_json.
+/// NaN and the infinities are written as `null`.
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, JsonError> {
+    let mut serializer = Serializer {
+        out: String::new(),
+        floats: FloatOptions::default(),
+    };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.out)
+}
+
+pub struct Serializer {
+    out: String,
+    floats: FloatOptions,
+}
+
+impl Serializer {
+    fn write_str(&mut self, s: &str) {
+        self.out.push('"');
+        self.out.push_str(&escape(s));
+        self.out.push('"');
+    }
+
+    fn write_f64(&mut self, x: f64) -> Result<(), JsonError> {
+        write_f64(&mut self.out, x, &self.floats).map_err(|e| JsonError::Serde(e.to_string()))
+    }
+}
+
+/// Arrays and objects, `first` decides whether a comma is needed and `close` is written by `end`.
+pub struct Compound<'a> {
+    ser: &'a mut Serializer,
+    first: bool,
+    close: &'static str,
+}
+
+impl Compound<'_> {
+    fn comma(&mut self) {
+        if !self.first {
+            self.ser.out.push(',');
+        }
+        self.first = false;
+    }
+}
+
+impl<'a> ser::Serializer for &'a mut Serializer {
+    type Ok = ();
+    type Error = JsonError;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), JsonError> {
+        self.out.push_str(if v { "true" } else { "false" });
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), JsonError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), JsonError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), JsonError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), JsonError> {
+        self.out.push_str(&v.to_string());
+        Ok(())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), JsonError> {
+        self.out.push_str(&v.to_string());
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), JsonError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), JsonError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), JsonError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), JsonError> {
+        self.out.push_str(&v.to_string());
+        Ok(())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), JsonError> {
+        self.out.push_str(&v.to_string());
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), JsonError> {
+        // through the shortest decimal of the f32, so that 0.1f32 isn't written as
+        // 0.10000000149011612
+        self.write_f64(v.to_string().parse().expect("f32 formats as a valid f64"))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), JsonError> {
+        self.write_f64(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), JsonError> {
+        self.write_str(v.encode_utf8(&mut [0; 4]));
+        Ok(())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), JsonError> {
+        self.write_str(v);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), JsonError> {
+        let mut seq = self.serialize_seq(Some(v.len()))?;
+        for byte in v {
+            seq.serialize_element(byte)?;
+        }
+        SerializeSeq::end(seq)
+    }
+
+    fn serialize_none(self) -> Result<(), JsonError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), JsonError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), JsonError> {
+        self.out.push_str("null");
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), JsonError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), JsonError> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), JsonError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), JsonError> {
+        self.out.push('{');
+        self.write_str(variant);
+        self.out.push(':');
+        value.serialize(&mut *self)?;
+        self.out.push('}');
+        Ok(())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a>, JsonError> {
+        self.out.push('[');
+        Ok(Compound {
+            ser: self,
+            first: true,
+            close: "]",
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'a>, JsonError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, JsonError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, JsonError> {
+        self.out.push('{');
+        self.write_str(variant);
+        self.out.push_str(":[");
+        Ok(Compound {
+            ser: self,
+            first: true,
+            close: "]}",
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a>, JsonError> {
+        self.out.push('{');
+        Ok(Compound {
+            ser: self,
+            first: true,
+            close: "}",
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a>, JsonError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, JsonError> {
+        self.out.push('{');
+        self.write_str(variant);
+        self.out.push_str(":{");
+        Ok(Compound {
+            ser: self,
+            first: true,
+            close: "}}",
+        })
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_> {
+    type Ok = ();
+    type Error = JsonError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), JsonError> {
+        self.comma();
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), JsonError> {
+        self.ser.out.push_str(self.close);
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_> {
+    type Ok = ();
+    type Error = JsonError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), JsonError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), JsonError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_> {
+    type Ok = ();
+    type Error = JsonError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), JsonError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), JsonError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_> {
+    type Ok = ();
+    type Error = JsonError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), JsonError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), JsonError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeMap for Compound<'_> {
+    type Ok = ();
+    type Error = JsonError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), JsonError> {
+        self.comma();
+        key.serialize(MapKeySerializer {
+            ser: &mut *self.ser,
+        })?;
+        self.ser.out.push(':');
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), JsonError> {
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), JsonError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_> {
+    type Ok = ();
+    type Error = JsonError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), JsonError> {
+        SerializeMap::serialize_entry(self, key, value)
+    }
+
+    fn end(self) -> Result<(), JsonError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_> {
+    type Ok = ();
+    type Error = JsonError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), JsonError> {
+        SerializeMap::serialize_entry(self, key, value)
+    }
+
+    fn end(self) -> Result<(), JsonError> {
+        SerializeSeq::end(self)
+    }
+}
+
+/// Object keys have to be strings. Integers, chars and unit variants are written as strings,
+/// anything else is an error.
+struct MapKeySerializer<'a> {
+    ser: &'a mut Serializer,
+}
+
+fn key_must_be_a_string() -> JsonError {
+    JsonError::Serde("object keys must be strings".to_string())
+}
+
+macro_rules! integer_key {
+    ($($method:ident: $t:ty),*) => {
+        $(fn $method(self, v: $t) -> Result<(), JsonError> {
+            self.ser.write_str(&v.to_string());
+            Ok(())
+        })*
+    };
+}
+
+impl ser::Serializer for MapKeySerializer<'_> {
+    type Ok = ();
+    type Error = JsonError;
+    type SerializeSeq = ser::Impossible<(), JsonError>;
+    type SerializeTuple = ser::Impossible<(), JsonError>;
+    type SerializeTupleStruct = ser::Impossible<(), JsonError>;
+    type SerializeTupleVariant = ser::Impossible<(), JsonError>;
+    type SerializeMap = ser::Impossible<(), JsonError>;
+    type SerializeStruct = ser::Impossible<(), JsonError>;
+    type SerializeStructVariant = ser::Impossible<(), JsonError>;
+
+    integer_key!(
+        serialize_i8: i8, serialize_i16: i16, serialize_i32: i32, serialize_i64: i64,
+        serialize_i128: i128, serialize_u8: u8, serialize_u16: u16, serialize_u32: u32,
+        serialize_u64: u64, serialize_u128: u128
+    );
+
+    fn serialize_str(self, v: &str) -> Result<(), JsonError> {
+        self.ser.write_str(v);
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), JsonError> {
+        self.ser.write_str(v.encode_utf8(&mut [0; 4]));
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), JsonError> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), JsonError> {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<(), JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<(), JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<(), JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_none(self) -> Result<(), JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<(), JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit(self) -> Result<(), JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, JsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, JsonError> {
+        Err(key_must_be_a_string())
+    }
+}
+
+/// Keys are serialized in sorted order, like `writer::to_string` does.
+impl Serialize for Json {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Json::Dict(x) => {
+                let mut keys: Vec<&String> = x.keys().collect();
+                keys.sort();
+                let mut map = serializer.serialize_map(Some(x.len()))?;
+                for key in keys {
+                    map.serialize_entry(key, &x[key])?;
+                }
+                map.end()
+            }
+            Json::List(x) => {
+                let mut seq = serializer.serialize_seq(Some(x.len()))?;
+                for value in x {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            Json::Value(c) => c.serialize(serializer),
+        }
+    }
+}
+
+/// `Constant::Number` is written as an integer if it fits into `i64`/`u64`, and as `f64`
+/// otherwise, since serde has no arbitrary precision number type.
+impl Serialize for Constant {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Constant::Float(x) => serializer.serialize_f64(*x),
+            Constant::Int(x) => serializer.serialize_i64(*x),
+            Constant::UInt(x) => serializer.serialize_u64(*x),
+            Constant::Number(x) => match (x.as_i64(), x.as_u64()) {
+                (Some(i), _) => serializer.serialize_i64(i),
+                (None, Some(u)) => serializer.serialize_u64(u),
+                (None, None) => serializer.serialize_f64(x.as_f64()),
+            },
+            Constant::StringLiteral(x) => serializer.serialize_str(x),
+            Constant::Null => serializer.serialize_unit(),
+            Constant::Boolean(x) => serializer.serialize_bool(*x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::de::from_str;
+    use crate::parser::parse;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Point,
+        Circle(f32),
+        Rect { w: u32, h: u32 },
+        Line(i8, i8),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        id: u64,
+        name: String,
+        parent: Option<Box<Event>>,
+        shapes: Vec<Shape>,
+        counts: BTreeMap<u8, f64>,
+    }
+
+    #[test]
+    fn test_to_string() {
+        let event = Event {
+            id: 7,
+            name: "line\nbreak".to_string(),
+            parent: None,
+            shapes: vec![
+                Shape::Point,
+                Shape::Circle(0.1),
+                Shape::Rect { w: 1, h: 2 },
+                Shape::Line(-1, 1),
+            ],
+            counts: BTreeMap::from([(1, 0.5), (2, f64::NAN)]),
+        };
+        let written = to_string(&event).expect("should not error");
+        assert_eq!(
+            written,
+            concat!(
+                r#"{"id":7,"name":"line\nbreak","parent":null,"#,
+                r#""shapes":["Point",{"Circle":0.1},{"Rect":{"w":1,"h":2}},{"Line":[-1,1]}],"#,
+                r#""counts":{"1":0.5,"2":null}}"#
+            )
+        );
+        assert!(matches!(
+            to_string(&BTreeMap::from([(vec![1], 1)])),
+            Err(JsonError::Serde(_))
+        ));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let event = Event {
+            id: u64::MAX,
+            name: "\u{1f600}\"".to_string(),
+            parent: Some(Box::new(Event {
+                id: 0,
+                name: String::new(),
+                parent: None,
+                shapes: vec![],
+                counts: BTreeMap::new(),
+            })),
+            shapes: vec![Shape::Circle(-1e-7), Shape::Line(i8::MIN, i8::MAX)],
+            counts: BTreeMap::from([(255, 1e300)]),
+        };
+        let written = to_string(&event).unwrap();
+        assert_eq!(from_str::<Event>(&written).unwrap(), event);
+
+        let json = parse(r#"{"b": [1, 2.5, "s", null], "a": {"c": true}}"#).unwrap();
+        let written = to_string(&json).unwrap();
+        assert_eq!(written, json.to_string());
+        assert_eq!(from_str::<Json>(&written).unwrap(), json);
+    }
+}
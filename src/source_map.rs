@@ -0,0 +1,389 @@
+//! Byte spans and line/column positions for every node of a document, keyed by JSON Pointer.
+use crate::cst::{Document, Element, Member, Node, NodeKind, TokenKind};
+use crate::lexer::{Constant, SpannedToken, Token};
+use crate::parser::{parse_tokens, DuplicateKeys, Json, JsonError, ParseOptions};
+use crate::pointer::escape_pointer_token;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// 1-based line and column, columns are counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub span: Range<usize>,
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMapEntry {
+    /// Location of the key (including quotes), `None` for array elements and the root.
+    pub key: Option<Location>,
+    pub value: Location,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMap {
+    entries: HashMap<String, SourceMapEntry>,
+}
+
+impl SourceMap {
+    /// The map of a document whose duplicate keys are resolved like `DuplicateKeys::LastWins`.
+    pub fn from_document(document: &Document) -> SourceMap {
+        SourceMap::build(document.source(), document.root(), DuplicateKeys::LastWins)
+    }
+
+    /// Walks the tree without recursion, so that the depth is only bounded by the limits it was
+    /// parsed with.
+    fn build(source: &str, root: &Node, duplicate_keys: DuplicateKeys) -> SourceMap {
+        let mut spans = Vec::new();
+        let mut stack = vec![(root, String::new(), None)];
+        while let Some((node, pointer, key)) = stack.pop() {
+            match &node.kind {
+                NodeKind::Object(members) => {
+                    let mut seen = HashSet::new();
+                    let mut occurrences = HashMap::new();
+                    let mut members: Vec<_> = members.iter().collect();
+                    if duplicate_keys == DuplicateKeys::LastWins {
+                        // so that the last occurrence is the one kept
+                        members.reverse();
+                    }
+                    for member in members {
+                        let mut child =
+                            format!("{}/{}", pointer, escape_pointer_token(&member.name));
+                        if duplicate_keys == DuplicateKeys::Collect {
+                            let n = occurrences.entry(&member.name).or_insert(0);
+                            child = format!("{}/{}", child, n);
+                            *n += 1;
+                        } else if !seen.insert(&member.name) {
+                            continue;
+                        }
+                        stack.push((&member.value, child, Some(member.key.clone())));
+                    }
+                }
+                NodeKind::Array(elements) => {
+                    for (i, element) in elements.iter().enumerate() {
+                        stack.push((&element.value, format!("{}/{}", pointer, i), None));
+                    }
+                }
+                NodeKind::Scalar(_) => {}
+            }
+            spans.push((pointer, key, node.span.clone()));
+        }
+
+        let mut offsets: Vec<usize> = spans
+            .iter()
+            .flat_map(|(_, key, value)| key.iter().chain([value]))
+            .flat_map(|span| [span.start, span.end])
+            .collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        let positions = positions(source, &offsets);
+        let location = |span: Range<usize>| {
+            let position = |offset| positions[offsets.binary_search(&offset).unwrap()];
+            Location {
+                start: position(span.start),
+                end: position(span.end),
+                span,
+            }
+        };
+        let entries = spans
+            .into_iter()
+            .map(|(pointer, key, value)| {
+                let entry = SourceMapEntry {
+                    key: key.map(location),
+                    value: location(value),
+                };
+                (pointer, entry)
+            })
+            .collect();
+        SourceMap { entries }
+    }
+
+    /// Looks up a node by its JSON Pointer, e.g. `/key4/key5`. The root is `""`.
+    pub fn get(&self, pointer: &str) -> Option<&SourceMapEntry> {
+        self.entries.get(pointer)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &SourceMapEntry)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// The positions of the sorted `offsets`, counted in a single pass over `source`.
+fn positions(source: &str, offsets: &[usize]) -> Vec<Position> {
+    let mut result = Vec::with_capacity(offsets.len());
+    let mut chars = source.char_indices().peekable();
+    let mut position = Position { line: 1, column: 1 };
+    for &offset in offsets {
+        while let Some((_, c)) = chars.next_if(|&(i, _)| i < offset) {
+            if c == '\n' {
+                position = Position {
+                    line: position.line + 1,
+                    column: 1,
+                };
+            } else {
+                position.column += 1;
+            }
+        }
+        result.push(position);
+    }
+    result
+}
+
+/// Parses `json` like `parser::parse` and additionally returns where each node came from.
+pub fn parse_with_spans(json: &str) -> Result<(Json, SourceMap), JsonError> {
+    parse_with_spans_and_options(json, &ParseOptions::default())
+}
+
+/// Like `parse_with_options`. The map resolves duplicate keys like `options.duplicate_keys`.
+/// With `DuplicateKeys::Collect`, the n-th occurrence of a key is at `/key/n`, like in the
+/// parsed `Json`, and `/key` itself has no entry.
+pub fn parse_with_spans_and_options(
+    json: &str,
+    options: &ParseOptions,
+) -> Result<(Json, SourceMap), JsonError> {
+    let (result, tokens) = parse_tokens(json, options)?;
+    // the parser has accepted these tokens, so they only need to be put back into a tree
+    let root = tree(&tokens);
+    Ok((
+        result,
+        SourceMap::build(json, &root, options.duplicate_keys),
+    ))
+}
+
+/// A container whose closing token hasn't been reached yet.
+struct Open {
+    start: usize,
+    kind: NodeKind,
+    /// Key of the member whose value comes next.
+    key: Option<(String, Range<usize>)>,
+    expects_key: bool,
+}
+
+/// Rebuilds the tree the parser saw in `tokens`, without recursion. Like the parser, it skips
+/// members without a value.
+fn tree(tokens: &[SpannedToken]) -> Node {
+    let mut stack: Vec<Open> = Vec::new();
+    for token in tokens {
+        let node = match &token.token {
+            Token::StartOfDict | Token::StartOfList => {
+                let is_dict = token.token == Token::StartOfDict;
+                stack.push(Open {
+                    start: token.span.start,
+                    kind: match is_dict {
+                        true => NodeKind::Object(Vec::new()),
+                        false => NodeKind::Array(Vec::new()),
+                    },
+                    key: None,
+                    expects_key: is_dict,
+                });
+                continue;
+            }
+            Token::Colon => continue,
+            Token::Comma => {
+                if let Some(open) = stack.last_mut() {
+                    open.expects_key = matches!(open.kind, NodeKind::Object(_));
+                    let comma = match &mut open.kind {
+                        NodeKind::Object(members) => members.last_mut().map(|m| &mut m.comma),
+                        NodeKind::Array(elements) => elements.last_mut().map(|e| &mut e.comma),
+                        NodeKind::Scalar(_) => None,
+                    };
+                    if let Some(comma) = comma {
+                        *comma = Some(token.span.start);
+                    }
+                }
+                continue;
+            }
+            Token::Constant(Constant::StringLiteral(name))
+                if stack.last().is_some_and(|open| open.expects_key) =>
+            {
+                let open = stack.last_mut().expect("checked above");
+                open.key = Some((name.clone(), token.span.clone()));
+                open.expects_key = false;
+                continue;
+            }
+            Token::Constant(constant) => Node {
+                kind: NodeKind::Scalar(match constant {
+                    Constant::StringLiteral(_) => TokenKind::String,
+                    Constant::Boolean(true) => TokenKind::True,
+                    Constant::Boolean(false) => TokenKind::False,
+                    Constant::Null => TokenKind::Null,
+                    _ => TokenKind::Number,
+                }),
+                span: token.span.clone(),
+            },
+            Token::EndOfDict | Token::EndOfList => match stack.pop() {
+                Some(open) => Node {
+                    kind: open.kind,
+                    span: open.start..token.span.end,
+                },
+                None => continue,
+            },
+        };
+        if let Some(root) = attach(&mut stack, node) {
+            return root;
+        }
+    }
+    // the parser lets containers inside the root go unclosed, e.g. `[[1]`
+    let end = tokens.last().map_or(0, |token| token.span.end);
+    loop {
+        let open = stack.pop().expect("the root is still open");
+        let node = Node {
+            kind: open.kind,
+            span: open.start..end,
+        };
+        if let Some(root) = attach(&mut stack, node) {
+            return root;
+        }
+    }
+}
+
+/// Adds `node` to the innermost open container, or returns it if it is the root.
+fn attach(stack: &mut [Open], value: Node) -> Option<Node> {
+    let Some(open) = stack.last_mut() else {
+        return Some(value);
+    };
+    match &mut open.kind {
+        NodeKind::Object(members) => {
+            if let Some((name, key)) = open.key.take() {
+                members.push(Member {
+                    name,
+                    key,
+                    value,
+                    comma: None,
+                });
+            }
+        }
+        NodeKind::Array(elements) => elements.push(Element { value, comma: None }),
+        NodeKind::Scalar(_) => unreachable!("scalars are never open"),
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::limits::{Limit, Limits};
+    use crate::parser::parse;
+
+    #[test]
+    fn test_parse_with_spans() {
+        let json = r#"{
+    "key1": 1,
+    "key3": [1, 2, 3],
+    "key4": {
+        "kéy/5": "value5",
+        "key6": 6
+    }
+}"#;
+        let (parsed, map) = parse_with_spans(json).expect("should not error");
+        assert_eq!(parsed, parse(json).unwrap());
+        assert_eq!(map.len(), 9);
+
+        let root = map.get("").unwrap();
+        assert_eq!(root.key, None);
+        assert_eq!(root.value.span, 0..json.len());
+
+        let entry = map.get("/key3/2").unwrap();
+        assert_eq!(&json[entry.value.span.clone()], "3");
+        assert_eq!(
+            entry.value.start,
+            Position {
+                line: 3,
+                column: 20
+            }
+        );
+
+        let entry = map.get("/key4/k\u{e9}y~15").unwrap();
+        let key = entry.key.as_ref().unwrap();
+        assert_eq!(&json[key.span.clone()], "\"k\u{e9}y/5\"");
+        assert_eq!(key.start, Position { line: 5, column: 9 });
+        assert_eq!(
+            entry.value.start,
+            Position {
+                line: 5,
+                column: 18
+            }
+        );
+        assert_eq!(
+            entry.value.end,
+            Position {
+                line: 5,
+                column: 26
+            }
+        );
+    }
+
+    #[test]
+    fn test_duplicate_keys_point_at_last_occurrence() {
+        let (_, map) = parse_with_spans(r#"{"a": {"x": 1}, "a": {"y": 2}}"#).unwrap();
+        assert!(map.get("/a/x").is_none());
+        assert_eq!(map.get("/a/y").unwrap().value.span, 27..28);
+    }
+
+    #[test]
+    fn test_duplicate_key_policies() {
+        let json = r#"{"a": {"x": 1}, "a": {"y": 2}}"#;
+        let options = |duplicate_keys| ParseOptions {
+            duplicate_keys,
+            ..ParseOptions::default()
+        };
+        let (_, map) = parse_with_spans_and_options(json, &options(DuplicateKeys::FirstWins))
+            .expect("should not error");
+        assert_eq!(map.get("/a/x").unwrap().value.span, 12..13);
+        assert!(map.get("/a/y").is_none());
+        let (_, map) = parse_with_spans_and_options(json, &options(DuplicateKeys::Collect))
+            .expect("should not error");
+        assert_eq!(map.get("/a/1/y/0").unwrap().value.span, 27..28);
+        assert_eq!(map.get("/a/0").unwrap().key.as_ref().unwrap().span, 1..4);
+        assert!(map.get("/a").is_none());
+        assert!(parse_with_spans_and_options(json, &options(DuplicateKeys::Reject)).is_err());
+    }
+
+    #[test]
+    fn test_follows_the_parser() {
+        assert!(matches!(
+            parse_with_spans("[1, 2"),
+            Err(JsonError::JsonParseError(_))
+        ));
+        assert!(parse_with_spans("// comment\n[1]").is_err());
+        let json = "[\"a\nb\", {\"c\": [1,], \"d\": true,}]";
+        let (parsed, map) = parse_with_spans(json).expect("should not error");
+        assert_eq!(parsed, parse(json).unwrap());
+        assert_eq!(
+            map.get("/0").unwrap().value.end,
+            Position { line: 2, column: 3 }
+        );
+        assert_eq!(&json[map.get("/1/c").unwrap().value.span.clone()], "[1,]");
+        assert_eq!(map.len(), 6);
+        let deep = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert!(matches!(
+            parse_with_spans(&deep),
+            Err(JsonError::LimitExceeded(Limit::Depth, 128))
+        ));
+        let options = ParseOptions {
+            limits: Limits {
+                max_depth: 200,
+                ..Limits::default()
+            },
+            ..ParseOptions::default()
+        };
+        let deep = format!("{}{}", "[".repeat(200), "]".repeat(200));
+        let (_, map) = parse_with_spans_and_options(&deep, &options).expect("should not error");
+        assert_eq!(map.len(), 200);
+    }
+}
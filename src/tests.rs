@@ -27,3 +27,24 @@ fn parse_json() {
         _ => panic!("should be a list"),
     }
 }
+
+#[test]
+fn parse_unescapes_strings() {
+    use crate::lexer::Constant::StringLiteral;
+    use crate::parser::Json;
+
+    let raw_json = r#"{"k\"ey": ["a\\b", "\u00e9\ud83d\ude00\n\/"]}"#;
+    let json = crate::parser::parse(raw_json).expect("should not error");
+    let Json::Dict(x) = json else {
+        panic!("should be a dict")
+    };
+    assert_eq!(
+        x.get("k\"ey").expect("key should be unescaped"),
+        &Json::List(vec![
+            Json::Value(StringLiteral("a\\b".to_string())),
+            Json::Value(StringLiteral("\u{e9}\u{1f600}\n/".to_string())),
+        ])
+    );
+    assert!(crate::parser::parse(r#"["\x"]"#).is_err());
+    assert!(crate::parser::parse(r#"["\ud83d"]"#).is_err());
+}
@@ -0,0 +1,143 @@
+//! Serializes `Json` back into text.
+use crate::float_format::{write_f64, FloatFormatError, FloatOptions};
+use crate::lexer::{escape, Constant};
+use crate::parser::Json;
+
+/// Compact JSON text for `json`. Object keys are written in sorted order so that the output
+/// doesn't depend on `HashMap` iteration order.
+pub fn to_string(json: &Json) -> String {
+    to_string_with(json, &FloatOptions::default())
+        .expect("the default float options never reject a value")
+}
+
+/// Like `to_string`, with control over how NaN, the infinities and -0.0 are written.
+pub fn to_string_with(json: &Json, floats: &FloatOptions) -> Result<String, FloatFormatError> {
+    let mut out = String::new();
+    write_json(&mut out, json, floats)?;
+    Ok(out)
+}
+
+fn write_json(
+    out: &mut String,
+    json: &Json,
+    floats: &FloatOptions,
+) -> Result<(), FloatFormatError> {
+    match json {
+        Json::Dict(x) => {
+            let mut keys: Vec<&String> = x.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(out, key);
+                out.push(':');
+                write_json(out, &x[key], floats)?;
+            }
+            out.push('}');
+        }
+        Json::List(x) => {
+            out.push('[');
+            for (i, value) in x.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json(out, value, floats)?;
+            }
+            out.push(']');
+        }
+        Json::Value(c) => write_constant(out, c, floats)?,
+    }
+    Ok(())
+}
+
+pub(crate) fn write_constant(
+    out: &mut String,
+    constant: &Constant,
+    floats: &FloatOptions,
+) -> Result<(), FloatFormatError> {
+    match constant {
+        Constant::Float(x) => write_f64(out, *x, floats)?,
+        Constant::Int(x) => out.push_str(&x.to_string()),
+        Constant::UInt(x) => out.push_str(&x.to_string()),
+        Constant::Number(x) => out.push_str(x.as_str()),
+        Constant::StringLiteral(x) => write_string(out, x),
+        Constant::Null => out.push_str("null"),
+        Constant::Boolean(x) => out.push_str(if *x { "true" } else { "false" }),
+    }
+    Ok(())
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    out.push_str(&escape(s));
+    out.push('"');
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&to_string(self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::float_format::SpecialFloat;
+    use crate::lexer::NumberMode;
+    use crate::parser::{parse, parse_with_options, ParseOptions};
+
+    #[test]
+    fn test_to_string() {
+        let json =
+            r#"{"key3": [1, -2.5, "a\"b\n"], "key1": null, "key2": {"x": 18446744073709551615}}"#;
+        let written = to_string(&parse(json).unwrap());
+        assert_eq!(
+            written,
+            r#"{"key1":null,"key2":{"x":18446744073709551615},"key3":[1,-2.5,"a\"b\n"]}"#
+        );
+        assert_eq!(parse(&written).unwrap(), parse(json).unwrap());
+    }
+
+    #[test]
+    fn test_float_policy() {
+        let json = Json::List(vec![
+            Json::Value(Constant::Float(0.1)),
+            Json::Value(Constant::Float(f64::NAN)),
+        ]);
+        assert_eq!(to_string(&json), "[0.1,null]");
+        let reject = FloatOptions {
+            non_finite: SpecialFloat::Reject,
+            ..Default::default()
+        };
+        assert!(to_string_with(&json, &reject).is_err());
+    }
+
+    #[test]
+    fn test_preserved_numbers_roundtrip() {
+        let json =
+            r#"[0.1000000000000000055511151231257827,100.50,-0,123456789012345678901234567890]"#;
+        let options = ParseOptions {
+            number_mode: NumberMode::Preserve,
+            ..Default::default()
+        };
+        let parsed = parse_with_options(json, &options).expect("should not error");
+        assert_eq!(parsed.to_string(), json);
+
+        let Json::List(numbers) = parsed else {
+            panic!("should be a list");
+        };
+        let number = |i: usize| match &numbers[i] {
+            Json::Value(Constant::Number(n)) => n.clone(),
+            _ => panic!("should be a number"),
+        };
+        assert_eq!(number(0).as_f64(), 0.1);
+        assert_eq!(number(0).as_i64(), None);
+        assert_eq!(number(1).as_f64(), 100.5);
+        assert!(!number(1).is_integer());
+        assert_eq!(number(2).as_i64(), Some(0));
+        assert_eq!(number(3).as_u64(), None);
+        assert_eq!(number(3).as_i128(), Some(123456789012345678901234567890));
+    }
+}
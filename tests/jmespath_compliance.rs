@@ -0,0 +1,112 @@
+//! Runs the JMESPath compliance tests in `tests/jmespath`, which are meant to be unchanged copies
+//! of the jmespath.test suite (https://github.com/jmespath/jmespath.test), and our own cases in
+//! the same format in `tests/jmespath_extra`. The copies are transcribed by hand for now, and the
+//! suite's generated `identifiers.json` and `benchmarks.json` are missing. Run
+//! `tests/vendor_jmespath.sh <commit>` to replace them with the upstream files, its LICENSE and a
+//! SOURCE file naming the commit.
+//!
+//! The cases in `SKIPPED` are the only ones that aren't run, see there. The `bench` key of
+//! benchmark cases is ignored, they are checked like any other case.
+use rusty_json::jmespath::{JmesPath, JmesPathError};
+use rusty_json::lexer::Constant;
+use rusty_json::parser::{parse, Json};
+use std::fs;
+
+/// Cases that are known to fail, by path below `tests` and expression. Upstream expects the
+/// members of objects in document order, but `Json` objects are unordered.
+const SKIPPED: &[(&str, &str)] = &[
+    ("jmespath/functions.json", "keys(objects)"),
+    ("jmespath/functions.json", "values(objects)"),
+];
+
+fn float(json: &Json) -> Option<f64> {
+    match json {
+        Json::Value(Constant::Int(x)) => Some(*x as f64),
+        Json::Value(Constant::UInt(x)) => Some(*x as f64),
+        Json::Value(Constant::Float(x)) => Some(*x),
+        _ => None,
+    }
+}
+
+/// Equality with numbers compared by value, as the suite expects `1.0` to match `1`.
+fn same(a: &Json, b: &Json) -> bool {
+    match (a, b) {
+        (Json::Dict(x), Json::Dict(y)) => {
+            x.len() == y.len() && x.iter().all(|(k, v)| y.get(k).is_some_and(|w| same(v, w)))
+        }
+        (Json::List(x), Json::List(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(v, w)| same(v, w))
+        }
+        _ => match (float(a), float(b)) {
+            (Some(x), Some(y)) => x == y,
+            _ => a == b,
+        },
+    }
+}
+
+fn error_kind(error: &JmesPathError) -> &'static str {
+    match error {
+        JmesPathError::Syntax(..) => "syntax",
+        JmesPathError::UnknownFunction(_) => "unknown-function",
+        JmesPathError::InvalidArity(_) => "invalid-arity",
+        JmesPathError::InvalidType(_) => "invalid-type",
+        JmesPathError::InvalidValue(_) => "invalid-value",
+    }
+}
+
+fn string(json: &Json) -> &str {
+    match json {
+        Json::Value(Constant::StringLiteral(x)) => x,
+        _ => panic!("expected a string, got {}", json),
+    }
+}
+
+#[test]
+fn test_compliance() {
+    let mut paths = ["tests/jmespath", "tests/jmespath_extra"]
+        .into_iter()
+        .flat_map(|directory| fs::read_dir(directory).unwrap())
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|x| x == "json"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty());
+    let mut failures = Vec::new();
+    let mut skipped = Vec::new();
+    for path in paths {
+        let name = path.strip_prefix("tests").unwrap().to_str().unwrap();
+        let Json::List(suites) = parse(&fs::read_to_string(&path).unwrap()).unwrap() else {
+            panic!("{:?} is not a list of suites", path);
+        };
+        for suite in &suites {
+            let Json::Dict(suite) = suite else { panic!() };
+            let Json::List(cases) = &suite["cases"] else {
+                panic!()
+            };
+            for case in cases {
+                let Json::Dict(case) = case else { panic!() };
+                let expression = string(&case["expression"]);
+                if SKIPPED.contains(&(name, expression)) {
+                    skipped.push((name.to_string(), expression.to_string()));
+                    continue;
+                }
+                let result = JmesPath::parse(expression).and_then(|x| x.search(&suite["given"]));
+                let passed = match (&result, case.get("result"), case.get("error")) {
+                    (Ok(actual), Some(expected), _) => same(actual, expected),
+                    (Err(e), _, Some(expected)) => error_kind(e) == string(expected),
+                    _ => false,
+                };
+                if !passed {
+                    failures.push(format!("{:?} {}: {:?}", path, expression, result));
+                }
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+    // so that the list doesn't outlive the cases
+    let skipped = skipped.iter().map(|(x, y)| (x.as_str(), y.as_str()));
+    assert!(
+        skipped.eq(SKIPPED.iter().copied()),
+        "not all SKIPPED cases were found"
+    );
+}
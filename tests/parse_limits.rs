@@ -0,0 +1,126 @@
+//! Memory and time needed to parse deeply nested input. This is a separate test binary because
+//! it replaces the global allocator to measure the peak heap usage.
+use rusty_json::limits::{Limit, Limits};
+use rusty_json::parser::{parse, parse_with_options, Json, JsonError, ParseOptions};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+/// The counters are global, so measurements are taken one at a time.
+static MEASURING: Mutex<()> = Mutex::new(());
+
+/// Runs `f` and returns its result and the peak heap usage above what was allocated before.
+fn measure<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let _guard = MEASURING.lock().unwrap_or_else(|e| e.into_inner());
+    let baseline = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    let result = f();
+    (result, PEAK.load(Ordering::Relaxed) - baseline)
+}
+
+/// Parses `levels` nested arrays around an array of 200_000 numbers and returns the peak heap
+/// usage and the time it took.
+fn parse_nested(levels: usize) -> (usize, Duration) {
+    let elements = vec!["0"; 200_000].join(",");
+    let json = format!(
+        "{}[{}]{}",
+        "[".repeat(levels),
+        elements,
+        "]".repeat(levels)
+    );
+
+    let start = Instant::now();
+    let (parsed, peak) = measure(|| parse(&json).expect("should not error"));
+    let elapsed = start.elapsed();
+    let mut parsed = parsed;
+
+    for _ in 0..levels {
+        parsed = match parsed {
+            Json::List(mut x) if x.len() == 1 => x.pop().unwrap(),
+            other => panic!("expected a single element list, got {:?}", other),
+        };
+    }
+    assert!(matches!(parsed, Json::List(x) if x.len() == 200_000));
+    (peak, elapsed)
+}
+
+#[test]
+fn deep_nesting_around_a_large_array() {
+    let (flat_peak, _) = parse_nested(0);
+    // just below the default depth limit of 128
+    let (peak, elapsed) = parse_nested(120);
+    assert!(
+        peak < flat_peak * 3 / 2,
+        "peak heap usage was {} bytes, {} without nesting",
+        peak,
+        flat_peak
+    );
+    assert!(elapsed < Duration::from_secs(10), "parsing took {:?}", elapsed);
+}
+
+/// The limits are checked while the input is lexed, so an input far beyond them doesn't have to be
+/// held in memory before it fails.
+#[test]
+fn limits_bound_memory() {
+    // about 10 MB each
+    let numbers = format!("[{}0]", "0,".repeat(5_000_000));
+    let string = format!(r#"["{}"]"#, "a".repeat(10_000_000));
+    let number = format!("[{}]", "1".repeat(10_000_000));
+    let keys = (0..1_000_000).map(|i| format!(r#""{}":0"#, i));
+    let members = format!("{{{}}}", keys.collect::<Vec<_>>().join(","));
+    for max in [10, 1000] {
+        let cases = [
+            (&numbers, Limit::ArrayLength),
+            (&numbers, Limit::NodeCount),
+            (&string, Limit::StringLength),
+            (&number, Limit::NumberLength),
+            (&members, Limit::MemberCount),
+        ];
+        for (json, expected) in cases {
+            let mut limits = Limits::unlimited();
+            *match expected {
+                Limit::ArrayLength => &mut limits.max_array_length,
+                Limit::NodeCount => &mut limits.max_nodes,
+                Limit::StringLength => &mut limits.max_string_length,
+                Limit::NumberLength => &mut limits.max_number_length,
+                _ => &mut limits.max_members,
+            } = max;
+            let options = ParseOptions {
+                limits,
+                ..Default::default()
+            };
+            let (result, peak) = measure(|| parse_with_options(json, &options));
+            match result {
+                Err(JsonError::LimitExceeded(limit, m)) => assert_eq!((limit, m), (expected, max)),
+                other => panic!("expected {:?} to be exceeded, got {:?}", expected, other),
+            }
+            // a few hundred bytes per value at most, whatever the size of the input
+            assert!(peak < 4096 + 256 * max, "{:?}: {} bytes", expected, peak);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+#!/bin/sh
+# Replaces tests/jmespath with unchanged copies of the jmespath.test suite at the given commit,
+# together with its LICENSE and a SOURCE file naming the commit.
+#
+#     tests/vendor_jmespath.sh <commit>
+set -eu
+commit=$1
+repository=https://github.com/jmespath/jmespath.test
+target=$(dirname "$0")/jmespath
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+curl -fsSL "$repository/archive/$commit.tar.gz" | tar -xz -C "$work" --strip-components=1
+rm -rf "$target"
+mkdir "$target"
+cp "$work"/tests/*.json "$target"/
+cp "$work"/LICENSE* "$target"/
+printf '%s\n%s\n' "$repository" "$commit" > "$target/SOURCE"